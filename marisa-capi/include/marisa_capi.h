@@ -0,0 +1,40 @@
+/* C interface to the marisa crate. See src/lib.rs for the contracts of
+ * each function. Strings are passed as pointer and length and need not be
+ * NUL-terminated; keys must be UTF-8. */
+#ifndef MARISA_CAPI_H
+#define MARISA_CAPI_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MarisaTrie MarisaTrie;
+typedef struct MarisaResults MarisaResults;
+
+MarisaTrie *marisa_trie_build(const char *const *keys, const size_t *lens, size_t num_keys);
+MarisaTrie *marisa_trie_load(const char *path);
+MarisaTrie *marisa_trie_mmap(const char *path);
+int marisa_trie_save(const MarisaTrie *trie, const char *path);
+void marisa_trie_free(MarisaTrie *trie);
+
+size_t marisa_trie_num_keys(const MarisaTrie *trie);
+int marisa_trie_lookup(const MarisaTrie *trie, const char *key, size_t len, size_t *id);
+MarisaResults *marisa_trie_complete(const MarisaTrie *trie, const char *prefix, size_t len,
+                                    size_t max_results);
+MarisaResults *marisa_trie_common_prefixes(const MarisaTrie *trie, const char *query,
+                                           size_t len);
+
+size_t marisa_results_len(const MarisaResults *results);
+const char *marisa_results_key(const MarisaResults *results, size_t index, size_t *len);
+size_t marisa_results_id(const MarisaResults *results, size_t index);
+void marisa_results_free(MarisaResults *results);
+
+const char *marisa_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
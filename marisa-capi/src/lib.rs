@@ -0,0 +1,439 @@
+//! A stable C ABI over the `marisa` crate's safe API, for services in C,
+//! Go (through cgo) or anything else with a C FFI. The declarations are in
+//! `include/marisa_capi.h`.
+//!
+//! Tries and result lists are opaque handles owned by the caller and
+//! released with their `_free` function. Functions report failure with a
+//! null handle or a negative return value; [`marisa_last_error`] then
+//! describes it. Panics never cross the boundary.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+};
+
+use marisa::{
+    error::{MarisaError, Result},
+    marisa::{Keyset, Trie},
+};
+
+/// A built, loaded or mapped trie.
+pub struct MarisaTrie {
+    trie: Trie,
+}
+
+/// Keys and ids returned by a search, each key stored with a trailing NUL.
+pub struct MarisaResults {
+    results: Vec<(usize, Vec<u8>)>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).expect("NULs were replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Runs `f`, turning errors and panics into `failed` and a last error.
+fn guard<T>(failed: T, f: impl FnOnce() -> Result<T>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(err)) => {
+            set_error(&err.to_string());
+            failed
+        }
+        Err(_) => {
+            set_error("panic inside marisa");
+            failed
+        }
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be null only when `len` is 0, and otherwise point to `len`
+/// readable bytes.
+unsafe fn str_arg<'a>(ptr: *const c_char, len: usize) -> Result<&'a str> {
+    if len == 0 {
+        return Ok("");
+    }
+    if ptr.is_null() {
+        return Err(MarisaError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "null string with a nonzero length",
+        )));
+    }
+    Ok(std::str::from_utf8(std::slice::from_raw_parts(
+        ptr.cast(),
+        len,
+    ))?)
+}
+
+/// # Safety
+///
+/// `path` must be null or a NUL-terminated string.
+unsafe fn path_arg(path: *const c_char) -> Result<PathBuf> {
+    if path.is_null() {
+        return Err(MarisaError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "null path",
+        )));
+    }
+    Ok(PathBuf::from(CStr::from_ptr(path).to_str()?))
+}
+
+fn into_handle(trie: Trie) -> *mut MarisaTrie {
+    Box::into_raw(Box::new(MarisaTrie { trie }))
+}
+
+fn into_results(results: Vec<(usize, String)>) -> Result<*mut MarisaResults> {
+    let results = results
+        .into_iter()
+        .map(|(id, key)| {
+            let mut bytes = key.into_bytes();
+            bytes.push(0);
+            (id, bytes)
+        })
+        .collect();
+    Ok(Box::into_raw(Box::new(MarisaResults { results })))
+}
+
+/// Builds a trie from `num_keys` keys, the `i`th being `lens[i]` bytes at
+/// `keys[i]`. Returns null on failure.
+///
+/// # Safety
+///
+/// `keys` and `lens` must point to `num_keys` entries each, and every key
+/// to as many bytes as its length says.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_build(
+    keys: *const *const c_char,
+    lens: *const usize,
+    num_keys: usize,
+) -> *mut MarisaTrie {
+    guard(ptr::null_mut(), || {
+        let mut keyset = Keyset::default();
+        if num_keys > 0 {
+            let keys = std::slice::from_raw_parts(keys, num_keys);
+            let lens = std::slice::from_raw_parts(lens, num_keys);
+            for (&key, &len) in keys.iter().zip(lens) {
+                keyset.push(str_arg(key, len)?, None)?;
+            }
+        }
+        Ok(into_handle(Trie::build_from(keyset)?))
+    })
+}
+
+/// Reads the trie saved at `path`. Returns null on failure.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_load(path: *const c_char) -> *mut MarisaTrie {
+    guard(ptr::null_mut(), || {
+        let mut trie = Trie::default();
+        trie.load(&path_arg(path)?)?;
+        Ok(into_handle(trie))
+    })
+}
+
+/// Maps the trie saved at `path` instead of reading it. Returns null on
+/// failure.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_mmap(path: *const c_char) -> *mut MarisaTrie {
+    guard(ptr::null_mut(), || {
+        let mut trie = Trie::default();
+        trie.mmap(&path_arg(path)?)?;
+        Ok(into_handle(trie))
+    })
+}
+
+/// Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `trie` must be a live handle and `path` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_save(trie: *const MarisaTrie, path: *const c_char) -> c_int {
+    guard(-1, || {
+        (*trie).trie.save(&path_arg(path)?)?;
+        Ok(0)
+    })
+}
+
+/// # Safety
+///
+/// `trie` must be null or a live handle, which is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_free(trie: *mut MarisaTrie) {
+    if !trie.is_null() {
+        drop(Box::from_raw(trie));
+    }
+}
+
+/// # Safety
+///
+/// `trie` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_num_keys(trie: *const MarisaTrie) -> usize {
+    (*trie).trie.num_keys()
+}
+
+/// Returns 1 and stores the key's id in `id` (unless null) if `key` is in
+/// the trie, 0 if it isn't, and -1 on failure.
+///
+/// # Safety
+///
+/// `trie` must be a live handle, `key` point to `len` bytes, and `id` be
+/// null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_lookup(
+    trie: *const MarisaTrie,
+    key: *const c_char,
+    len: usize,
+    id: *mut usize,
+) -> c_int {
+    guard(-1, || match (*trie).trie.lookup(str_arg(key, len)?) {
+        Some(found) => {
+            if !id.is_null() {
+                *id = found;
+            }
+            Ok(1)
+        }
+        None => Ok(0),
+    })
+}
+
+/// Up to `max_results` keys starting with `prefix`, or all of them when
+/// `max_results` is 0. Returns null on failure.
+///
+/// # Safety
+///
+/// `trie` must be a live handle and `prefix` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_complete(
+    trie: *const MarisaTrie,
+    prefix: *const c_char,
+    len: usize,
+    max_results: usize,
+) -> *mut MarisaResults {
+    guard(ptr::null_mut(), || {
+        let prefix = str_arg(prefix, len)?;
+        let limit = if max_results == 0 {
+            usize::MAX
+        } else {
+            max_results
+        };
+        into_results((*trie).trie.predictive_iter(prefix).take(limit).collect())
+    })
+}
+
+/// Keys that are prefixes of `query`, shortest first. Returns null on
+/// failure.
+///
+/// # Safety
+///
+/// `trie` must be a live handle and `query` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_trie_common_prefixes(
+    trie: *const MarisaTrie,
+    query: *const c_char,
+    len: usize,
+) -> *mut MarisaResults {
+    guard(ptr::null_mut(), || {
+        into_results((*trie).trie.common_prefix_search(str_arg(query, len)?))
+    })
+}
+
+/// # Safety
+///
+/// `results` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_results_len(results: *const MarisaResults) -> usize {
+    (*results).results.len()
+}
+
+/// The `index`th key, NUL-terminated and valid until the results are
+/// freed, with its length in bytes stored in `len` unless null. Keys may
+/// hold NUL bytes themselves, so use `len` rather than `strlen`. Null if
+/// `index` is out of range.
+///
+/// # Safety
+///
+/// `results` must be a live handle and `len` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_results_key(
+    results: *const MarisaResults,
+    index: usize,
+    len: *mut usize,
+) -> *const c_char {
+    let results = &*results;
+    match results.results.get(index) {
+        Some((_, key)) => {
+            if !len.is_null() {
+                *len = key.len() - 1;
+            }
+            key.as_ptr().cast()
+        }
+        None => ptr::null(),
+    }
+}
+
+/// The id of the `index`th key, or `SIZE_MAX` if `index` is out of range.
+///
+/// # Safety
+///
+/// `results` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_results_id(results: *const MarisaResults, index: usize) -> usize {
+    let results = &*results;
+    results.results.get(index).map_or(usize::MAX, |&(id, _)| id)
+}
+
+/// # Safety
+///
+/// `results` must be null or a live handle, which is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn marisa_results_free(results: *mut MarisaResults) {
+    if !results.is_null() {
+        drop(Box::from_raw(results));
+    }
+}
+
+/// The last failure on this thread, NUL-terminated and valid until the
+/// next call that fails; empty if nothing failed yet.
+#[no_mangle]
+pub extern "C" fn marisa_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, CStr, CString};
+
+    use super::*;
+
+    fn build(keys: &[&str]) -> *mut MarisaTrie {
+        let ptrs: Vec<*const c_char> = keys.iter().map(|key| key.as_ptr().cast()).collect();
+        let lens: Vec<usize> = keys.iter().map(|key| key.len()).collect();
+        unsafe { marisa_trie_build(ptrs.as_ptr(), lens.as_ptr(), keys.len()) }
+    }
+
+    fn keys(results: *mut MarisaResults) -> Vec<String> {
+        unsafe {
+            let keys = (0..marisa_results_len(results))
+                .map(|i| {
+                    let mut len = 0;
+                    let key = marisa_results_key(results, i, &mut len).cast::<u8>();
+                    assert_eq!(*key.add(len), 0);
+                    String::from_utf8(std::slice::from_raw_parts(key, len).to_vec()).unwrap()
+                })
+                .collect();
+            marisa_results_free(results);
+            keys
+        }
+    }
+
+    #[test]
+    fn build_search_and_save() {
+        let trie = build(&["fu", "fufi", "pes"]);
+        assert!(!trie.is_null());
+        unsafe {
+            assert_eq!(marisa_trie_num_keys(trie), 3);
+            let mut id = usize::MAX;
+            assert_eq!(
+                marisa_trie_lookup(trie, "fufi".as_ptr().cast(), 4, &mut id),
+                1
+            );
+            assert!(id < 3);
+            assert_eq!(marisa_trie_lookup(trie, "f".as_ptr().cast(), 1, &mut id), 0);
+
+            let mut completions = keys(marisa_trie_complete(trie, "fu".as_ptr().cast(), 2, 0));
+            completions.sort();
+            assert_eq!(completions, vec!["fu", "fufi"]);
+            assert_eq!(
+                keys(marisa_trie_complete(trie, "".as_ptr().cast(), 0, 1)).len(),
+                1
+            );
+            assert_eq!(
+                keys(marisa_trie_common_prefixes(
+                    trie,
+                    "fufik".as_ptr().cast(),
+                    5
+                )),
+                vec!["fu", "fufi"]
+            );
+            let results = marisa_trie_complete(trie, "pes".as_ptr().cast(), 3, 0);
+            assert!(marisa_results_id(results, 0) < 3);
+            assert_eq!(marisa_results_id(results, 1), usize::MAX);
+            assert!(marisa_results_key(results, 1, ptr::null_mut()).is_null());
+            marisa_results_free(results);
+
+            let path = std::env::temp_dir().join(format!("marisa-capi-{}", std::process::id()));
+            let c_path = CString::new(path.to_str().unwrap()).unwrap();
+            assert_eq!(marisa_trie_save(trie, c_path.as_ptr()), 0);
+            marisa_trie_free(trie);
+
+            for open in [marisa_trie_load, marisa_trie_mmap] {
+                let loaded = open(c_path.as_ptr());
+                assert!(!loaded.is_null());
+                assert_eq!(marisa_trie_num_keys(loaded), 3);
+                marisa_trie_free(loaded);
+            }
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn keys_with_nul_bytes() {
+        let trie = build(&["fu\0fi", "fu", "pes"]);
+        unsafe {
+            let mut completions = keys(marisa_trie_complete(trie, "fu".as_ptr().cast(), 2, 0));
+            completions.sort();
+            assert_eq!(completions, vec!["fu", "fu\0fi"]);
+            assert_eq!(
+                keys(marisa_trie_common_prefixes(
+                    trie,
+                    "fu\0fik".as_ptr().cast(),
+                    6
+                )),
+                vec!["fu", "fu\0fi"]
+            );
+            marisa_trie_free(trie);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        unsafe {
+            let missing = CString::new("/nonexistent/marisa").unwrap();
+            assert!(marisa_trie_load(missing.as_ptr()).is_null());
+            let error = CStr::from_ptr(marisa_last_error()).to_str().unwrap();
+            assert!(error.contains("/nonexistent/marisa"), "{error}");
+
+            assert!(build(&[""]).is_null());
+            let trie = build(&["fu"]);
+            let invalid = [0xffu8];
+            assert_eq!(
+                marisa_trie_lookup(trie, invalid.as_ptr().cast(), 1, ptr::null_mut()),
+                -1
+            );
+            assert!(CStr::from_ptr(marisa_last_error())
+                .to_str()
+                .unwrap()
+                .contains("UTF-8"));
+            marisa_trie_free(trie);
+            marisa_trie_free(ptr::null_mut());
+        }
+    }
+}
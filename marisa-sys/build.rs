@@ -2,6 +2,15 @@ use std::env;
 use std::path::PathBuf;
 
 fn main() {
+    println!("cargo:rerun-if-changed=shim.h");
+    println!("cargo:rerun-if-changed=shim.cc");
+    // Before libmarisa, which the shim links against.
+    cc::Build::new()
+        .cpp(true)
+        .std("c++17")
+        .file("shim.cc")
+        .compile("marisa_shim");
+
     println!("cargo:rustc-link-search=/usr/lib/x86_64-linux-gnu/");
     println!("cargo:rustc-link-lib=marisa");
 
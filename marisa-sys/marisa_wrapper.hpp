@@ -1,2 +1 @@
-#include <marisa/trie.h>
-
+#include "shim.h"
@@ -0,0 +1,44 @@
+#include "shim.h"
+
+#include <new>
+
+extern "C" {
+
+marisa::Trie *marisa_shim_trie_new() { return new (std::nothrow) marisa::Trie; }
+
+void marisa_shim_trie_free(marisa::Trie *trie) { delete trie; }
+
+int marisa_shim_trie_build(marisa::Trie *trie, marisa::Keyset *keyset, int config_flags) {
+  try {
+    trie->build(*keyset, config_flags);
+    return 0;
+  } catch (const std::bad_alloc &) {
+    return 1;
+  } catch (...) {
+    return 2;
+  }
+}
+
+marisa::Keyset *marisa_shim_keyset_new() { return new (std::nothrow) marisa::Keyset; }
+
+void marisa_shim_keyset_free(marisa::Keyset *keyset) { delete keyset; }
+
+std::size_t marisa_shim_keyset_size(const marisa::Keyset *keyset) { return keyset->size(); }
+
+std::size_t marisa_shim_keyset_total_length(const marisa::Keyset *keyset) {
+  return keyset->total_length();
+}
+
+const marisa::Key *marisa_shim_keyset_key(const marisa::Keyset *keyset, std::size_t i) {
+  return &(*keyset)[i];
+}
+
+marisa::Agent *marisa_shim_agent_new() { return new (std::nothrow) marisa::Agent; }
+
+void marisa_shim_agent_free(marisa::Agent *agent) { delete agent; }
+
+const marisa::Key *marisa_shim_agent_key(const marisa::Agent *agent) { return &agent->key(); }
+
+void marisa_shim_agent_clear_key(marisa::Agent *agent) { agent->key().clear(); }
+
+}
@@ -0,0 +1,32 @@
+// Heap-allocating constructors and accessors for libmarisa's classes, so
+// the Rust side only ever holds pointers and never depends on their layout,
+// which differs between libmarisa versions. The accessors cover what
+// libmarisa only provides as inline members.
+#pragma once
+
+#include <cstddef>
+
+#include <marisa/trie.h>
+
+extern "C" {
+
+marisa::Trie *marisa_shim_trie_new();
+void marisa_shim_trie_free(marisa::Trie *trie);
+// Builds `trie` from `keyset`, catching what libmarisa throws: returns 0 on
+// success, 1 if it ran out of memory and 2 on any other error, leaving
+// `trie` as it was.
+int marisa_shim_trie_build(marisa::Trie *trie, marisa::Keyset *keyset, int config_flags);
+
+marisa::Keyset *marisa_shim_keyset_new();
+void marisa_shim_keyset_free(marisa::Keyset *keyset);
+std::size_t marisa_shim_keyset_size(const marisa::Keyset *keyset);
+std::size_t marisa_shim_keyset_total_length(const marisa::Keyset *keyset);
+// `i` must be less than the size.
+const marisa::Key *marisa_shim_keyset_key(const marisa::Keyset *keyset, std::size_t i);
+
+marisa::Agent *marisa_shim_agent_new();
+void marisa_shim_agent_free(marisa::Agent *agent);
+const marisa::Key *marisa_shim_agent_key(const marisa::Agent *agent);
+void marisa_shim_agent_clear_key(marisa::Agent *agent);
+
+}
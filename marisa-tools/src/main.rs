@@ -0,0 +1,398 @@
+//! The `marisa` command: builds dictionaries from word lists and queries
+//! them, in the spirit of libmarisa's `marisa-build`, `marisa-lookup` and
+//! friends. Query commands read one query per line from stdin.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use marisa::{
+    config::{CacheLevel, Config, NodeOrder, TailMode},
+    error::MarisaError,
+    import,
+    marisa::{Keyset, Trie},
+};
+
+const USAGE: &str = "\
+usage: marisa <command> [options] [args]
+
+commands:
+  build [-n NUM_TRIES] [--cache-level LEVEL] [--binary-tail] [--label-order]
+        [--manifest SOURCE] [-o OUTPUT] [INPUT...]
+                                   build from word lists (`key[\\tweight]` lines),
+                                   stdin if no INPUT is given
+  lookup DICT                      print `id\\tkey`, or `-1\\tkey` if missing
+  reverse-lookup DICT              print the key of each id
+  common-prefix-search DICT [-n MAX]
+  predictive-search DICT [-n MAX]  print `N found` and up to MAX matches
+  dump DICT                        print every key
+  info DICT                        print sizes and the build config
+  export-fst DICT OUTPUT           write the keys as an fst set
+";
+
+#[derive(Debug)]
+enum Error {
+    Usage(String),
+    Marisa(MarisaError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{message}\n\n{USAGE}"),
+            Error::Marisa(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<MarisaError> for Error {
+    fn from(err: MarisaError) -> Self {
+        Error::Marisa(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Marisa(err.into())
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn usage(message: impl Into<String>) -> Error {
+    Error::Usage(message.into())
+}
+
+/// Options and positional arguments of one command. `-x VALUE` and
+/// `--long VALUE` take a value only if listed in `with_value`.
+struct Args {
+    options: Vec<(String, Option<String>)>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String], with_value: &[&str]) -> Result<Self> {
+        let mut options = Vec::new();
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref().cloned());
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                let value = if with_value.contains(&arg.as_str()) {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| usage(format!("{arg} needs a value")))?;
+                    Some(value.clone())
+                } else {
+                    None
+                };
+                options.push((arg.clone(), value));
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Self {
+            options,
+            positional,
+        })
+    }
+
+    /// Fails on options other than `known`.
+    fn check(&self, known: &[&str]) -> Result<()> {
+        match self
+            .options
+            .iter()
+            .find(|(name, _)| !known.contains(&name.as_str()))
+        {
+            Some((name, _)) => Err(usage(format!("unknown option {name}"))),
+            None => Ok(()),
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    fn parsed<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>> {
+        self.value(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| usage(format!("invalid value for {name}: {value}")))
+            })
+            .transpose()
+    }
+
+    /// The dictionary path, the first positional argument.
+    fn dictionary(&self) -> Result<Trie> {
+        let path = self
+            .positional
+            .first()
+            .ok_or_else(|| usage("missing dictionary path"))?;
+        let mut trie = Trie::default();
+        trie.mmap(Path::new(path))?;
+        Ok(trie)
+    }
+}
+
+fn run(args: &[String], stdin: impl BufRead, mut out: impl Write) -> Result<()> {
+    let (command, rest) = args.split_first().ok_or_else(|| usage("missing command"))?;
+    match command.as_str() {
+        "build" => build(rest, stdin),
+        "lookup" => {
+            let args = Args::parse(rest, &[])?;
+            args.check(&[])?;
+            let trie = args.dictionary()?;
+            for query in stdin.lines() {
+                let query = query?;
+                match trie.lookup(&query) {
+                    Some(id) => writeln!(out, "{id}\t{query}")?,
+                    None => writeln!(out, "-1\t{query}")?,
+                }
+            }
+            Ok(())
+        }
+        "reverse-lookup" => {
+            let args = Args::parse(rest, &[])?;
+            args.check(&[])?;
+            let trie = args.dictionary()?;
+            for query in stdin.lines() {
+                let query = query?;
+                let key = query
+                    .trim()
+                    .parse()
+                    .ok()
+                    .and_then(|id| trie.reverse_lookup(id));
+                match key {
+                    Some(key) => writeln!(out, "{}\t{key}", query.trim())?,
+                    None => writeln!(out, "{}\t<invalid id>", query.trim())?,
+                }
+            }
+            Ok(())
+        }
+        "common-prefix-search" | "predictive-search" => {
+            let args = Args::parse(rest, &["-n"])?;
+            args.check(&["-n"])?;
+            let max = args.parsed::<usize>("-n")?.unwrap_or(10);
+            let trie = args.dictionary()?;
+            for query in stdin.lines() {
+                let query = query?;
+                let found = if command == "predictive-search" {
+                    trie.predictive_search(&query)
+                } else {
+                    trie.common_prefix_search(&query)
+                };
+                writeln!(out, "{} found", found.len())?;
+                for (id, key) in found.into_iter().take(max) {
+                    writeln!(out, "{id}\t{key}")?;
+                }
+            }
+            Ok(())
+        }
+        "dump" => {
+            let args = Args::parse(rest, &[])?;
+            args.check(&[])?;
+            for (_, key) in args.dictionary()?.predictive_iter("") {
+                writeln!(out, "{key}")?;
+            }
+            Ok(())
+        }
+        "info" => {
+            let args = Args::parse(rest, &[])?;
+            args.check(&[])?;
+            let stats = args.dictionary()?.stats()?;
+            writeln!(out, "keys: {}", stats.num_keys)?;
+            writeln!(out, "tries: {}", stats.num_tries)?;
+            writeln!(out, "nodes: {}", stats.num_nodes)?;
+            writeln!(out, "total size: {}", stats.total_size)?;
+            writeln!(out, "io size: {}", stats.io_size)?;
+            writeln!(out, "tail bytes: {}", stats.tail_bytes())?;
+            writeln!(out, "cache bytes: {}", stats.cache_bytes())?;
+            if let Some(config) = stats.config {
+                writeln!(out, "config: {config:?}")?;
+            }
+            Ok(())
+        }
+        "export-fst" => export_fst(rest),
+        "help" | "-h" | "--help" => Ok(write!(out, "{USAGE}")?),
+        other => Err(usage(format!("unknown command {other}"))),
+    }
+}
+
+fn build(rest: &[String], stdin: impl BufRead) -> Result<()> {
+    let args = Args::parse(rest, &["-n", "-o", "--cache-level", "--manifest"])?;
+    args.check(&[
+        "-n",
+        "-o",
+        "--cache-level",
+        "--binary-tail",
+        "--label-order",
+        "--manifest",
+    ])?;
+    let mut config = Config::default();
+    if let Some(num_tries) = args.parsed("-n")? {
+        config.num_tries = num_tries;
+    }
+    if let Some(level) = args.value("--cache-level") {
+        config.cache_level = match level {
+            "huge" => CacheLevel::Huge,
+            "large" => CacheLevel::Large,
+            "normal" => CacheLevel::Normal,
+            "small" => CacheLevel::Small,
+            "tiny" => CacheLevel::Tiny,
+            other => return Err(usage(format!("unknown cache level {other}"))),
+        };
+    }
+    if args.flag("--binary-tail") {
+        config.tail_mode = TailMode::Binary;
+    }
+    if args.flag("--label-order") {
+        config.node_order = NodeOrder::Label;
+    }
+    let output = args
+        .value("-o")
+        .ok_or_else(|| usage("build needs -o OUTPUT"))?;
+
+    let keyset = if args.positional.is_empty() {
+        import::read_wordlist(stdin)?
+    } else {
+        let mut keyset = Keyset::default();
+        for input in &args.positional {
+            keyset.append(&import::load_wordlist(Path::new(input))?)?;
+        }
+        keyset
+    };
+    let trie = Trie::build_from_config(keyset, &config)?;
+    match args.value("--manifest") {
+        Some(source) => save_with_manifest(&trie, Path::new(output), source),
+        None => Ok(trie.save(Path::new(output))?),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn save_with_manifest(trie: &Trie, path: &Path, source: &str) -> Result<()> {
+    trie.save_with_manifest(path, source)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn save_with_manifest(_: &Trie, _: &Path, _: &str) -> Result<()> {
+    Err(usage(
+        "--manifest needs marisa-tools built with the `serde` feature",
+    ))
+}
+
+#[cfg(feature = "fst")]
+fn export_fst(rest: &[String]) -> Result<()> {
+    let args = Args::parse(rest, &[])?;
+    args.check(&[])?;
+    let output = args
+        .positional
+        .get(1)
+        .ok_or_else(|| usage("missing output path"))?;
+    let set = args.dictionary()?.to_fst_set()?;
+    std::fs::write(output, set.as_fst().as_bytes()).map_err(|source| MarisaError::File {
+        operation: "write",
+        path: output.into(),
+        source,
+    })?;
+    Ok(())
+}
+
+#[cfg(not(feature = "fst"))]
+fn export_fst(_: &[String]) -> Result<()> {
+    Err(usage(
+        "export-fst needs marisa-tools built with the `fst` feature",
+    ))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args, io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("marisa: {err}");
+            match err {
+                Error::Usage(_) => ExitCode::from(2),
+                Error::Marisa(_) => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Error};
+
+    fn run_str(args: &[&str], stdin: &str) -> Result<String, Error> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut out = Vec::new();
+        run(&args, stdin.as_bytes(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn build_and_query() {
+        let path = std::env::temp_dir().join(format!("marisa-tools-{}", std::process::id()));
+        let dict = path.to_str().unwrap();
+        run_str(
+            &["build", "-n", "2", "-o", dict],
+            "apple\t2\napricot\nbanana\n",
+        )
+        .unwrap();
+
+        let lookup = run_str(&["lookup", dict], "apple\ncherry\n").unwrap();
+        let mut lines = lookup.lines();
+        let id = lines.next().unwrap().strip_suffix("\tapple").unwrap();
+        assert!(id.parse::<usize>().is_ok());
+        assert_eq!(lines.next(), Some("-1\tcherry"));
+
+        assert_eq!(
+            run_str(&["reverse-lookup", dict], &format!("{id}\n99\n")).unwrap(),
+            format!("{id}\tapple\n99\t<invalid id>\n")
+        );
+
+        let predictive = run_str(&["predictive-search", dict, "-n", "1"], "ap\n").unwrap();
+        assert!(predictive.starts_with("2 found\n"));
+        assert_eq!(predictive.lines().count(), 2);
+
+        let mut keys: Vec<String> = run_str(&["dump", dict], "")
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["apple", "apricot", "banana"]);
+        assert!(run_str(&["info", dict], "")
+            .unwrap()
+            .starts_with("keys: 3\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert!(matches!(run_str(&[], ""), Err(Error::Usage(_))));
+        assert!(matches!(run_str(&["frobnicate"], ""), Err(Error::Usage(_))));
+        assert!(matches!(run_str(&["build"], "a\n"), Err(Error::Usage(_))));
+        assert!(matches!(
+            run_str(&["lookup", "-x", "dict"], ""),
+            Err(Error::Usage(_))
+        ));
+        assert!(matches!(
+            run_str(&["lookup", "/nonexistent/marisa-dict"], ""),
+            Err(Error::Marisa(_))
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+//! Short codes mapped to expansions: emoji shortcodes, abbreviations,
+//! text snippets.
+
+use crate::{error::Result, map::TrieMap};
+
+pub struct AliasTable {
+    aliases: TrieMap<String>,
+}
+
+impl AliasTable {
+    /// Builds from `(code, expansion)` pairs; a code given more than once
+    /// keeps its last expansion.
+    pub fn new<C, E>(aliases: impl IntoIterator<Item = (C, E)>) -> Result<Self>
+    where
+        C: AsRef<str>,
+        E: Into<String>,
+    {
+        Ok(Self {
+            aliases: TrieMap::new(
+                aliases
+                    .into_iter()
+                    .filter(|(code, _)| !code.as_ref().is_empty())
+                    .map(|(code, expansion)| (code, expansion.into())),
+            )?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    pub fn get(&self, code: impl AsRef<str>) -> Option<&str> {
+        let code = code.as_ref();
+        self.aliases.get(code).map(String::as_str)
+    }
+
+    /// Codes starting with `prefix` and their expansions, sorted by code.
+    pub fn complete(&self, prefix: impl AsRef<str>) -> Vec<(String, &str)> {
+        let prefix = prefix.as_ref();
+        let mut completions: Vec<(String, &str)> = self
+            .aliases
+            .predictive_iter(prefix)
+            .map(|(code, expansion)| (code, expansion.as_str()))
+            .collect();
+        completions.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        completions
+    }
+
+    /// Replaces every code in `text` with its expansion, scanning left to
+    /// right and taking the longest code at each position. Expansions are
+    /// not expanded again.
+    pub fn expand(&self, text: impl AsRef<str>) -> String {
+        let text = text.as_ref();
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            match self.aliases.longest_prefix(rest) {
+                Some((len, expansion)) => {
+                    expanded.push_str(expansion);
+                    rest = &rest[len..];
+                }
+                None => {
+                    expanded.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AliasTable;
+
+    #[test]
+    fn expands_and_completes() {
+        let table = AliasTable::new([
+            (":smile:", "😄"),
+            (":sm:", "small"),
+            ("brb", "be right back"),
+            ("", "ignored"),
+        ])
+        .unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get("brb"), Some("be right back"));
+
+        assert_eq!(
+            table.expand("brb :smile: :sm: :smi"),
+            "be right back 😄 small :smi"
+        );
+        assert_eq!(table.expand("čau"), "čau");
+
+        let codes: Vec<String> = table.complete(":s").into_iter().map(|(c, _)| c).collect();
+        assert_eq!(codes, vec![":sm:", ":smile:"]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Batch searches writing their keys into one shared buffer, for offline
+//! jobs that would otherwise allocate a `String` per result.
+
+use std::ops::Range;
+
+use crate::marisa::Trie;
+
+/// Result keys packed into a single `String`, addressed by entry index.
+/// Reuse one arena across batches with [`KeyArena::clear`] to keep its
+/// capacity.
+#[derive(Debug, Clone, Default)]
+pub struct KeyArena {
+    text: String,
+    entries: Vec<(usize, Range<usize>)>,
+}
+
+impl KeyArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(num_keys: usize, num_bytes: usize) -> Self {
+        Self {
+            text: String::with_capacity(num_bytes),
+            entries: Vec::with_capacity(num_keys),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The id and key of entry `index`.
+    pub fn get(&self, index: usize) -> Option<(usize, &str)> {
+        let (id, bytes) = self.entries.get(index)?;
+        Some((*id, &self.text[bytes.clone()]))
+    }
+
+    /// Entries in `range`, as returned by the `_into` searches.
+    pub fn slice(&self, range: Range<usize>) -> impl Iterator<Item = (usize, &str)> + '_ {
+        self.entries[range]
+            .iter()
+            .map(|(id, bytes)| (*id, &self.text[bytes.clone()]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> + '_ {
+        self.slice(0..self.entries.len())
+    }
+
+    pub fn push(&mut self, id: usize, key: &str) {
+        let start = self.text.len();
+        self.text.push_str(key);
+        self.entries.push((id, start..self.text.len()));
+    }
+}
+
+impl Trie {
+    /// Appends every key starting with `prefix` to `arena` and returns the
+    /// indices of the new entries.
+    pub fn predictive_search_into(
+        &self,
+        prefix: impl AsRef<str>,
+        arena: &mut KeyArena,
+    ) -> Range<usize> {
+        let prefix = prefix.as_ref();
+        let start = arena.len();
+        self.for_each_predictive(prefix, |id, key| arena.push(id, key));
+        start..arena.len()
+    }
+
+    /// Runs `predictive_search_into` for each of `prefixes`, returning one
+    /// range per prefix.
+    pub fn predictive_search_batch(
+        &self,
+        prefixes: &[impl AsRef<str>],
+        arena: &mut KeyArena,
+    ) -> Vec<Range<usize>> {
+        prefixes
+            .iter()
+            .map(|prefix| self.predictive_search_into(prefix, arena))
+            .collect()
+    }
+
+    pub fn common_prefix_search_into(
+        &self,
+        query: impl AsRef<str>,
+        arena: &mut KeyArena,
+    ) -> Range<usize> {
+        let query = query.as_ref();
+        let start = arena.len();
+        self.for_each_common_prefix(query, |id, key| arena.push(id, key));
+        start..arena.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyArena;
+    use crate::marisa::Trie;
+
+    #[test]
+    fn batch_into_one_buffer() {
+        let trie = Trie::try_from(&["fu", "fufi", "fi", "pes"][..]).unwrap();
+        let mut arena = KeyArena::with_capacity(8, 64);
+
+        let ranges = trie.predictive_search_batch(&["fu", "x", "p"], &mut arena);
+        assert_eq!(ranges.len(), 3);
+        let mut fu: Vec<&str> = arena.slice(ranges[0].clone()).map(|(_, key)| key).collect();
+        fu.sort();
+        assert_eq!(fu, vec!["fu", "fufi"]);
+        assert!(ranges[1].is_empty());
+        let (id, key) = arena.get(ranges[2].start).unwrap();
+        assert_eq!(key, "pes");
+        assert_eq!(trie.lookup("pes"), Some(id));
+
+        let range = trie.common_prefix_search_into("fufi", &mut arena);
+        assert_eq!(range, 3..5);
+        assert_eq!(arena.len(), 5);
+
+        arena.clear();
+        assert!(arena.is_empty());
+        assert_eq!(arena.iter().count(), 0);
+    }
+}
@@ -0,0 +1,152 @@
+//! Traits shared by the libmarisa binding and the pure-Rust fallback, so
+//! code generic over them runs on either, and [`PrefixDictionary`] for code
+//! that would rather hold a `&dyn` than be generic.
+
+use crate::error::Result;
+
+pub trait KeysetBackend: Default {
+    fn push(&mut self, key: impl AsRef<str>, weight: Option<f32>) -> Result<()>;
+    fn num_keys(&self) -> usize;
+}
+
+pub trait TrieBackend: Default {
+    type Keyset: KeysetBackend;
+
+    fn build(&mut self, keyset: &mut Self::Keyset);
+    fn num_keys(&self) -> usize;
+    fn lookup(&self, key: impl AsRef<str>) -> Option<usize>;
+    fn reverse_lookup(&self, id: usize) -> Option<String>;
+    fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<(usize, String)>;
+}
+
+/// The read-only operations of a string dictionary, object-safe so callers
+/// can take any trie (or a mock) as `&dyn PrefixDictionary`. Ids are those
+/// of the implementation and are not comparable between implementations.
+pub trait PrefixDictionary {
+    fn contains(&self, key: &str) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    fn lookup(&self, key: &str) -> Option<usize>;
+
+    /// `(id, key)` for keys that are prefixes of `query`, shortest first.
+    fn prefix_iter<'a>(&'a self, query: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a>;
+
+    /// `(id, key)` for keys starting with `prefix`, in no particular order.
+    fn complete<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a>;
+}
+
+#[cfg(feature = "libmarisa")]
+mod libmarisa {
+    use super::{KeysetBackend, PrefixDictionary, TrieBackend};
+    use crate::{
+        error::Result,
+        marisa::{Keyset, Trie},
+    };
+
+    impl KeysetBackend for Keyset {
+        fn push(&mut self, key: impl AsRef<str>, weight: Option<f32>) -> Result<()> {
+            Keyset::push(self, key, weight)
+        }
+
+        fn num_keys(&self) -> usize {
+            Keyset::num_keys(self)
+        }
+    }
+
+    impl TrieBackend for Trie {
+        type Keyset = Keyset;
+
+        fn build(&mut self, keyset: &mut Keyset) {
+            Trie::build(self, keyset)
+        }
+
+        fn num_keys(&self) -> usize {
+            Trie::num_keys(self)
+        }
+
+        fn lookup(&self, key: impl AsRef<str>) -> Option<usize> {
+            Trie::lookup(self, key)
+        }
+
+        fn reverse_lookup(&self, id: usize) -> Option<String> {
+            Trie::reverse_lookup(self, id)
+        }
+
+        fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<(usize, String)> {
+            Trie::predictive_search(self, prefix)
+        }
+    }
+
+    /// Also covers mapped tries, which are plain [`Trie`]s.
+    impl PrefixDictionary for Trie {
+        fn lookup(&self, key: &str) -> Option<usize> {
+            Trie::lookup(self, key)
+        }
+
+        fn prefix_iter<'a>(
+            &'a self,
+            query: &str,
+        ) -> Box<dyn Iterator<Item = (usize, String)> + 'a> {
+            Box::new(self.common_prefix_iter(query))
+        }
+
+        fn complete<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a> {
+            Box::new(self.predictive_iter(prefix))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefixDictionary;
+
+    fn check(dict: &dyn PrefixDictionary) {
+        assert!(dict.contains("fufi"));
+        assert!(!dict.contains("f"));
+        assert!(dict.lookup("pes").is_some());
+        assert_eq!(dict.lookup("pe"), None);
+
+        let prefixes: Vec<String> = dict.prefix_iter("fufik").map(|(_, key)| key).collect();
+        assert_eq!(prefixes, ["fu", "fufi"]);
+        for (id, key) in dict.prefix_iter("fufik") {
+            assert_eq!(dict.lookup(&key), Some(id));
+        }
+
+        let mut completions: Vec<String> = dict.complete("f").map(|(_, key)| key).collect();
+        completions.sort();
+        assert_eq!(completions, ["fi", "fu", "fufi"]);
+        for (id, key) in dict.complete("") {
+            assert_eq!(dict.lookup(&key), Some(id));
+        }
+        assert_eq!(dict.complete("x").count(), 0);
+    }
+
+    #[cfg(feature = "libmarisa")]
+    #[test]
+    fn implementations_are_interchangeable() {
+        use crate::{dynamic::DynamicTrie, marisa::Trie};
+
+        check(&Trie::try_from(&["fi", "fu", "fufi", "pes"][..]).unwrap());
+
+        let mut dynamic = DynamicTrie::new([("fu", ()), ("pes", ()), ("x", ())]).unwrap();
+        dynamic.insert("fufi", ());
+        dynamic.insert("fi", ());
+        dynamic.remove("x");
+        check(&dynamic);
+    }
+
+    #[cfg(feature = "pure-rust")]
+    #[test]
+    fn pure_backend_is_a_dictionary() {
+        use crate::{backend::KeysetBackend, pure};
+
+        let mut keyset = pure::Keyset::default();
+        for key in ["pes", "fufi", "fi", "fu"] {
+            KeysetBackend::push(&mut keyset, key, None).unwrap();
+        }
+        let mut trie = pure::Trie::default();
+        trie.build(&mut keyset);
+        check(&trie);
+    }
+}
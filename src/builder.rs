@@ -0,0 +1,277 @@
+//! Building a trie from raw `(key, weight)` input with deduplication and
+//! pruning, reporting what happened along the way.
+
+use std::{
+    collections::HashMap,
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::Config,
+    error::{MarisaError, Result},
+    marisa::{Keyset, Trie},
+};
+
+/// What a [`Builder`] did, for ingestion pipelines to log and alert on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildReport {
+    /// Keys pushed into the builder, duplicates included.
+    pub keys_read: usize,
+    /// Pushes folded into an earlier occurrence of the same key.
+    pub duplicates_merged: usize,
+    /// Distinct keys dropped for falling below the minimum weight.
+    pub keys_pruned: usize,
+    pub num_keys: usize,
+    pub num_nodes: usize,
+    /// In-memory size of the built trie in bytes.
+    pub total_size: usize,
+    /// Size of the saved image in bytes.
+    pub io_size: usize,
+    /// Estimated bytes held by the collected input.
+    pub input_bytes: usize,
+    /// Estimated bytes of the deduplication index.
+    pub dedupe_bytes: usize,
+    /// Estimated bytes of the keyset handed to libmarisa.
+    pub keyset_bytes: usize,
+    /// Largest estimated total of the above alive at once, plus the built
+    /// trie together with its keyset, which is freed as the build returns.
+    /// libmarisa's own scratch space during `build` is not included.
+    pub peak_bytes: usize,
+    pub dedupe_time: Duration,
+    pub keyset_time: Duration,
+    pub build_time: Duration,
+}
+
+impl BuildReport {
+    pub fn total_time(&self) -> Duration {
+        self.dedupe_time + self.keyset_time + self.build_time
+    }
+}
+
+/// How the weights of a key pushed more than once are combined.
+///
+/// Weights decide node order in weight-ordered tries, so the choice shows
+/// up in result ranking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Add the weights up, as libmarisa does.
+    #[default]
+    Sum,
+    Max,
+    /// Keep the weight of the first push.
+    First,
+    /// Fail the build with `MarisaError::DuplicateKey`.
+    Error,
+}
+
+/// Collects keys and builds a trie with the given [`Config`].
+///
+/// Duplicate keys are merged according to the [`DuplicatePolicy`] before
+/// `min_weight` is applied.
+#[derive(Debug, Default)]
+pub struct Builder {
+    config: Config,
+    min_weight: Option<f32>,
+    duplicates: DuplicatePolicy,
+    allow_empty_key: bool,
+    keys: Vec<(String, f32)>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Drops keys whose merged weight is below `min_weight`.
+    pub fn min_weight(mut self, min_weight: f32) -> Self {
+        self.min_weight = Some(min_weight);
+        self
+    }
+
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Accepts the empty string as a key instead of failing the build.
+    pub fn allow_empty_key(mut self, allow: bool) -> Self {
+        self.allow_empty_key = allow;
+        self
+    }
+
+    pub fn push(&mut self, key: impl AsRef<str>, weight: f32) {
+        self.keys.push((key.as_ref().to_owned(), weight));
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Fails with `DuplicateKey` under [`DuplicatePolicy::Error`], with
+    /// `EmptyKey` unless allowed, and with `KeyTooLong` or
+    /// `TooManyKeys` if the merged input exceeds libmarisa's limits.
+    pub fn build(self) -> Result<(Trie, BuildReport)> {
+        let mut report = BuildReport {
+            keys_read: self.keys.len(),
+            input_bytes: entries_bytes(&self.keys),
+            ..BuildReport::default()
+        };
+
+        let start = Instant::now();
+        let mut merged: Vec<(String, f32)> = Vec::with_capacity(self.keys.len());
+        let mut index: HashMap<String, usize> = HashMap::with_capacity(self.keys.len());
+        let mut index_key_bytes = 0;
+        for (key, weight) in self.keys {
+            match index.get(&key) {
+                Some(&i) => {
+                    let merged = &mut merged[i].1;
+                    match self.duplicates {
+                        DuplicatePolicy::Sum => *merged += weight,
+                        DuplicatePolicy::Max => *merged = merged.max(weight),
+                        DuplicatePolicy::First => {}
+                        DuplicatePolicy::Error => return Err(MarisaError::DuplicateKey(key)),
+                    }
+                    report.duplicates_merged += 1;
+                }
+                None => {
+                    index_key_bytes += key.len();
+                    index.insert(key.clone(), merged.len());
+                    merged.push((key, weight));
+                }
+            }
+        }
+        report.dedupe_bytes =
+            index.capacity() * (mem::size_of::<(String, usize)>() + 1) + index_key_bytes;
+        // Input and merged entries share their strings, so only the extra
+        // vector is counted on top.
+        report.peak_bytes = report.input_bytes
+            + merged.capacity() * mem::size_of::<(String, f32)>()
+            + report.dedupe_bytes;
+        drop(index);
+        if report.duplicates_merged > 0 {
+            log_warn!(
+                "merged {} duplicate keys ({:?})",
+                report.duplicates_merged,
+                self.duplicates
+            );
+        }
+        if let Some(min_weight) = self.min_weight {
+            let before = merged.len();
+            merged.retain(|&(_, weight)| weight >= min_weight);
+            report.keys_pruned = before - merged.len();
+        }
+        report.dedupe_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut keyset = Keyset::default();
+        keyset.set_allow_empty_key(self.allow_empty_key);
+        for (key, weight) in &merged {
+            keyset.push(key, Some(*weight))?;
+        }
+        report.keyset_bytes = keyset.memory_size();
+        report.keyset_time = start.elapsed();
+        let merged_bytes = entries_bytes(&merged);
+        drop(merged);
+
+        let start = Instant::now();
+        let trie = Trie::build_from_config(keyset, &self.config)?;
+        report.build_time = start.elapsed();
+
+        report.num_keys = trie.num_keys();
+        report.num_nodes = trie.num_nodes();
+        report.total_size = trie.total_size();
+        report.io_size = trie.io_size();
+        report.peak_bytes = report
+            .peak_bytes
+            .max(merged_bytes + report.keyset_bytes)
+            .max(report.keyset_bytes + report.total_size);
+        Ok((trie, report))
+    }
+}
+
+fn entries_bytes(entries: &Vec<(String, f32)>) -> usize {
+    entries.capacity() * mem::size_of::<(String, f32)>()
+        + entries.iter().map(|(key, _)| key.capacity()).sum::<usize>()
+}
+
+impl<S: AsRef<str>> Extend<(S, f32)> for Builder {
+    fn extend<I: IntoIterator<Item = (S, f32)>>(&mut self, iter: I) {
+        for (key, weight) in iter {
+            self.push(key.as_ref(), weight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Builder, DuplicatePolicy};
+    use crate::{
+        config::{Config, NodeOrder},
+        error::MarisaError,
+    };
+
+    #[test]
+    fn merges_and_prunes() {
+        let mut builder = Builder::new().min_weight(2.0);
+        builder.extend([("a", 1.0), ("b", 1.0), ("a", 1.5), ("c", 3.0), ("b", 0.5)]);
+
+        let (trie, report) = builder.build().unwrap();
+        assert_eq!(report.keys_read, 5);
+        assert_eq!(report.duplicates_merged, 2);
+        assert_eq!(report.keys_pruned, 1);
+        assert_eq!(report.num_keys, 2);
+        assert!(report.io_size > 0);
+        assert!(report.input_bytes >= 5);
+        assert!(report.keyset_bytes >= 2);
+        assert!(report.peak_bytes >= report.input_bytes + report.dedupe_bytes);
+        assert!(trie.lookup("a").is_some());
+        assert!(trie.lookup("b").is_none());
+        assert!(trie.lookup("c").is_some());
+    }
+
+    #[test]
+    fn uses_config() {
+        let config = Config {
+            num_tries: 1,
+            node_order: NodeOrder::Label,
+            ..Config::default()
+        };
+        let (trie, report) = Builder::new().config(config).build().unwrap();
+        assert_eq!(report.num_keys, 0);
+        assert_eq!(trie.num_tries(), 1);
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let input = [("a", 1.0), ("b", 5.0), ("a", 3.0), ("a", 2.0)];
+        // The merged weight of "a" (6, 3 or 1) shows up as whether it
+        // survives a 2.5 threshold.
+        let survives = |policy| {
+            let mut builder = Builder::new().duplicates(policy).min_weight(2.5);
+            builder.extend(input);
+            let (trie, report) = builder.build().unwrap();
+            assert_eq!(report.duplicates_merged, 2);
+            trie.lookup("a").is_some()
+        };
+        assert!(survives(DuplicatePolicy::Sum));
+        assert!(survives(DuplicatePolicy::Max));
+        assert!(!survives(DuplicatePolicy::First));
+
+        let mut builder = Builder::new().duplicates(DuplicatePolicy::Error);
+        builder.extend(input);
+        assert!(matches!(
+            builder.build(),
+            Err(MarisaError::DuplicateKey(key)) if key == "a"
+        ));
+    }
+}
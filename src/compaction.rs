@@ -0,0 +1,317 @@
+//! Folding [`DynamicTrie`] overlays into their trie on a background
+//! thread, throttled so rebuilds of large dictionaries leave CPU to the
+//! serving threads on the same host.
+//!
+//! A compaction snapshots the entries under a read lock, builds the new
+//! trie without holding any lock, and takes the write lock only to swap
+//! it in, carrying over whatever changed during the build.
+
+use std::{
+    sync::{Arc, Condvar, Mutex, RwLock},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{dynamic::DynamicTrie, error::Result, map::TrieMap};
+
+/// Whether the host is too busy for a compaction right now.
+pub type LoadProbe = dyn Fn() -> bool + Send + Sync;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactionPolicy {
+    /// Pending insertions and removals that make a compaction worthwhile.
+    pub min_pending: usize,
+    /// Largest fraction of wall time spent compacting: after a build
+    /// taking `t`, the next one waits at least `t * (1 - duty_cycle) /
+    /// duty_cycle`.
+    pub duty_cycle: f64,
+    /// How often the pending count and the load probe are checked.
+    pub interval: Duration,
+    /// How long a due compaction is put off while the probe reports load,
+    /// before it runs anyway.
+    pub max_deferral: Duration,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            min_pending: 1000,
+            duty_cycle: 0.25,
+            interval: Duration::from_secs(1),
+            max_deferral: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub runs: usize,
+    pub failures: usize,
+    /// Compactions put off because of load.
+    pub deferrals: usize,
+    pub last_duration: Duration,
+}
+
+/// Compacts `trie` without blocking readers or writers for the build,
+/// returning how long it took. Only one compaction of a trie runs at a
+/// time: this and [`DynamicTrie::compact`] fail with `CompactionConflict`
+/// while another is in flight, rather than dropping its changes.
+pub fn compact_in_background<V: Clone>(trie: &RwLock<DynamicTrie<V>>) -> Result<Duration> {
+    let start = Instant::now();
+    let token = trie.write().unwrap().begin_compaction()?;
+    let entries = trie.read().unwrap().snapshot();
+    match TrieMap::new(entries) {
+        Ok(base) => {
+            trie.write().unwrap().finish_compaction(token, base)?;
+            Ok(start.elapsed())
+        }
+        Err(err) => {
+            trie.write().unwrap().abort_compaction(token);
+            Err(err)
+        }
+    }
+}
+
+struct Shared {
+    stopped: Mutex<bool>,
+    wake: Condvar,
+    stats: Mutex<CompactionStats>,
+}
+
+pub struct CompactionScheduler {
+    policy: CompactionPolicy,
+    load: Option<Arc<LoadProbe>>,
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CompactionScheduler {
+    pub fn new(policy: CompactionPolicy) -> Self {
+        Self {
+            policy,
+            load: None,
+            shared: Arc::new(Shared {
+                stopped: Mutex::new(false),
+                wake: Condvar::new(),
+                stats: Mutex::default(),
+            }),
+            handle: None,
+        }
+    }
+
+    /// Defers compactions while `busy` returns true, e.g. when request
+    /// latency or the load average is high.
+    pub fn with_load_probe(mut self, busy: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        self.load = Some(Arc::new(busy));
+        self
+    }
+
+    /// Starts compacting `trie` on a background thread. Calling it again
+    /// first stops the previous thread.
+    pub fn start<V>(&mut self, trie: Arc<RwLock<DynamicTrie<V>>>)
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        self.stop();
+        *self.shared.stopped.lock().unwrap() = false;
+        let policy = self.policy;
+        let load = self.load.clone();
+        let shared = self.shared.clone();
+        self.handle = Some(std::thread::spawn(move || {
+            run(&trie, policy, load.as_deref(), &shared)
+        }));
+    }
+
+    pub fn stats(&self) -> CompactionStats {
+        *self.shared.stats.lock().unwrap()
+    }
+
+    /// Stops the background thread, waiting for a compaction in progress.
+    pub fn stop(&mut self) {
+        *self.shared.stopped.lock().unwrap() = true;
+        self.shared.wake.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for CompactionScheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run<V: Clone>(
+    trie: &RwLock<DynamicTrie<V>>,
+    policy: CompactionPolicy,
+    load: Option<&LoadProbe>,
+    shared: &Shared,
+) {
+    let duty_cycle = policy.duty_cycle.clamp(0.01, 1.0);
+    let mut wait = policy.interval;
+    let mut deferred_since: Option<Instant> = None;
+    loop {
+        {
+            let stopped = shared.stopped.lock().unwrap();
+            let (stopped, _) = shared
+                .wake
+                .wait_timeout_while(stopped, wait, |stopped| !*stopped)
+                .unwrap();
+            if *stopped {
+                return;
+            }
+        }
+        wait = policy.interval;
+        if trie.read().unwrap().num_pending() < policy.min_pending {
+            deferred_since = None;
+            continue;
+        }
+        if load.is_some_and(|busy| busy()) {
+            let since = *deferred_since.get_or_insert_with(Instant::now);
+            if since.elapsed() < policy.max_deferral {
+                shared.stats.lock().unwrap().deferrals += 1;
+                continue;
+            }
+        }
+        deferred_since = None;
+
+        let result = compact_in_background(trie);
+        let mut stats = shared.stats.lock().unwrap();
+        match result {
+            Ok(took) => {
+                stats.runs += 1;
+                stats.last_duration = took;
+                wait = wait.max(took.mul_f64((1.0 - duty_cycle) / duty_cycle));
+            }
+            Err(err) => {
+                stats.failures += 1;
+                log_warn!("background compaction failed: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, RwLock,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::{compact_in_background, CompactionPolicy, CompactionScheduler};
+    use crate::{dynamic::DynamicTrie, error::MarisaError, map::TrieMap};
+
+    #[test]
+    fn compacts_and_keeps_concurrent_changes() {
+        let trie = RwLock::new(DynamicTrie::new([("fu", 1), ("pes", 2)]).unwrap());
+        {
+            let mut trie = trie.write().unwrap();
+            trie.insert("fufi", 3);
+            trie.remove("pes");
+        }
+        compact_in_background(&trie).unwrap();
+        let trie = trie.into_inner().unwrap();
+        assert_eq!(trie.num_pending(), 0);
+        assert_eq!(trie.len(), 2);
+
+        // Changes between the snapshot and the swap survive it.
+        let mut trie = trie;
+        let token = trie.begin_compaction().unwrap();
+        let entries = trie.snapshot();
+        trie.insert("fi", 4);
+        trie.remove("fufi");
+        *trie.get_mut("fu").unwrap() = 10;
+        trie.finish_compaction(token, TrieMap::new(entries).unwrap())
+            .unwrap();
+        assert_eq!(
+            trie.predictive_search(""),
+            vec![("fi".to_owned(), &4), ("fu".to_owned(), &10)]
+        );
+    }
+
+    #[test]
+    fn one_compaction_at_a_time() {
+        let trie = RwLock::new(DynamicTrie::new([("fu", 1)]).unwrap());
+        let token = trie.write().unwrap().begin_compaction().unwrap();
+        let entries = trie.read().unwrap().snapshot();
+        trie.write().unwrap().insert("fufi", 2);
+
+        // Neither a foreground nor a second background compaction may swap
+        // the trie under the one in flight.
+        assert!(matches!(
+            trie.write().unwrap().compact(),
+            Err(MarisaError::CompactionConflict)
+        ));
+        assert!(matches!(
+            compact_in_background(&trie),
+            Err(MarisaError::CompactionConflict)
+        ));
+        trie.write().unwrap().insert("pes", 3);
+
+        let mut trie = trie.into_inner().unwrap();
+        assert!(matches!(
+            trie.finish_compaction(token + 1, TrieMap::new(entries.clone()).unwrap()),
+            Err(MarisaError::CompactionConflict)
+        ));
+        trie.finish_compaction(token, TrieMap::new(entries.clone()).unwrap())
+            .unwrap();
+        assert_eq!(
+            trie.predictive_search(""),
+            vec![
+                ("fu".to_owned(), &1),
+                ("fufi".to_owned(), &2),
+                ("pes".to_owned(), &3)
+            ]
+        );
+        // A stale token can't finish again once the trie has moved on.
+        assert!(matches!(
+            trie.finish_compaction(token, TrieMap::new(entries).unwrap()),
+            Err(MarisaError::CompactionConflict)
+        ));
+        trie.compact().unwrap();
+        assert_eq!(trie.num_pending(), 0);
+        assert_eq!(trie.len(), 3);
+    }
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn scheduler_runs_and_defers() {
+        let trie = Arc::new(RwLock::new(DynamicTrie::new([("a", 0)]).unwrap()));
+        let busy = Arc::new(AtomicBool::new(true));
+        let probe = busy.clone();
+        let mut scheduler = CompactionScheduler::new(CompactionPolicy {
+            min_pending: 2,
+            duty_cycle: 0.5,
+            interval: Duration::from_millis(5),
+            max_deferral: Duration::from_secs(60),
+        })
+        .with_load_probe(move || probe.load(Ordering::Relaxed));
+        scheduler.start(trie.clone());
+
+        trie.write().unwrap().insert("b", 1);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(scheduler.stats().runs, 0);
+
+        trie.write().unwrap().insert("c", 2);
+        wait_until(|| scheduler.stats().deferrals > 0);
+        assert_eq!(trie.read().unwrap().num_pending(), 2);
+
+        busy.store(false, Ordering::Relaxed);
+        wait_until(|| scheduler.stats().runs == 1);
+        assert_eq!(trie.read().unwrap().num_pending(), 0);
+        assert_eq!(trie.read().unwrap().len(), 3);
+        scheduler.stop();
+        assert_eq!(scheduler.stats().failures, 0);
+    }
+}
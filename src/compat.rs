@@ -0,0 +1,127 @@
+//! Compatibility with dictionaries written by the upstream C++ library and
+//! its `marisa-build` tool.
+//!
+//! libmarisa aborts the process when handed an image it cannot read, so
+//! `Trie::load` and `Trie::mmap` run `check_file` first, and `Trie::map`
+//! runs `check_bytes`, reporting such images as
+//! `MarisaError::IncompatibleFormat`.
+
+use std::path::Path;
+
+use crate::{error::Result, format};
+
+pub use crate::format::ImageInfo;
+
+/// Checks that `path` holds an image this build of libmarisa can open:
+/// 64-bit units, native (little-endian) byte order, and sizes that match
+/// the file.
+pub fn check_file(path: &Path) -> Result<ImageInfo> {
+    format::inspect_file(path)
+}
+
+pub fn check_bytes(image: &[u8]) -> Result<ImageInfo> {
+    format::inspect_bytes(image)
+}
+
+#[cfg(all(test, feature = "libmarisa"))]
+mod tests {
+    use std::{
+        io::Write,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use super::check_file;
+    use crate::{
+        marisa::{Keyset, Trie},
+        test_util::temp_path,
+    };
+
+    // The round trips below need the upstream tools in PATH, so they are
+    // ignored by default; run them with `cargo test -- --ignored`.
+    fn tool(name: &str) -> Command {
+        let found = Command::new(name)
+            .arg("--help")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok();
+        assert!(found, "{name} from upstream libmarisa is not in PATH");
+        Command::new(name)
+    }
+
+    fn run(mut command: Command, input: &str) -> String {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn marisa_build(keys: &[&str], path: &Path) {
+        let mut command = tool("marisa-build");
+        command.arg("-o").arg(path);
+        run(command, &(keys.join("\n") + "\n"));
+    }
+
+    #[test]
+    #[ignore = "needs upstream marisa-build in PATH"]
+    fn loads_marisa_build_output() {
+        let path = temp_path("compat-upstream");
+        marisa_build(&["apple", "banana", "cherry"], &path);
+        let info = check_file(&path).unwrap();
+        assert_eq!(info.io_size, std::fs::metadata(&path).unwrap().len());
+
+        let mut trie = Trie::default();
+        trie.load(&path).unwrap();
+        assert_eq!(trie.num_keys(), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs upstream marisa-lookup in PATH"]
+    fn key_round_trip_with_marisa_tools() {
+        let mut lookup = tool("marisa-lookup");
+        let mut keyset = Keyset::default();
+        keyset.push("apple", None).unwrap();
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        let path = temp_path("compat-round-trip");
+        trie.save(&path).unwrap();
+
+        lookup.arg(&path);
+        let output = run(lookup, "apple\n");
+        assert_eq!(
+            output,
+            format!("{}\tapple\n", trie.lookup("apple").unwrap())
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs upstream marisa-build in PATH"]
+    fn upstream_keys_round_trip() {
+        let keys = ["apple", "app", "banana"];
+        let path = temp_path("compat-upstream-keys");
+        marisa_build(&keys, &path);
+        let mut trie = Trie::default();
+        trie.load(&path).unwrap();
+        for key in keys {
+            let id = trie.lookup(key).unwrap();
+            assert_eq!(trie.reverse_lookup(id).as_deref(), Some(key));
+        }
+        assert_eq!(trie.lookup("appl"), None);
+        assert_eq!(trie.predictive_search("app").len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! Build configuration, encoded the way libmarisa packs it into the
+//! `config_flags` argument of `Trie::build` and into saved images.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MarisaError, Result};
+
+pub const MIN_NUM_TRIES: u32 = 0x00001;
+pub const MAX_NUM_TRIES: u32 = 0x0007F;
+pub const DEFAULT_NUM_TRIES: u32 = 0x00003;
+
+/// Longest key in bytes. libmarisa stores lengths as 32-bit values.
+pub const MAX_KEY_LENGTH: usize = u32::MAX as usize;
+/// Key ids are 32-bit with `u32::MAX` reserved as the invalid id.
+pub const MAX_NUM_KEYS: usize = u32::MAX as usize - 1;
+
+const NUM_TRIES_MASK: u32 = 0x0007F;
+const CACHE_LEVEL_MASK: u32 = 0x00F80;
+const TAIL_MODE_MASK: u32 = 0x0F000;
+const NODE_ORDER_MASK: u32 = 0xF0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CacheLevel {
+    Huge,
+    Large,
+    #[default]
+    Normal,
+    Small,
+    Tiny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TailMode {
+    #[default]
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NodeOrder {
+    Label,
+    #[default]
+    Weight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Config {
+    pub num_tries: u32,
+    pub cache_level: CacheLevel,
+    pub tail_mode: TailMode,
+    pub node_order: NodeOrder,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            num_tries: DEFAULT_NUM_TRIES,
+            cache_level: CacheLevel::default(),
+            tail_mode: TailMode::default(),
+            node_order: NodeOrder::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn flags(&self) -> u32 {
+        let cache_level = match self.cache_level {
+            CacheLevel::Huge => 0x00080,
+            CacheLevel::Large => 0x00100,
+            CacheLevel::Normal => 0x00200,
+            CacheLevel::Small => 0x00400,
+            CacheLevel::Tiny => 0x00800,
+        };
+        let tail_mode = match self.tail_mode {
+            TailMode::Text => 0x01000,
+            TailMode::Binary => 0x02000,
+        };
+        let node_order = match self.node_order {
+            NodeOrder::Label => 0x10000,
+            NodeOrder::Weight => 0x20000,
+        };
+        self.num_tries.clamp(MIN_NUM_TRIES, MAX_NUM_TRIES) | cache_level | tail_mode | node_order
+    }
+
+    /// Decodes `flags`, filling unset fields with libmarisa's defaults.
+    /// Returns `None` for values libmarisa would reject.
+    pub fn from_flags(flags: u32) -> Option<Config> {
+        if flags & !(NUM_TRIES_MASK | CACHE_LEVEL_MASK | TAIL_MODE_MASK | NODE_ORDER_MASK) != 0 {
+            return None;
+        }
+        let num_tries = match flags & NUM_TRIES_MASK {
+            0 => DEFAULT_NUM_TRIES,
+            n => n,
+        };
+        let cache_level = match flags & CACHE_LEVEL_MASK {
+            0 => CacheLevel::default(),
+            0x00080 => CacheLevel::Huge,
+            0x00100 => CacheLevel::Large,
+            0x00200 => CacheLevel::Normal,
+            0x00400 => CacheLevel::Small,
+            0x00800 => CacheLevel::Tiny,
+            _ => return None,
+        };
+        let tail_mode = match flags & TAIL_MODE_MASK {
+            0 => TailMode::default(),
+            0x01000 => TailMode::Text,
+            0x02000 => TailMode::Binary,
+            _ => return None,
+        };
+        let node_order = match flags & NODE_ORDER_MASK {
+            0 => NodeOrder::default(),
+            0x10000 => NodeOrder::Label,
+            0x20000 => NodeOrder::Weight,
+            _ => return None,
+        };
+        Some(Config {
+            num_tries,
+            cache_level,
+            tail_mode,
+            node_order,
+        })
+    }
+}
+
+/// Checks that a key of `length` bytes may be added to a keyset that
+/// already holds `num_keys` keys.
+#[cfg_attr(
+    not(any(feature = "libmarisa", feature = "pure-rust")),
+    allow(dead_code)
+)]
+pub(crate) fn check_limits(num_keys: usize, length: usize) -> Result<()> {
+    if length > MAX_KEY_LENGTH {
+        return Err(MarisaError::KeyTooLong {
+            length,
+            max: MAX_KEY_LENGTH,
+        });
+    }
+    if num_keys >= MAX_NUM_KEYS {
+        return Err(MarisaError::TooManyKeys { max: MAX_NUM_KEYS });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_limits, CacheLevel, Config, NodeOrder, TailMode, MAX_KEY_LENGTH, MAX_NUM_KEYS,
+    };
+    use crate::error::MarisaError;
+
+    #[test]
+    fn flags_round_trip() {
+        let config = Config {
+            num_tries: 5,
+            cache_level: CacheLevel::Tiny,
+            tail_mode: TailMode::Binary,
+            node_order: NodeOrder::Label,
+        };
+        assert_eq!(config.flags(), 0x12805);
+        assert_eq!(Config::from_flags(config.flags()), Some(config));
+        assert_eq!(Config::from_flags(0), Some(Config::default()));
+        assert_eq!(Config::default().flags(), 0x21203);
+        assert_eq!(Config::from_flags(0x3000), None);
+        assert_eq!(Config::from_flags(0x100000), None);
+    }
+
+    #[test]
+    fn limits() {
+        assert!(check_limits(0, MAX_KEY_LENGTH).is_ok());
+        assert!(matches!(
+            check_limits(0, MAX_KEY_LENGTH + 1),
+            Err(MarisaError::KeyTooLong { .. })
+        ));
+        assert!(matches!(
+            check_limits(MAX_NUM_KEYS, 1),
+            Err(MarisaError::TooManyKeys { .. })
+        ));
+    }
+}
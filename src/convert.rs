@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::{Keyset, Trie},
+};
+
+/// Panics on a key that `Keyset::push` rejects: an empty key, or a key or
+/// key count past libmarisa's limits. Use [`Keyset::try_from_keys`] or
+/// [`Trie::try_from_keys`], or `Trie::try_from` for a collection, to handle
+/// that as an error.
+impl<S: AsRef<str>> FromIterator<S> for Keyset {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut keyset = Keyset::default();
+        for key in iter {
+            if let Err(err) = keyset.push(key.as_ref(), None) {
+                panic!("cannot collect keys into a keyset: {err}");
+            }
+        }
+        keyset
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for Trie {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut keyset: Keyset = iter.into_iter().collect();
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        trie
+    }
+}
+
+impl Keyset {
+    /// Like `collect`, but fails where it would panic.
+    pub fn try_from_keys<S: AsRef<str>>(keys: impl IntoIterator<Item = S>) -> Result<Self> {
+        let mut keyset = Keyset::default();
+        keyset.push_iter(keys, None)?;
+        Ok(keyset)
+    }
+}
+
+impl Trie {
+    /// Like `collect`, but fails where it would panic.
+    pub fn try_from_keys<S: AsRef<str>>(keys: impl IntoIterator<Item = S>) -> Result<Self> {
+        Trie::build_from(Keyset::try_from_keys(keys)?)
+    }
+
+    /// Every key, indexed by its id.
+    pub fn id_map(&self) -> Vec<String> {
+        let mut keys = vec![String::new(); self.num_keys()];
+        for (id, key) in self.predictive_search("") {
+            keys[id] = key;
+        }
+        keys
+    }
+
+    /// Every key mapped to its id.
+    pub fn to_btreemap(&self) -> BTreeMap<String, usize> {
+        self.predictive_search("")
+            .into_iter()
+            .map(|(id, key)| (key, id))
+            .collect()
+    }
+}
+
+impl TryFrom<Vec<String>> for Trie {
+    type Error = MarisaError;
+
+    fn try_from(keys: Vec<String>) -> Result<Self> {
+        Trie::try_from_keys(keys)
+    }
+}
+
+impl TryFrom<&[&str]> for Trie {
+    type Error = MarisaError;
+
+    fn try_from(keys: &[&str]) -> Result<Self> {
+        Trie::try_from_keys(keys)
+    }
+}
+
+impl TryFrom<HashSet<String>> for Trie {
+    type Error = MarisaError;
+
+    fn try_from(keys: HashSet<String>) -> Result<Self> {
+        Trie::try_from_keys(keys)
+    }
+}
+
+impl TryFrom<BTreeSet<String>> for Trie {
+    type Error = MarisaError;
+
+    fn try_from(keys: BTreeSet<String>) -> Result<Self> {
+        Trie::try_from_keys(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use crate::{
+        error::MarisaError,
+        marisa::{Keyset, Trie},
+    };
+
+    #[test]
+    fn from_collections() {
+        let vec = vec!["b".to_owned(), "a".to_owned(), "b".to_owned()];
+        let hash: HashSet<String> = vec.iter().cloned().collect();
+        let btree: BTreeSet<String> = vec.iter().cloned().collect();
+
+        // Duplicates collapse into one key.
+        assert_eq!(Trie::try_from(vec).unwrap().num_keys(), 2);
+        assert_eq!(Trie::try_from(hash).unwrap().num_keys(), 2);
+        assert_eq!(Trie::try_from(btree).unwrap().num_keys(), 2);
+        assert!(Trie::try_from(&["x", "y"][..])
+            .unwrap()
+            .lookup("y")
+            .is_some());
+        assert!(matches!(
+            Trie::try_from(vec![String::new()]),
+            Err(MarisaError::EmptyKey)
+        ));
+    }
+
+    #[test]
+    fn id_mapping() {
+        let trie = Trie::try_from(&["pes", "kocka", "kos"][..]).unwrap();
+
+        let ids = trie.id_map();
+        assert_eq!(ids.len(), 3);
+        for (id, key) in ids.iter().enumerate() {
+            assert_eq!(trie.lookup(key), Some(id));
+        }
+
+        let map = trie.to_btreemap();
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["kocka", "kos", "pes"]);
+        for (key, &id) in &map {
+            assert_eq!(ids[id], *key);
+        }
+    }
+
+    #[test]
+    fn collect() {
+        let keyset: Keyset = ["one", "two"].into_iter().collect();
+        assert_eq!(keyset.num_keys(), 2);
+
+        let trie: Trie = "a b c".split(' ').collect();
+        assert_eq!(trie.num_keys(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty key")]
+    fn collect_panics_with_the_error() {
+        let _: Keyset = ["a", ""].into_iter().collect();
+    }
+
+    #[test]
+    fn fallible_construction() {
+        assert_eq!(Trie::try_from_keys(["a", "b"]).unwrap().num_keys(), 2);
+        assert!(matches!(
+            Trie::try_from_keys(["a", ""]),
+            Err(MarisaError::EmptyKey)
+        ));
+        let keyset = Keyset::try_from_keys(["a"]).unwrap();
+        assert!(keyset.try_at(0).is_ok());
+        assert!(matches!(
+            keyset.try_at(1),
+            Err(MarisaError::InvalidId { id: 1, num_keys: 1 })
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+//! Domain blocklists matched by suffix, as used for ad blocking and
+//! network policy.
+//!
+//! Rules are stored with their labels reversed (`example.com` becomes
+//! `com.example.`), so the rules covering a domain are the keys along the
+//! path of its reversed labels.
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::{Keyset, Trie},
+};
+
+/// Matches domains against rules such as `example.com`, which covers the
+/// domain and every subdomain, and `ads.*.example.com`, where `*` stands
+/// for exactly one label. Matching is ASCII case-insensitive and ignores a
+/// trailing dot.
+pub struct DomainMatcher {
+    rules: Trie,
+}
+
+fn normalize(domain: &str) -> String {
+    domain
+        .strip_suffix('.')
+        .unwrap_or(domain)
+        .to_ascii_lowercase()
+}
+
+fn reversed_key(domain: &str) -> String {
+    let mut key = String::with_capacity(domain.len() + 1);
+    for label in domain.rsplit('.') {
+        key.push_str(label);
+        key.push('.');
+    }
+    key
+}
+
+impl DomainMatcher {
+    /// Fails with `MarisaError::Parse`, numbered from 1, on a rule with an
+    /// empty label.
+    pub fn new<'a>(rules: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut keyset = Keyset::default();
+        for (index, rule) in rules.into_iter().enumerate() {
+            let rule = normalize(rule);
+            if rule.split('.').any(str::is_empty) {
+                return Err(MarisaError::Parse {
+                    line: index + 1,
+                    details: format!("empty label in domain rule {rule:?}"),
+                });
+            }
+            keyset.push(reversed_key(&rule), None)?;
+        }
+        Ok(Self {
+            rules: Trie::build_from(keyset)?,
+        })
+    }
+
+    pub fn is_blocked(&self, domain: impl AsRef<str>) -> bool {
+        let domain = domain.as_ref();
+        self.longest_match(domain).is_some()
+    }
+
+    /// The most specific rule covering `domain`: the one matching the most
+    /// labels, preferring literal labels over `*` at the same depth.
+    pub fn longest_match(&self, domain: impl AsRef<str>) -> Option<String> {
+        let domain = domain.as_ref();
+        let domain = normalize(domain);
+        let labels: Vec<&str> = domain.rsplit('.').collect();
+        let mut best = None;
+        self.search(&labels, &mut String::new(), 0, &mut best);
+        best.map(|(_, key)| {
+            let key: &str = &key;
+            let mut labels: Vec<&str> = key.strip_suffix('.').unwrap_or(key).split('.').collect();
+            labels.reverse();
+            labels.join(".")
+        })
+    }
+
+    // Depth-first over the reversed labels, trying each label literally and
+    // as `*`, and pruning paths no rule starts with.
+    fn search(
+        &self,
+        labels: &[&str],
+        key: &mut String,
+        depth: usize,
+        best: &mut Option<(usize, String)>,
+    ) {
+        let Some(label) = labels.get(depth) else {
+            return;
+        };
+        for candidate in [*label, "*"] {
+            let len = key.len();
+            key.push_str(candidate);
+            key.push('.');
+            if self.rules.predictive_iter(key.as_str()).next().is_some() {
+                // The literal label is tried first, so it wins ties.
+                let deeper = !matches!(best, Some((found, _)) if *found > depth);
+                if deeper && self.rules.lookup(key.as_str()).is_some() {
+                    *best = Some((depth + 1, key.clone()));
+                }
+                self.search(labels, key, depth + 1, best);
+            }
+            key.truncate(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DomainMatcher;
+    use crate::error::MarisaError;
+
+    #[test]
+    fn suffix_and_wildcard_rules() {
+        let matcher =
+            DomainMatcher::new(["example.com", "ads.*.example.com", "*.tracker.net", "Org."])
+                .unwrap();
+
+        assert!(matcher.is_blocked("example.com"));
+        assert!(matcher.is_blocked("sub.a.EXAMPLE.com."));
+        assert!(!matcher.is_blocked("notexample.com"));
+        assert!(!matcher.is_blocked("com"));
+
+        assert_eq!(
+            matcher.longest_match("x.ads.cdn.example.com").as_deref(),
+            Some("ads.*.example.com")
+        );
+        assert_eq!(
+            matcher.longest_match("ads.example.com").as_deref(),
+            Some("example.com")
+        );
+
+        assert!(!matcher.is_blocked("tracker.net"));
+        assert_eq!(
+            matcher.longest_match("a.b.tracker.net").as_deref(),
+            Some("*.tracker.net")
+        );
+        assert!(matcher.is_blocked("wikipedia.org"));
+    }
+
+    #[test]
+    fn rejects_empty_labels() {
+        assert!(matches!(
+            DomainMatcher::new(["ok.com", "bad..com"]),
+            Err(MarisaError::Parse { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Write},
+};
+
+use crate::marisa::Trie;
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<char, usize>,
+    id: Option<usize>,
+}
+
+impl Trie {
+    /// Renders the key tree as a Graphviz digraph, one node per character,
+    /// breadth first. Once `max_nodes` nodes have been written the
+    /// remaining subtrees are collapsed into `…` placeholders.
+    ///
+    /// The graph shows the logical tree of keys rather than libmarisa's
+    /// internal LOUDS levels, which are not observable through its API.
+    pub fn to_dot<W: Write>(&self, mut writer: W, max_nodes: usize) -> io::Result<()> {
+        let mut nodes = vec![Node::default()];
+        for (id, key) in self.predictive_search("") {
+            let mut current = 0;
+            for c in key.chars() {
+                current = match nodes[current].children.get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        nodes.push(Node::default());
+                        let next = nodes.len() - 1;
+                        nodes[current].children.insert(c, next);
+                        next
+                    }
+                };
+            }
+            nodes[current].id = Some(id);
+        }
+
+        writeln!(writer, "digraph trie {{")?;
+        writeln!(writer, "  node [shape=circle, label=\"\"];")?;
+        let mut written = 0;
+        let mut elided = 0;
+        let mut queue = VecDeque::from([0]);
+        while let Some(index) = queue.pop_front() {
+            let node = &nodes[index];
+            match node.id {
+                Some(id) => writeln!(writer, "  n{index} [shape=doublecircle, label=\"{id}\"];")?,
+                None => writeln!(writer, "  n{index};")?,
+            }
+            written += 1;
+            for (&c, &child) in &node.children {
+                if written + queue.len() < max_nodes {
+                    writeln!(writer, "  n{index} -> n{child} [label=\"{}\"];", escape(c))?;
+                    queue.push_back(child);
+                } else {
+                    writeln!(writer, "  e{elided} [shape=plaintext, label=\"…\"];")?;
+                    writeln!(writer, "  n{index} -> e{elided} [label=\"{}\"];", escape(c))?;
+                    elided += 1;
+                }
+            }
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+fn escape(c: char) -> String {
+    match c {
+        '"' => "\\\"".to_owned(),
+        '\\' => "\\\\".to_owned(),
+        c if c.is_control() => c.escape_unicode().to_string(),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marisa::Trie;
+
+    fn dot(trie: &Trie, max_nodes: usize) -> String {
+        let mut out = Vec::new();
+        trie.to_dot(&mut out, max_nodes).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn renders_key_tree() {
+        let trie = Trie::try_from(&["ab", "ac"][..]).unwrap();
+        let out = dot(&trie, 100);
+
+        assert!(out.starts_with("digraph trie {\n"));
+        assert!(out.ends_with("}\n"));
+        assert_eq!(out.matches(" -> ").count(), 3);
+        assert_eq!(out.matches("doublecircle").count(), 2);
+        assert!(out.contains("[label=\"a\"]"));
+        assert!(!out.contains('…'));
+    }
+
+    #[test]
+    fn elides_past_node_limit() {
+        let trie = Trie::try_from(&["abc", "abd", "x\"y"][..]).unwrap();
+        let out = dot(&trie, 3);
+
+        let nodes = out
+            .lines()
+            .filter(|line| line.starts_with("  n") && !line.starts_with("  node"))
+            .filter(|line| !line.contains(" -> "))
+            .count();
+        assert_eq!(nodes, 3);
+        assert_eq!(out.matches('…').count(), 2);
+        assert!(out.contains("[label=\"\\\"\"]"));
+    }
+}
@@ -0,0 +1,768 @@
+//! A [`TrieMap`] that also takes insertions.
+//!
+//! New keys go into a sorted overlay next to the static trie and are
+//! folded into it by [`DynamicTrie::compact`], so lookups stay cheap while
+//! the dictionary grows and rebuilds happen when the caller chooses.
+//!
+//! Everything changed since the trie was built (additions, updated values
+//! and removals) can be saved on its own with [`DynamicTrie::save_delta`],
+//! so frequent small updates don't rewrite the whole dictionary.
+
+use std::{
+    collections::{
+        btree_map::{self, BTreeMap},
+        BTreeSet,
+    },
+    fs,
+    io::{BufWriter, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    backend::PrefixDictionary,
+    error::{MarisaError, Result},
+    map::TrieMap,
+    marisa::Trie,
+};
+
+pub use crate::map::OccupiedEntry;
+
+const DELTA_MAGIC: &[u8; 8] = b"MRSDELTA";
+const DELTA_VERSION: u32 = 1;
+
+pub struct DynamicTrie<V> {
+    base: TrieMap<V>,
+    /// Keys not in `base`, or removed from it and inserted again.
+    overlay: BTreeMap<String, V>,
+    /// Ids of `base` keys that were removed.
+    removed: BTreeSet<usize>,
+    /// Ids of `base` keys whose value may have changed.
+    changed: BTreeSet<usize>,
+    base_fingerprint: OnceLock<u64>,
+    /// Keys touched since a background compaction took its snapshot.
+    journal: Option<BTreeSet<String>>,
+    /// Bumped when a background compaction starts and when the static trie
+    /// is replaced, so a compaction only swaps in a trie built from the
+    /// state it started on.
+    generation: u64,
+}
+
+impl<V> Default for DynamicTrie<V> {
+    fn default() -> Self {
+        Self::from_base(TrieMap::new(std::iter::empty::<(&str, V)>()).expect("empty map builds"))
+    }
+}
+
+/// Values [`DynamicTrie::save_delta`] can write and
+/// [`DynamicTrie::load_delta`] read back.
+pub trait DeltaValue: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    /// `None` if `bytes` isn't something `encode` wrote.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! delta_value_le {
+    ($($ty:ty),*) => {$(
+        impl DeltaValue for $ty {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Option<Self> {
+                Some(Self::from_le_bytes(bytes.try_into().ok()?))
+            }
+        }
+    )*};
+}
+
+delta_value_le!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl DeltaValue for () {
+    fn encode(&self, _: &mut Vec<u8>) {}
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        bytes.is_empty().then_some(())
+    }
+}
+
+impl DeltaValue for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl DeltaValue for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+/// One change to a [`DynamicTrie`], as saved in deltas.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Change<V> {
+    Insert(String, V),
+    Remove(String),
+}
+
+const INSERT: u8 = 0;
+const REMOVE: u8 = 1;
+
+/// Appends a change as a tag, the key and, for insertions, the value, each
+/// length-prefixed.
+pub(crate) fn encode_change<V: DeltaValue>(change: Change<&V>, out: &mut Vec<u8>) {
+    let (tag, key, value) = match change {
+        Change::Insert(key, value) => (INSERT, key, Some(value)),
+        Change::Remove(key) => (REMOVE, key, None),
+    };
+    out.push(tag);
+    out.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    out.extend_from_slice(key.as_bytes());
+    if let Some(value) = value {
+        let len_at = out.len();
+        out.extend_from_slice(&[0; 4]);
+        value.encode(out);
+        let len = (out.len() - len_at - 4) as u32;
+        out[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+/// Reads a change written by [`encode_change`] from the start of `bytes`,
+/// with the number of bytes it took; `None` if it is cut short or
+/// malformed.
+pub(crate) fn decode_change<V: DeltaValue>(bytes: &[u8]) -> Option<(Change<V>, usize)> {
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        let (head, rest) = bytes.split_at_checked(len)?;
+        *bytes = rest;
+        Some(head)
+    }
+    fn take_len(bytes: &mut &[u8]) -> Option<usize> {
+        Some(u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?) as usize)
+    }
+
+    let mut rest = bytes;
+    let tag = take(&mut rest, 1)?[0];
+    let len = take_len(&mut rest)?;
+    let key = std::str::from_utf8(take(&mut rest, len)?).ok()?.to_owned();
+    let change = match tag {
+        INSERT => {
+            let len = take_len(&mut rest)?;
+            Change::Insert(key, V::decode(take(&mut rest, len)?)?)
+        }
+        REMOVE => Change::Remove(key),
+        _ => return None,
+    };
+    Some((change, bytes.len() - rest.len()))
+}
+
+/// A key's place in a [`DynamicTrie`], from [`DynamicTrie::entry`].
+pub enum Entry<'a, V> {
+    Occupied(OccupiedEntry<'a, V>),
+    Vacant(VacantEntry<'a, V>),
+}
+
+pub struct VacantEntry<'a, V> {
+    entry: btree_map::VacantEntry<'a, String, V>,
+}
+
+impl<'a, V> Entry<'a, V> {
+    pub fn key(&self) -> &str {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, V> VacantEntry<'a, V> {
+    pub fn key(&self) -> &str {
+        self.entry.key()
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.entry.insert(value)
+    }
+}
+
+impl<V> DynamicTrie<V> {
+    /// Starts from `(key, value)` entries built into the static trie; for
+    /// a key given more than once, the last value is kept.
+    pub fn new<K: AsRef<str>>(entries: impl IntoIterator<Item = (K, V)>) -> Result<Self> {
+        Ok(Self::from_base(TrieMap::new(entries)?))
+    }
+
+    fn from_base(base: TrieMap<V>) -> Self {
+        Self {
+            base,
+            overlay: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            changed: BTreeSet::new(),
+            base_fingerprint: OnceLock::new(),
+            journal: None,
+            generation: 0,
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(journal) = &mut self.journal {
+            journal.insert(key.to_owned());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.base.len() - self.removed.len() + self.overlay.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keys inserted or removed since the last [`DynamicTrie::compact`].
+    pub fn num_pending(&self) -> usize {
+        self.overlay.len() + self.removed.len()
+    }
+
+    /// The id of `key` in the static trie, unless it was removed.
+    fn base_id(&self, key: &str) -> Option<usize> {
+        self.base
+            .keys()
+            .lookup(key)
+            .filter(|id| !self.removed.contains(id))
+    }
+
+    pub fn get(&self, key: impl AsRef<str>) -> Option<&V> {
+        let key = key.as_ref();
+        match self.base_id(key) {
+            Some(id) => self.base.get_by_id(id),
+            None => self.overlay.get(key),
+        }
+    }
+
+    pub fn get_mut(&mut self, key: impl AsRef<str>) -> Option<&mut V> {
+        let key = key.as_ref();
+        self.touch(key);
+        match self.base_id(key) {
+            Some(id) => {
+                self.changed.insert(id);
+                self.base.get_by_id_mut(id)
+            }
+            None => self.overlay.get_mut(key),
+        }
+    }
+
+    pub fn contains_key(&self, key: impl AsRef<str>) -> bool {
+        let key = key.as_ref();
+        self.get(key).is_some()
+    }
+
+    /// Sets the value of `key`, returning the previous one.
+    pub fn insert(&mut self, key: impl AsRef<str>, value: V) -> Option<V> {
+        let key = key.as_ref();
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        }
+    }
+
+    /// The entry for `key`, for updating or inserting with one lookup in
+    /// each of the trie and the overlay.
+    pub fn entry(&mut self, key: impl AsRef<str>) -> Entry<'_, V> {
+        let key = key.as_ref();
+        self.touch(key);
+        if let Some(id) = self.base_id(key) {
+            self.changed.insert(id);
+            return Entry::Occupied(OccupiedEntry::new(
+                key.to_owned(),
+                self.base.get_by_id_mut(id).expect("id from the trie"),
+            ));
+        }
+        match self.overlay.entry(key.to_owned()) {
+            btree_map::Entry::Occupied(entry) => {
+                let key = entry.key().clone();
+                Entry::Occupied(OccupiedEntry::new(key, entry.into_mut()))
+            }
+            btree_map::Entry::Vacant(entry) => Entry::Vacant(VacantEntry { entry }),
+        }
+    }
+
+    /// Removes `key`, returning whether it was present. The value of a key
+    /// in the static trie is only dropped by the next compaction.
+    pub fn remove(&mut self, key: impl AsRef<str>) -> bool {
+        let key = key.as_ref();
+        self.touch(key);
+        if let Some(id) = self.base_id(key) {
+            self.removed.insert(id);
+            self.changed.remove(&id);
+            return true;
+        }
+        self.overlay.remove(key).is_some()
+    }
+
+    /// Entries whose key starts with `prefix`, sorted by key.
+    pub fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<(String, &V)> {
+        let prefix = prefix.as_ref();
+        let mut found: Vec<(String, &V)> = if self.removed.is_empty() {
+            self.base.predictive_iter(prefix).collect()
+        } else {
+            self.base
+                .keys()
+                .predictive_iter(prefix)
+                .filter(|(id, _)| !self.removed.contains(id))
+                .map(|(id, key)| (key, self.base.get_by_id(id).expect("id from the trie")))
+                .collect()
+        };
+        found.extend(
+            self.overlay
+                .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| (key.clone(), value)),
+        );
+        found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    /// Rebuilds the static trie with the pending keys folded in. The new
+    /// trie is built before anything moves, so on failure the map is left
+    /// as it was. Fails with `CompactionConflict` while a background
+    /// compaction is in flight, since swapping the trie under it would
+    /// drop the changes it journals.
+    pub fn compact(&mut self) -> Result<()> {
+        self.compact_with(TrieMap::<V>::build_keys)
+    }
+
+    fn compact_with(&mut self, build: impl FnOnce(Vec<String>) -> Result<Trie>) -> Result<()> {
+        if self.journal.is_some() {
+            return Err(MarisaError::CompactionConflict);
+        }
+        if self.overlay.is_empty() && self.removed.is_empty() {
+            self.changed.clear();
+            return Ok(());
+        }
+        let keys = build(
+            self.predictive_search("")
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect(),
+        )?;
+        let Self {
+            base,
+            overlay,
+            removed,
+            ..
+        } = std::mem::take(self);
+        let entries = base
+            .into_entries()
+            .into_iter()
+            .enumerate()
+            .filter(|(id, _)| !removed.contains(id))
+            .map(|(_, entry)| entry)
+            .chain(overlay);
+        let generation = self.generation;
+        *self = Self::from_base(TrieMap::with_keys(keys, entries));
+        self.generation = generation + 1;
+        Ok(())
+    }
+
+    /// Starts recording the keys changed from here on, for a compaction
+    /// that snapshots the entries and builds without holding the map.
+    /// Returns the token to finish or abort it with; fails with
+    /// `CompactionConflict` if another one is in flight.
+    pub(crate) fn begin_compaction(&mut self) -> Result<u64> {
+        if self.journal.is_some() {
+            return Err(MarisaError::CompactionConflict);
+        }
+        self.generation += 1;
+        self.journal = Some(BTreeSet::new());
+        Ok(self.generation)
+    }
+
+    /// Every entry, for a compaction to build from.
+    pub(crate) fn snapshot(&self) -> Vec<(String, V)>
+    where
+        V: Clone,
+    {
+        self.predictive_search("")
+            .into_iter()
+            .map(|(key, value)| (key, value.clone()))
+            .collect()
+    }
+
+    /// Swaps in `base`, built from a snapshot taken after the
+    /// [`DynamicTrie::begin_compaction`] that returned `token`, and carries
+    /// over the keys changed since. Fails with `CompactionConflict`,
+    /// leaving the map as it is, if the compaction is no longer the one in
+    /// flight.
+    pub(crate) fn finish_compaction(&mut self, token: u64, base: TrieMap<V>) -> Result<()>
+    where
+        V: Clone,
+    {
+        if token != self.generation {
+            return Err(MarisaError::CompactionConflict);
+        }
+        let Some(touched) = self.journal.take() else {
+            return Err(MarisaError::CompactionConflict);
+        };
+        let mut compacted = Self::from_base(base);
+        compacted.generation = self.generation + 1;
+        for key in touched {
+            match self.get(&key) {
+                Some(value) => {
+                    compacted.insert(&key, value.clone());
+                }
+                None => {
+                    compacted.remove(&key);
+                }
+            }
+        }
+        *self = compacted;
+        Ok(())
+    }
+
+    /// Stops recording without swapping, e.g. after a failed build, unless
+    /// another compaction has taken over since `token`.
+    pub(crate) fn abort_compaction(&mut self, token: u64) {
+        if token == self.generation {
+            self.journal = None;
+        }
+    }
+
+    /// Identifies the static trie, so deltas are only applied to the trie
+    /// they were saved against. Computed once per build.
+    pub(crate) fn base_fingerprint(&self) -> Result<u64> {
+        if let Some(&fingerprint) = self.base_fingerprint.get() {
+            return Ok(fingerprint);
+        }
+        let fingerprint = self.base.keys().fingerprint()?;
+        Ok(*self.base_fingerprint.get_or_init(|| fingerprint))
+    }
+
+    /// Every change since the static trie was built: removals first, then
+    /// updated and added entries.
+    pub(crate) fn changes(&self) -> impl Iterator<Item = Change<&V>> + '_ {
+        let key = |id: usize| {
+            self.base
+                .keys()
+                .reverse_lookup(id)
+                .expect("built from &str keys")
+        };
+        let removals = self.removed.iter().map(move |&id| Change::Remove(key(id)));
+        let updates = self.changed.iter().map(move |&id| {
+            Change::Insert(key(id), self.base.get_by_id(id).expect("id from the trie"))
+        });
+        let additions = self
+            .overlay
+            .iter()
+            .map(|(key, value)| Change::Insert(key.clone(), value));
+        removals.chain(updates).chain(additions)
+    }
+
+    pub(crate) fn apply(&mut self, change: Change<V>) {
+        match change {
+            Change::Insert(key, value) => {
+                self.insert(&key, value);
+            }
+            Change::Remove(key) => {
+                self.remove(&key);
+            }
+        }
+    }
+
+    /// Writes the changes since the static trie was built to `path`,
+    /// without the trie itself. The file is replaced atomically.
+    pub fn save_delta(&self, path: &Path) -> Result<()>
+    where
+        V: DeltaValue,
+    {
+        let at = |err: std::io::Error| MarisaError::from(err).at("save", path);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(DELTA_MAGIC);
+        bytes.extend_from_slice(&DELTA_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.base_fingerprint()?.to_le_bytes());
+        for change in self.changes() {
+            encode_change(change, &mut bytes);
+        }
+
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut out = BufWriter::new(fs::File::create(&temp).map_err(at)?);
+        out.write_all(&bytes).map_err(at)?;
+        out.into_inner()
+            .map_err(|err| at(err.into_error()))?
+            .sync_all()
+            .map_err(at)?;
+        fs::rename(&temp, path).map_err(at)
+    }
+
+    /// Applies a delta written by [`DynamicTrie::save_delta`] on top of the
+    /// current entries. The static trie must be the one it was saved
+    /// against.
+    pub fn load_delta(&mut self, path: &Path) -> Result<()>
+    where
+        V: DeltaValue,
+    {
+        let bytes = fs::read(path).map_err(|err| MarisaError::from(err).at("load", path))?;
+        let header_len = DELTA_MAGIC.len() + 4 + 8;
+        if bytes.len() < header_len || &bytes[..DELTA_MAGIC.len()] != DELTA_MAGIC {
+            return Err(MarisaError::incompatible("not a dictionary delta"));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().expect("4 bytes"));
+        if version != DELTA_VERSION {
+            return Err(MarisaError::incompatible_with(
+                "unsupported delta version",
+                DELTA_VERSION.to_string(),
+                version.to_string(),
+            ));
+        }
+        let saved = u64::from_le_bytes(bytes[12..20].try_into().expect("8 bytes"));
+        let fingerprint = self.base_fingerprint()?;
+        if saved != fingerprint {
+            return Err(MarisaError::ManifestMismatch {
+                field: "base_fingerprint",
+                expected: format!("{fingerprint:016x}"),
+                found: format!("{saved:016x}"),
+            });
+        }
+
+        let mut changes = Vec::new();
+        let mut rest = &bytes[header_len..];
+        while !rest.is_empty() {
+            let (change, len) = decode_change(rest).ok_or_else(|| MarisaError::Corrupt {
+                id: None,
+                details: format!(
+                    "malformed delta record at byte {}",
+                    bytes.len() - rest.len()
+                ),
+            })?;
+            changes.push(change);
+            rest = &rest[len..];
+        }
+        for change in changes {
+            self.apply(change);
+        }
+        Ok(())
+    }
+}
+
+impl<V> DynamicTrie<V> {
+    /// Pending keys are numbered after the static trie's ids, in key order.
+    fn pending_id(&self, key: &str) -> usize {
+        self.base.len()
+            + self
+                .overlay
+                .range::<str, _>((Bound::Unbounded, Bound::Excluded(key)))
+                .count()
+    }
+}
+
+/// Keys in the static trie keep their ids; pending keys get ids past them,
+/// which change as keys are inserted or removed until the next compaction.
+impl<V> PrefixDictionary for DynamicTrie<V> {
+    fn contains(&self, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    fn lookup(&self, key: &str) -> Option<usize> {
+        self.base_id(key)
+            .or_else(|| self.overlay.contains_key(key).then(|| self.pending_id(key)))
+    }
+
+    fn prefix_iter<'a>(&'a self, query: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a> {
+        let mut found: Vec<(usize, String)> = self
+            .base
+            .keys()
+            .common_prefix_iter(query)
+            .filter(|(id, _)| !self.removed.contains(id))
+            .collect();
+        found.extend(
+            (0..=query.len())
+                .filter(|&end| query.is_char_boundary(end))
+                .map(|end| &query[..end])
+                .filter(|key| self.overlay.contains_key(*key))
+                .map(|key| (self.pending_id(key), key.to_owned())),
+        );
+        found.sort_unstable_by_key(|(_, key)| key.len());
+        Box::new(found.into_iter())
+    }
+
+    fn complete<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a> {
+        let first = self.pending_id(prefix);
+        let owned = prefix.to_owned();
+        let pending = self
+            .overlay
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(move |key| key.starts_with(&owned))
+            .enumerate()
+            .map(move |(offset, key)| (first + offset, key.clone()));
+        Box::new(
+            self.base
+                .keys()
+                .predictive_iter(prefix)
+                .filter(|(id, _)| !self.removed.contains(id))
+                .chain(pending),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_change, encode_change, Change, DynamicTrie, Entry};
+    use crate::error::MarisaError;
+
+    #[test]
+    fn entries_update_and_insert() {
+        let mut trie = DynamicTrie::new([("fu", 1), ("fi", 2)]).unwrap();
+
+        *trie.entry("fu").or_insert(0) += 10;
+        *trie.entry("fufi").or_insert(0) += 10;
+        trie.entry("fufi").and_modify(|v| *v *= 2).or_insert(0);
+        *trie.entry("pes").or_default() += 1;
+        assert!(matches!(trie.entry("fi"), Entry::Occupied(_)));
+        assert_eq!(trie.entry("pe").key(), "pe");
+
+        assert_eq!(trie.get("fu"), Some(&11));
+        assert_eq!(trie.get("fufi"), Some(&20));
+        assert_eq!(trie.insert("fi", 5), Some(2));
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.num_pending(), 2);
+    }
+
+    #[test]
+    fn compacts_pending_keys() {
+        let mut trie = DynamicTrie::new([("fu", 1)]).unwrap();
+        trie.insert("fufi", 2);
+        trie.insert("fi", 3);
+        let before = trie.predictive_search("fu");
+        assert_eq!(before, vec![("fu".to_owned(), &1), ("fufi".to_owned(), &2)]);
+
+        trie.compact().unwrap();
+        assert_eq!(trie.num_pending(), 0);
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.get("fi"), Some(&3));
+        assert_eq!(
+            trie.predictive_search("fu"),
+            vec![("fu".to_owned(), &1), ("fufi".to_owned(), &2)]
+        );
+    }
+
+    #[test]
+    fn failed_compaction_keeps_entries() {
+        let mut trie = DynamicTrie::new([("fu", 1), ("fi", 2)]).unwrap();
+        trie.insert("fufi", 3);
+        trie.remove("fi");
+        let before = trie.snapshot();
+
+        let failed = trie.compact_with(|_| Err(MarisaError::TooManyKeys { max: 0 }));
+        assert!(matches!(failed, Err(MarisaError::TooManyKeys { .. })));
+        assert_eq!(trie.snapshot(), before);
+        assert_eq!(trie.num_pending(), 2);
+
+        trie.compact().unwrap();
+        assert_eq!(trie.snapshot(), before);
+        assert_eq!(trie.num_pending(), 0);
+    }
+
+    #[test]
+    fn removes_keys() {
+        let mut trie = DynamicTrie::new([("fu", 1), ("fufi", 2)]).unwrap();
+        trie.insert("fi", 3);
+        assert!(trie.remove("fu"));
+        assert!(trie.remove("fi"));
+        assert!(!trie.remove("fu"));
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.get("fu"), None);
+        assert_eq!(trie.predictive_search("f"), vec![("fufi".to_owned(), &2)]);
+
+        trie.insert("fu", 4);
+        assert_eq!(trie.get("fu"), Some(&4));
+        trie.compact().unwrap();
+        assert_eq!(trie.num_pending(), 0);
+        assert_eq!(
+            trie.predictive_search("f"),
+            vec![("fu".to_owned(), &4), ("fufi".to_owned(), &2)]
+        );
+    }
+
+    #[test]
+    fn change_records() {
+        let mut bytes = Vec::new();
+        encode_change(Change::Insert("fu".to_owned(), &7u32), &mut bytes);
+        encode_change::<u32>(Change::Remove("pes".to_owned()), &mut bytes);
+        let (first, len) = decode_change::<u32>(&bytes).unwrap();
+        assert_eq!(first, Change::Insert("fu".to_owned(), 7));
+        let (second, rest) = decode_change::<u32>(&bytes[len..]).unwrap();
+        assert_eq!(second, Change::Remove("pes".to_owned()));
+        assert_eq!(len + rest, bytes.len());
+        assert!(decode_change::<u32>(&bytes[..len - 1]).is_none());
+        assert!(decode_change::<u64>(&bytes).is_none());
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let path = std::env::temp_dir().join(format!("marisa-delta-{}.delta", std::process::id()));
+        let base = [("fu", 1u32), ("fufi", 2), ("pes", 3)];
+        let mut trie = DynamicTrie::new(base).unwrap();
+        *trie.get_mut("fu").unwrap() += 10;
+        trie.remove("pes");
+        trie.insert("fi", 4);
+        trie.save_delta(&path).unwrap();
+
+        let mut restored = DynamicTrie::new(base).unwrap();
+        restored.load_delta(&path).unwrap();
+        let entries = |trie: &DynamicTrie<u32>| -> Vec<(String, u32)> {
+            trie.predictive_search("")
+                .into_iter()
+                .map(|(key, value)| (key, *value))
+                .collect()
+        };
+        assert_eq!(entries(&restored), entries(&trie));
+        assert_eq!(restored.get("fu"), Some(&11));
+
+        let mut other = DynamicTrie::new([("x", 0u32)]).unwrap();
+        assert!(matches!(
+            other.load_delta(&path),
+            Err(MarisaError::ManifestMismatch { .. })
+        ));
+        std::fs::write(&path, b"nope").unwrap();
+        assert!(restored.load_delta(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fmt, path::PathBuf};
+
+#[derive(Debug)]
+// Kotlin and Swift see an exception per variant, carrying the message.
+#[cfg_attr(
+    all(feature = "uniffi", feature = "libmarisa"),
+    derive(uniffi::Error),
+    uniffi(flat_error)
+)]
+pub enum MarisaError {
+    Io(std::io::Error),
+    /// An I/O failure while working on a dictionary file, e.g. `load` of a
+    /// missing path. The OS error code is available from `source`.
+    File {
+        operation: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    UnknownDictionary(String),
+    NotReloadable(String),
+    Utf8(std::str::Utf8Error),
+    IncompatibleFormat {
+        details: String,
+        /// What libmarisa expects there, when the check knows.
+        expected: Option<String>,
+        found: Option<String>,
+    },
+    /// Malformed input; `line` counts from 1, or is 0 for binary formats.
+    Parse {
+        line: usize,
+        details: String,
+    },
+    ManifestMismatch {
+        field: &'static str,
+        expected: String,
+        found: String,
+    },
+    /// The trie was used before being built, loaded or mapped, or after
+    /// `clear`.
+    NotReady,
+    /// A key was pushed twice into a builder that rejects duplicates.
+    DuplicateKey(String),
+    /// The empty string was pushed into a keyset that doesn't allow it.
+    EmptyKey,
+    KeyTooLong {
+        length: usize,
+        max: usize,
+    },
+    TooManyKeys {
+        max: usize,
+    },
+    /// A key id at or past the number of keys in the trie.
+    InvalidId {
+        id: usize,
+        num_keys: usize,
+    },
+    /// `Trie::verify` found an inconsistency, at key `id` when it concerns one.
+    Corrupt {
+        id: Option<usize>,
+        details: String,
+    },
+    /// A `DynamicTrie` compaction was started, or a background one
+    /// finished, while another compaction had the trie.
+    CompactionConflict,
+    #[cfg(feature = "fst")]
+    Fst(fst::Error),
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+}
+
+pub type Result<T, E = MarisaError> = std::result::Result<T, E>;
+
+impl fmt::Display for MarisaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarisaError::Io(err) => write!(f, "i/o error: {err}"),
+            MarisaError::File {
+                operation,
+                path,
+                source,
+            } => write!(f, "failed to {operation} {}: {source}", path.display()),
+            MarisaError::UnknownDictionary(name) => write!(f, "unknown dictionary `{name}`"),
+            MarisaError::NotReloadable(name) => {
+                write!(f, "dictionary `{name}` was not loaded from a file")
+            }
+            MarisaError::Utf8(err) => write!(f, "key is not valid UTF-8: {err}"),
+            MarisaError::IncompatibleFormat {
+                details,
+                expected: Some(expected),
+                found: Some(found),
+            } => write!(
+                f,
+                "incompatible dictionary format: {details} (expected {expected}, found {found})"
+            ),
+            MarisaError::IncompatibleFormat { details, .. } => {
+                write!(f, "incompatible dictionary format: {details}")
+            }
+            MarisaError::Parse { line, details } => write!(f, "line {line}: {details}"),
+            MarisaError::ManifestMismatch {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "dictionary does not match its manifest: {field} is {found}, expected {expected}"
+            ),
+            MarisaError::NotReady => write!(f, "trie has not been built, loaded or mapped"),
+            MarisaError::DuplicateKey(key) => write!(f, "duplicate key {key:?}"),
+            MarisaError::EmptyKey => write!(f, "empty keys are not allowed in this keyset"),
+            MarisaError::KeyTooLong { length, max } => {
+                write!(f, "key of {length} bytes exceeds the {max}-byte limit")
+            }
+            MarisaError::TooManyKeys { max } => write!(f, "keyset exceeds {max} keys"),
+            MarisaError::InvalidId { id, num_keys } => {
+                write!(
+                    f,
+                    "key id {id} is out of range for a trie of {num_keys} keys"
+                )
+            }
+            MarisaError::Corrupt {
+                id: Some(id),
+                details,
+            } => {
+                write!(f, "dictionary failed verification at id {id}: {details}")
+            }
+            MarisaError::Corrupt { id: None, details } => {
+                write!(f, "dictionary failed verification: {details}")
+            }
+            MarisaError::CompactionConflict => {
+                write!(f, "another compaction of this trie is in flight")
+            }
+            #[cfg(feature = "fst")]
+            MarisaError::Fst(err) => write!(f, "fst error: {err}"),
+            #[cfg(feature = "serde")]
+            MarisaError::Json(err) => write!(f, "json error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MarisaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MarisaError::Io(err) => Some(err),
+            MarisaError::File { source, .. } => Some(source),
+            MarisaError::Utf8(err) => Some(err),
+            #[cfg(feature = "fst")]
+            MarisaError::Fst(err) => Some(err),
+            #[cfg(feature = "serde")]
+            MarisaError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl MarisaError {
+    /// Attaches `operation` and `path` to a bare `Io` error.
+    #[cfg(feature = "libmarisa")]
+    pub(crate) fn at(self, operation: &'static str, path: &std::path::Path) -> Self {
+        match self {
+            MarisaError::Io(source) => MarisaError::File {
+                operation,
+                path: path.to_owned(),
+                source,
+            },
+            other => other,
+        }
+    }
+
+    pub(crate) fn incompatible(details: impl Into<String>) -> Self {
+        MarisaError::IncompatibleFormat {
+            details: details.into(),
+            expected: None,
+            found: None,
+        }
+    }
+
+    pub(crate) fn incompatible_with(
+        details: impl Into<String>,
+        expected: impl Into<String>,
+        found: impl Into<String>,
+    ) -> Self {
+        MarisaError::IncompatibleFormat {
+            details: details.into(),
+            expected: Some(expected.into()),
+            found: Some(found.into()),
+        }
+    }
+}
+
+impl From<std::io::Error> for MarisaError {
+    fn from(err: std::io::Error) -> Self {
+        MarisaError::Io(err)
+    }
+}
+
+impl From<std::str::Utf8Error> for MarisaError {
+    fn from(err: std::str::Utf8Error) -> Self {
+        MarisaError::Utf8(err)
+    }
+}
+
+#[cfg(feature = "fst")]
+impl From<fst::Error> for MarisaError {
+    fn from(err: fst::Error) -> Self {
+        MarisaError::Fst(err)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for MarisaError {
+    fn from(err: serde_json::Error) -> Self {
+        MarisaError::Json(err)
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Write};
+
+use crate::marisa::Trie;
+
+impl Trie {
+    /// Writes every key as a JSON array of `{"id": .., "key": ..}` objects,
+    /// one per line, ordered by id.
+    pub fn export_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        export_json(self, writer, None)
+    }
+
+    /// Like `export_json`, adding a `weight` field taken from `weights[id]`.
+    pub fn export_json_weighted<W: Write>(&self, writer: W, weights: &[f32]) -> io::Result<()> {
+        export_json(self, writer, Some(weights))
+    }
+
+    /// Writes every key as CSV with an `id,key` header, ordered by id.
+    pub fn export_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        export_csv(self, writer, None)
+    }
+
+    /// Like `export_csv`, adding a `weight` column taken from `weights[id]`.
+    pub fn export_csv_weighted<W: Write>(&self, writer: W, weights: &[f32]) -> io::Result<()> {
+        export_csv(self, writer, Some(weights))
+    }
+}
+
+fn rows(trie: &Trie) -> Vec<(usize, String)> {
+    let mut rows = trie.predictive_search("");
+    rows.sort_unstable_by_key(|&(id, _)| id);
+    rows
+}
+
+fn weight(weights: &[f32], id: usize) -> io::Result<f32> {
+    weights.get(id).copied().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no weight for key id {id}"),
+        )
+    })
+}
+
+fn export_json<W: Write>(trie: &Trie, mut writer: W, weights: Option<&[f32]>) -> io::Result<()> {
+    writer.write_all(b"[")?;
+    for (i, (id, key)) in rows(trie).into_iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write!(writer, "\n{{\"id\":{id},\"key\":")?;
+        write_json_str(&mut writer, &key)?;
+        if let Some(weights) = weights {
+            write!(writer, ",\"weight\":{}", weight(weights, id)?)?;
+        }
+        writer.write_all(b"}")?;
+    }
+    writer.write_all(b"\n]\n")
+}
+
+fn write_json_str<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\n' => writer.write_all(b"\\n")?,
+            '\r' => writer.write_all(b"\\r")?,
+            '\t' => writer.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{c}")?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+fn export_csv<W: Write>(trie: &Trie, mut writer: W, weights: Option<&[f32]>) -> io::Result<()> {
+    match weights {
+        Some(_) => writer.write_all(b"id,key,weight\n")?,
+        None => writer.write_all(b"id,key\n")?,
+    }
+    for (id, key) in rows(trie) {
+        write!(writer, "{id},")?;
+        write_csv_field(&mut writer, &key)?;
+        if let Some(weights) = weights {
+            write!(writer, ",{}", weight(weights, id)?)?;
+        }
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn write_csv_field<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    if value.contains([',', '"', '\n', '\r']) {
+        write!(writer, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        writer.write_all(value.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{marisa::Trie, test_util::build};
+
+    fn key_for(trie: &Trie, id: usize) -> String {
+        trie.reverse_lookup(id).unwrap()
+    }
+
+    #[test]
+    fn csv_quotes_special_keys() {
+        let trie = build(&["a,b", "say \"hi\""]);
+        let mut out = Vec::new();
+        trie.export_csv(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("id,key"));
+        for (id, line) in lines.enumerate() {
+            let expected = key_for(&trie, id);
+            let field = format!("\"{}\"", expected.replace('"', "\"\""));
+            assert_eq!(line, format!("{id},{field}"));
+        }
+    }
+
+    #[test]
+    fn json_escapes_and_weights() {
+        let trie = build(&["tab\there", "plain"]);
+        let mut out = Vec::new();
+        trie.export_json_weighted(&mut out, &[0.5, 2.0]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("[\n{\"id\":0,"));
+        assert!(out.contains("\"key\":\"tab\\there\""));
+        assert!(out.contains("\"weight\":0.5"));
+        assert!(out.contains("\"weight\":2"));
+        assert!(out.ends_with("}\n]\n"));
+    }
+
+    #[test]
+    fn missing_weight_is_an_error() {
+        let trie = build(&["a", "b"]);
+        assert!(trie.export_csv_weighted(Vec::new(), &[1.0]).is_err());
+    }
+}
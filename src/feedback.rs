@@ -0,0 +1,229 @@
+//! Learning from what users pick: selection counts per key id, blended
+//! into [`WeightedTrie`] rankings and saved to disk now and then.
+//!
+//! Counts are keyed by id, so a store belongs to one build of a
+//! dictionary; start a new one when the trie is rebuilt.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    weighted::WeightedTrie,
+};
+
+pub struct FeedbackStore {
+    counts: HashMap<u32, u32>,
+    max_count: u32,
+    /// Added to a key's weight per unit of `ln(1 + count)`.
+    boost: f32,
+    persistence: Option<Persistence>,
+}
+
+struct Persistence {
+    path: PathBuf,
+    interval: Duration,
+    last_save: Instant,
+    dirty: bool,
+}
+
+impl Default for FeedbackStore {
+    fn default() -> Self {
+        Self {
+            counts: HashMap::new(),
+            max_count: 0,
+            boost: 1.0,
+            persistence: None,
+        }
+    }
+}
+
+impl FeedbackStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how strongly counts move rankings, 1.0 by default.
+    pub fn with_boost(mut self, boost: f32) -> Self {
+        self.boost = boost;
+        self
+    }
+
+    /// Loads counts from `path` if it exists, and lets
+    /// [`FeedbackStore::save_if_due`] write them back at most every
+    /// `interval`.
+    pub fn with_persistence(mut self, path: &Path, interval: Duration) -> Result<Self> {
+        if path.exists() {
+            self.load(path)?;
+        }
+        self.persistence = Some(Persistence {
+            path: path.to_owned(),
+            interval,
+            last_save: Instant::now(),
+            dirty: false,
+        });
+        Ok(self)
+    }
+
+    /// Records that the key `id` was picked.
+    pub fn record(&mut self, id: usize) {
+        let count = self.counts.entry(id as u32).or_default();
+        *count = count.saturating_add(1);
+        self.max_count = self.max_count.max(*count);
+        if let Some(persistence) = &mut self.persistence {
+            persistence.dirty = true;
+        }
+    }
+
+    pub fn count(&self, id: usize) -> u32 {
+        self.counts.get(&(id as u32)).copied().unwrap_or(0)
+    }
+
+    /// Number of keys picked at least once.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// What the feedback adds to the weight of key `id`.
+    pub fn boost(&self, id: usize) -> f32 {
+        self.boost * (1.0 + self.count(id) as f32).ln()
+    }
+
+    /// The `k` best keys of `trie` starting with `prefix` once feedback
+    /// is added to their weights, as `(id, key, weight)`. Keys are taken
+    /// in static weight order only until none of the rest could overtake
+    /// the `k`th, so this stays as cheap as `top_k` unless feedback is
+    /// strong.
+    pub fn top_k(
+        &self,
+        trie: &WeightedTrie,
+        prefix: impl AsRef<str>,
+        k: usize,
+    ) -> Vec<(usize, String, f32)> {
+        let max_boost = self.boost * (1.0 + self.max_count as f32).ln();
+        trie.rerank(prefix.as_ref(), k, max_boost, |id, weight| {
+            weight + self.boost(id)
+        })
+    }
+
+    /// Writes the counts as `id count` lines, replacing `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let at = |err: std::io::Error| MarisaError::from(err).at("save", path);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut out = BufWriter::new(fs::File::create(&temp).map_err(at)?);
+        let mut counts: Vec<(&u32, &u32)> = self.counts.iter().collect();
+        counts.sort_unstable();
+        for (id, count) in counts {
+            writeln!(out, "{id} {count}").map_err(at)?;
+        }
+        out.into_inner()
+            .map_err(|err| at(err.into_error()))?
+            .sync_all()
+            .map_err(at)?;
+        fs::rename(&temp, path).map_err(at)
+    }
+
+    /// Adds the counts saved at `path` to the ones held.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let file = fs::File::open(path).map_err(|err| MarisaError::from(err).at("load", path))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let parsed = line
+                .split_once(' ')
+                .and_then(|(id, count)| Some((id.parse().ok()?, count.parse().ok()?)));
+            let Some((id, count)) = parsed else {
+                return Err(MarisaError::Parse {
+                    line: index + 1,
+                    details: format!("expected `id count`, found {line:?}"),
+                });
+            };
+            let total = self.counts.entry(id).or_default();
+            *total = total.saturating_add(count);
+            self.max_count = self.max_count.max(*total);
+        }
+        Ok(())
+    }
+
+    /// Saves to the persistence path when there are unsaved picks and the
+    /// interval has passed. Returns whether it saved.
+    pub fn save_if_due(&mut self) -> Result<bool> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(false);
+        };
+        if !persistence.dirty || persistence.last_save.elapsed() < persistence.interval {
+            return Ok(false);
+        }
+        self.save(&persistence.path)?;
+        let persistence = self.persistence.as_mut().expect("checked above");
+        persistence.last_save = Instant::now();
+        persistence.dirty = false;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FeedbackStore;
+    use crate::{error::MarisaError, weighted::WeightedTrie};
+
+    fn keys(top: Vec<(usize, String, f32)>) -> Vec<String> {
+        top.into_iter().map(|(_, key, _)| key).collect()
+    }
+
+    #[test]
+    fn picks_move_rankings() {
+        let trie =
+            WeightedTrie::build([("fufi", 5.0), ("fu", 4.0), ("fuj", 1.0), ("pes", 9.0)]).unwrap();
+        let mut feedback = FeedbackStore::new().with_boost(2.0);
+        assert_eq!(keys(feedback.top_k(&trie, "f", 2)), vec!["fufi", "fu"]);
+
+        let fuj = trie.keys().lookup("fuj").unwrap();
+        for _ in 0..10 {
+            feedback.record(fuj);
+        }
+        assert_eq!(feedback.count(fuj), 10);
+        assert_eq!(keys(feedback.top_k(&trie, "f", 2)), vec!["fuj", "fufi"]);
+        assert_eq!(keys(feedback.top_k(&trie, "f", 10)).len(), 3);
+        assert!(feedback.top_k(&trie, "f", 0).is_empty());
+        assert_eq!(trie.top_k("f", 1)[0].1, "fufi");
+    }
+
+    #[test]
+    fn persists_counts() {
+        let path = std::env::temp_dir().join(format!("marisa-feedback-{}", std::process::id()));
+        let mut feedback = FeedbackStore::new()
+            .with_persistence(&path, Duration::ZERO)
+            .unwrap();
+        assert!(!feedback.save_if_due().unwrap());
+        feedback.record(3);
+        feedback.record(3);
+        feedback.record(7);
+        assert!(feedback.save_if_due().unwrap());
+        assert!(!feedback.save_if_due().unwrap());
+
+        let loaded = FeedbackStore::new()
+            .with_persistence(&path, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!((loaded.count(3), loaded.count(7), loaded.len()), (2, 1, 2));
+
+        std::fs::write(&path, "1 2\nbad\n").unwrap();
+        assert!(matches!(
+            FeedbackStore::new().load(&path),
+            Err(MarisaError::Parse { line: 2, .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,433 @@
+//! Structural inspection of serialized MARISA images.
+//!
+//! The layout mirrors what libmarisa 0.2.x writes: a 16-byte header
+//! followed by one LOUDS trie per level. Every vector is stored as a 64-bit
+//! byte count, the elements, and padding up to the next 8-byte boundary, all
+//! in the byte order of the machine that built the dictionary.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    config::Config,
+    error::{MarisaError, Result},
+};
+
+pub const HEADER: &[u8; 16] = b"We love Marisa.\0";
+
+// Element sizes as seen by a 64-bit libmarisa.
+const UNIT_SIZE: u64 = 8;
+const RANK_INDEX_SIZE: u64 = 12;
+const SELECT_SIZE: u64 = 4;
+const CACHE_SIZE: u64 = 12;
+
+const MAX_NUM_TRIES: usize = 0x7F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitVectorInfo {
+    pub bytes: u64,
+    pub size: u32,
+    pub num_1s: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelInfo {
+    pub louds: BitVectorInfo,
+    pub terminal_flags: BitVectorInfo,
+    pub link_flags: BitVectorInfo,
+    pub bases_bytes: u64,
+    pub extras_bytes: u64,
+    pub tail_bytes: u64,
+    pub cache_bytes: u64,
+    pub num_l1_nodes: u32,
+    pub config_flags: u32,
+}
+
+impl LevelInfo {
+    /// Bytes taken by this level, not counting the tries nested in it.
+    pub fn bytes(&self) -> u64 {
+        self.louds.bytes
+            + self.terminal_flags.bytes
+            + self.link_flags.bytes
+            + self.bases_bytes
+            + self.extras_bytes
+            + self.tail_bytes
+            + self.cache_bytes
+            // num_l1_nodes and config_flags
+            + 8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub io_size: u64,
+    /// Outermost trie first.
+    pub levels: Vec<LevelInfo>,
+}
+
+impl ImageInfo {
+    pub fn config_flags(&self) -> u32 {
+        self.levels.first().map_or(0, |level| level.config_flags)
+    }
+
+    pub fn config(&self) -> Option<Config> {
+        Config::from_flags(self.config_flags())
+    }
+
+    /// Each key ends at one terminal node of the outermost trie.
+    pub fn num_keys(&self) -> usize {
+        self.levels
+            .first()
+            .map_or(0, |level| level.terminal_flags.num_1s as usize)
+    }
+}
+
+/// Walks the image at the reader's current position without loading it.
+pub fn inspect<R: Read + Seek>(reader: R) -> Result<ImageInfo> {
+    let mut reader = ImageReader::new(reader)?;
+    let mut levels = Vec::new();
+    reader.header()?;
+    reader.level(&mut levels)?;
+    Ok(ImageInfo {
+        io_size: reader.offset,
+        levels,
+    })
+}
+
+pub fn inspect_file(path: &Path) -> Result<ImageInfo> {
+    inspect(BufReader::new(File::open(path)?))
+}
+
+pub fn inspect_bytes(image: &[u8]) -> Result<ImageInfo> {
+    inspect(io::Cursor::new(image))
+}
+
+fn invalid(details: impl Into<String>) -> MarisaError {
+    MarisaError::incompatible(details)
+}
+
+struct ImageReader<R> {
+    reader: R,
+    start: u64,
+    offset: u64,
+    len: u64,
+}
+
+impl<R: Read + Seek> ImageReader<R> {
+    fn new(mut reader: R) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            reader,
+            start,
+            offset: 0,
+            len: end.saturating_sub(start),
+        })
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8], what: &str) -> Result<()> {
+        self.reader.read_exact(buf).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                invalid(format!("image truncated while reading {what}"))
+            } else {
+                MarisaError::from(err)
+            }
+        })?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf, what)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self, what: &str) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf, what)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn skip(&mut self, bytes: u64, what: &str) -> Result<()> {
+        if bytes > self.len - self.offset {
+            return Err(MarisaError::incompatible_with(
+                format!("{what} runs past the end of the image"),
+                format!("{bytes} bytes"),
+                format!("only {} remain", self.len - self.offset),
+            ));
+        }
+        self.offset += bytes;
+        self.reader
+            .seek(SeekFrom::Start(self.start + self.offset))?;
+        Ok(())
+    }
+
+    fn header(&mut self) -> Result<()> {
+        let mut header = [0; 16];
+        self.read_exact(&mut header, "header")?;
+        if &header != HEADER {
+            return Err(MarisaError::incompatible_with(
+                "missing \"We love Marisa.\" header",
+                format!("{:?}", HEADER.escape_ascii().to_string()),
+                format!("{:?}", header.escape_ascii().to_string()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the byte count of a vector whose elements are `elem` bytes.
+    fn vector(&mut self, elem: u64, what: &str) -> Result<u64> {
+        let total = self.u64(what)?;
+        if total > self.len - self.offset {
+            // A byte-swapped length is the typical sign of an image from a
+            // machine with the other endianness.
+            if total.swap_bytes() <= self.len - self.offset {
+                return Err(MarisaError::incompatible_with(
+                    format!(
+                        "{what} size is byte-swapped; the dictionary was built on a \
+                         machine with different endianness"
+                    ),
+                    "little-endian",
+                    "big-endian",
+                ));
+            }
+            return Err(MarisaError::incompatible_with(
+                format!("{what} runs past the end of the image"),
+                format!("{total} bytes"),
+                format!("only {} remain", self.len - self.offset),
+            ));
+        }
+        if total % elem != 0 {
+            if elem == UNIT_SIZE && total % 4 == 0 {
+                return Err(MarisaError::incompatible_with(
+                    format!(
+                        "{what} holds 32-bit units; the dictionary was built by a \
+                         32-bit libmarisa and cannot be read on a 64-bit one"
+                    ),
+                    "64-bit units",
+                    "32-bit units",
+                ));
+            }
+            return Err(invalid(format!(
+                "{what} size {total} is not a multiple of its {elem}-byte elements"
+            )));
+        }
+        let padding = (8 - total % 8) % 8;
+        self.skip(total + padding, what)?;
+        Ok(total)
+    }
+
+    fn bit_vector(&mut self, what: &str) -> Result<BitVectorInfo> {
+        let start = self.offset;
+        self.vector(UNIT_SIZE, what)?;
+        let size = self.u32(what)?;
+        let num_1s = self.u32(what)?;
+        if num_1s > size {
+            return Err(invalid(format!(
+                "{what} has {num_1s} set bits but only {size} bits"
+            )));
+        }
+        self.vector(RANK_INDEX_SIZE, what)?;
+        self.vector(SELECT_SIZE, what)?;
+        self.vector(SELECT_SIZE, what)?;
+        Ok(BitVectorInfo {
+            bytes: self.offset - start,
+            size,
+            num_1s,
+        })
+    }
+
+    fn flat_vector(&mut self, what: &str) -> Result<u64> {
+        let start = self.offset;
+        self.vector(UNIT_SIZE, what)?;
+        let value_size = self.u32(what)?;
+        if value_size > 32 {
+            return Err(invalid(format!("{what} has {value_size}-bit values")));
+        }
+        self.u32(what)?;
+        self.u64(what)?;
+        Ok(self.offset - start)
+    }
+
+    fn level(&mut self, levels: &mut Vec<LevelInfo>) -> Result<()> {
+        let depth = levels.len();
+        if depth >= MAX_NUM_TRIES {
+            return Err(invalid(format!("more than {MAX_NUM_TRIES} nested tries")));
+        }
+        let louds = self.bit_vector("louds")?;
+        let terminal_flags = self.bit_vector("terminal flags")?;
+        let link_flags = self.bit_vector("link flags")?;
+        let bases_bytes = {
+            let start = self.offset;
+            self.vector(1, "bases")?;
+            self.offset - start
+        };
+        let extras_bytes = self.flat_vector("extras")?;
+        let start = self.offset;
+        let tail_buf = self.vector(1, "tail")?;
+        self.bit_vector("tail end flags")?;
+        let tail_bytes = self.offset - start;
+
+        levels.push(LevelInfo {
+            louds,
+            terminal_flags,
+            link_flags,
+            bases_bytes,
+            extras_bytes,
+            tail_bytes,
+            cache_bytes: 0,
+            num_l1_nodes: 0,
+            config_flags: 0,
+        });
+        if link_flags.num_1s != 0 && tail_buf == 0 {
+            self.level(levels)?;
+        }
+
+        let start = self.offset;
+        self.vector(CACHE_SIZE, "cache")?;
+        let cache_bytes = self.offset - start;
+        let num_l1_nodes = self.u32("node count")?;
+        let config_flags = self.u32("config flags")?;
+        check_config(config_flags)?;
+
+        let level = &mut levels[depth];
+        level.cache_bytes = cache_bytes;
+        level.num_l1_nodes = num_l1_nodes;
+        level.config_flags = config_flags;
+        Ok(())
+    }
+}
+
+fn check_config(flags: u32) -> Result<()> {
+    let num_tries = flags & 0x7F;
+    let cache_level = flags & 0xF80;
+    let tail_mode = flags & 0xF000;
+    let node_order = flags & 0xF0000;
+    if num_tries == 0
+        || !matches!(cache_level, 0x80 | 0x100 | 0x200 | 0x400 | 0x800)
+        || !matches!(tail_mode, 0x1000 | 0x2000)
+        || !matches!(node_order, 0x10000 | 0x20000)
+        || flags & !0xFFFFF != 0
+    {
+        return Err(invalid(format!("invalid config flags {flags:#x}")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{inspect_bytes, HEADER};
+    use crate::error::MarisaError;
+
+    fn vector(out: &mut Vec<u8>, data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(data);
+        out.resize(out.len().div_ceil(8) * 8, 0);
+    }
+
+    fn bit_vector(out: &mut Vec<u8>, units: &[u8], size: u32, num_1s: u32) {
+        vector(out, units);
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&num_1s.to_le_bytes());
+        vector(out, &[]);
+        vector(out, &[]);
+        vector(out, &[]);
+    }
+
+    /// A minimal single-level image in the libmarisa layout.
+    pub(crate) fn image(louds_units: &[u8], tail: &[u8]) -> Vec<u8> {
+        let mut out = HEADER.to_vec();
+        bit_vector(&mut out, louds_units, 0, 0);
+        bit_vector(&mut out, &[], 0, 0);
+        bit_vector(&mut out, &[], 0, 0);
+        vector(&mut out, &[]);
+        vector(&mut out, &[]);
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        vector(&mut out, tail);
+        bit_vector(&mut out, &[], 0, 0);
+        vector(&mut out, &[]);
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0x21203u32.to_le_bytes());
+        out
+    }
+
+    fn details(result: Result<super::ImageInfo, MarisaError>) -> String {
+        match result {
+            Err(MarisaError::IncompatibleFormat { details, .. }) => details,
+            other => panic!("expected a format error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn inspects_minimal_image() {
+        let image = image(&[0; 8], b"tail");
+        let info = inspect_bytes(&image).unwrap();
+        assert_eq!(info.io_size, image.len() as u64);
+        assert_eq!(info.levels.len(), 1);
+        assert_eq!(info.config_flags(), 0x21203);
+        assert_eq!(info.levels[0].tail_bytes, 8 + 8 + 40);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut image = image(&[], b"");
+        image[0] = b'w';
+        match inspect_bytes(&image) {
+            Err(err @ MarisaError::IncompatibleFormat { .. }) => {
+                let message = err.to_string();
+                assert!(message.contains("header"), "{message}");
+                assert!(message.contains(r#"expected "We love"#), "{message}");
+                assert!(message.contains(r#"found "we love"#), "{message}");
+            }
+            other => panic!("expected a format error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_image() {
+        let image = image(&[0; 16], b"some tail");
+        assert!(details(inspect_bytes(&image[..image.len() - 3])).contains("truncated"));
+        assert!(details(inspect_bytes(&image[..30])).contains("past the end"));
+    }
+
+    #[test]
+    fn detects_32_bit_units() {
+        let image = image(&[0; 4], b"");
+        assert!(details(inspect_bytes(&image)).contains("32-bit"));
+    }
+
+    #[test]
+    fn detects_other_endianness() {
+        let mut image = image(&[0; 8], b"");
+        image[16..24].reverse();
+        assert!(details(inspect_bytes(&image)).contains("endianness"));
+    }
+
+    #[cfg(feature = "libmarisa")]
+    #[test]
+    fn inspects_saved_trie() {
+        use super::inspect_file;
+        use crate::marisa::{Keyset, Trie};
+
+        let mut keyset = Keyset::default();
+        keyset.push("abc", None).unwrap();
+        keyset.push("abd", None).unwrap();
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+
+        let path = std::env::temp_dir().join(format!("marisa-format-{}", std::process::id()));
+        trie.save(&path).unwrap();
+        let info = inspect_file(&path).unwrap();
+        assert_eq!(info.io_size, std::fs::metadata(&path).unwrap().len());
+        let levels: u64 = info.levels.iter().map(|level| level.bytes()).sum();
+        assert_eq!(HEADER.len() as u64 + levels, info.io_size);
+        std::fs::remove_file(path).unwrap();
+    }
+}
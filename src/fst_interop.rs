@@ -0,0 +1,69 @@
+use fst::{IntoStreamer, Set, Streamer};
+
+use crate::{
+    error::Result,
+    marisa::{Keyset, Trie},
+};
+
+impl Trie {
+    /// Collects every key into an `fst::Set`. Ids are not preserved: an fst
+    /// set has no notion of them.
+    pub fn to_fst_set(&self) -> Result<Set<Vec<u8>>> {
+        let mut keys: Vec<String> = self
+            .predictive_search("")
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        // fst needs its input in lexicographic byte order.
+        keys.sort_unstable();
+        Ok(Set::from_iter(keys)?)
+    }
+
+    pub fn from_fst_set<D: AsRef<[u8]>>(set: &Set<D>) -> Result<Trie> {
+        let mut keyset = Keyset::default();
+        let mut stream = set.into_stream();
+        while let Some(key) = stream.next() {
+            keyset.push(std::str::from_utf8(key)?, None)?;
+        }
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        Ok(trie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fst::Set;
+
+    use crate::{
+        error::MarisaError,
+        marisa::{Keyset, Trie},
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut keyset = Keyset::default();
+        for key in ["banana", "apple", "cherry", "äpfel"] {
+            keyset.push(key, None).unwrap();
+        }
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+
+        let set = trie.to_fst_set().unwrap();
+        let keys: Vec<String> = set.stream().into_strs().unwrap();
+        assert_eq!(keys, vec!["apple", "banana", "cherry", "äpfel"]);
+
+        let back = Trie::from_fst_set(&set).unwrap();
+        assert_eq!(back.num_keys(), 4);
+        assert!(back.lookup("äpfel").is_some());
+    }
+
+    #[test]
+    fn rejects_non_utf8_keys() {
+        let set = Set::from_iter([&b"\xff"[..]]).unwrap();
+        assert!(matches!(
+            Trie::from_fst_set(&set),
+            Err(MarisaError::Utf8(_))
+        ));
+    }
+}
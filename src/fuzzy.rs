@@ -0,0 +1,349 @@
+//! Approximate key matching by edit distance.
+//!
+//! libmarisa exposes no node-level traversal, so candidates are enumerated
+//! with a predictive search on the first characters of the query, which
+//! are taken to be typed correctly, and then filtered by distance.
+
+use std::collections::HashMap;
+
+use crate::marisa::Trie;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub id: usize,
+    pub key: String,
+    /// Levenshtein distance in characters.
+    pub distance: u32,
+}
+
+/// Levenshtein distance between `a` and `b`, or `None` once it is known to
+/// exceed `max`.
+pub(crate) fn distance(a: &[char], b: &[char], max: u32) -> Option<u32> {
+    if a.len().abs_diff(b.len()) > max as usize {
+        return None;
+    }
+    let mut row: Vec<u32> = (0..=b.len() as u32).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i as u32 + 1;
+        let mut best = row[0];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + (ca != cb) as u32;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+            best = best.min(row[j + 1]);
+        }
+        if best > max {
+            return None;
+        }
+    }
+    Some(row[b.len()]).filter(|&d| d <= max)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedMatch {
+    pub id: usize,
+    pub key: String,
+    /// Total cost of the cheapest edits turning the query into the key.
+    pub cost: f32,
+}
+
+/// Edit costs for [`Trie::fuzzy_search_weighted`]: a per-pair substitution
+/// cost, falling back to a default, and one cost for insertions and
+/// deletions. Pairs are symmetric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubstitutionCosts {
+    pairs: HashMap<(char, char), f32>,
+    substitution: f32,
+    indel: f32,
+}
+
+impl Default for SubstitutionCosts {
+    fn default() -> Self {
+        Self {
+            pairs: HashMap::new(),
+            substitution: 1.0,
+            indel: 1.0,
+        }
+    }
+}
+
+const QWERTY_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Pairs OCR commonly mistakes for one another.
+const OCR_CONFUSIONS: [(char, char); 12] = [
+    ('0', 'o'),
+    ('0', 'O'),
+    ('o', 'O'),
+    ('1', 'l'),
+    ('1', 'I'),
+    ('l', 'I'),
+    ('5', 'S'),
+    ('8', 'B'),
+    ('2', 'Z'),
+    ('6', 'b'),
+    ('c', 'e'),
+    ('u', 'v'),
+];
+
+impl SubstitutionCosts {
+    /// Every edit costs 1, as in plain Levenshtein distance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Substituting a key for one next to it on a QWERTY keyboard, in the
+    /// same row or diagonally in the row above or below, costs `cost`.
+    /// Letters are matched in both cases.
+    pub fn qwerty(cost: f32) -> Self {
+        let rows: Vec<Vec<char>> = QWERTY_ROWS
+            .iter()
+            .map(|row| row.chars().collect())
+            .collect();
+        let mut costs = Self::new();
+        for (r, row) in rows.iter().enumerate() {
+            for (i, &key) in row.iter().enumerate() {
+                let mut neighbors: Vec<char> = row.get(i + 1).copied().into_iter().collect();
+                // Each row is shifted about half a key right of the one above.
+                if let Some(below) = rows.get(r + 1) {
+                    neighbors.extend(below.get(i.wrapping_sub(1)).copied());
+                    neighbors.extend(below.get(i).copied());
+                }
+                for neighbor in neighbors {
+                    for (a, b) in [
+                        (key, neighbor),
+                        (key.to_ascii_uppercase(), neighbor.to_ascii_uppercase()),
+                    ] {
+                        costs = costs.with_cost(a, b, cost);
+                    }
+                }
+            }
+        }
+        costs
+    }
+
+    /// Substituting characters OCR often confuses, such as `0` and `O` or
+    /// `1` and `l`, costs `cost`.
+    pub fn ocr(cost: f32) -> Self {
+        OCR_CONFUSIONS
+            .iter()
+            .fold(Self::new(), |costs, &(a, b)| costs.with_cost(a, b, cost))
+    }
+
+    /// Substituting `a` for `b`, or `b` for `a`, costs `cost`.
+    pub fn with_cost(mut self, a: char, b: char, cost: f32) -> Self {
+        self.pairs.insert((a, b), cost);
+        self.pairs.insert((b, a), cost);
+        self
+    }
+
+    /// Cost of substituting a pair not given explicitly. Defaults to `1.0`.
+    pub fn with_default_substitution(mut self, cost: f32) -> Self {
+        self.substitution = cost;
+        self
+    }
+
+    /// Cost of inserting or deleting a character. Defaults to `1.0`.
+    pub fn with_indel(mut self, cost: f32) -> Self {
+        self.indel = cost;
+        self
+    }
+
+    /// Adds the pairs of `other`, keeping its cost where both have one.
+    pub fn merge(mut self, other: &Self) -> Self {
+        self.pairs.extend(&other.pairs);
+        self
+    }
+
+    pub fn substitution(&self, a: char, b: char) -> f32 {
+        if a == b {
+            0.0
+        } else {
+            self.pairs
+                .get(&(a, b))
+                .copied()
+                .unwrap_or(self.substitution)
+        }
+    }
+}
+
+/// Weighted edit distance between `a` and `b`, or `None` once it is known
+/// to exceed `max`.
+pub(crate) fn weighted_distance(
+    a: &[char],
+    b: &[char],
+    costs: &SubstitutionCosts,
+    max: f32,
+) -> Option<f32> {
+    let indel = costs.indel;
+    if a.len().abs_diff(b.len()) as f32 * indel > max {
+        return None;
+    }
+    let mut row: Vec<f32> = (0..=b.len()).map(|j| j as f32 * indel).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = (i + 1) as f32 * indel;
+        let mut best = row[0];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + costs.substitution(ca, cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + indel).min(diagonal + indel);
+            best = best.min(row[j + 1]);
+        }
+        if best > max {
+            return None;
+        }
+    }
+    Some(row[b.len()]).filter(|&d| d <= max)
+}
+
+impl Trie {
+    /// Keys within `max_distance` edits of `query` whose first
+    /// `prefix_len` characters match it exactly, closest first. A
+    /// `prefix_len` of 0 scans every key.
+    pub fn fuzzy_search(
+        &self,
+        query: impl AsRef<str>,
+        max_distance: u32,
+        prefix_len: usize,
+    ) -> Vec<FuzzyMatch> {
+        let query = query.as_ref();
+        let query: Vec<char> = query.chars().collect();
+        let prefix: String = query.iter().take(prefix_len).collect();
+        let mut matches = Vec::new();
+        let mut key_chars = Vec::new();
+        self.for_each_predictive(&prefix, |id, key| {
+            key_chars.clear();
+            key_chars.extend(key.chars());
+            if let Some(distance) = distance(&query, &key_chars, max_distance) {
+                matches.push(FuzzyMatch {
+                    id,
+                    key: key.to_owned(),
+                    distance,
+                });
+            }
+        });
+        matches.sort_by(|a, b| a.distance.cmp(&b.distance).then_with(|| a.key.cmp(&b.key)));
+        matches
+    }
+
+    /// Like [`Trie::fuzzy_search`], but with edits priced by `costs`, so
+    /// that likely typos rank above arbitrary ones. Keys within `max_cost`
+    /// are returned cheapest first.
+    pub fn fuzzy_search_weighted(
+        &self,
+        query: impl AsRef<str>,
+        costs: &SubstitutionCosts,
+        max_cost: f32,
+        prefix_len: usize,
+    ) -> Vec<WeightedMatch> {
+        let query: Vec<char> = query.as_ref().chars().collect();
+        let prefix: String = query.iter().take(prefix_len).collect();
+        let mut matches = Vec::new();
+        let mut key_chars = Vec::new();
+        self.for_each_predictive(&prefix, |id, key| {
+            key_chars.clear();
+            key_chars.extend(key.chars());
+            if let Some(cost) = weighted_distance(&query, &key_chars, costs, max_cost) {
+                matches.push(WeightedMatch {
+                    id,
+                    key: key.to_owned(),
+                    cost,
+                });
+            }
+        });
+        matches.sort_by(|a, b| a.cost.total_cmp(&b.cost).then_with(|| a.key.cmp(&b.key)));
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{distance, weighted_distance, SubstitutionCosts};
+    use crate::marisa::Trie;
+
+    #[test]
+    fn bounded_distance() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(distance(&chars("kitten"), &chars("sitting"), 3), Some(3));
+        assert_eq!(distance(&chars("kitten"), &chars("sitting"), 2), None);
+        assert_eq!(distance(&chars("かんじ"), &chars("かんじ"), 0), Some(0));
+        assert_eq!(distance(&chars(""), &chars("ab"), 1), None);
+    }
+
+    #[test]
+    fn fuzzy_search() {
+        let trie = Trie::try_from(&["apple", "apply", "ample", "maple", "app"][..]).unwrap();
+        let keys = |query: &str, max_distance: u32, prefix_len: usize| -> Vec<(String, u32)> {
+            trie.fuzzy_search(query, max_distance, prefix_len)
+                .into_iter()
+                .map(|m| (m.key, m.distance))
+                .collect()
+        };
+
+        assert_eq!(
+            keys("appel", 2, 1),
+            vec![
+                ("app".to_owned(), 2),
+                ("apple".to_owned(), 2),
+                ("apply".to_owned(), 2)
+            ]
+        );
+        assert_eq!(keys("aple", 1, 2), vec![("apple".to_owned(), 1)]);
+        assert_eq!(keys("mple", 1, 1), vec![("maple".to_owned(), 1)]);
+        assert_eq!(
+            keys("mple", 1, 0),
+            vec![("ample".to_owned(), 1), ("maple".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn substitution_costs() {
+        let qwerty = SubstitutionCosts::qwerty(0.5);
+        assert_eq!(qwerty.substitution('s', 'a'), 0.5);
+        assert_eq!(qwerty.substitution('s', 'w'), 0.5);
+        assert_eq!(qwerty.substitution('s', 'z'), 0.5);
+        assert_eq!(qwerty.substitution('S', 'D'), 0.5);
+        assert_eq!(qwerty.substitution('s', 'p'), 1.0);
+        assert_eq!(qwerty.substitution('s', 's'), 0.0);
+
+        let ocr = SubstitutionCosts::ocr(0.2).merge(&qwerty);
+        assert_eq!(ocr.substitution('l', '1'), 0.2);
+        assert_eq!(ocr.substitution('q', 'w'), 0.5);
+
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        let plain = SubstitutionCosts::new();
+        assert_eq!(
+            weighted_distance(&chars("kitten"), &chars("sitting"), &plain, 3.0),
+            Some(3.0)
+        );
+        assert_eq!(
+            weighted_distance(&chars("kitten"), &chars("sitting"), &plain, 2.5),
+            None
+        );
+        let cheap_indel = plain.with_indel(0.25);
+        assert_eq!(
+            weighted_distance(&chars("ab"), &chars("abcd"), &cheap_indel, 1.0),
+            Some(0.5)
+        );
+    }
+
+    #[test]
+    fn adjacent_typos_rank_first() {
+        let trie = Trie::try_from(&["cat", "car", "cap", "cut"][..]).unwrap();
+        let keys: Vec<(String, f32)> = trie
+            .fuzzy_search_weighted("cay", &SubstitutionCosts::qwerty(0.5), 1.0, 1)
+            .into_iter()
+            .map(|m| (m.key, m.cost))
+            .collect();
+        // t sits next to y; p, r and the u in "cut" don't.
+        assert_eq!(
+            keys,
+            vec![
+                ("cat".to_owned(), 0.5),
+                ("cap".to_owned(), 1.0),
+                ("car".to_owned(), 1.0)
+            ]
+        );
+    }
+}
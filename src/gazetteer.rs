@@ -0,0 +1,130 @@
+//! Dictionary matching over pre-tokenized text, as used for gazetteer
+//! features in named-entity recognition.
+
+use crate::marisa::Trie;
+
+/// Tokens `start..end` form the dictionary entry `id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpanMatch {
+    pub start: usize,
+    pub end: usize,
+    pub id: usize,
+}
+
+/// Finds entries made of consecutive tokens. Entries are keys of the trie
+/// with their tokens joined by the separator, e.g. `"new york city"` with
+/// the default `" "`.
+pub struct Gazetteer {
+    entries: Trie,
+    separator: String,
+    max_tokens: usize,
+}
+
+impl Gazetteer {
+    pub fn new(entries: Trie) -> Self {
+        Self {
+            entries,
+            separator: " ".to_owned(),
+            max_tokens: 8,
+        }
+    }
+
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_owned();
+        self
+    }
+
+    /// Longest entry, in tokens, to look for. Each start position joins up
+    /// to this many tokens for one common prefix search.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn entries(&self) -> &Trie {
+        &self.entries
+    }
+
+    /// Every match, overlapping ones included, ordered by start and then
+    /// by length.
+    pub fn find_all(&self, tokens: &[&str]) -> Vec<SpanMatch> {
+        let mut matches = Vec::new();
+        let mut joined = String::new();
+        // Byte offset in `joined` where each token ends.
+        let mut ends = Vec::new();
+        for start in 0..tokens.len() {
+            joined.clear();
+            ends.clear();
+            for token in tokens[start..].iter().take(self.max_tokens) {
+                if !joined.is_empty() {
+                    joined.push_str(&self.separator);
+                }
+                joined.push_str(token);
+                ends.push(joined.len());
+            }
+            self.entries.for_each_common_prefix(&joined, |id, key| {
+                // Keys ending inside a token don't count.
+                if let Ok(i) = ends.binary_search(&key.len()) {
+                    matches.push(SpanMatch {
+                        start,
+                        end: start + i + 1,
+                        id,
+                    });
+                }
+            });
+        }
+        matches
+    }
+
+    /// Non-overlapping matches, taking the longest match at the leftmost
+    /// position and continuing after it.
+    pub fn find_longest(&self, tokens: &[&str]) -> Vec<SpanMatch> {
+        let mut longest: Vec<SpanMatch> = Vec::new();
+        for m in self.find_all(tokens) {
+            match longest.last_mut() {
+                Some(last) if last.start == m.start => *last = m,
+                Some(last) if m.start < last.end => {}
+                _ => longest.push(m),
+            }
+        }
+        longest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gazetteer, SpanMatch};
+    use crate::marisa::Trie;
+
+    fn spans(matches: Vec<SpanMatch>) -> Vec<(usize, usize)> {
+        matches.into_iter().map(|m| (m.start, m.end)).collect()
+    }
+
+    #[test]
+    fn token_spans() {
+        let gazetteer = Gazetteer::new(
+            Trie::try_from(&["new york", "new york city", "york", "city hall", "ne"][..]).unwrap(),
+        );
+        let tokens = ["in", "new", "york", "city", "hall"];
+
+        assert_eq!(
+            spans(gazetteer.find_all(&tokens)),
+            vec![(1, 3), (1, 4), (2, 3), (3, 5)]
+        );
+        assert_eq!(spans(gazetteer.find_longest(&tokens)), vec![(1, 4)]);
+
+        let m = gazetteer.find_longest(&tokens)[0];
+        assert_eq!(
+            gazetteer.entries().reverse_lookup(m.id).as_deref(),
+            Some("new york city")
+        );
+        assert_eq!(gazetteer.with_max_tokens(1).find_all(&tokens).len(), 1);
+    }
+
+    #[test]
+    fn custom_separator() {
+        let gazetteer =
+            Gazetteer::new(Trie::try_from(&["new_york"][..]).unwrap()).with_separator("_");
+        assert_eq!(spans(gazetteer.find_all(&["new", "york"])), vec![(0, 2)]);
+    }
+}
@@ -0,0 +1,78 @@
+//! Locating dictionary keys in running text, for highlighting and
+//! content filters.
+
+use crate::marisa::Trie;
+
+/// The key `id` occurs at bytes `start..end` of the searched text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub id: usize,
+}
+
+impl Trie {
+    /// Every occurrence of every key in `text`, overlapping ones included,
+    /// ordered by start and then by length. Matches start on character
+    /// boundaries; the empty key never matches.
+    pub fn find_all(&self, text: impl AsRef<str>) -> Vec<Match> {
+        let text = text.as_ref();
+        let mut matches = Vec::new();
+        for (start, _) in text.char_indices() {
+            self.for_each_common_prefix(&text[start..], |id, key| {
+                if !key.is_empty() {
+                    matches.push(Match {
+                        start,
+                        end: start + key.len(),
+                        id,
+                    });
+                }
+            });
+        }
+        matches
+    }
+
+    /// Non-overlapping occurrences, taking the longest key at the leftmost
+    /// position and continuing after it.
+    pub fn find_longest(&self, text: impl AsRef<str>) -> Vec<Match> {
+        let text = text.as_ref();
+        let mut longest: Vec<Match> = Vec::new();
+        for m in self.find_all(text) {
+            match longest.last_mut() {
+                Some(last) if last.start == m.start => *last = m,
+                Some(last) if m.start < last.end => {}
+                _ => longest.push(m),
+            }
+        }
+        longest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Match;
+    use crate::marisa::Trie;
+
+    fn spans(text: &str, matches: Vec<Match>) -> Vec<&str> {
+        matches.into_iter().map(|m| &text[m.start..m.end]).collect()
+    }
+
+    #[test]
+    fn all_and_leftmost_longest() {
+        let trie = Trie::try_from(&["he", "hers", "she", "his", "č"][..]).unwrap();
+        let text = "ushers čhis";
+
+        assert_eq!(
+            spans(text, trie.find_all(text)),
+            vec!["she", "he", "hers", "č", "his"]
+        );
+        assert_eq!(
+            spans(text, trie.find_longest(text)),
+            vec!["she", "č", "his"]
+        );
+
+        let m = trie.find_longest(text)[0];
+        assert_eq!(trie.reverse_lookup(m.id).as_deref(), Some("she"));
+        assert!(trie.find_all("nothing").is_empty());
+    }
+}
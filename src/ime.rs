@@ -0,0 +1,247 @@
+//! Candidate generation for input methods: a trie keyed by reading, the
+//! surface forms and scores for each reading alongside it, and a single
+//! [`Ime::candidates`] call combining exact, completion and fuzzy matches.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    error::Result,
+    marisa::{Keyset, Trie},
+    pipeline::QueryPipeline,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchKind {
+    /// The reading equals the input.
+    Exact,
+    /// The reading starts with the input.
+    Completion,
+    /// The reading is within the configured edit distance of the input.
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub surface: String,
+    pub reading: String,
+    /// The entry's score times the weight for its match kind.
+    pub score: f32,
+    pub kind: MatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImeConfig {
+    /// Score multiplier for completions.
+    pub completion_weight: f32,
+    /// Score multiplier per edit for fuzzy matches.
+    pub fuzzy_weight: f32,
+    /// Largest edit distance of a fuzzy match; 0 turns fuzzy matching off.
+    pub max_distance: u32,
+    /// Leading characters a fuzzy match must share with the input.
+    pub fuzzy_prefix_len: usize,
+}
+
+impl Default for ImeConfig {
+    fn default() -> Self {
+        Self {
+            completion_weight: 0.5,
+            fuzzy_weight: 0.25,
+            max_distance: 1,
+            fuzzy_prefix_len: 1,
+        }
+    }
+}
+
+pub struct Ime {
+    readings: Trie,
+    /// Surfaces and scores, indexed by reading id.
+    surfaces: Vec<Vec<(String, f32)>>,
+    config: ImeConfig,
+    pipeline: QueryPipeline,
+}
+
+impl Ime {
+    /// Builds from `(reading, surface, score)` entries. A reading may have
+    /// any number of surfaces; a repeated pair keeps the higher score.
+    pub fn build<R, S>(
+        entries: impl IntoIterator<Item = (R, S, f32)>,
+        config: ImeConfig,
+    ) -> Result<Ime>
+    where
+        R: Into<String>,
+        S: Into<String>,
+    {
+        let mut grouped: BTreeMap<String, HashMap<String, f32>> = BTreeMap::new();
+        for (reading, surface, score) in entries {
+            let best = grouped
+                .entry(reading.into())
+                .or_default()
+                .entry(surface.into())
+                .or_insert(score);
+            *best = best.max(score);
+        }
+
+        let mut keyset = Keyset::default();
+        for reading in grouped.keys() {
+            keyset.push(reading, None)?;
+        }
+        let readings = Trie::build_from(keyset)?;
+        let mut surfaces = vec![Vec::new(); readings.num_keys()];
+        for (reading, entries) in grouped {
+            let id = readings.lookup(&reading).expect("reading was just added");
+            let mut entries: Vec<(String, f32)> = entries.into_iter().collect();
+            entries.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            surfaces[id] = entries;
+        }
+        Ok(Ime {
+            readings,
+            surfaces,
+            config,
+            pipeline: QueryPipeline::default(),
+        })
+    }
+
+    pub fn config(&self) -> &ImeConfig {
+        &self.config
+    }
+
+    /// Runs `pipeline` on the input of [`Ime::candidates`] and on the
+    /// reading of every candidate, dropping candidates whose reading a
+    /// hook drops.
+    pub fn with_pipeline(mut self, pipeline: QueryPipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// The `k` best candidates for `input`, highest score first. A surface
+    /// reachable through several readings appears once, with its best score.
+    pub fn candidates(&self, input: impl AsRef<str>, k: usize) -> Vec<Candidate> {
+        let input = &self.pipeline.preprocess(input.as_ref());
+        // Reading id, score multiplier and match kind.
+        let mut matches: Vec<(usize, f32, MatchKind)> = Vec::new();
+        let exact = self.readings.lookup(input);
+        if let Some(id) = exact {
+            matches.push((id, 1.0, MatchKind::Exact));
+        }
+        self.readings.for_each_predictive(input, |id, _| {
+            if Some(id) != exact {
+                matches.push((id, self.config.completion_weight, MatchKind::Completion));
+            }
+        });
+        if self.config.max_distance > 0 {
+            for found in self.readings.fuzzy_search(
+                input,
+                self.config.max_distance,
+                self.config.fuzzy_prefix_len,
+            ) {
+                if found.distance > 0 {
+                    let weight = self.config.fuzzy_weight.powi(found.distance as i32);
+                    matches.push((found.id, weight, MatchKind::Fuzzy));
+                }
+            }
+        }
+
+        let mut best: HashMap<&str, (usize, f32, MatchKind)> = HashMap::new();
+        for (id, weight, kind) in matches {
+            for (surface, score) in &self.surfaces[id] {
+                let candidate = (id, score * weight, kind);
+                match best.get(surface.as_str()) {
+                    Some(&(_, existing, _)) if existing >= candidate.1 => {}
+                    _ => {
+                        best.insert(surface, candidate);
+                    }
+                }
+            }
+        }
+        let mut ranked: Vec<(&str, (usize, f32, MatchKind))> = best.into_iter().collect();
+        ranked.sort_by(|a, b| b.1 .1.total_cmp(&a.1 .1).then_with(|| a.0.cmp(b.0)));
+        ranked
+            .into_iter()
+            .filter_map(|(surface, (id, score, kind))| {
+                let reading = self.readings.reverse_lookup(id).unwrap_or_default();
+                Some(Candidate {
+                    surface: surface.to_owned(),
+                    reading: self.pipeline.postprocess(&reading)?,
+                    score,
+                    kind,
+                })
+            })
+            .take(k)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ime, ImeConfig, MatchKind};
+    use crate::pipeline::QueryPipeline;
+
+    fn ime() -> Ime {
+        Ime::build(
+            [
+                ("かん", "缶", 3.0),
+                ("かん", "感", 5.0),
+                ("かんじ", "漢字", 10.0),
+                ("かんじ", "感じ", 4.0),
+                ("かし", "菓子", 2.0),
+                ("かし", "菓子", 6.0),
+            ],
+            ImeConfig::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ranks_exact_completion_and_fuzzy() {
+        let ime = ime();
+        let candidates = ime.candidates("かん", 10);
+        let surfaces: Vec<(&str, MatchKind)> = candidates
+            .iter()
+            .map(|c| (c.surface.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            surfaces,
+            vec![
+                ("感", MatchKind::Exact),
+                ("漢字", MatchKind::Completion),
+                ("缶", MatchKind::Exact),
+                ("感じ", MatchKind::Completion),
+                ("菓子", MatchKind::Fuzzy),
+            ]
+        );
+        assert_eq!(candidates[1].reading, "かんじ");
+        assert_eq!(candidates[4].score, 6.0 * 0.25);
+
+        assert_eq!(ime.candidates("かん", 2).len(), 2);
+        assert!(ime.candidates("さ", 10).is_empty());
+    }
+
+    #[test]
+    fn fuzzy_can_be_disabled() {
+        let ime = Ime::build(
+            [("かし", "菓子", 1.0)],
+            ImeConfig {
+                max_distance: 0,
+                ..ImeConfig::default()
+            },
+        )
+        .unwrap();
+        assert!(ime.candidates("かじ", 10).is_empty());
+        assert_eq!(ime.candidates("かし", 10).len(), 1);
+    }
+
+    #[test]
+    fn pipeline_wraps_candidates() {
+        let ime = ime().with_pipeline(
+            QueryPipeline::new()
+                .trim()
+                .filter_result(|reading| reading != "かんじ"),
+        );
+        let surfaces: Vec<String> = ime
+            .candidates(" かん ", 2)
+            .into_iter()
+            .map(|c| c.surface)
+            .collect();
+        assert_eq!(surfaces, vec!["感", "缶"]);
+    }
+}
@@ -0,0 +1,446 @@
+//! Loaders turning existing dictionary assets into a `Keyset`.
+//!
+//! The `load_*` functions decompress `.gz` and `.zst` files on the fly when
+//! the `gzip` or `zstd` feature is enabled.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::Keyset,
+};
+
+fn parse_error(line: usize, details: impl Into<String>) -> MarisaError {
+    MarisaError::Parse {
+        line,
+        details: details.into(),
+    }
+}
+
+/// Reads the `marisa-build` input format: one key per line, optionally
+/// followed by a tab and a weight. A key the keyset rejects is a `Parse`
+/// error on its line.
+pub fn read_wordlist<R: BufRead>(reader: R) -> Result<Keyset> {
+    let mut keyset = Keyset::default();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if line.is_empty() {
+            continue;
+        }
+        match line.rsplit_once('\t') {
+            Some((key, weight)) => {
+                let weight = weight
+                    .parse::<f32>()
+                    .map_err(|err| parse_error(index + 1, format!("invalid weight: {err}")))?;
+                keyset.push(key, Some(weight))
+            }
+            None => keyset.push(line, None),
+        }
+        .map_err(|err| parse_error(index + 1, err.to_string()))?;
+    }
+    Ok(keyset)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunspellEntry {
+    pub word: String,
+    /// Affix flags as written after the `/`, undecoded.
+    pub flags: Option<String>,
+}
+
+/// Reads a Hunspell `.dic` word list. The leading word count is skipped and
+/// morphological fields after the word are ignored.
+pub fn read_hunspell_dic<R: BufRead>(reader: R) -> Result<Vec<HunspellEntry>> {
+    Ok(read_numbered_hunspell_dic(reader)?
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .collect())
+}
+
+/// Like `read_hunspell_dic`, with the 1-based line number of each entry.
+fn read_numbered_hunspell_dic<R: BufRead>(reader: R) -> Result<Vec<(usize, HunspellEntry)>> {
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if index == 0 && line.trim().parse::<usize>().is_ok() {
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with('\t') {
+            continue;
+        }
+        entries.push((index + 1, parse_hunspell_line(line)));
+    }
+    Ok(entries)
+}
+
+fn parse_hunspell_line(line: &str) -> HunspellEntry {
+    let mut word = String::new();
+    let mut flags = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('/') => word.push('/'),
+                Some(other) => {
+                    word.push('\\');
+                    word.push(other);
+                }
+                None => word.push('\\'),
+            },
+            '/' => {
+                let rest: String = chars.by_ref().take_while(|c| !c.is_whitespace()).collect();
+                flags = Some(rest);
+                break;
+            }
+            '\t' | ' ' => break,
+            c => word.push(c),
+        }
+    }
+    HunspellEntry { word, flags }
+}
+
+/// Reads a Hunspell `.dic` file into a keyset, dropping affix flags. A word
+/// the keyset rejects, such as the empty word of a `/AB` line, is a
+/// `Parse` error on its line.
+pub fn hunspell_keyset<R: BufRead>(reader: R) -> Result<Keyset> {
+    let mut keyset = Keyset::default();
+    for (line, entry) in read_numbered_hunspell_dic(reader)? {
+        keyset
+            .push(&entry.word, None)
+            .map_err(|err| parse_error(line, err.to_string()))?;
+    }
+    Ok(keyset)
+}
+
+// dawgdic dictionary units, see dawgdic/dictionary-unit.h.
+const IS_LEAF_BIT: u32 = 1 << 31;
+const HAS_LEAF_BIT: u32 = 1 << 8;
+const EXTENSION_BIT: u32 = 1 << 9;
+const MAX_KEY_LENGTH: usize = 1 << 16;
+
+fn offset(unit: u32) -> u32 {
+    (unit >> 10) << ((unit & EXTENSION_BIT) >> 6)
+}
+
+fn dawgdic_error(details: impl Into<String>) -> MarisaError {
+    MarisaError::incompatible(format!("dawgdic: {}", details.into()))
+}
+
+/// Reads a dawgdic `Dictionary` (as written by `Dictionary::Write`) and
+/// returns every key with its stored value.
+pub fn read_dawgdic<R: Read>(mut reader: R) -> Result<Vec<(String, i32)>> {
+    let mut buf = [0; 4];
+    reader
+        .read_exact(&mut buf)
+        .map_err(|_| dawgdic_error("missing unit count"))?;
+    let size = u32::from_le_bytes(buf) as usize;
+    let mut bytes = Vec::new();
+    reader.take(size as u64 * 4).read_to_end(&mut bytes)?;
+    if bytes.len() != size * 4 {
+        return Err(dawgdic_error(format!(
+            "expected {size} units, found {}",
+            bytes.len() / 4
+        )));
+    }
+    let units: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    if units.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    collect_dawgdic(&units)
+}
+
+// Walks the units depth first with an explicit stack, so a deep or looping
+// file can't overflow the call stack. Every unit has one parent in a valid
+// dictionary; one reached twice means the file loops back on itself.
+fn collect_dawgdic(units: &[u32]) -> Result<Vec<(String, i32)>> {
+    let mut keys = Vec::new();
+    let mut key = Vec::new();
+    let mut visited = vec![false; units.len()];
+    // (base of the unit's children, next label to try)
+    let mut stack = Vec::new();
+    let mut index = 0u32;
+    loop {
+        if std::mem::replace(&mut visited[index as usize], true) {
+            return Err(parse_error(
+                0,
+                format!("dawgdic: unit {index} reached twice; the file is corrupted"),
+            ));
+        }
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(dawgdic_error(
+                "key longer than 64 KiB; the file is corrupted",
+            ));
+        }
+        let unit = units[index as usize];
+        let base = index ^ offset(unit);
+        if unit & HAS_LEAF_BIT != 0 {
+            let leaf = *units
+                .get(base as usize)
+                .ok_or_else(|| dawgdic_error("leaf index out of range"))?;
+            let word = String::from_utf8(key.clone()).map_err(|err| err.utf8_error())?;
+            keys.push((word, (leaf & !IS_LEAF_BIT) as i32));
+        }
+        stack.push((base, 1u16));
+
+        // Find the next child of the deepest unit that still has one.
+        loop {
+            let Some((base, label)) = stack.last_mut() else {
+                return Ok(keys);
+            };
+            let child = (*label..=255).find(|&label| {
+                matches!(
+                    units.get((*base ^ label as u32) as usize),
+                    Some(&child) if child & (IS_LEAF_BIT | 0xFF) == label as u32
+                )
+            });
+            match child {
+                Some(found) => {
+                    *label = found + 1;
+                    index = *base ^ found as u32;
+                    key.push(found as u8);
+                    break;
+                }
+                None => {
+                    stack.pop();
+                    key.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Reads a dawgdic dictionary into a keyset, using the stored values as
+/// weights.
+pub fn dawgdic_keyset<R: Read>(reader: R) -> Result<Keyset> {
+    let mut keyset = Keyset::default();
+    for (key, value) in read_dawgdic(reader)? {
+        keyset.push(&key, Some(value as f32))?;
+    }
+    Ok(keyset)
+}
+
+/// Opens `path`, decompressing it according to its extension.
+pub fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    match extension {
+        #[cfg(feature = "gzip")]
+        Some("gz") => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            file,
+        )))),
+        #[cfg(feature = "zstd")]
+        Some("zst") => Ok(Box::new(BufReader::new(zstd::Decoder::new(file)?))),
+        #[cfg(not(feature = "gzip"))]
+        Some("gz") => Err(unsupported("gzip")),
+        #[cfg(not(feature = "zstd"))]
+        Some("zst") => Err(unsupported("zstd")),
+        _ => Ok(Box::new(BufReader::new(file))),
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(feature: &str) -> MarisaError {
+    MarisaError::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("reading compressed input requires the `{feature}` feature"),
+    ))
+}
+
+pub fn load_wordlist(path: &Path) -> Result<Keyset> {
+    read_wordlist(open(path)?)
+}
+
+pub fn load_hunspell_dic(path: &Path) -> Result<Keyset> {
+    hunspell_keyset(open(path)?)
+}
+
+pub fn load_dawgdic(path: &Path) -> Result<Keyset> {
+    dawgdic_keyset(open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        hunspell_keyset, load_wordlist, read_dawgdic, read_hunspell_dic, read_wordlist,
+        HunspellEntry,
+    };
+    use crate::{error::MarisaError, test_util::temp_path};
+
+    #[test]
+    fn wordlist_with_weights() {
+        let keyset = read_wordlist(&b"apple\t2.5\nbanana\r\n\ncherry\t1\n"[..]).unwrap();
+        assert_eq!(keyset.num_keys(), 3);
+        assert_eq!(keyset.at(0).as_str().unwrap(), "apple");
+        assert_eq!(keyset.at(0).weight(), 2.5);
+        assert_eq!(keyset.at(1).as_str().unwrap(), "banana");
+        assert_eq!(keyset.at(1).weight(), 1.0);
+
+        assert!(matches!(
+            read_wordlist(&b"ok\nbad\tweight\n"[..]),
+            Err(MarisaError::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            read_wordlist(&b"ok\n\n\t2\n"[..]),
+            Err(MarisaError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn hunspell_entries() {
+        let dic = "4\nhello/AB\nand/or\\/slash\nplain\tpo:noun\nworld/X st:world\n";
+        let entries = read_hunspell_dic(dic.as_bytes()).unwrap();
+        let entry = |word: &str, flags: Option<&str>| HunspellEntry {
+            word: word.to_owned(),
+            flags: flags.map(str::to_owned),
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry("hello", Some("AB")),
+                entry("and", Some("or\\/slash")),
+                entry("plain", None),
+                entry("world", Some("X")),
+            ]
+        );
+        let escaped = read_hunspell_dic(&b"1\nTCP\\/IP/M\n"[..]).unwrap();
+        assert_eq!(escaped, vec![entry("TCP/IP", Some("M"))]);
+
+        assert_eq!(hunspell_keyset(dic.as_bytes()).unwrap().num_keys(), 4);
+        assert!(matches!(
+            hunspell_keyset(
+                &b"2
+hello
+/AB
+"[..]
+            ),
+            Err(MarisaError::Parse { line: 3, .. })
+        ));
+    }
+
+    // Units laid out by hand the way dawgdic's DictionaryBuilder would for
+    // the keys "a" => 7 and "ab" => 9.
+    fn dawgdic_image() -> Vec<u8> {
+        let unit = |offset: u32, flags: u32| (offset << 10) | flags;
+        let mut units = vec![0u32; 8];
+        // Root at 0 with children based at 0 ^ 2 = 2.
+        units[0] = unit(2, 0);
+        // 'a' (0x61) lives at 2 ^ 0x61 = 0x63, which needs a larger array.
+        units.resize(0x80, 0);
+        units[0x63] = unit(0x63 ^ 0x10, 0x61 | 1 << 8);
+        // Leaf for "a" at 0x63 ^ offset = 0x10; children of "a" based there too.
+        units[0x10] = 7 | 1 << 31;
+        // 'b' (0x62) at 0x10 ^ 0x62 = 0x72.
+        units[0x72] = unit(0x72 ^ 0x11, 0x62 | 1 << 8);
+        units[0x11] = 9 | 1 << 31;
+
+        let mut bytes = (units.len() as u32).to_le_bytes().to_vec();
+        for unit in units {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn dawgdic_keys_and_values() {
+        let keys = read_dawgdic(&dawgdic_image()[..]).unwrap();
+        assert_eq!(keys, vec![("a".to_owned(), 7), ("ab".to_owned(), 9)]);
+    }
+
+    fn dawgdic_bytes(units: &[u32]) -> Vec<u8> {
+        let mut bytes = (units.len() as u32).to_le_bytes().to_vec();
+        for unit in units {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn looping_dawgdic() {
+        // 'a' at 0x63 bases its children back at 2, so it is its own child.
+        let mut units = vec![0u32; 0x80];
+        units[0] = 2 << 10;
+        units[0x63] = (0x63 ^ 2) << 10 | 0x61;
+        assert!(matches!(
+            read_dawgdic(&dawgdic_bytes(&units)[..]),
+            Err(MarisaError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn deep_dawgdic() {
+        // A chain of 'a's longer than any key may be; unit n's child is n + 1.
+        let units: Vec<u32> = (0..70_000u32)
+            .map(|n| (n ^ (n + 1) ^ 0x61) << 10 | if n == 0 { 0 } else { 0x61 })
+            .collect();
+        assert!(matches!(
+            read_dawgdic(&dawgdic_bytes(&units)[..]),
+            Err(MarisaError::IncompatibleFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn truncated_dawgdic() {
+        let image = dawgdic_image();
+        assert!(matches!(
+            read_dawgdic(&image[..image.len() - 2]),
+            Err(MarisaError::IncompatibleFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn plain_file() {
+        let path = temp_path("import-words.txt");
+        std::fs::write(&path, "a\nb\n").unwrap();
+        assert_eq!(load_wordlist(&path).unwrap().num_keys(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_file() {
+        use std::io::Write;
+
+        let path = temp_path("import-words.txt.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"a\t2\nb\nc\n").unwrap();
+        encoder.finish().unwrap();
+
+        let keyset = load_wordlist(&path).unwrap();
+        assert_eq!(keyset.num_keys(), 3);
+        assert_eq!(keyset.at(0).weight(), 2.0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_file() {
+        let path = temp_path("import-words.txt.zst");
+        let compressed = zstd::encode_all(&b"x\ny\n"[..], 0).unwrap();
+        std::fs::write(&path, compressed).unwrap();
+
+        assert_eq!(load_wordlist(&path).unwrap().num_keys(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn gzip_needs_feature() {
+        let path = temp_path("import-missing-feature.gz");
+        std::fs::write(&path, b"").unwrap();
+        assert!(matches!(load_wordlist(&path), Err(MarisaError::Io(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Longest-prefix matching of IP addresses, for routing, geo and ASN
+//! tables.
+//!
+//! Prefixes are stored one character per bit (`'0'` or `'1'`) after a
+//! family tag, so a `/n` prefix is an `n + 1`-byte key and the prefixes of
+//! an address are found with a single common prefix search. libmarisa's
+//! tail and LOUDS compression absorb most of the redundancy.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{error::Result, map::TrieMap};
+
+/// An address with all bits past `len` cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpPrefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl IpPrefix {
+    /// `None` if `len` is longer than the address.
+    pub fn new(addr: IpAddr, len: u8) -> Option<Self> {
+        let addr = match addr {
+            IpAddr::V4(v4) if len <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+            }
+            IpAddr::V6(v6) if len <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+            }
+            _ => return None,
+        };
+        Some(Self { addr, len })
+    }
+
+    /// Parses CIDR notation such as `10.0.0.0/8` or `2001:db8::/32`.
+    pub fn parse(cidr: &str) -> Option<Self> {
+        let (addr, len) = cidr.split_once('/')?;
+        Self::new(addr.parse().ok()?, len.parse().ok()?)
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    /// Whether this is a zero-length prefix, matching every address of its
+    /// family.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn key(&self) -> String {
+        let mut key = bits(self.addr);
+        key.truncate(1 + self.len as usize);
+        key
+    }
+}
+
+impl std::fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+fn bits(addr: IpAddr) -> String {
+    let (tag, value, width) = match addr {
+        IpAddr::V4(v4) => ('4', u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => ('6', u128::from(v6), 128),
+    };
+    let mut key = String::with_capacity(1 + width);
+    key.push(tag);
+    for i in (0..width).rev() {
+        key.push(if value >> i & 1 == 1 { '1' } else { '0' });
+    }
+    key
+}
+
+/// Prefixes with a payload each, mixing IPv4 and IPv6.
+pub struct IpTrie<T> {
+    prefixes: TrieMap<T>,
+}
+
+impl<T> IpTrie<T> {
+    /// Builds from `(prefix, payload)` entries; for a prefix given more
+    /// than once, the last payload is kept.
+    pub fn new(entries: impl IntoIterator<Item = (IpPrefix, T)>) -> Result<Self> {
+        Ok(Self {
+            prefixes: TrieMap::new(
+                entries
+                    .into_iter()
+                    .map(|(prefix, payload)| (prefix.key(), payload)),
+            )?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The longest stored prefix containing `addr`.
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(IpPrefix, &T)> {
+        let (len, payload) = self.prefixes.longest_prefix(bits(addr))?;
+        Some((IpPrefix::new(addr, (len - 1) as u8)?, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IpPrefix, IpTrie};
+
+    fn prefix(cidr: &str) -> IpPrefix {
+        IpPrefix::parse(cidr).unwrap()
+    }
+
+    #[test]
+    fn prefixes() {
+        let p = prefix("10.1.2.3/8");
+        assert_eq!(p.to_string(), "10.0.0.0/8");
+        assert_eq!(prefix("0.0.0.0/0").len(), 0);
+        assert_eq!(prefix("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(IpPrefix::parse("10.0.0.0/33"), None);
+        assert_eq!(IpPrefix::parse("10.0.0.0"), None);
+    }
+
+    #[test]
+    fn longest_match() {
+        let table = IpTrie::new([
+            (prefix("0.0.0.0/0"), "default"),
+            (prefix("10.0.0.0/8"), "private"),
+            (prefix("10.1.0.0/16"), "office"),
+            (prefix("2001:db8::/32"), "docs"),
+            (prefix("10.1.0.0/16"), "campus"),
+        ])
+        .unwrap();
+        assert_eq!(table.len(), 4);
+
+        let lookup = |addr: &str| {
+            table
+                .longest_match(addr.parse().unwrap())
+                .map(|(prefix, payload)| (prefix.to_string(), *payload))
+        };
+        assert_eq!(
+            lookup("10.1.200.7"),
+            Some(("10.1.0.0/16".to_owned(), "campus"))
+        );
+        assert_eq!(
+            lookup("10.2.0.1"),
+            Some(("10.0.0.0/8".to_owned(), "private"))
+        );
+        assert_eq!(lookup("8.8.8.8"), Some(("0.0.0.0/0".to_owned(), "default")));
+        assert_eq!(
+            lookup("2001:db8:1::5"),
+            Some(("2001:db8::/32".to_owned(), "docs"))
+        );
+        assert_eq!(lookup("2001:db9::"), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! Predictive text for phone keypads (T9): words are indexed by the digits
+//! that type them, so `4663` offers "good", "home" and "gone" ranked by
+//! weight.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::{Keyset, Trie},
+};
+
+/// The key typing `c`: letters on 2–9 as on ITU E.161 keypads, digits on
+/// themselves, space on 0, and apostrophes and hyphens on 1.
+pub fn key_of(c: char) -> Option<char> {
+    let key = match c.to_ascii_lowercase() {
+        'a'..='c' => '2',
+        'd'..='f' => '3',
+        'g'..='i' => '4',
+        'j'..='l' => '5',
+        'm'..='o' => '6',
+        'p'..='s' => '7',
+        't'..='v' => '8',
+        'w'..='z' => '9',
+        ' ' => '0',
+        '\'' | '-' => '1',
+        digit @ '0'..='9' => digit,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The digits typing `word`, or `None` if a character is not on the keypad.
+pub fn encode(word: &str) -> Option<String> {
+    word.chars().map(key_of).collect()
+}
+
+pub struct KeypadIndex {
+    codes: Trie,
+    /// Words and weights, indexed by code id, heaviest first.
+    words: Vec<Vec<(String, f32)>>,
+}
+
+impl KeypadIndex {
+    /// Builds from `(word, weight)` entries; a word given more than once
+    /// keeps its highest weight. Fails with `MarisaError::Parse`, numbered
+    /// from 1, on a word that cannot be typed on the keypad.
+    pub fn new<W: Into<String>>(entries: impl IntoIterator<Item = (W, f32)>) -> Result<Self> {
+        let mut grouped: BTreeMap<String, HashMap<String, f32>> = BTreeMap::new();
+        for (index, (word, weight)) in entries.into_iter().enumerate() {
+            let word = word.into();
+            let Some(code) = encode(&word) else {
+                return Err(MarisaError::Parse {
+                    line: index + 1,
+                    details: format!("{word:?} cannot be typed on a keypad"),
+                });
+            };
+            let best = grouped
+                .entry(code)
+                .or_default()
+                .entry(word)
+                .or_insert(weight);
+            *best = best.max(weight);
+        }
+
+        let mut keyset = Keyset::default();
+        for code in grouped.keys() {
+            keyset.push(code, None)?;
+        }
+        let codes = Trie::build_from(keyset)?;
+        let mut words = vec![Vec::new(); codes.num_keys()];
+        for (code, entries) in grouped {
+            let id = codes.lookup(&code).expect("code was just added");
+            let mut entries: Vec<(String, f32)> = entries.into_iter().collect();
+            entries.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            words[id] = entries;
+        }
+        Ok(Self { codes, words })
+    }
+
+    /// Number of distinct digit sequences.
+    pub fn num_codes(&self) -> usize {
+        self.codes.num_keys()
+    }
+
+    /// Words typed exactly by `digits`, heaviest first.
+    pub fn words(&self, digits: impl AsRef<str>) -> &[(String, f32)] {
+        let digits = digits.as_ref();
+        match self.codes.lookup(digits) {
+            Some(id) => &self.words[id],
+            None => &[],
+        }
+    }
+
+    /// The `k` best words for `digits`: words it types exactly, heaviest
+    /// first, then longer words it starts, heaviest first.
+    pub fn complete(&self, digits: impl AsRef<str>, k: usize) -> Vec<(&str, f32)> {
+        let digits = digits.as_ref();
+        let exact = self.codes.lookup(digits);
+        let mut ranked: Vec<(&str, f32)> = exact
+            .map(|id| {
+                self.words[id]
+                    .iter()
+                    .map(|(w, s)| (w.as_str(), *s))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if ranked.len() >= k {
+            ranked.truncate(k);
+            return ranked;
+        }
+
+        let mut completions: Vec<(&str, f32)> = Vec::new();
+        self.codes.for_each_predictive(digits, |id, _| {
+            if Some(id) != exact {
+                completions.extend(self.words[id].iter().map(|(w, s)| (w.as_str(), *s)));
+            }
+        });
+        completions.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        ranked.extend(completions.into_iter().take(k - ranked.len()));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, KeypadIndex};
+    use crate::error::MarisaError;
+
+    #[test]
+    fn encodes_words() {
+        assert_eq!(encode("Good").as_deref(), Some("4663"));
+        assert_eq!(encode("don't stop").as_deref(), Some("3661807867"));
+        assert_eq!(encode("café"), None);
+    }
+
+    #[test]
+    fn ranks_exact_then_completions() {
+        let index = KeypadIndex::new([
+            ("good", 5.0),
+            ("home", 8.0),
+            ("gone", 3.0),
+            ("goodbye", 9.0),
+            ("homer", 1.0),
+            ("in", 2.0),
+            ("good", 6.0),
+        ])
+        .unwrap();
+        assert_eq!(index.num_codes(), 4);
+        assert_eq!(
+            index.words("4663"),
+            &[
+                ("home".to_owned(), 8.0),
+                ("good".to_owned(), 6.0),
+                ("gone".to_owned(), 3.0)
+            ]
+        );
+
+        let words = |digits, k| -> Vec<&str> {
+            index
+                .complete(digits, k)
+                .into_iter()
+                .map(|(w, _)| w)
+                .collect()
+        };
+        assert_eq!(
+            words("4663", 10),
+            vec!["home", "good", "gone", "goodbye", "homer"]
+        );
+        assert_eq!(words("4663", 2), vec!["home", "good"]);
+        assert_eq!(words("46", 2), vec!["in", "goodbye"]);
+        assert!(words("5", 10).is_empty());
+    }
+
+    #[test]
+    fn rejects_untypeable_words() {
+        assert!(matches!(
+            KeypadIndex::new([("ok", 1.0), ("naïve", 1.0)]),
+            Err(MarisaError::Parse { line: 2, .. })
+        ));
+    }
+}
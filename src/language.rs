@@ -0,0 +1,303 @@
+//! Routing queries to per-language dictionaries, for multilingual
+//! autocomplete.
+//!
+//! Tags are compared case-insensitively, and a tag with no dictionary of
+//! its own falls back to its primary subtag: `en-GB` is served by `en`
+//! unless `en-GB` was added.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use crate::{marisa::Trie, pipeline::QueryPipeline};
+
+/// Guesses the languages of a query as `(tag, confidence)` pairs, in any
+/// order.
+pub type DetectFn = dyn Fn(&str) -> Vec<(String, f32)> + Send + Sync;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageResult {
+    /// The tag the dictionary was added under.
+    pub language: Arc<str>,
+    pub id: usize,
+    pub key: String,
+    /// The detector's confidence in the language; `1.0` when the caller
+    /// named it.
+    pub confidence: f32,
+}
+
+pub struct LanguageRouter {
+    /// Keyed by lowercased tag.
+    tries: BTreeMap<String, (Arc<str>, Arc<Trie>)>,
+    detector: Option<Arc<DetectFn>>,
+    margin: f32,
+    pipeline: QueryPipeline,
+}
+
+impl Default for LanguageRouter {
+    fn default() -> Self {
+        Self {
+            tries: BTreeMap::new(),
+            detector: None,
+            margin: 0.2,
+            pipeline: QueryPipeline::default(),
+        }
+    }
+}
+
+impl LanguageRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the dictionary for `tag`.
+    pub fn insert(&mut self, tag: &str, trie: Arc<Trie>) {
+        self.tries
+            .insert(tag.to_ascii_lowercase(), (tag.into(), trie));
+    }
+
+    pub fn remove(&mut self, tag: &str) -> Option<Arc<Trie>> {
+        self.tries
+            .remove(&tag.to_ascii_lowercase())
+            .map(|(_, trie)| trie)
+    }
+
+    /// Used by [`LanguageRouter::predictive_search`] to pick languages.
+    pub fn with_detector(
+        mut self,
+        detector: impl Fn(&str) -> Vec<(String, f32)> + Send + Sync + 'static,
+    ) -> Self {
+        self.detector = Some(Arc::new(detector));
+        self
+    }
+
+    /// Detected languages whose confidence is within `margin` of the best
+    /// one are all searched and their results merged. Defaults to `0.2`.
+    pub fn with_ambiguity_margin(mut self, margin: f32) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// Runs `pipeline` on the prefix of the searches, before it is routed,
+    /// and on every result key after. Keys that a hook rewrites to the same
+    /// string are merged like keys found in several languages.
+    pub fn with_pipeline(mut self, pipeline: QueryPipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.tries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tries.is_empty()
+    }
+
+    /// Tags with a dictionary, as they were added, sorted.
+    pub fn languages(&self) -> impl Iterator<Item = &str> + '_ {
+        self.tries.values().map(|(tag, _)| &**tag)
+    }
+
+    /// The dictionary serving `tag`.
+    pub fn trie(&self, tag: &str) -> Option<&Arc<Trie>> {
+        self.resolve(tag).map(|(_, trie)| trie)
+    }
+
+    fn resolve(&self, tag: &str) -> Option<&(Arc<str>, Arc<Trie>)> {
+        let tag = tag.to_ascii_lowercase();
+        self.tries.get(&tag).or_else(|| {
+            let (primary, _) = tag.split_once(['-', '_'])?;
+            self.tries.get(primary)
+        })
+    }
+
+    /// The languages a query is routed to, with their confidence, most
+    /// confident first. Without a detector, or when it names no language
+    /// with a dictionary, every language is searched with confidence `0.0`.
+    pub fn route(&self, query: &str) -> Vec<(Arc<str>, f32)> {
+        let mut routed: Vec<(Arc<str>, f32)> = Vec::new();
+        if let Some(detector) = &self.detector {
+            let mut best: HashMap<&str, f32> = HashMap::new();
+            for (tag, confidence) in detector(query) {
+                if let Some((tag, _)) = self.resolve(&tag) {
+                    let entry = best.entry(tag).or_insert(confidence);
+                    *entry = entry.max(confidence);
+                }
+            }
+            let top = best.values().copied().fold(f32::NEG_INFINITY, f32::max);
+            routed = best
+                .into_iter()
+                .filter(|&(_, confidence)| confidence >= top - self.margin)
+                .map(|(tag, confidence)| (tag.into(), confidence))
+                .collect();
+        }
+        if routed.is_empty() {
+            routed = self
+                .tries
+                .values()
+                .map(|(tag, _)| (tag.clone(), 0.0))
+                .collect();
+        }
+        routed.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        routed
+    }
+
+    /// Keys starting with `prefix` in the languages it is routed to. A key
+    /// found in several languages is reported once, from the most
+    /// confident one. Results are ordered by confidence, then by key.
+    pub fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<LanguageResult> {
+        let prefix = &self.pipeline.preprocess(prefix.as_ref());
+        let routed = self.route(prefix);
+        self.merge(prefix, &routed)
+    }
+
+    /// Keys starting with `prefix` in the dictionary serving `tag`, sorted
+    /// by key; empty if no dictionary serves it.
+    pub fn predictive_search_in(
+        &self,
+        tag: impl AsRef<str>,
+        prefix: impl AsRef<str>,
+    ) -> Vec<LanguageResult> {
+        let tag = tag.as_ref();
+        let prefix = &self.pipeline.preprocess(prefix.as_ref());
+        match self.resolve(tag) {
+            Some((tag, _)) => self.merge(prefix, &[(tag.clone(), 1.0)]),
+            None => Vec::new(),
+        }
+    }
+
+    fn merge(&self, prefix: &str, routed: &[(Arc<str>, f32)]) -> Vec<LanguageResult> {
+        let mut best: HashMap<String, LanguageResult> = HashMap::new();
+        for (tag, confidence) in routed {
+            let Some((_, trie)) = self.resolve(tag) else {
+                continue;
+            };
+            for (id, key) in trie.predictive_search(prefix) {
+                let Some(key) = self.pipeline.postprocess(&key) else {
+                    continue;
+                };
+                match best.get(&key) {
+                    Some(existing) if existing.confidence >= *confidence => {}
+                    _ => {
+                        best.insert(
+                            key.clone(),
+                            LanguageResult {
+                                language: tag.clone(),
+                                id,
+                                key,
+                                confidence: *confidence,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        let mut results: Vec<LanguageResult> = best.into_values().collect();
+        results.sort_by(|a, b| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{LanguageResult, LanguageRouter};
+    use crate::{pipeline::QueryPipeline, test_util::build};
+
+    fn keyed(results: Vec<LanguageResult>) -> Vec<(String, String)> {
+        results
+            .into_iter()
+            .map(|r| (r.language.to_string(), r.key))
+            .collect()
+    }
+
+    fn router() -> LanguageRouter {
+        let mut router = LanguageRouter::new().with_detector(|query| {
+            if query.contains('ř') {
+                vec![("cs".to_owned(), 0.9)]
+            } else if query.starts_with("pe") {
+                vec![("cs".to_owned(), 0.5), ("en-US".to_owned(), 0.6)]
+            } else {
+                vec![("de".to_owned(), 1.0)]
+            }
+        });
+        router.insert("cs", Arc::new(build(&["pes", "peří", "řeka"])));
+        router.insert("en", Arc::new(build(&["pen", "pes", "river"])));
+        router
+    }
+
+    #[test]
+    fn routes_by_tag() {
+        assert_eq!(LanguageRouter::default().margin, 0.2);
+        let router = router();
+        assert_eq!(router.languages().collect::<Vec<_>>(), vec!["cs", "en"]);
+        assert!(router.trie("EN-gb").is_some());
+        assert!(router.trie("de").is_none());
+        assert_eq!(
+            keyed(router.predictive_search_in("en-GB", "p")),
+            vec![
+                ("en".to_owned(), "pen".to_owned()),
+                ("en".to_owned(), "pes".to_owned())
+            ]
+        );
+        assert!(router.predictive_search_in("de", "p").is_empty());
+    }
+
+    #[test]
+    fn merges_ambiguous_languages() {
+        let router = router();
+        assert_eq!(
+            keyed(router.predictive_search("ře")),
+            vec![("cs".to_owned(), "řeka".to_owned())]
+        );
+        // Both detected languages are close, so both are searched and the
+        // shared "pes" comes from the more confident one.
+        assert_eq!(
+            keyed(router.predictive_search("pe")),
+            vec![
+                ("en".to_owned(), "pen".to_owned()),
+                ("en".to_owned(), "pes".to_owned()),
+                ("cs".to_owned(), "peří".to_owned()),
+            ]
+        );
+        let narrow = router.with_ambiguity_margin(0.05);
+        assert_eq!(narrow.predictive_search("pe").len(), 2);
+
+        // An unknown language searches everything.
+        let routed = narrow.route("ri");
+        assert_eq!(routed.len(), 2);
+        assert_eq!(
+            keyed(narrow.predictive_search("ri")),
+            vec![("en".to_owned(), "river".to_owned())]
+        );
+    }
+
+    #[test]
+    fn pipeline_wraps_searches() {
+        let router = router().with_pipeline(
+            QueryPipeline::new()
+                .lowercase()
+                .filter_result(|key| key != "pen")
+                .map_result(str::to_uppercase),
+        );
+        assert_eq!(
+            keyed(router.predictive_search_in("en", "PE")),
+            vec![("en".to_owned(), "PES".to_owned())]
+        );
+        // Lowercased before routing, so the detector sees "pe".
+        assert_eq!(
+            keyed(router.predictive_search("PE")),
+            vec![
+                ("en".to_owned(), "PES".to_owned()),
+                ("cs".to_owned(), "PEŘÍ".to_owned())
+            ]
+        );
+    }
+}
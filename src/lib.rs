@@ -1,32 +1,203 @@
+//! Rust bindings to libmarisa, the MARISA static trie library.
+//!
+//! The default `libmarisa` feature links the C++ library and provides
+//! [`marisa::Trie`] and everything built on it. The `pure-rust` feature
+//! adds [`pure`], a std-only backend covering only building and the
+//! searches, for targets that can't link C++; code generic over
+//! [`backend::TrieBackend`] runs on either.
+
+#[macro_use]
+mod trace;
+
+#[cfg(feature = "libmarisa")]
+pub mod alias;
+#[cfg(feature = "libmarisa")]
+pub mod arena;
+pub mod backend;
+#[cfg(feature = "libmarisa")]
+pub mod builder;
+#[cfg(feature = "libmarisa")]
+pub mod compaction;
+pub mod compat;
+pub mod config;
+#[cfg(feature = "libmarisa")]
+mod convert;
+#[cfg(feature = "libmarisa")]
+pub mod domain;
+#[cfg(feature = "libmarisa")]
+mod dot;
+#[cfg(feature = "libmarisa")]
+pub mod dynamic;
+pub mod error;
+#[cfg(feature = "libmarisa")]
+mod export;
+#[cfg(feature = "libmarisa")]
+pub mod feedback;
+pub mod format;
+#[cfg(all(feature = "fst", feature = "libmarisa"))]
+mod fst_interop;
+#[cfg(feature = "libmarisa")]
+pub mod fuzzy;
+#[cfg(feature = "libmarisa")]
+pub mod gazetteer;
+#[cfg(feature = "libmarisa")]
+pub mod highlight;
+#[cfg(feature = "libmarisa")]
+pub mod ime;
+#[cfg(feature = "libmarisa")]
+pub mod import;
+#[cfg(feature = "libmarisa")]
+pub mod ip;
+#[cfg(feature = "libmarisa")]
+pub mod keypad;
+#[cfg(feature = "libmarisa")]
+pub mod language;
+#[cfg(all(feature = "serde", feature = "libmarisa"))]
+pub mod manifest;
+#[cfg(feature = "libmarisa")]
+pub mod map;
+#[cfg(feature = "libmarisa")]
+pub mod metadata;
+pub mod metrics;
+#[cfg(all(feature = "uniffi", feature = "libmarisa"))]
+pub mod mobile;
+#[cfg(feature = "libmarisa")]
+pub mod multi;
+#[cfg(all(feature = "libmarisa", target_os = "linux"))]
+pub mod numa;
+#[cfg(feature = "libmarisa")]
+pub mod open;
+#[cfg(feature = "libmarisa")]
+pub mod overlay;
+#[cfg(feature = "libmarisa")]
+pub mod phrase;
+pub mod pipeline;
+#[cfg(feature = "libmarisa")]
+pub mod profile;
+#[cfg(feature = "pure-rust")]
+pub mod pure;
+#[cfg(all(feature = "pyo3", feature = "libmarisa"))]
+pub mod python;
+#[cfg(all(test, any(feature = "libmarisa", feature = "pure-rust")))]
+mod reference;
+#[cfg(feature = "libmarisa")]
+pub mod registry;
+#[cfg(feature = "libmarisa")]
+pub mod router;
+#[cfg(feature = "libmarisa")]
+pub mod search;
+#[cfg(feature = "libmarisa")]
+pub mod sharded;
+#[cfg(feature = "libmarisa")]
+pub mod spill;
+#[cfg(feature = "libmarisa")]
+pub mod stats;
+#[cfg(feature = "libmarisa")]
+pub mod synonym;
+#[cfg(all(test, feature = "libmarisa"))]
+mod test_util;
+#[cfg(feature = "libmarisa")]
+pub mod typeahead;
+#[cfg(all(feature = "io-uring", feature = "libmarisa", target_os = "linux"))]
+pub mod uring;
+#[cfg(feature = "libmarisa")]
+pub mod utf8;
+#[cfg(feature = "libmarisa")]
+mod verify;
+#[cfg(feature = "libmarisa")]
+pub mod wal;
+#[cfg(feature = "libmarisa")]
+pub mod weighted;
+
+// The crate is the `marisa` UniFFI namespace; the exported types are in
+// `mobile`.
+#[cfg(all(feature = "uniffi", feature = "libmarisa"))]
+uniffi::setup_scaffolding!();
+
+/// The types most code needs, for `use marisa::prelude::*`. With only the
+/// `pure-rust` backend, `Keyset` and `Trie` are the pure-Rust ones.
+pub mod prelude {
+    #[cfg(all(feature = "pure-rust", not(feature = "libmarisa")))]
+    pub use crate::pure::{Keyset, Trie};
+    pub use crate::{
+        backend::{KeysetBackend, PrefixDictionary, TrieBackend},
+        config::Config,
+        error::{MarisaError, Result},
+    };
+    #[cfg(feature = "libmarisa")]
+    pub use crate::{
+        map::{TrieMap, TrieMultimap},
+        marisa::{Agent, BatchOrder, Key, Keyset, Trie, TrieState},
+        weighted::WeightedTrie,
+    };
+}
+
+#[cfg(feature = "libmarisa")]
 mod utils {
-    use std::ffi::CString;
-
-    #[inline(always)]
-    pub fn to_raw(key: &str) -> (*mut i8, usize) {
-        let cstr = CString::new(key).expect("CString new failed");
-        let bytes = cstr.as_bytes_with_nul();
-        let size = bytes.len();
-        (cstr.into_raw(), size)
+    use std::{ffi::CString, path::Path};
+
+    /// libmarisa takes file names as C strings, so non-UTF-8 paths are
+    /// converted lossily. Fails with `InvalidInput` on a NUL byte.
+    pub fn c_path(path: &Path) -> std::io::Result<CString> {
+        if path.to_str().is_none() {
+            log_warn!("path {path:?} is not valid UTF-8 and is converted lossily");
+        }
+        CString::new(path.to_string_lossy().as_bytes()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "path contains a NUL byte")
+        })
+    }
+
+    /// 64-bit FNV-1a, used where a hash has to stay stable across builds
+    /// and platforms (unlike `DefaultHasher`).
+    pub struct Fnv1a(u64);
+
+    impl Default for Fnv1a {
+        fn default() -> Self {
+            Self(0xcbf29ce484222325)
+        }
+    }
+
+    impl Fnv1a {
+        pub fn update(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.0 ^= byte as u64;
+                self.0 = self.0.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        pub fn finish(&self) -> u64 {
+            self.0
+        }
     }
 }
 
+#[cfg(feature = "libmarisa")]
 pub mod marisa {
     use std::{
-        ffi::{CStr, CString},
+        alloc::Layout,
+        cell::RefCell,
+        io::{Read, Seek, SeekFrom},
+        ptr::NonNull,
         str::Utf8Error,
+        sync::atomic::{AtomicUsize, Ordering},
     };
 
-    use ffi::{
-        marisa_Key, marisa_Key_Union, marisa_Keyset, marisa_Keyset_KEY_BLOCK_SIZE, marisa_Trie,
-    };
+    use ffi::{marisa_Agent, marisa_Key, marisa_Key_Union, marisa_Keyset, marisa_Trie};
     pub use marisa_sys as ffi;
 
-    use crate::utils;
+    use crate::{
+        compat,
+        config::{self, Config},
+        error::{MarisaError, Result},
+        format, utils,
+    };
 
-    #[derive(Clone)]
+    /// A key as libmarisa sees it: a pointer and an exact byte length.
+    /// Keys have no terminator, so they may contain NUL bytes.
     pub struct Key {
         key: marisa_Key,
-        drop: bool,
+        // The bytes `key` points into, when this key owns them.
+        owned: Option<Box<[u8]>>,
     }
 
     impl Default for Key {
@@ -37,32 +208,29 @@ pub mod marisa {
                     length_: 0,
                     union_: marisa_Key_Union { id: 0 },
                 },
-                drop: false,
+                owned: None,
             }
         }
     }
 
-    impl Drop for Key {
-        fn drop(&mut self) {
-            if !self.key.ptr_.is_null() && self.drop {
-                let str = unsafe { CString::from_raw(self.key.ptr_ as *mut i8) };
-                drop(str);
+    impl Clone for Key {
+        fn clone(&self) -> Self {
+            let mut key = Key {
+                key: self.key,
+                owned: None,
+            };
+            if self.owned.is_some() {
+                key.set_bytes(self.as_bytes());
             }
+            key
         }
     }
 
     impl Key {
-        pub fn new(key: &str) -> Key {
-            let (ptr, size) = utils::to_raw(key);
-
-            Key {
-                key: marisa_Key {
-                    ptr_: ptr,
-                    length_: size as u32,
-                    union_: marisa_Key_Union { id: 0 },
-                },
-                drop: true,
-            }
+        pub fn new(key: impl AsRef<str>) -> Key {
+            let mut out = Key::default();
+            out.set_str(key);
+            out
         }
 
         pub fn set_id(&mut self, id: u32) {
@@ -81,149 +249,1003 @@ pub mod marisa {
             unsafe { self.key.union_.weight }
         }
 
-        pub fn set_str(&mut self, key: &str) {
-            let (ptr, size) = utils::to_raw(key);
+        pub fn set_str(&mut self, key: impl AsRef<str>) {
+            self.set_bytes(key.as_ref().as_bytes());
+        }
+
+        fn set_bytes(&mut self, key: &[u8]) {
+            let owned: Box<[u8]> = key.into();
+            self.key.ptr_ = owned.as_ptr() as *const _;
+            self.key.length_ = owned.len() as u32;
+            self.owned = Some(owned);
+        }
 
-            self.key.ptr_ = ptr;
-            self.key.length_ = size as u32;
-            self.drop = true;
+        pub fn as_str(&self) -> Result<&str, Utf8Error> {
+            std::str::from_utf8(self.as_bytes())
         }
 
+        #[deprecated(since = "0.2.0", note = "renamed to `as_str`")]
         pub fn str(&self) -> Result<&str, Utf8Error> {
-            let c = unsafe { CStr::from_ptr(self.key.ptr_ as *const i8) };
-            let key = c.to_str()?;
-            Ok(key)
+            self.as_str()
         }
 
-        pub fn ptr(&self) -> *const i8 {
+        /// The key's bytes, exactly `len()` of them.
+        pub fn as_bytes(&self) -> &[u8] {
+            if self.key.ptr_.is_null() {
+                return &[];
+            }
+            unsafe {
+                std::slice::from_raw_parts(self.key.ptr_ as *const u8, self.key.length_ as usize)
+            }
+        }
+
+        pub fn as_ptr(&self) -> *const i8 {
             self.key.ptr_
         }
 
+        #[deprecated(since = "0.2.0", note = "renamed to `as_ptr`")]
+        pub fn ptr(&self) -> *const i8 {
+            self.as_ptr()
+        }
+
+        /// Length in bytes.
+        pub fn len(&self) -> usize {
+            self.key.length_ as usize
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.key.length_ == 0
+        }
+
+        #[deprecated(since = "0.2.0", note = "use `len`, which returns a `usize`")]
         pub fn length(&self) -> u32 {
-            return self.key.length_;
+            self.key.length_
         }
 
+        #[deprecated(since = "0.2.0", note = "use the `From<marisa_Key>` impl")]
         pub fn from(existing: marisa_Key) -> Key {
+            existing.into()
+        }
+    }
+
+    /// Borrows the bytes `existing` points to, which must outlive the key.
+    impl From<marisa_Key> for Key {
+        fn from(existing: marisa_Key) -> Self {
             Self {
                 key: existing,
-                drop: false,
+                owned: None,
             }
         }
     }
 
+    /// Keys to build a trie from.
+    ///
+    /// The empty string is rejected with `MarisaError::EmptyKey` unless
+    /// allowed with [`Keyset::set_allow_empty_key`]; once in a trie, it is
+    /// found by `lookup("")` and `predictive_search("")` like any other key.
     #[derive(Debug)]
     pub struct Keyset {
-        keyset: marisa_Keyset,
+        keyset: NonNull<marisa_Keyset>,
+        allow_empty_key: bool,
+    }
+
+    /// The libmarisa objects are allocated by the shim in `marisa-sys`, so
+    /// their size and layout never leak into the public types.
+    fn allocated<T>(ptr: *mut T) -> NonNull<T> {
+        NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(Layout::new::<T>()))
     }
 
     impl Default for Keyset {
         fn default() -> Self {
             Self {
-                keyset: unsafe { marisa_Keyset::new() },
+                keyset: allocated(unsafe { ffi::marisa_shim_keyset_new() }),
+                allow_empty_key: false,
             }
         }
     }
 
     impl Drop for Keyset {
         fn drop(&mut self) {
-            self.clear();
+            unsafe { ffi::marisa_shim_keyset_free(self.keyset.as_ptr()) }
         }
     }
 
     impl Keyset {
-        pub fn push(&mut self, key: &str, weight: Option<f32>) {
-            let (ptr, size) = utils::to_raw(key);
+        /// Adds `key`, or fails with `KeyTooLong`/`TooManyKeys` where
+        /// libmarisa would abort.
+        pub fn push(&mut self, key: impl AsRef<str>, weight: Option<f32>) -> Result<()> {
+            let key = key.as_ref();
+            if key.is_empty() && !self.allow_empty_key {
+                return Err(MarisaError::EmptyKey);
+            }
+            config::check_limits(self.num_keys(), key.len())?;
+            self.push_checked(key.as_bytes(), weight.unwrap_or(1.0));
+            Ok(())
+        }
+
+        /// Adds every key in `keys` with weight 1.0. The whole batch is
+        /// checked first, so on error nothing has been pushed.
+        ///
+        /// Keys go to libmarisa straight from their slices with explicit
+        /// lengths; the only copy is into the keyset's own blocks.
+        pub fn push_all(&mut self, keys: &[impl AsRef<str>]) -> Result<()> {
+            for (i, key) in keys.iter().map(AsRef::as_ref).enumerate() {
+                if key.is_empty() && !self.allow_empty_key {
+                    return Err(MarisaError::EmptyKey);
+                }
+                config::check_limits(self.num_keys() + i, key.len())?;
+            }
+            for key in keys {
+                self.push_checked(key.as_ref().as_bytes(), 1.0);
+            }
+            Ok(())
+        }
+
+        /// Adds every key from `keys` with `weight`, 1.0 if `None`. Unlike
+        /// `push_all` the keys are checked as they come, so on error the
+        /// ones before the failing key remain pushed.
+        pub fn push_iter<I>(&mut self, keys: I, weight: Option<f32>) -> Result<()>
+        where
+            I: IntoIterator,
+            I::Item: AsRef<str>,
+        {
+            for key in keys {
+                self.push(key, weight)?;
+            }
+            Ok(())
+        }
+
+        /// Copies every key of `other`, with its weight, after the keys
+        /// already here. Fails without copying anything if the result would
+        /// exceed `MAX_NUM_KEYS`, or if `other` holds the empty key and this
+        /// keyset doesn't allow it.
+        pub fn append(&mut self, other: &Keyset) -> Result<()> {
+            if self.num_keys() + other.num_keys() > config::MAX_NUM_KEYS {
+                return Err(MarisaError::TooManyKeys {
+                    max: config::MAX_NUM_KEYS,
+                });
+            }
+            let keys = (0..other.num_keys()).map(|i| other.at(i));
+            if !self.allow_empty_key && keys.clone().any(|key| key.is_empty()) {
+                return Err(MarisaError::EmptyKey);
+            }
+            for key in keys {
+                self.push_checked(key.as_bytes(), key.weight());
+            }
+            Ok(())
+        }
 
+        /// Like `push`, for tests that need keys that aren't UTF-8, as
+        /// found in dictionaries built by other tools.
+        #[cfg(test)]
+        pub(crate) fn push_bytes(&mut self, key: &[u8], weight: Option<f32>) -> Result<()> {
+            if key.is_empty() && !self.allow_empty_key {
+                return Err(MarisaError::EmptyKey);
+            }
+            config::check_limits(self.num_keys(), key.len())?;
+            self.push_checked(key, weight.unwrap_or(1.0));
+            Ok(())
+        }
+
+        fn push_checked(&mut self, key: &[u8], weight: f32) {
+            // libmarisa copies the bytes into its own blocks.
             unsafe {
-                self.keyset.push_back3(ptr, size, weight.unwrap_or(1.0));
+                self.keyset
+                    .as_mut()
+                    .push_back3(key.as_ptr() as *const _, key.len(), weight);
             }
-            let str = unsafe { CString::from_raw(ptr as *mut i8) };
-            drop(str);
         }
 
+        pub fn set_allow_empty_key(&mut self, allow: bool) {
+            self.allow_empty_key = allow;
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.num_keys() == 0
+        }
+
+        #[deprecated(since = "0.2.0", note = "renamed to `is_empty`")]
         pub fn empty(&self) -> bool {
-            self.keyset.size_ == 0
+            self.is_empty()
         }
 
         pub fn reset(&mut self) {
             unsafe {
-                self.keyset.reset();
+                self.keyset.as_mut().reset();
             }
         }
 
         pub fn clear(&mut self) {
             unsafe {
-                self.keyset.clear();
+                self.keyset.as_mut().clear();
             }
         }
 
         pub fn num_keys(&self) -> usize {
-            self.keyset.size_
+            unsafe { ffi::marisa_shim_keyset_size(self.keyset.as_ptr()) }
+        }
+
+        /// Bytes of key data held by the keyset.
+        pub fn total_length(&self) -> usize {
+            unsafe { ffi::marisa_shim_keyset_total_length(self.keyset.as_ptr()) }
         }
 
+        /// Approximate heap usage: key data plus one `marisa::Key` per key.
+        pub fn memory_size(&self) -> usize {
+            self.total_length() + self.num_keys() * std::mem::size_of::<marisa_Key>()
+        }
+
+        /// The key pushed `index`-th. Panics if `index` is past the last
+        /// key; see [`Keyset::try_at`].
         pub fn at(&self, index: usize) -> Key {
-            let outer = unsafe {
-                std::slice::from_raw_parts(self.keyset.key_blocks_.array_, self.keyset.size_)
-            };
-            let outer_index = index / marisa_Keyset_KEY_BLOCK_SIZE as usize;
-            let inner_index = index % marisa_Keyset_KEY_BLOCK_SIZE as usize;
+            self.try_at(index).unwrap_or_else(|err| panic!("{err}"))
+        }
+
+        /// The key pushed `index`-th, if there is one.
+        pub fn get(&self, index: usize) -> Option<Key> {
+            self.try_at(index).ok()
+        }
+
+        /// Like `at`, but fails with `InvalidId` instead of panicking.
+        pub fn try_at(&self, index: usize) -> Result<Key> {
+            if index >= self.num_keys() {
+                return Err(MarisaError::InvalidId {
+                    id: index,
+                    num_keys: self.num_keys(),
+                });
+            }
+            Ok(unsafe { *ffi::marisa_shim_keyset_key(self.keyset.as_ptr(), index) }.into())
+        }
+    }
+
+    /// Search state for one query at a time. Its buffers are reused across
+    /// `set_query` calls: the query is copied into an owned buffer that only
+    /// grows, and libmarisa keeps its traversal state between queries.
+    pub struct Agent {
+        agent: NonNull<marisa_Agent>,
+        // libmarisa keeps a pointer to the query, so it lives here.
+        query: Vec<u8>,
+        // The id set by `set_query_id`, checked before a reverse lookup.
+        query_id: usize,
+    }
+
+    impl Default for Agent {
+        fn default() -> Self {
+            Self {
+                agent: allocated(unsafe { ffi::marisa_shim_agent_new() }),
+                query: Vec::new(),
+                query_id: 0,
+            }
+        }
+    }
+
+    impl Drop for Agent {
+        fn drop(&mut self) {
+            unsafe { ffi::marisa_shim_agent_free(self.agent.as_ptr()) }
+        }
+    }
+
+    /// Queries longer than this don't keep their buffer in the thread's
+    /// cached agent.
+    const CACHED_QUERY_CAPACITY: usize = 1 << 10;
+
+    thread_local! {
+        static CACHED_AGENT: RefCell<Agent> = RefCell::new(Agent::with_capacity(32));
+    }
+
+    /// Runs `f` with this thread's cached agent, so one-shot lookups don't
+    /// allocate a query buffer and libmarisa state per call. Falls back to
+    /// a fresh agent when the cached one is in use, e.g. for a lookup
+    /// from inside a search callback.
+    fn with_cached_agent<R>(f: impl FnOnce(&mut Agent) -> R) -> R {
+        let mut f = Some(f);
+        let cached = CACHED_AGENT.try_with(|cached| {
+            let mut agent = cached.try_borrow_mut().ok()?;
+            let result = f.take().expect("called once")(&mut agent);
+            if agent.query_capacity() > CACHED_QUERY_CAPACITY {
+                agent.shrink_to_fit();
+            }
+            Some(result)
+        });
+        match cached {
+            Ok(Some(result)) => result,
+            _ => f.take().expect("not called yet")(&mut Agent::default()),
+        }
+    }
+
+    impl Agent {
+        /// An agent whose query buffer holds `capacity` bytes up front.
+        pub fn with_capacity(capacity: usize) -> Self {
+            let mut agent = Self::default();
+            agent.query.reserve(capacity);
+            agent
+        }
+
+        pub fn query_capacity(&self) -> usize {
+            self.query.capacity()
+        }
+
+        /// Forgets the query and the last key, keeping the buffers.
+        pub fn clear(&mut self) {
+            self.set_query("");
+            unsafe { ffi::marisa_shim_agent_clear_key(self.agent.as_ptr()) }
+        }
+
+        /// Releases the query buffer and libmarisa's search state, e.g.
+        /// after an unusually long query.
+        pub fn shrink_to_fit(&mut self) {
+            self.query = Vec::new();
+            unsafe { ffi::marisa_shim_agent_free(self.agent.as_ptr()) }
+            self.agent = allocated(unsafe { ffi::marisa_shim_agent_new() });
+        }
+
+        pub fn set_query(&mut self, query: impl AsRef<str>) {
+            self.set_query_bytes(query.as_ref().as_bytes());
+        }
+
+        /// Queries by bytes, e.g. a prefix ending partway through a
+        /// character.
+        pub(crate) fn set_query_bytes(&mut self, query: &[u8]) {
+            self.query.clear();
+            self.query.extend_from_slice(query);
+            unsafe {
+                self.agent
+                    .as_mut()
+                    .set_query1(self.query.as_ptr() as *const _, self.query.len())
+            }
+        }
 
-            let inner_array = &outer[outer_index];
-            let inner =
-                unsafe { std::slice::from_raw_parts(inner_array.array_, self.keyset.size_) };
-            let out_key = inner[inner_index];
-            Key::from(out_key)
+        pub fn set_query_id(&mut self, id: usize) {
+            self.query_id = id;
+            unsafe { self.agent.as_mut().set_query2(id) }
         }
+
+        fn raw_key(&self) -> &marisa_Key {
+            unsafe { &*ffi::marisa_shim_agent_key(self.agent.as_ptr()) }
+        }
+
+        pub fn key(&self) -> Key {
+            (*self.raw_key()).into()
+        }
+
+        /// The bytes of the key found by the last search, borrowed from the
+        /// agent until its next query or search step.
+        pub fn key_bytes(&self) -> &[u8] {
+            let key = self.raw_key();
+            if key.ptr_.is_null() {
+                return &[];
+            }
+            unsafe { std::slice::from_raw_parts(key.ptr_ as *const u8, key.length_ as usize) }
+        }
+    }
+
+    /// Indices of `keys` in the order a batch lookup visits them.
+    fn batch_order(keys: &[impl AsRef<str>], order: BatchOrder) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..keys.len()).collect();
+        if order == BatchOrder::Sorted {
+            indices.sort_unstable_by(|&a, &b| keys[a].as_ref().cmp(keys[b].as_ref()));
+        }
+        indices
+    }
+
+    /// Where a trie's contents came from. libmarisa throws on any query to
+    /// an `Empty` trie, so the wrapper answers those itself.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TrieState {
+        /// Never built or loaded, or cleared since.
+        Empty,
+        Built,
+        /// Read into memory by `load`.
+        Loaded,
+        /// Backed by a file mapping or borrowed image (`mmap`, `map`).
+        Mapped,
+    }
+
+    /// The order [`Trie::lookup_many`] visits a batch in. Results always
+    /// come back in the order the keys were given.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum BatchOrder {
+        #[default]
+        AsGiven,
+        /// Sorts the batch first, so consecutive lookups walk mostly the
+        /// same nodes and hit them in cache. Pays off on large tries.
+        Sorted,
     }
 
     pub struct Trie {
-        trie: marisa_Trie,
+        trie: NonNull<marisa_Trie>,
+        state: TrieState,
+        // The image `trie` is mapped onto after `read_from`, in 8-byte
+        // units for alignment. Only dropped after `drop` frees `trie`.
+        image: Option<Box<[u64]>>,
     }
 
     impl Default for Trie {
         fn default() -> Self {
             Self {
-                trie: unsafe { marisa_Trie::new() },
+                trie: allocated(unsafe { ffi::marisa_shim_trie_new() }),
+                state: TrieState::Empty,
+                image: None,
             }
         }
     }
 
+    impl Drop for Trie {
+        fn drop(&mut self) {
+            unsafe { ffi::marisa_shim_trie_free(self.trie.as_ptr()) }
+        }
+    }
+
+    // libmarisa only mutates a trie through `&mut` methods; searches keep
+    // their traversal state in the caller's agent, so shared access is safe.
+    unsafe impl Send for Trie {}
+    unsafe impl Sync for Trie {}
+
     impl Trie {
+        /// Panics if libmarisa fails to build, e.g. when it runs out of
+        /// memory; [`Trie::build_from`] returns the error instead.
         pub fn build(&mut self, keyset: &mut Keyset) {
-            unsafe {
-                self.trie.build(&mut keyset.keyset, 0);
+            self.build_with_flags(keyset, 0)
+                .unwrap_or_else(|err| panic!("{err}"));
+        }
+
+        pub fn build_with_config(&mut self, keyset: &mut Keyset, config: &Config) {
+            self.build_with_flags(keyset, config.flags())
+                .unwrap_or_else(|err| panic!("{err}"));
+        }
+
+        /// Builds from `keyset` and frees it right away, so the keys and the
+        /// trie are only held together during the build itself. Fails with
+        /// an `Io` error of kind `OutOfMemory` if libmarisa runs out of
+        /// memory, or of kind `Other` for anything else it throws.
+        pub fn build_from(keyset: Keyset) -> Result<Trie> {
+            Self::build_from_config(keyset, &Config::default())
+        }
+
+        pub fn build_from_config(mut keyset: Keyset, config: &Config) -> Result<Trie> {
+            let mut trie = Trie::default();
+            trie.build_with_flags(&mut keyset, config.flags())?;
+            drop(keyset);
+            Ok(trie)
+        }
+
+        fn build_with_flags(&mut self, keyset: &mut Keyset, flags: u32) -> Result<()> {
+            let _span = trace_span!("marisa::build", num_keys = keyset.num_keys());
+            // The shim catches libmarisa's exceptions, which must not
+            // unwind into Rust, and leaves the trie as it was.
+            let status = unsafe {
+                ffi::marisa_shim_trie_build(
+                    self.trie.as_ptr(),
+                    keyset.keyset.as_ptr(),
+                    flags as i32,
+                )
+            };
+            match status {
+                0 => {}
+                1 => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::OutOfMemory,
+                        "libmarisa ran out of memory building the trie",
+                    )
+                    .into())
+                }
+                _ => return Err(std::io::Error::other("libmarisa failed to build the trie").into()),
             }
+            self.state = TrieState::Built;
+            self.image = None;
+            trace_event!(
+                "built trie",
+                num_keys = self.num_keys(),
+                num_nodes = self.num_nodes()
+            );
+            Ok(())
+        }
+
+        pub fn state(&self) -> TrieState {
+            self.state
+        }
+
+        /// Whether the trie has been built, loaded or mapped. Searches on a
+        /// trie that isn't ready find nothing, sizes are zero, and `save`
+        /// fails with `MarisaError::NotReady`.
+        pub fn is_ready(&self) -> bool {
+            self.state != TrieState::Empty
         }
 
         pub fn num_tries(&self) -> usize {
-            unsafe { self.trie.num_tries() }
+            if !self.is_ready() {
+                return 0;
+            }
+            unsafe { self.trie.as_ref().num_tries() }
         }
 
         pub fn num_keys(&self) -> usize {
-            unsafe { self.trie.num_keys() }
+            if !self.is_ready() {
+                return 0;
+            }
+            unsafe { self.trie.as_ref().num_keys() }
         }
 
         pub fn num_nodes(&self) -> usize {
-            unsafe { self.trie.num_nodes() }
+            if !self.is_ready() {
+                return 0;
+            }
+            unsafe { self.trie.as_ref().num_nodes() }
+        }
+
+        /// Bytes used by the trie in memory.
+        pub fn total_size(&self) -> usize {
+            if !self.is_ready() {
+                return 0;
+            }
+            unsafe { self.trie.as_ref().total_size() }
+        }
+
+        /// Bytes written by `save`.
+        pub fn io_size(&self) -> usize {
+            if !self.is_ready() {
+                return 0;
+            }
+            unsafe { self.trie.as_ref().io_size() }
         }
 
         pub fn clear(&mut self) {
-            unsafe { self.trie.clear() }
+            unsafe { self.trie.as_mut().clear() }
+            self.state = TrieState::Empty;
+            self.image = None;
         }
 
-        pub fn save(&self, path: &std::path::Path) {
-            let (path, _) = utils::to_raw(&path.to_string_lossy());
+        pub fn save(&self, path: &std::path::Path) -> Result<()> {
+            if !self.is_ready() {
+                return Err(MarisaError::NotReady);
+            }
+            let _span = trace_span!(
+                "marisa::save",
+                path = path.to_str().unwrap_or_default(),
+                num_keys = self.num_keys()
+            );
+            // libmarisa aborts if it can't open the file, so open it here
+            // first to report why.
+            std::fs::File::create(path).map_err(|err| MarisaError::from(err).at("save", path))?;
+            let c_path =
+                utils::c_path(path).map_err(|err| MarisaError::from(err).at("save", path))?;
             unsafe {
-                self.trie.save(path);
+                self.trie.as_ref().save(c_path.as_ptr());
+            }
+            Ok(())
+        }
+
+        /// Like `save`, but holds an exclusive advisory lock on the file
+        /// while truncating and writing it, so readers going through
+        /// `load_locked` never see a partial image. Processes that don't
+        /// lock are not held back.
+        pub fn save_locked(&self, path: &std::path::Path) -> Result<()> {
+            if !self.is_ready() {
+                return Err(MarisaError::NotReady);
             }
+            let _span = trace_span!(
+                "marisa::save_locked",
+                path = path.to_str().unwrap_or_default(),
+                num_keys = self.num_keys()
+            );
+            let at = |err: std::io::Error| MarisaError::from(err).at("save", path);
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(at)?;
+            file.lock().map_err(at)?;
+            file.set_len(0).map_err(at)?;
+            self.write_to(&file).map_err(|err| err.at("save", path))
+        }
+
+        #[cfg(unix)]
+        pub(crate) fn write_to(&self, file: &std::fs::File) -> Result<()> {
+            use std::os::unix::io::AsRawFd;
+            unsafe { self.trie.as_ref().write(file.as_raw_fd()) };
+            Ok(())
+        }
+
+        // libmarisa's descriptors are CRT ones here, so write the image
+        // through the handle instead.
+        #[cfg(not(unix))]
+        pub(crate) fn write_to(&self, mut file: &std::fs::File) -> Result<()> {
+            use std::io::Write;
+            file.write_all(&self.to_bytes()?)?;
+            Ok(())
+        }
+
+        /// The image `save` would write.
+        pub fn to_bytes(&self) -> Result<Vec<u8>> {
+            // libmarisa only serializes to files, so go through a temporary one.
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "marisa-image-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            self.save(&path)?;
+            let bytes = std::fs::read(&path);
+            let _ = std::fs::remove_file(&path);
+            bytes.map_err(|err| MarisaError::from(err).at("read", &path))
+        }
+
+        /// 64-bit FNV-1a hash of the serialized image. It equals the
+        /// `content_hash` a manifest records for the saved file, and changes
+        /// whenever the keys or the build config do.
+        pub fn fingerprint(&self) -> Result<u64> {
+            let mut hasher = utils::Fnv1a::default();
+            hasher.update(&self.to_bytes()?);
+            Ok(hasher.finish())
+        }
+
+        pub fn lookup(&self, key: impl AsRef<str>) -> Option<usize> {
+            let key = key.as_ref();
+            if !self.is_ready() {
+                return None;
+            }
+            let _span = trace_span!("marisa::lookup", key_len = key.len());
+            with_cached_agent(|agent| {
+                agent.set_query(key);
+                let found = unsafe { self.trie.as_ref().lookup(agent.agent.as_ptr()) };
+                trace_event!("lookup finished", found = found);
+                found.then(|| agent.key().id() as usize)
+            })
+        }
+
+        pub fn reverse_lookup(&self, id: usize) -> Option<String> {
+            // libmarisa throws on out-of-range ids.
+            if id >= self.num_keys() {
+                return None;
+            }
+            let _span = trace_span!("marisa::reverse_lookup", id = id);
+            with_cached_agent(|agent| {
+                agent.set_query_id(id);
+                unsafe { self.trie.as_ref().reverse_lookup(agent.agent.as_ptr()) };
+                match agent.key().as_str() {
+                    Ok(key) => Some(key.to_owned()),
+                    Err(err) => {
+                        log_warn!("reverse_lookup({id}) skipped a non-UTF-8 key: {err}");
+                        None
+                    }
+                }
+            })
+        }
+
+        /// Restores the keys of `ids`, in the same order, through one agent.
+        /// Ids are visited sorted and each distinct id once, which keeps
+        /// libmarisa's accesses local when restoring millions of them.
+        pub fn reverse_lookup_many(&self, ids: &[usize]) -> Vec<Result<String>> {
+            let _span = trace_span!("marisa::reverse_lookup_many", ids = ids.len());
+            let mut order: Vec<usize> = (0..ids.len()).collect();
+            order.sort_unstable_by_key(|&i| ids[i]);
+
+            let mut agent = Agent::default();
+            let mut keys: Vec<Option<Result<String>>> = (0..ids.len()).map(|_| None).collect();
+            let mut previous: Option<usize> = None;
+            for i in order {
+                let repeated = previous.filter(|&p| ids[p] == ids[i]);
+                let key = match repeated.and_then(|p| keys[p].as_ref()) {
+                    Some(Ok(key)) => Ok(key.clone()),
+                    // Errors aren't `Clone`; they're cheap to produce again.
+                    _ => self.reverse_lookup_in(&mut agent, ids[i]),
+                };
+                keys[i] = Some(key);
+                previous = Some(i);
+            }
+            keys.into_iter()
+                .map(|key| key.expect("every index was visited"))
+                .collect()
+        }
+
+        /// Looks up every key of `keys` through one agent, returning their ids
+        /// in the same order.
+        pub fn lookup_many(
+            &self,
+            keys: &[impl AsRef<str>],
+            order: BatchOrder,
+        ) -> Vec<Option<usize>> {
+            let _span = trace_span!("marisa::lookup_many", keys = keys.len());
+            let mut ids = vec![None; keys.len()];
+            self.lookup_indices(
+                keys,
+                &batch_order(keys, order),
+                &mut Agent::default(),
+                |i, id| ids[i] = id,
+            );
+            ids
+        }
+
+        /// [`Trie::lookup_many`] split across `threads` threads, or one per
+        /// available core when `threads` is 0. With [`BatchOrder::Sorted`]
+        /// each thread gets a contiguous run of the sorted batch.
+        pub fn par_lookup_many(
+            &self,
+            keys: &[impl AsRef<str> + Sync],
+            order: BatchOrder,
+            threads: usize,
+        ) -> Vec<Option<usize>> {
+            let _span = trace_span!("marisa::par_lookup_many", keys = keys.len());
+            let threads = match threads {
+                0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                n => n,
+            };
+            let indices = batch_order(keys, order);
+            let chunk = indices.len().div_ceil(threads).max(1);
+            let found: Vec<Vec<(usize, Option<usize>)>> = std::thread::scope(|scope| {
+                let workers: Vec<_> = indices
+                    .chunks(chunk)
+                    .map(|indices| {
+                        scope.spawn(move || {
+                            let mut found = Vec::with_capacity(indices.len());
+                            self.lookup_indices(keys, indices, &mut Agent::default(), |i, id| {
+                                found.push((i, id))
+                            });
+                            found
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().expect("lookup worker panicked"))
+                    .collect()
+            });
+            let mut ids = vec![None; keys.len()];
+            for (i, id) in found.into_iter().flatten() {
+                ids[i] = id;
+            }
+            ids
+        }
+
+        fn lookup_indices(
+            &self,
+            keys: &[impl AsRef<str>],
+            indices: &[usize],
+            agent: &mut Agent,
+            mut found: impl FnMut(usize, Option<usize>),
+        ) {
+            for &i in indices {
+                agent.set_query(keys[i].as_ref());
+                let hit = self.lookup_agent(agent);
+                found(i, hit.then(|| agent.key().id() as usize));
+            }
+        }
+
+        fn reverse_lookup_in(&self, agent: &mut Agent, id: usize) -> Result<String> {
+            if !self.is_ready() {
+                return Err(MarisaError::NotReady);
+            }
+            agent.set_query_id(id);
+            if !self.reverse_lookup_agent(agent) {
+                return Err(MarisaError::InvalidId {
+                    id,
+                    num_keys: self.num_keys(),
+                });
+            }
+            Ok(std::str::from_utf8(agent.key_bytes())?.to_owned())
+        }
+
+        pub fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<(usize, String)> {
+            self.predictive_search_counting(prefix.as_ref(), &mut 0)
+        }
+
+        /// Keys that are prefixes of `query`, shortest first.
+        pub fn common_prefix_search(&self, query: impl AsRef<str>) -> Vec<(usize, String)> {
+            self.common_prefix_iter(query).collect()
+        }
+
+        /// Looks up the agent's query, leaving the key in `agent.key()`.
+        pub fn lookup_agent(&self, agent: &mut Agent) -> bool {
+            self.is_ready() && unsafe { self.trie.as_ref().lookup(agent.agent.as_ptr()) }
+        }
+
+        /// Restores the key whose id was set with `agent.set_query_id`.
+        /// Returns false for ids out of range instead of aborting.
+        pub fn reverse_lookup_agent(&self, agent: &mut Agent) -> bool {
+            if agent.query_id >= self.num_keys() {
+                return false;
+            }
+            unsafe { self.trie.as_ref().reverse_lookup(agent.agent.as_ptr()) };
+            true
+        }
+
+        /// Steps a predictive search started by `agent.set_query`; each
+        /// `true` leaves the next key in the agent.
+        pub fn predictive_search_agent(&self, agent: &mut Agent) -> bool {
+            self.is_ready() && unsafe { self.trie.as_ref().predictive_search(agent.agent.as_ptr()) }
+        }
+
+        pub fn common_prefix_search_agent(&self, agent: &mut Agent) -> bool {
+            self.is_ready()
+                && unsafe {
+                    self.trie
+                        .as_ref()
+                        .common_prefix_search(agent.agent.as_ptr())
+                }
+        }
+
+        pub(crate) fn reverse_lookup_bytes(&self, id: usize) -> Option<Vec<u8>> {
+            if id >= self.num_keys() {
+                return None;
+            }
+            with_cached_agent(|agent| {
+                agent.set_query_id(id);
+                unsafe { self.trie.as_ref().reverse_lookup(agent.agent.as_ptr()) };
+                Some(agent.key().as_bytes().to_vec())
+            })
+        }
+
+        /// Calls `f` with the id and raw bytes of every key starting with
+        /// `prefix`, stopping early when it returns an error.
+        pub(crate) fn predictive_search_bytes(
+            &self,
+            prefix: &str,
+            mut f: impl FnMut(usize, &[u8]) -> Result<()>,
+        ) -> Result<()> {
+            if !self.is_ready() {
+                return Ok(());
+            }
+            let mut agent = Agent::default();
+            agent.set_query(prefix);
+            while unsafe { self.trie.as_ref().predictive_search(agent.agent.as_ptr()) } {
+                let key = agent.key();
+                f(key.id() as usize, key.as_bytes())?;
+            }
+            Ok(())
+        }
+
+        /// `predictive_search` that also counts how often the agent was
+        /// stepped, including steps that landed on non-UTF-8 keys.
+        pub(crate) fn predictive_search_counting(
+            &self,
+            prefix: &str,
+            steps: &mut usize,
+        ) -> Vec<(usize, String)> {
+            if !self.is_ready() {
+                return Vec::new();
+            }
+            let _span = trace_span!("marisa::predictive_search", prefix_len = prefix.len());
+            let mut agent = Agent::default();
+            agent.set_query(prefix);
+            let mut results = Vec::new();
+            loop {
+                *steps += 1;
+                if !unsafe { self.trie.as_ref().predictive_search(agent.agent.as_ptr()) } {
+                    break;
+                }
+                let key = agent.key();
+                match key.as_str() {
+                    Ok(str) => results.push((key.id() as usize, str.to_owned())),
+                    Err(err) => log_warn!(
+                        "predictive search skipped non-UTF-8 key {}: {err}",
+                        key.id()
+                    ),
+                }
+            }
+            trace_event!("predictive search finished", num_results = results.len());
+            results
+        }
+
+        pub fn load(&mut self, path: &std::path::Path) -> Result<()> {
+            let _span = trace_span!("marisa::load", path = path.to_str().unwrap_or_default());
+            // libmarisa aborts on missing or unreadable files, so check up front.
+            compat::check_file(path).map_err(|err| err.at("load", path))?;
+            let path =
+                utils::c_path(path).map_err(|err| MarisaError::from(err).at("load", path))?;
+            unsafe {
+                self.trie.as_mut().load(path.as_ptr());
+            }
+            self.state = TrieState::Loaded;
+            self.image = None;
+            trace_event!("loaded trie", num_keys = self.num_keys());
+            Ok(())
+        }
+
+        /// Like `load`, but holds a shared advisory lock on the file while
+        /// reading it, waiting for a `save_locked` in progress to finish.
+        pub fn load_locked(&mut self, path: &std::path::Path) -> Result<()> {
+            let _span = trace_span!(
+                "marisa::load_locked",
+                path = path.to_str().unwrap_or_default()
+            );
+            let at = |err: std::io::Error| MarisaError::from(err).at("load", path);
+            let mut file = std::fs::File::open(path).map_err(at)?;
+            file.lock_shared().map_err(at)?;
+            self.read_from(&mut file)
+                .map_err(|err| err.at("load", path))
+        }
+
+        /// Maps `path` instead of reading it. The file must not be truncated
+        /// or rewritten while mapped, which `save` does; replace it by
+        /// renaming a new file over it instead.
+        pub fn mmap(&mut self, path: &std::path::Path) -> Result<()> {
+            let _span = trace_span!("marisa::mmap", path = path.to_str().unwrap_or_default());
+            compat::check_file(path).map_err(|err| err.at("mmap", path))?;
+            let path =
+                utils::c_path(path).map_err(|err| MarisaError::from(err).at("mmap", path))?;
+            unsafe {
+                self.trie.as_mut().mmap(path.as_ptr());
+            }
+            self.state = TrieState::Mapped;
+            self.image = None;
+            trace_event!("mapped trie", num_keys = self.num_keys());
+            Ok(())
+        }
+
+        /// Uses `image` in place, without copying. The image is validated
+        /// first, like a file passed to `load`.
+        pub fn map(&mut self, image: &'static [u8]) -> Result<()> {
+            // SAFETY: the image lives forever.
+            unsafe { self.map_unbounded(image) }
+        }
+
+        /// `map` for an image the caller keeps alive by other means.
+        ///
+        /// # Safety
+        ///
+        /// `image` must stay valid and unchanged for as long as the trie
+        /// uses it, i.e. until it is dropped, cleared, rebuilt or reloaded.
+        pub(crate) unsafe fn map_unbounded(&mut self, image: &[u8]) -> Result<()> {
+            let _span = trace_span!("marisa::map", bytes = image.len());
+            compat::check_bytes(image)?;
+            unsafe {
+                self.trie
+                    .as_mut()
+                    .map(image.as_ptr() as *const _, image.len());
+            }
+            self.state = TrieState::Mapped;
+            self.image = None;
+            trace_event!("mapped trie", num_keys = self.num_keys());
+            Ok(())
+        }
+
+        /// Loads the image starting at `offset` in `file`, e.g. a dictionary
+        /// embedded in an archive. Moves the file position past the image.
+        pub fn read_at(&mut self, file: &std::fs::File, offset: u64) -> Result<()> {
+            let mut file = file;
+            file.seek(SeekFrom::Start(offset))?;
+            self.read_from(&mut file)
+        }
+
+        /// Loads the image at the reader's current position, leaving the
+        /// reader just past it. Only the image's own bytes are read, so
+        /// whatever follows it in a container stays unread.
+        pub fn read_from<R: Read + Seek>(&mut self, reader: &mut R) -> Result<()> {
+            let start = reader.stream_position()?;
+            let info = format::inspect(&mut *reader)?;
+            let _span = trace_span!("marisa::read", bytes = info.io_size);
+            reader.seek(SeekFrom::Start(start))?;
+            self.read_image(info.io_size as usize, |bytes| Ok(reader.read_exact(bytes)?))
+        }
+
+        /// Has `fill` write a `len`-byte image into an aligned buffer the
+        /// trie then owns and maps. The trie is left as it was if `fill`
+        /// fails or the bytes are not a valid image.
+        pub(crate) fn read_image(
+            &mut self,
+            len: usize,
+            fill: impl FnOnce(&mut [u8]) -> Result<()>,
+        ) -> Result<()> {
+            let mut image = vec![0u64; len.div_ceil(8)].into_boxed_slice();
+            let bytes =
+                unsafe { std::slice::from_raw_parts_mut(image.as_mut_ptr() as *mut u8, len) };
+            fill(bytes)?;
+            // libmarisa trusts a mapped image, so a bad one must not reach it.
+            compat::check_bytes(bytes)?;
+            unsafe {
+                self.trie.as_mut().map(bytes.as_ptr() as *const _, len);
+            }
+            self.state = TrieState::Loaded;
+            self.image = Some(image);
+            trace_event!("read trie", num_keys = self.num_keys());
+            Ok(())
         }
     }
 
     #[cfg(test)]
     mod tests {
         mod key_tests {
-            use std::ffi::CStr;
-
             use crate::marisa::{Key, Keyset};
 
             #[test]
@@ -236,7 +1258,7 @@ pub mod marisa {
                 let s = String::from("koko");
                 let k = Key::new(&s);
 
-                assert_eq!(k.str(), Ok("koko"));
+                assert_eq!(k.as_str(), Ok("koko"));
             }
 
             #[test]
@@ -245,10 +1267,10 @@ pub mod marisa {
                 let k = Key::new(&s);
 
                 {
-                    let existing = Key::from(k.key);
-                    assert_eq!(existing.str(), Ok("koko"));
+                    let existing: Key = k.key.into();
+                    assert_eq!(existing.as_str(), Ok("koko"));
                 }
-                assert_eq!(k.str(), Ok("koko"));
+                assert_eq!(k.as_str(), Ok("koko"));
             }
 
             #[test]
@@ -273,13 +1295,45 @@ pub mod marisa {
                 let text = "kockopes".to_owned();
                 k.set_str(&text);
 
-                let c = unsafe { CStr::from_ptr(k.key.ptr_ as *const i8) };
-                let key = c.to_str();
+                assert_eq!(k.as_bytes(), text.as_bytes());
+                assert_eq!(k.key.length_, text.len() as u32);
+            }
+
+            #[test]
+            fn clone_owns_its_bytes() {
+                let k = Key::new("fu\0fi");
+                let copy = k.clone();
+                drop(k);
+
+                assert_eq!(copy.as_str(), Ok("fu\0fi"));
+                assert_eq!(copy.len(), 5);
+            }
+
+            #[test]
+            fn public_types_only_hold_pointers() {
+                use std::mem::size_of;
 
-                assert!(key.is_ok());
+                use crate::marisa::Trie;
 
-                assert_eq!(key.unwrap(), &text);
-                assert_eq!(k.key.length_, (text.len() + 1) as u32);
+                // Independent of the libmarisa version's object layouts.
+                assert_eq!(size_of::<Keyset>(), 2 * size_of::<usize>());
+                assert_eq!(size_of::<Trie>(), 4 * size_of::<usize>());
+            }
+
+            #[test]
+            fn prelude_and_conventional_names() {
+                use crate::prelude::*;
+
+                let mut keyset = Keyset::default();
+                assert!(keyset.is_empty());
+                keyset.push("fu", None).unwrap();
+                assert!(!keyset.is_empty());
+                assert_eq!(keyset.get(0).map(|key| key.len()), Some(2));
+                assert!(keyset.get(1).is_none());
+                assert!(Key::default().is_empty());
+
+                let trie = Trie::build_from(keyset).unwrap();
+                assert!(trie.lookup("fu").is_some());
             }
 
             #[test]
@@ -288,7 +1342,7 @@ pub mod marisa {
                 let text = "pes".to_owned();
                 k.set_str(&text);
 
-                if let Ok(res) = k.str() {
+                if let Ok(res) = k.as_str() {
                     assert_eq!(res, text);
                 } else {
                     panic!("str() failed")
@@ -299,17 +1353,456 @@ pub mod marisa {
             fn work_with_keyset() {
                 let mut keyset = Keyset::default();
 
-                keyset.push("fufi", Some(0.8));
-                keyset.push("fi", Some(0.5));
-                keyset.push("fu", None);
+                keyset.push("fufi", Some(0.8)).unwrap();
+                keyset.push("fi", Some(0.5)).unwrap();
+                keyset.push("fu", None).unwrap();
 
                 assert_eq!(keyset.num_keys(), 3);
-                assert_eq!(keyset.at(0).str().unwrap(), "fufi");
+                assert_eq!(keyset.at(0).as_str().unwrap(), "fufi");
                 assert_eq!(keyset.at(0).weight(), 0.8);
-                assert_eq!(keyset.at(1).str().unwrap(), "fi");
+                assert_eq!(keyset.at(1).as_str().unwrap(), "fi");
                 assert_eq!(keyset.at(1).weight(), 0.5);
-                assert_eq!(keyset.at(2).str().unwrap(), "fu");
+                assert_eq!(keyset.at(2).as_str().unwrap(), "fu");
+                assert_eq!(keyset.at(2).weight(), 1.0);
+            }
+
+            #[test]
+            fn push_all() {
+                let mut keyset = Keyset::default();
+                keyset.push_all(&["fufi", "fi", "fu"]).unwrap();
+                assert_eq!(keyset.num_keys(), 3);
+                assert_eq!(keyset.total_length(), 8);
+                assert_eq!(keyset.at(1).as_str().unwrap(), "fi");
                 assert_eq!(keyset.at(2).weight(), 1.0);
+
+                assert!(keyset.push_all(&["pes", ""]).is_err());
+                assert_eq!(keyset.num_keys(), 3);
+            }
+
+            #[test]
+            fn append() {
+                let mut first = Keyset::default();
+                first.push_all(&["fufi", "fi"]).unwrap();
+                let mut second = Keyset::default();
+                second.push("fu", Some(3.0)).unwrap();
+                second.push("pes", None).unwrap();
+
+                first.append(&second).unwrap();
+                assert_eq!(first.num_keys(), 4);
+                assert_eq!(first.total_length(), 11);
+                assert_eq!(first.at(2).as_str().unwrap(), "fu");
+                assert_eq!(first.at(2).weight(), 3.0);
+                assert_eq!(second.num_keys(), 2);
+
+                let mut with_empty = Keyset::default();
+                with_empty.set_allow_empty_key(true);
+                with_empty.push("", None).unwrap();
+                assert!(first.append(&with_empty).is_err());
+                assert_eq!(first.num_keys(), 4);
+            }
+
+            #[test]
+            fn push_iter() {
+                let mut keyset = Keyset::default();
+                let owned = vec![String::from("fufi"), String::from("fi")];
+                keyset.push_iter(&owned, Some(2.0)).unwrap();
+                keyset.push_iter(std::iter::once("fu"), None).unwrap();
+                assert_eq!(keyset.num_keys(), 3);
+                assert_eq!(keyset.at(1).weight(), 2.0);
+                assert_eq!(keyset.at(2).weight(), 1.0);
+
+                assert!(keyset.push_iter(["pes", "", "x"], None).is_err());
+                assert_eq!(keyset.num_keys(), 4);
+            }
+        }
+
+        mod trie_tests {
+            use crate::{
+                error::MarisaError,
+                marisa::{
+                    with_cached_agent, Agent, BatchOrder, Keyset, Trie, TrieState, CACHED_AGENT,
+                    CACHED_QUERY_CAPACITY,
+                },
+                test_util::build,
+            };
+
+            #[test]
+            fn build_from() {
+                let mut keyset = Keyset::default();
+                keyset.push_all(&["fufi", "fi"]).unwrap();
+                let trie = Trie::build_from(keyset).unwrap();
+                assert_eq!(trie.state(), TrieState::Built);
+                assert!(trie.lookup("fi").is_some());
+
+                // What libmarisa throws comes back as an error, and the trie
+                // keeps what it had.
+                let mut trie = build(&["pes"]);
+                let mut keyset = Keyset::default();
+                keyset.push("fu", None).unwrap();
+                assert!(matches!(
+                    trie.build_with_flags(&mut keyset, u32::MAX),
+                    Err(MarisaError::Io(err)) if err.kind() == std::io::ErrorKind::Other
+                ));
+                assert!(trie.lookup("pes").is_some());
+            }
+
+            #[test]
+            fn agent_searches() {
+                let trie = build(&["fufi", "fi", "fu"]);
+                let mut agent = Agent::default();
+                assert!(agent.key_bytes().is_empty());
+
+                agent.set_query("fufi");
+                assert!(trie.lookup_agent(&mut agent));
+                assert_eq!(agent.key_bytes(), b"fufi");
+
+                agent.set_query_id(agent.key().id() as usize);
+                assert!(trie.reverse_lookup_agent(&mut agent));
+                assert_eq!(agent.key_bytes(), b"fufi");
+                agent.set_query_id(3);
+                assert!(!trie.reverse_lookup_agent(&mut agent));
+
+                agent.set_query("fu");
+                let mut keys = Vec::new();
+                while trie.predictive_search_agent(&mut agent) {
+                    keys.push(agent.key_bytes().to_vec());
+                }
+                keys.sort();
+                assert_eq!(keys, vec![b"fu".to_vec(), b"fufi".to_vec()]);
+            }
+
+            #[test]
+            fn agent_reuses_buffers() {
+                let trie = build(&["fufi", "fu"]);
+                let mut agent = Agent::with_capacity(16);
+                let capacity = agent.query_capacity();
+                assert!(capacity >= 16);
+                for query in ["fu", "fufi", "pes"] {
+                    agent.set_query(query);
+                    trie.lookup_agent(&mut agent);
+                }
+                assert_eq!(agent.query_capacity(), capacity);
+
+                agent.set_query("fu");
+                assert!(trie.lookup_agent(&mut agent));
+                agent.clear();
+                assert!(agent.key_bytes().is_empty());
+                assert_eq!(agent.query_capacity(), capacity);
+
+                agent.shrink_to_fit();
+                assert_eq!(agent.query_capacity(), 0);
+                agent.set_query("fufi");
+                assert!(trie.lookup_agent(&mut agent));
+            }
+
+            #[test]
+            fn lookup_and_reverse_lookup() {
+                let trie = build(&["fufi", "fi", "fu"]);
+
+                let id = trie.lookup("fufi").unwrap();
+                assert_eq!(trie.reverse_lookup(id).as_deref(), Some("fufi"));
+                assert_eq!(trie.lookup("f"), None);
+                assert_eq!(trie.reverse_lookup(3), None);
+            }
+
+            #[test]
+            fn cached_agent_is_reentrant() {
+                let trie = build(&["fufi", "fi", "fu"]);
+                let long = "f".repeat(4 << 10);
+                assert_eq!(trie.lookup(&long), None);
+
+                let mut found = Vec::new();
+                with_cached_agent(|_| {
+                    trie.for_each_predictive("fu", |id, key| {
+                        found.push((trie.lookup(key), trie.reverse_lookup(id)));
+                    });
+                });
+                found.sort();
+                assert_eq!(
+                    found,
+                    vec![
+                        (trie.lookup("fu"), Some("fu".to_owned())),
+                        (trie.lookup("fufi"), Some("fufi".to_owned()))
+                    ]
+                );
+                CACHED_AGENT.with(|agent| {
+                    assert!(agent.borrow().query_capacity() <= CACHED_QUERY_CAPACITY)
+                });
+            }
+
+            #[test]
+            fn empty_key() {
+                let mut keyset = Keyset::default();
+                assert!(matches!(
+                    keyset.push("", None),
+                    Err(crate::error::MarisaError::EmptyKey)
+                ));
+
+                keyset.set_allow_empty_key(true);
+                keyset.push("", None).unwrap();
+                keyset.push("a", None).unwrap();
+                let mut trie = Trie::default();
+                trie.build(&mut keyset);
+
+                let id = trie.lookup("").unwrap();
+                assert_eq!(trie.reverse_lookup(id).as_deref(), Some(""));
+                assert_eq!(trie.predictive_search("").len(), 2);
+                assert_eq!(build(&["a"]).lookup(""), None);
+            }
+
+            #[test]
+            fn map_validates_image() {
+                let image = Box::leak(build(&["fu", "fi"]).to_bytes().unwrap().into_boxed_slice());
+                let mut trie = Trie::default();
+                trie.map(image).unwrap();
+                assert!(trie.lookup("fi").is_some());
+
+                let truncated = &image[..image.len() - 8];
+                assert!(matches!(
+                    Trie::default().map(truncated),
+                    Err(crate::error::MarisaError::IncompatibleFormat { .. })
+                ));
+            }
+
+            #[test]
+            fn not_ready() {
+                let check = |trie: &Trie| {
+                    assert!(!trie.is_ready());
+                    assert_eq!(trie.num_keys(), 0);
+                    assert_eq!(trie.lookup("fu"), None);
+                    assert_eq!(trie.reverse_lookup(0), None);
+                    assert!(trie.predictive_search("").is_empty());
+                    assert!(matches!(
+                        trie.save(&std::env::temp_dir().join("marisa-not-ready")),
+                        Err(crate::error::MarisaError::NotReady)
+                    ));
+                };
+                check(&Trie::default());
+
+                let mut trie = build(&["fu"]);
+                assert_eq!(trie.state(), TrieState::Built);
+                trie.clear();
+                assert_eq!(trie.state(), TrieState::Empty);
+                check(&trie);
+            }
+
+            #[test]
+            fn io_errors_name_the_file() {
+                let missing = std::env::temp_dir().join("marisa-missing-dir/fu");
+                let err = Trie::default().load(&missing).unwrap_err();
+                match &err {
+                    crate::error::MarisaError::File {
+                        operation, path, ..
+                    } => {
+                        assert_eq!(*operation, "load");
+                        assert_eq!(path, &missing);
+                    }
+                    other => panic!("unexpected error {other:?}"),
+                }
+                let source = std::error::Error::source(&err).unwrap();
+                let source = source.downcast_ref::<std::io::Error>().unwrap();
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+                assert!(err.to_string().contains("marisa-missing-dir"));
+
+                assert!(matches!(
+                    build(&["fu"]).save(&missing),
+                    Err(crate::error::MarisaError::File {
+                        operation: "save",
+                        ..
+                    })
+                ));
+
+                // Paths with a NUL byte are an error, not a panic.
+                let nul = std::path::Path::new("marisa\0fu");
+                assert!(Trie::default().mmap(nul).is_err());
+                assert!(build(&["fu"]).save(nul).is_err());
+                assert_eq!(
+                    crate::utils::c_path(nul).unwrap_err().kind(),
+                    std::io::ErrorKind::InvalidInput
+                );
+            }
+
+            #[test]
+            fn read_embedded_images() {
+                let first = build(&["fu", "fufi"]).to_bytes().unwrap();
+                let second = build(&["pes"]).to_bytes().unwrap();
+                let mut container = b"header".to_vec();
+                container.extend_from_slice(&first);
+                container.extend_from_slice(&second);
+                container.extend_from_slice(b"trailer");
+
+                let mut reader = std::io::Cursor::new(&container);
+                reader.set_position(6);
+                let mut trie = Trie::default();
+                trie.read_from(&mut reader).unwrap();
+                assert_eq!(trie.state(), TrieState::Loaded);
+                assert!(trie.lookup("fufi").is_some());
+                assert_eq!(reader.position() as usize, 6 + first.len());
+                trie.read_from(&mut reader).unwrap();
+                assert_eq!(trie.num_keys(), 1);
+                assert!(trie.lookup("pes").is_some());
+
+                let path =
+                    std::env::temp_dir().join(format!("marisa-read-at-{}", std::process::id()));
+                std::fs::write(&path, &container).unwrap();
+                let file = std::fs::File::open(&path).unwrap();
+                let mut trie = Trie::default();
+                trie.read_at(&file, 6 + first.len() as u64).unwrap();
+                assert!(trie.lookup("pes").is_some());
+                assert!(trie.read_at(&file, 0).is_err());
+                std::fs::remove_file(&path).unwrap();
+            }
+
+            #[test]
+            fn read_rejects_bad_images() {
+                let image = build(&["fu", "fufi"]).to_bytes().unwrap();
+                let mut trie = build(&["pes"]);
+                for bad in [&image[..image.len() - 3], &[0xA5; 64][..], &[][..]] {
+                    assert!(matches!(
+                        trie.read_from(&mut std::io::Cursor::new(bad)),
+                        Err(MarisaError::IncompatibleFormat { .. })
+                    ));
+                }
+                assert!(matches!(
+                    trie.read_image(64, |bytes| {
+                        bytes.fill(0xA5);
+                        Ok(())
+                    }),
+                    Err(MarisaError::IncompatibleFormat { .. })
+                ));
+                assert!(trie.lookup("pes").is_some());
+                assert_eq!(trie.num_keys(), 1);
+            }
+
+            #[test]
+            fn locked_save_and_load() {
+                let path =
+                    std::env::temp_dir().join(format!("marisa-locked-{}", std::process::id()));
+                build(&["fu", "fufi", "pes"]).save_locked(&path).unwrap();
+                build(&["fi"]).save_locked(&path).unwrap();
+
+                let mut trie = Trie::default();
+                trie.load_locked(&path).unwrap();
+                assert_eq!(trie.num_keys(), 1);
+                assert!(trie.lookup("fi").is_some());
+
+                // Both locks are released once the calls return.
+                let file = std::fs::File::open(&path).unwrap();
+                assert!(file.try_lock().is_ok());
+                drop(file);
+                std::fs::remove_file(&path).unwrap();
+
+                assert!(matches!(
+                    Trie::default().load_locked(&path),
+                    Err(MarisaError::File {
+                        operation: "load",
+                        ..
+                    })
+                ));
+            }
+
+            #[test]
+            fn fingerprint() {
+                let a = build(&["fu", "fi"]).fingerprint().unwrap();
+                assert_eq!(build(&["fi", "fu"]).fingerprint().unwrap(), a);
+                assert_ne!(build(&["fi", "fu", "pes"]).fingerprint().unwrap(), a);
+            }
+
+            #[test]
+            fn exact_lengths() {
+                let trie = build(&["fu", "fu\0fi", "fufi"]);
+                assert_eq!(trie.num_keys(), 3);
+
+                let id = trie.lookup("fu\0fi").unwrap();
+                assert_eq!(trie.reverse_lookup(id).as_deref(), Some("fu\0fi"));
+                assert_ne!(trie.lookup("fu"), Some(id));
+                assert_eq!(trie.lookup("fu\0"), None);
+                assert_eq!(trie.predictive_search("fu\0").len(), 1);
+            }
+
+            #[test]
+            fn common_prefix_search() {
+                let trie = build(&["f", "fu", "fufi", "pes"]);
+
+                let keys: Vec<String> = trie
+                    .common_prefix_search("fufik")
+                    .into_iter()
+                    .map(|(_, key)| key)
+                    .collect();
+                assert_eq!(keys, vec!["f", "fu", "fufi"]);
+                assert!(trie.common_prefix_search("pe").is_empty());
+                assert!(Trie::default().common_prefix_search("fu").is_empty());
+            }
+
+            #[test]
+            fn string_like_arguments() {
+                use std::{borrow::Cow, rc::Rc};
+
+                let mut keyset = Keyset::default();
+                keyset.push(String::from("fufi"), None).unwrap();
+                keyset.push(Rc::<str>::from("fu"), None).unwrap();
+                keyset.push_all(&[String::from("pes")]).unwrap();
+                let trie = Trie::build_from(keyset).unwrap();
+
+                assert!(trie.lookup(Cow::Borrowed("fu")).is_some());
+                assert!(trie.lookup(String::from("pes")).is_some());
+                assert_eq!(trie.predictive_search(Rc::<str>::from("fu")).len(), 2);
+                assert_eq!(trie.common_prefix_search(String::from("fufik")).len(), 2);
+            }
+
+            #[test]
+            fn lookup_many() {
+                let trie = build(&["f", "fu", "fufi", "pes"]);
+                let keys = ["pes", "x", "fu", "f", "pes", "fufik"];
+                let expected: Vec<Option<usize>> = keys.iter().map(|k| trie.lookup(k)).collect();
+                for order in [BatchOrder::AsGiven, BatchOrder::Sorted] {
+                    assert_eq!(trie.lookup_many(&keys, order), expected);
+                    for threads in [0, 1, 4, 16] {
+                        assert_eq!(trie.par_lookup_many(&keys, order, threads), expected);
+                    }
+                }
+                assert!(trie
+                    .par_lookup_many(&[""; 0], BatchOrder::Sorted, 2)
+                    .is_empty());
+                assert_eq!(
+                    Trie::default().lookup_many(&["f"], BatchOrder::AsGiven),
+                    vec![None]
+                );
+            }
+
+            #[test]
+            fn reverse_lookup_many() {
+                let trie = build(&["f", "fu", "fufi", "pes"]);
+                let fu = trie.lookup("fu").unwrap();
+                let pes = trie.lookup("pes").unwrap();
+
+                let keys = trie.reverse_lookup_many(&[pes, fu, 9, pes]);
+                assert_eq!(keys[0].as_deref().unwrap(), "pes");
+                assert_eq!(keys[1].as_deref().unwrap(), "fu");
+                assert!(matches!(
+                    keys[2],
+                    Err(MarisaError::InvalidId { id: 9, num_keys: 4 })
+                ));
+                assert_eq!(keys[3].as_deref().unwrap(), "pes");
+
+                assert!(matches!(
+                    Trie::default().reverse_lookup_many(&[0])[0],
+                    Err(MarisaError::NotReady)
+                ));
+            }
+
+            #[test]
+            fn predictive_search() {
+                let trie = build(&["fufi", "fi", "fu", "pes"]);
+
+                let mut keys: Vec<String> = trie
+                    .predictive_search("fu")
+                    .into_iter()
+                    .map(|(_, key)| key)
+                    .collect();
+                keys.sort();
+                assert_eq!(keys, vec!["fu", "fufi"]);
+                assert_eq!(trie.predictive_search("").len(), 4);
             }
         }
     }
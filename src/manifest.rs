@@ -0,0 +1,215 @@
+//! A JSON description of a built dictionary, stored next to it as
+//! `<dictionary>.manifest.json`.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    error::{MarisaError, Result},
+    format,
+    marisa::Trie,
+    utils::Fnv1a,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Where the keys came from: a corpus name, URL or pipeline id.
+    pub source: String,
+    pub config: Config,
+    pub num_keys: usize,
+    /// `fnv1a64:` followed by the hex digest of the dictionary file.
+    pub content_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+}
+
+pub(crate) fn content_hash(path: &Path) -> Result<String> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut hasher = Fnv1a::default();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("fnv1a64:{:016x}", hasher.finish()))
+}
+
+impl Manifest {
+    /// Describes the dictionary saved at `path`.
+    pub fn for_file(path: &Path, source: &str) -> Result<Manifest> {
+        let info = format::inspect_file(path)?;
+        let config = info.config().ok_or_else(|| {
+            MarisaError::incompatible(format!("invalid config flags {:#x}", info.config_flags()))
+        })?;
+        Ok(Manifest {
+            source: source.to_owned(),
+            config,
+            num_keys: info.num_keys(),
+            content_hash: content_hash(path)?,
+            license: None,
+        })
+    }
+
+    pub fn with_license(mut self, license: &str) -> Self {
+        self.license = Some(license.to_owned());
+        self
+    }
+
+    /// The manifest location used for the dictionary at `dictionary`.
+    pub fn path_for(dictionary: &Path) -> PathBuf {
+        let mut path = dictionary.as_os_str().to_owned();
+        path.push(".manifest.json");
+        PathBuf::from(path)
+    }
+
+    /// Writes the manifest as JSON, replacing `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let at = |err: std::io::Error| MarisaError::from(err).at("save", path);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut out = BufWriter::new(File::create(&temp).map_err(at)?);
+        serde_json::to_writer_pretty(&mut out, self)?;
+        out.into_inner()
+            .map_err(|err| at(err.into_error()))?
+            .sync_all()
+            .map_err(at)?;
+        std::fs::rename(&temp, path).map_err(at)
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Checks that the dictionary at `path` is the build this manifest
+    /// describes.
+    pub fn check(&self, path: &Path) -> Result<()> {
+        let found = Manifest::for_file(path, &self.source)?;
+        let mismatch = |field, expected: String, found: String| {
+            Err(MarisaError::ManifestMismatch {
+                field,
+                expected,
+                found,
+            })
+        };
+        if found.content_hash != self.content_hash {
+            return mismatch(
+                "content_hash",
+                self.content_hash.clone(),
+                found.content_hash,
+            );
+        }
+        if found.num_keys != self.num_keys {
+            return mismatch(
+                "num_keys",
+                self.num_keys.to_string(),
+                found.num_keys.to_string(),
+            );
+        }
+        if found.config != self.config {
+            return mismatch(
+                "config",
+                format!("{:?}", self.config),
+                format!("{:?}", found.config),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Trie {
+    /// Saves the trie and writes its manifest next to it.
+    pub fn save_with_manifest(&self, path: &Path, source: &str) -> Result<Manifest> {
+        self.save(path)?;
+        let manifest = Manifest::for_file(path, source)?;
+        manifest.save(&Manifest::path_for(path))?;
+        Ok(manifest)
+    }
+
+    /// Loads the trie after checking it against the manifest stored next
+    /// to it, returning both.
+    pub fn load_with_manifest(path: &Path) -> Result<(Trie, Manifest)> {
+        let manifest = Manifest::load(&Manifest::path_for(path))?;
+        manifest.check(path)?;
+        let mut trie = Trie::default();
+        trie.load(path)?;
+        Ok((trie, manifest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use crate::{config::Config, error::MarisaError, marisa::Trie, test_util::temp_path};
+
+    #[test]
+    fn save_and_check() {
+        let path = temp_path("manifest-ok");
+        let trie = Trie::try_from(&["a", "b"][..]).unwrap();
+        let manifest = trie.save_with_manifest(&path, "unit-test").unwrap();
+        assert_eq!(manifest.config, Config::default());
+        assert_eq!(
+            manifest.content_hash,
+            format!("fnv1a64:{:016x}", trie.fingerprint().unwrap())
+        );
+
+        let (loaded, manifest) = Trie::load_with_manifest(&path).unwrap();
+        assert_eq!(loaded.num_keys(), 2);
+        assert_eq!(manifest.source, "unit-test");
+
+        let json = std::fs::read_to_string(Manifest::path_for(&path)).unwrap();
+        assert!(!json.contains("license"));
+
+        std::fs::remove_file(Manifest::path_for(&path)).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detects_replaced_dictionary() {
+        let path = temp_path("manifest-replaced");
+        Trie::try_from(&["a"][..])
+            .unwrap()
+            .save_with_manifest(&path, "v1")
+            .unwrap();
+        Trie::try_from(&["a", "b"][..])
+            .unwrap()
+            .save(&path)
+            .unwrap();
+
+        assert!(matches!(
+            Trie::load_with_manifest(&path),
+            Err(MarisaError::ManifestMismatch {
+                field: "content_hash",
+                ..
+            })
+        ));
+
+        std::fs::remove_file(Manifest::path_for(&path)).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn license_round_trip() {
+        let path = temp_path("manifest-license.json");
+        let manifest = Manifest {
+            source: "wiki".to_owned(),
+            config: Config::default(),
+            num_keys: 10,
+            content_hash: "fnv1a64:0".to_owned(),
+            license: None,
+        }
+        .with_license("CC-BY-SA-4.0");
+        manifest.save(&path).unwrap();
+        assert_eq!(Manifest::load(&path).unwrap(), manifest);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! A string-keyed map storing its keys in a trie and its values in a
+//! vector indexed by key id.
+
+use std::collections::HashMap;
+
+use crate::{
+    error::Result,
+    marisa::{Keyset, Trie},
+};
+
+pub struct TrieMap<V> {
+    /// Only ever built from `&str` keys, never loaded or mapped, so every
+    /// key is UTF-8.
+    keys: Trie,
+    /// Indexed by key id.
+    values: Vec<V>,
+}
+
+impl<V> TrieMap<V> {
+    /// Builds from `(key, value)` entries; for a key given more than once,
+    /// the last value is kept. The empty key is allowed.
+    pub fn new<K: AsRef<str>>(entries: impl IntoIterator<Item = (K, V)>) -> Result<Self> {
+        let entries: Vec<(K, V)> = entries.into_iter().collect();
+        let keys = Self::build_keys(entries.iter().map(|(key, _)| key))?;
+        Ok(Self::with_keys(keys, entries))
+    }
+
+    /// The key trie `new` would build for `keys`.
+    pub(crate) fn build_keys(keys: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Trie> {
+        let mut keyset = Keyset::default();
+        keyset.set_allow_empty_key(true);
+        for key in keys {
+            keyset.push(key, None)?;
+        }
+        Trie::build_from(keyset)
+    }
+
+    /// Pairs `keys` with the values of `entries`, which must hold every key
+    /// of the trie and no other.
+    pub(crate) fn with_keys<K: AsRef<str>>(
+        keys: Trie,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Self {
+        let mut values: Vec<Option<V>> = (0..keys.num_keys()).map(|_| None).collect();
+        for (key, value) in entries {
+            values[keys.lookup(key.as_ref()).expect("key was just added")] = Some(value);
+        }
+        Self {
+            keys,
+            values: values.into_iter().map(Option::unwrap).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, key: impl AsRef<str>) -> Option<&V> {
+        let key = key.as_ref();
+        self.keys.lookup(key).map(|id| &self.values[id])
+    }
+
+    pub fn get_mut(&mut self, key: impl AsRef<str>) -> Option<&mut V> {
+        let key = key.as_ref();
+        self.keys.lookup(key).map(|id| &mut self.values[id])
+    }
+
+    pub fn contains_key(&self, key: impl AsRef<str>) -> bool {
+        let key = key.as_ref();
+        self.keys.lookup(key).is_some()
+    }
+
+    /// The entry for `key`, or `None` if the map doesn't hold it: the keys
+    /// are fixed when the map is built, so every entry is occupied. Use a
+    /// [`DynamicTrie`](crate::dynamic::DynamicTrie) to insert.
+    pub fn entry(&mut self, key: impl AsRef<str>) -> Option<OccupiedEntry<'_, V>> {
+        let key = key.as_ref();
+        let id = self.keys.lookup(key)?;
+        Some(OccupiedEntry::new(key.to_owned(), &mut self.values[id]))
+    }
+
+    /// The value of the key with the given id in [`TrieMap::keys`].
+    pub fn get_by_id(&self, id: usize) -> Option<&V> {
+        self.values.get(id)
+    }
+
+    pub(crate) fn get_by_id_mut(&mut self, id: usize) -> Option<&mut V> {
+        self.values.get_mut(id)
+    }
+
+    pub fn keys(&self) -> &Trie {
+        &self.keys
+    }
+
+    /// Entries whose key starts with `prefix`.
+    pub fn predictive_iter(
+        &self,
+        prefix: impl AsRef<str>,
+    ) -> impl Iterator<Item = (String, &V)> + '_ {
+        let prefix = prefix.as_ref();
+        self.keys
+            .predictive_iter(prefix)
+            .map(|(id, key)| (key, &self.values[id]))
+    }
+
+    /// Every entry, in key id order.
+    pub fn into_entries(self) -> Vec<(String, V)> {
+        let keys = self.keys;
+        self.values
+            .into_iter()
+            .enumerate()
+            .map(|(id, value)| {
+                (
+                    keys.reverse_lookup(id).expect("built from &str keys"),
+                    value,
+                )
+            })
+            .collect()
+    }
+
+    /// The entry with the longest key that is a prefix of `query`, as the
+    /// key's length in bytes and its value.
+    pub fn longest_prefix(&self, query: impl AsRef<str>) -> Option<(usize, &V)> {
+        let query = query.as_ref();
+        let mut longest = None;
+        self.keys
+            .for_each_common_prefix(query, |id, key| longest = Some((key.len(), id)));
+        longest.map(|(len, id)| (len, &self.values[id]))
+    }
+}
+
+/// An entry known to hold a value, from [`TrieMap::entry`] or
+/// [`DynamicTrie::entry`](crate::dynamic::DynamicTrie::entry).
+pub struct OccupiedEntry<'a, V> {
+    key: String,
+    value: &'a mut V,
+}
+
+impl<'a, V> OccupiedEntry<'a, V> {
+    pub(crate) fn new(key: String, value: &'a mut V) -> Self {
+        Self { key, value }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.value
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.value, value)
+    }
+}
+
+/// A string-keyed map allowing several values per key, kept in the order
+/// they were given.
+pub struct TrieMultimap<V> {
+    map: TrieMap<Vec<V>>,
+}
+
+impl<V> TrieMultimap<V> {
+    pub fn new<K: AsRef<str>>(entries: impl IntoIterator<Item = (K, V)>) -> Result<Self> {
+        let mut grouped: Vec<(K, Vec<V>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for (key, value) in entries {
+            match index.get(key.as_ref()) {
+                Some(&i) => grouped[i].1.push(value),
+                None => {
+                    index.insert(key.as_ref().to_owned(), grouped.len());
+                    grouped.push((key, vec![value]));
+                }
+            }
+        }
+        Ok(Self {
+            map: TrieMap::new(grouped)?,
+        })
+    }
+
+    /// Number of distinct keys.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The values of `key`, empty if it has none.
+    pub fn get(&self, key: impl AsRef<str>) -> &[V] {
+        let key = key.as_ref();
+        self.map.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn contains_key(&self, key: impl AsRef<str>) -> bool {
+        let key = key.as_ref();
+        self.map.contains_key(key)
+    }
+
+    pub fn keys(&self) -> &Trie {
+        self.map.keys()
+    }
+
+    /// Keys that are prefixes of `query`, shortest first, with their values.
+    pub fn common_prefixes(&self, query: impl AsRef<str>) -> Vec<(usize, &[V])> {
+        let query = query.as_ref();
+        let mut found = Vec::new();
+        self.map.keys().for_each_common_prefix(query, |id, key| {
+            found.push((
+                key.len(),
+                self.map.get_by_id(id).map_or(&[][..], Vec::as_slice),
+            ));
+        });
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrieMap, TrieMultimap};
+
+    #[test]
+    fn map_operations() {
+        let map = TrieMap::new([("fu", 1), ("fufi", 2), ("fi", 3), ("fu", 4)]).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get("fu"), Some(&4));
+        assert_eq!(map.get("f"), None);
+        assert!(map.contains_key("fi"));
+
+        let id = map.keys().lookup("fufi").unwrap();
+        assert_eq!(map.get_by_id(id), Some(&2));
+
+        let mut completions: Vec<(String, i32)> =
+            map.predictive_iter("fu").map(|(k, v)| (k, *v)).collect();
+        completions.sort();
+        assert_eq!(
+            completions,
+            vec![("fu".to_owned(), 4), ("fufi".to_owned(), 2)]
+        );
+
+        assert_eq!(map.longest_prefix("fufik"), Some((4, &2)));
+        assert_eq!(map.longest_prefix("x"), None);
+
+        let mut map = map;
+        *map.get_mut("fi").unwrap() += 10;
+        assert!(map.entry("f").is_none());
+        let mut entry = map.entry("fufi").unwrap();
+        assert_eq!(entry.key(), "fufi");
+        *entry.get_mut() += 1;
+        assert_eq!(entry.insert(5), 3);
+        assert_eq!(map.get("fufi"), Some(&5));
+        *map.entry("fufi").unwrap().into_mut() -= 3;
+        let mut entries = map.into_entries();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ("fi".to_owned(), 13),
+                ("fu".to_owned(), 4),
+                ("fufi".to_owned(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn multimap_groups_values() {
+        let map = TrieMultimap::new([("tv", 1), ("tele", 2), ("tv", 3)]).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("tv"), &[1, 3]);
+        assert!(map.get("t").is_empty());
+        assert_eq!(map.common_prefixes("tvs"), vec![(2, &[1, 3][..])]);
+    }
+}
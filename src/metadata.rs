@@ -0,0 +1,290 @@
+//! Application metadata embedded in front of a saved trie.
+//!
+//! A file written by [`Trie::save_with_metadata`] is a metadata header
+//! followed by the plain libmarisa image:
+//!
+//! ```text
+//! magic      8 bytes   "MRSMETA\0"
+//! version    u32       1; bumped only for incompatible layout changes
+//! length     u32       bytes of header body that follow
+//! body       schema_version u32, producer str, num_keys u64,
+//!            field count u32, then (name str, value str) pairs
+//! image      what `Trie::save` writes
+//! ```
+//!
+//! Integers are little-endian and `str` is a `u32` byte length followed by
+//! UTF-8. Readers skip body bytes past the fields they know, so later
+//! versions can append fields without breaking older readers. Such files
+//! can't be `mmap`ed, since libmarisa maps whole files.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::Trie,
+};
+
+const MAGIC: &[u8; 8] = b"MRSMETA\0";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metadata {
+    /// The application's version of what keys and ids mean. Loading
+    /// checks it against the version the caller expects.
+    pub schema_version: u32,
+    /// The tool or pipeline that built the dictionary.
+    pub producer: String,
+    /// Filled in by `save_with_metadata` and checked against the trie on
+    /// load.
+    pub num_keys: u64,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn new(schema_version: u32, producer: &str) -> Self {
+        Self {
+            schema_version,
+            producer: producer.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_field(mut self, name: &str, value: &str) -> Self {
+        self.fields.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.schema_version.to_le_bytes());
+        put_str(&mut body, &self.producer);
+        body.extend_from_slice(&self.num_keys.to_le_bytes());
+        body.extend_from_slice(&(self.fields.len() as u32).to_le_bytes());
+        for (name, value) in &self.fields {
+            put_str(&mut body, name);
+            put_str(&mut body, value);
+        }
+
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        header.extend_from_slice(&body);
+        header
+    }
+
+    /// Reads the header at the reader's position, leaving it at the image.
+    /// `Ok(None)` if there is no header there.
+    pub fn read_from(reader: &mut impl Read) -> Result<Option<Metadata>> {
+        let mut magic = [0; 8];
+        match reader.read_exact(&mut magic) {
+            Ok(()) if &magic == MAGIC => {}
+            Ok(()) => return Ok(None),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(MarisaError::incompatible_with(
+                "unsupported metadata header version",
+                VERSION.to_string(),
+                version.to_string(),
+            ));
+        }
+        // Read through `take` rather than into a buffer of the stated length,
+        // so a corrupt length can't allocate more than the file holds.
+        let len = read_u32(reader)?;
+        let mut body = Vec::new();
+        reader
+            .take(len.into())
+            .read_to_end(&mut body)
+            .map_err(truncated)?;
+        if body.len() != len as usize {
+            return Err(MarisaError::incompatible("truncated metadata header"));
+        }
+
+        let mut body = &body[..];
+        let schema_version = read_u32(&mut body)?;
+        let producer = read_str(&mut body)?;
+        let num_keys = read_u64(&mut body)?;
+        let mut fields = BTreeMap::new();
+        for _ in 0..read_u32(&mut body)? {
+            let name = read_str(&mut body)?;
+            fields.insert(name, read_str(&mut body)?);
+        }
+        Ok(Some(Metadata {
+            schema_version,
+            producer,
+            num_keys,
+            fields,
+        }))
+    }
+
+    /// The metadata of the file at `path`, `None` for a plain image.
+    pub fn read_file(path: &Path) -> Result<Option<Metadata>> {
+        let mut file = File::open(path).map_err(|err| MarisaError::from(err).at("load", path))?;
+        Metadata::read_from(&mut file)
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn truncated(err: std::io::Error) -> MarisaError {
+    if err.kind() == std::io::ErrorKind::UnexpectedEof {
+        MarisaError::incompatible("truncated metadata header")
+    } else {
+        err.into()
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_str(reader: &mut &[u8]) -> Result<String> {
+    let len = read_u32(reader)? as usize;
+    if len > reader.len() {
+        return Err(MarisaError::incompatible("truncated metadata header"));
+    }
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(std::str::from_utf8(bytes)?.to_owned())
+}
+
+impl Trie {
+    /// Saves the trie behind a header holding `metadata`, with its
+    /// `num_keys` set to this trie's.
+    pub fn save_with_metadata(&self, path: &Path, metadata: &Metadata) -> Result<()> {
+        if !self.is_ready() {
+            return Err(MarisaError::NotReady);
+        }
+        let metadata = Metadata {
+            num_keys: self.num_keys() as u64,
+            ..metadata.clone()
+        };
+        let at = |err: std::io::Error| MarisaError::from(err).at("save", path);
+        let mut file = File::create(path).map_err(at)?;
+        file.write_all(&metadata.to_bytes()).map_err(at)?;
+        self.write_to(&file).map_err(|err| err.at("save", path))
+    }
+
+    /// Loads a file written by `save_with_metadata`, rejecting it with
+    /// `ManifestMismatch` unless its schema version is `schema_version` and
+    /// its key count matches the image.
+    pub fn load_with_metadata(path: &Path, schema_version: u32) -> Result<(Trie, Metadata)> {
+        let file = File::open(path).map_err(|err| MarisaError::from(err).at("load", path))?;
+        let mut reader = BufReader::new(file);
+        let metadata = Metadata::read_from(&mut reader)?
+            .ok_or_else(|| MarisaError::incompatible("no metadata header"))?;
+        let mismatch = |field, expected: String, found: String| {
+            Err(MarisaError::ManifestMismatch {
+                field,
+                expected,
+                found,
+            })
+        };
+        if metadata.schema_version != schema_version {
+            return mismatch(
+                "schema_version",
+                schema_version.to_string(),
+                metadata.schema_version.to_string(),
+            );
+        }
+        let mut trie = Trie::default();
+        trie.read_from(&mut reader)
+            .map_err(|err| err.at("load", path))?;
+        if metadata.num_keys != trie.num_keys() as u64 {
+            return mismatch(
+                "num_keys",
+                metadata.num_keys.to_string(),
+                trie.num_keys().to_string(),
+            );
+        }
+        Ok((trie, metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metadata, MAGIC};
+    use crate::{error::MarisaError, marisa::Trie, test_util::temp_path};
+
+    #[test]
+    fn round_trip_and_schema_check() {
+        let path = temp_path("metadata-ok");
+        let metadata = Metadata::new(3, "unit-test").with_field("locale", "cs");
+        Trie::try_from(&["fu", "fufi"][..])
+            .unwrap()
+            .save_with_metadata(&path, &metadata)
+            .unwrap();
+
+        let found = Metadata::read_file(&path).unwrap().unwrap();
+        assert_eq!(found.num_keys, 2);
+        assert_eq!(found.fields["locale"], "cs");
+
+        let (trie, loaded) = Trie::load_with_metadata(&path, 3).unwrap();
+        assert_eq!(loaded, found);
+        assert!(trie.lookup("fufi").is_some());
+
+        assert!(matches!(
+            Trie::load_with_metadata(&path, 4),
+            Err(MarisaError::ManifestMismatch {
+                field: "schema_version",
+                ..
+            })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_unknown_fields_and_rejects_plain_images() {
+        let path = temp_path("metadata-future");
+        let trie = Trie::try_from(&["pes"][..]).unwrap();
+        let mut header = Metadata {
+            num_keys: 1,
+            ..Metadata::new(1, "newer")
+        }
+        .to_bytes();
+        // A later writer appending a field the body length accounts for.
+        let body_len = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        header[12..16].copy_from_slice(&(body_len + 4).to_le_bytes());
+        header.extend_from_slice(b"new!");
+        header.extend_from_slice(&trie.to_bytes().unwrap());
+        std::fs::write(&path, &header).unwrap();
+        assert!(header.starts_with(MAGIC));
+
+        let (loaded, metadata) = Trie::load_with_metadata(&path, 1).unwrap();
+        assert_eq!(metadata.producer, "newer");
+        assert!(loaded.lookup("pes").is_some());
+
+        // A body length far past the end of the file.
+        header[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Metadata::read_from(&mut &header[..]),
+            Err(MarisaError::IncompatibleFormat { .. })
+        ));
+
+        trie.save(&path).unwrap();
+        assert_eq!(Metadata::read_file(&path).unwrap(), None);
+        assert!(matches!(
+            Trie::load_with_metadata(&path, 1),
+            Err(MarisaError::IncompatibleFormat { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,25 @@
+//! Hooks for exporting search and reload statistics.
+//!
+//! `MultiTrie` and `DictionaryRegistry` report to a `MetricsSink` when one
+//! is set, so counters and latency histograms (Prometheus, StatsD, ...) can
+//! be fed without wrapping every call.
+
+use std::time::Duration;
+
+/// Receives one call per operation. Every method defaults to doing nothing,
+/// so implementations only override what they export.
+pub trait MetricsSink: Send + Sync {
+    /// An exact-match lookup finished; `hit` is whether any dictionary had the key.
+    fn on_lookup(&self, hit: bool, latency: Duration) {
+        let _ = (hit, latency);
+    }
+
+    fn on_predictive_search(&self, num_results: usize, latency: Duration) {
+        let _ = (num_results, latency);
+    }
+
+    /// A registry reload of `dictionary` finished, successfully or not.
+    fn on_reload(&self, dictionary: &str, success: bool, latency: Duration) {
+        let _ = (dictionary, success, latency);
+    }
+}
@@ -0,0 +1,159 @@
+//! UniFFI bindings, so Kotlin and Swift apps (input methods, offline
+//! search) can ship dictionaries without hand-written JNI.
+//!
+//! Build the library as a `cdylib` and generate the bindings from it:
+//!
+//! ```text
+//! cargo rustc --release --features uniffi --crate-type cdylib
+//! uniffi-bindgen generate --library target/release/libmarisa.so --language kotlin --out-dir out
+//! ```
+//!
+//! Ids cross the boundary as `u64`; errors are `MarisaException` in Kotlin
+//! and `MarisaError` in Swift.
+
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    error::Result,
+    marisa::{Keyset, Trie},
+};
+
+/// A key and its id.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct Completion {
+    pub id: u64,
+    pub key: String,
+}
+
+impl Completion {
+    fn new((id, key): (usize, String)) -> Self {
+        Self { id: id as u64, key }
+    }
+}
+
+/// A read-only dictionary, safe to share between threads.
+#[derive(uniffi::Object)]
+pub struct MarisaTrie {
+    trie: Trie,
+}
+
+#[uniffi::export]
+impl MarisaTrie {
+    /// Builds from `keys`; duplicates are kept once.
+    #[uniffi::constructor]
+    pub fn new(keys: Vec<String>) -> Result<Arc<Self>> {
+        let mut keyset = Keyset::default();
+        keyset.push_all(&keys)?;
+        Ok(Arc::new(Self {
+            trie: Trie::build_from(keyset)?,
+        }))
+    }
+
+    #[uniffi::constructor]
+    pub fn load(path: String) -> Result<Arc<Self>> {
+        let mut trie = Trie::default();
+        trie.load(Path::new(&path))?;
+        Ok(Arc::new(Self { trie }))
+    }
+
+    /// Maps the file instead of reading it, e.g. a dictionary extracted
+    /// from the APK or app bundle; pages are loaded on first use.
+    #[uniffi::constructor]
+    pub fn mmap(path: String) -> Result<Arc<Self>> {
+        let mut trie = Trie::default();
+        trie.mmap(Path::new(&path))?;
+        Ok(Arc::new(Self { trie }))
+    }
+
+    pub fn save(&self, path: String) -> Result<()> {
+        self.trie.save(Path::new(&path))
+    }
+
+    pub fn num_keys(&self) -> u64 {
+        self.trie.num_keys() as u64
+    }
+
+    pub fn contains(&self, key: String) -> bool {
+        self.trie.lookup(key).is_some()
+    }
+
+    pub fn lookup(&self, key: String) -> Option<u64> {
+        self.trie.lookup(key).map(|id| id as u64)
+    }
+
+    pub fn reverse_lookup(&self, id: u64) -> Option<String> {
+        self.trie.reverse_lookup(usize::try_from(id).ok()?)
+    }
+
+    /// Keys starting with `prefix`, at most `limit` of them when given.
+    pub fn predictive_search(&self, prefix: String, limit: Option<u32>) -> Vec<Completion> {
+        self.trie
+            .predictive_iter(prefix)
+            .take(limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(Completion::new)
+            .collect()
+    }
+
+    /// Keys that are prefixes of `query`, shortest first.
+    pub fn common_prefix_search(&self, query: String) -> Vec<Completion> {
+        self.trie
+            .common_prefix_search(query)
+            .into_iter()
+            .map(Completion::new)
+            .collect()
+    }
+
+    /// The longest key that is a prefix of `query`, as used for
+    /// segmenting input.
+    pub fn longest_prefix(&self, query: String) -> Option<Completion> {
+        self.trie
+            .common_prefix_search(query)
+            .pop()
+            .map(Completion::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Completion, MarisaTrie};
+    use crate::error::MarisaError;
+
+    #[test]
+    fn exported_api() {
+        let trie =
+            MarisaTrie::new(["ka", "kana", "kanji", "ki"].map(String::from).to_vec()).unwrap();
+        assert_eq!(trie.num_keys(), 4);
+        assert!(trie.contains("kana".to_owned()));
+
+        let id = trie.lookup("kanji".to_owned()).unwrap();
+        assert_eq!(trie.reverse_lookup(id).as_deref(), Some("kanji"));
+        assert_eq!(trie.reverse_lookup(u64::MAX), None);
+
+        let mut keys: Vec<String> = trie
+            .predictive_search("ka".to_owned(), None)
+            .into_iter()
+            .map(|c| c.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["ka", "kana", "kanji"]);
+        assert_eq!(trie.predictive_search("k".to_owned(), Some(2)).len(), 2);
+        assert_eq!(trie.common_prefix_search("kanai".to_owned()).len(), 2);
+        assert_eq!(
+            trie.longest_prefix("kanai".to_owned()),
+            Some(Completion {
+                id: trie.lookup("kana".to_owned()).unwrap(),
+                key: "kana".to_owned()
+            })
+        );
+
+        let path = std::env::temp_dir().join(format!("marisa-mobile-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        trie.save(path.clone()).unwrap();
+        assert_eq!(MarisaTrie::mmap(path.clone()).unwrap().num_keys(), 4);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            MarisaTrie::load(path),
+            Err(MarisaError::File { .. })
+        ));
+    }
+}
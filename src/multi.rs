@@ -0,0 +1,645 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{marisa::Trie, metrics::MetricsSink, overlay::UserOverlays, pipeline::QueryPipeline};
+
+pub type ScoreFn = dyn Fn(&str) -> f32 + Send + Sync;
+
+/// Called with the query, how long it took and how many results it returned.
+pub type SlowQueryFn = dyn Fn(&str, Duration, usize) + Send + Sync;
+
+struct Dictionary {
+    name: Arc<str>,
+    trie: Arc<Trie>,
+    priority: i32,
+    score: Option<Arc<ScoreFn>>,
+}
+
+struct Overlays {
+    name: Arc<str>,
+    users: Arc<UserOverlays>,
+    priority: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub dictionary: Arc<str>,
+    pub priority: i32,
+    pub id: usize,
+    /// Shared with the prefix cache, so cached results are served without
+    /// copying their keys.
+    pub key: Arc<str>,
+    pub score: f32,
+}
+
+/// A completion list shared with the prefix cache, dereferencing to its
+/// first `len` results.
+#[derive(Debug, Clone)]
+pub struct SharedResults {
+    list: Arc<[SearchResult]>,
+    len: usize,
+}
+
+impl std::ops::Deref for SharedResults {
+    type Target = [SearchResult];
+
+    fn deref(&self) -> &[SearchResult] {
+        &self.list[..self.len]
+    }
+}
+
+impl SharedResults {
+    fn new(list: Arc<[SearchResult]>, k: usize) -> Self {
+        let len = k.min(list.len());
+        Self { list, len }
+    }
+}
+
+/// Which completion lists [`MultiTrie::top_k`] keeps materialized.
+///
+/// Short prefixes match the most keys, so they are both the most common
+/// autocomplete queries and the most expensive predictive searches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixCacheConfig {
+    /// Prefixes of up to this many characters are cached.
+    pub max_prefix_chars: usize,
+    /// Results kept per prefix; a larger `k` bypasses the cache.
+    pub k: usize,
+    /// Once this many prefixes are cached, others are searched uncached.
+    pub max_entries: usize,
+}
+
+impl Default for PrefixCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_prefix_chars: 2,
+            k: 10,
+            max_entries: 4096,
+        }
+    }
+}
+
+struct PrefixCache {
+    config: PrefixCacheConfig,
+    lists: RwLock<HashMap<String, Arc<[SearchResult]>>>,
+}
+
+/// Several tries searched as one.
+///
+/// When a key is present in more than one dictionary only the hit from the
+/// highest-priority dictionary is kept. Merged results are ordered by
+/// priority, then by score (from the dictionary's score function, `0.0`
+/// without one), then by key.
+#[derive(Default)]
+pub struct MultiTrie {
+    dictionaries: Vec<Dictionary>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    slow_query: Option<(Duration, Arc<SlowQueryFn>)>,
+    prefix_cache: Option<PrefixCache>,
+    pipeline: QueryPipeline,
+    overlays: Option<Overlays>,
+}
+
+impl MultiTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, name: &str, trie: Arc<Trie>, priority: i32) {
+        self.invalidate();
+        self.dictionaries.push(Dictionary {
+            name: name.into(),
+            trie,
+            priority,
+            score: None,
+        });
+    }
+
+    /// Sets a per-result score adjustment for the dictionary called `name`.
+    /// Returns `false` if no such dictionary was pushed.
+    pub fn set_score_fn(
+        &mut self,
+        name: &str,
+        score: impl Fn(&str) -> f32 + Send + Sync + 'static,
+    ) -> bool {
+        match self.dictionaries.iter_mut().find(|d| &*d.name == name) {
+            Some(dictionary) => {
+                dictionary.score = Some(Arc::new(score));
+                self.invalidate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_priority(&mut self, name: &str, priority: i32) -> bool {
+        match self.dictionaries.iter_mut().find(|d| &*d.name == name) {
+            Some(dictionary) => {
+                dictionary.priority = priority;
+                self.invalidate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Swaps in a new trie for the dictionary called `name`, e.g. after a
+    /// reload. Returns `false` if no such dictionary was pushed.
+    pub fn replace(&mut self, name: &str, trie: Arc<Trie>) -> bool {
+        match self.dictionaries.iter_mut().find(|d| &*d.name == name) {
+            Some(dictionary) => {
+                dictionary.trie = trie;
+                self.invalidate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces every dictionary for which `current` returns a different
+    /// trie, returning how many changed.
+    pub(crate) fn refresh_with(&mut self, current: impl Fn(&str) -> Option<Arc<Trie>>) -> usize {
+        let mut changed = 0;
+        for dictionary in &mut self.dictionaries {
+            match current(&dictionary.name) {
+                Some(trie) if !Arc::ptr_eq(&trie, &dictionary.trie) => {
+                    dictionary.trie = trie;
+                    changed += 1;
+                }
+                _ => {}
+            }
+        }
+        if changed > 0 {
+            self.invalidate();
+        }
+        changed
+    }
+
+    /// Caches the results of [`MultiTrie::top_k`] for short prefixes. The
+    /// cache is emptied whenever dictionaries, priorities or score
+    /// functions change.
+    pub fn set_prefix_cache(&mut self, config: PrefixCacheConfig) {
+        self.prefix_cache = Some(PrefixCache {
+            config,
+            lists: RwLock::default(),
+        });
+    }
+
+    /// Runs `pipeline` on every query before searching and on every
+    /// result key after. A rewritten key keeps the `id` of the dictionary
+    /// key it came from.
+    pub fn set_pipeline(&mut self, pipeline: QueryPipeline) {
+        self.pipeline = pipeline;
+        self.invalidate();
+    }
+
+    /// Layers per-user dictionaries over the shared ones, searched by
+    /// [`MultiTrie::lookup_for`], [`MultiTrie::predictive_search_for`] and
+    /// [`MultiTrie::top_k_for`]. Their hits are reported as coming from the
+    /// dictionary `name`, with `priority`, the overlay's score for the word
+    /// and its id within the user's overlay.
+    pub fn set_user_overlays(&mut self, name: &str, users: Arc<UserOverlays>, priority: i32) {
+        self.overlays = Some(Overlays {
+            name: name.into(),
+            users,
+            priority,
+        });
+    }
+
+    pub fn user_overlays(&self) -> Option<&Arc<UserOverlays>> {
+        self.overlays.as_ref().map(|overlays| &overlays.users)
+    }
+
+    fn invalidate(&self) {
+        if let Some(cache) = &self.prefix_cache {
+            cache.lists.write().unwrap().clear();
+        }
+    }
+
+    /// Reports every lookup and predictive search to `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn MetricsSink>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Calls `on_slow_query` for every lookup or predictive search that
+    /// takes at least `threshold`.
+    pub fn on_slow_query(
+        &mut self,
+        threshold: Duration,
+        on_slow_query: impl Fn(&str, Duration, usize) + Send + Sync + 'static,
+    ) {
+        self.slow_query = Some((threshold, Arc::new(on_slow_query)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.dictionaries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dictionaries.is_empty()
+    }
+
+    /// Looks `key` up in every dictionary and returns the preferred hit.
+    pub fn lookup(&self, key: impl AsRef<str>) -> Option<SearchResult> {
+        self.lookup_in(key.as_ref(), None)
+    }
+
+    /// Like [`MultiTrie::lookup`], also looking in `user`'s overlay.
+    pub fn lookup_for(&self, user: &str, key: impl AsRef<str>) -> Option<SearchResult> {
+        self.lookup_in(key.as_ref(), Some(user))
+    }
+
+    fn lookup_in(&self, key: &str, user: Option<&str>) -> Option<SearchResult> {
+        let key = &self.pipeline.preprocess(key);
+        let start = Instant::now();
+        let mut results: Vec<SearchResult> = self
+            .dictionaries
+            .iter()
+            .filter_map(|d| d.trie.lookup(key).map(|id| d.result(id, key.to_owned())))
+            .collect();
+        if let (Some(overlays), Some(user)) = (&self.overlays, user) {
+            if let Some((id, score)) = overlays.users.lookup(user, key) {
+                results.push(overlays.result(id, key.to_owned(), score));
+            }
+        }
+        sort(&mut results);
+        self.postprocess(&mut results);
+        let elapsed = start.elapsed();
+        if let Some(metrics) = &self.metrics {
+            metrics.on_lookup(!results.is_empty(), elapsed);
+        }
+        self.check_slow(key, elapsed, results.len().min(1));
+        results.into_iter().next()
+    }
+
+    pub fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<SearchResult> {
+        self.predictive_search_preprocessed(&self.pipeline.preprocess(prefix.as_ref()), None)
+    }
+
+    /// Like [`MultiTrie::predictive_search`], also searching `user`'s
+    /// overlay.
+    pub fn predictive_search_for(&self, user: &str, prefix: impl AsRef<str>) -> Vec<SearchResult> {
+        self.predictive_search_preprocessed(&self.pipeline.preprocess(prefix.as_ref()), Some(user))
+    }
+
+    fn predictive_search_preprocessed(
+        &self,
+        prefix: &str,
+        user: Option<&str>,
+    ) -> Vec<SearchResult> {
+        let start = Instant::now();
+        let mut best: HashMap<Arc<str>, SearchResult> = HashMap::new();
+        let mut offer = |result: SearchResult| match best.get(&result.key) {
+            Some(existing) if !preferred(&result, existing) => {}
+            _ => {
+                best.insert(result.key.clone(), result);
+            }
+        };
+        for dictionary in &self.dictionaries {
+            for (id, key) in dictionary.trie.predictive_search(prefix) {
+                offer(dictionary.result(id, key));
+            }
+        }
+        if let (Some(overlays), Some(user)) = (&self.overlays, user) {
+            for (id, key, score) in overlays.users.predictive_search(user, prefix) {
+                offer(overlays.result(id, key, score));
+            }
+        }
+        let mut results: Vec<SearchResult> = best.into_values().collect();
+        sort(&mut results);
+        self.postprocess(&mut results);
+        let elapsed = start.elapsed();
+        if let Some(metrics) = &self.metrics {
+            metrics.on_predictive_search(results.len(), elapsed);
+        }
+        self.check_slow(prefix, elapsed, results.len());
+        results
+    }
+
+    /// The first `k` results of `predictive_search(prefix)`, served from
+    /// the prefix cache when one is set and covers the query.
+    pub fn top_k(&self, prefix: impl AsRef<str>, k: usize) -> Vec<SearchResult> {
+        self.top_k_shared(prefix, k).to_vec()
+    }
+
+    /// Like [`MultiTrie::top_k`], but a cached list is handed out as is
+    /// rather than copied.
+    pub fn top_k_shared(&self, prefix: impl AsRef<str>, k: usize) -> SharedResults {
+        let prefix = &self.pipeline.preprocess(prefix.as_ref());
+        let cache = self.prefix_cache.as_ref().filter(|cache| {
+            k <= cache.config.k && prefix.chars().count() <= cache.config.max_prefix_chars
+        });
+        let Some(cache) = cache else {
+            let mut results = self.predictive_search_preprocessed(prefix, None);
+            results.truncate(k);
+            return SharedResults::new(results.into(), k);
+        };
+        if let Some(list) = cache.lists.read().unwrap().get(prefix) {
+            return SharedResults::new(list.clone(), k);
+        }
+        let mut list = self.predictive_search_preprocessed(prefix, None);
+        list.truncate(cache.config.k);
+        let list: Arc<[SearchResult]> = list.into();
+        let mut lists = cache.lists.write().unwrap();
+        if lists.len() < cache.config.max_entries {
+            lists.insert(prefix.to_owned(), list.clone());
+        }
+        SharedResults::new(list, k)
+    }
+
+    /// Like [`MultiTrie::top_k`], also searching `user`'s overlay. Never
+    /// served from the prefix cache, which holds shared results only.
+    pub fn top_k_for(&self, user: &str, prefix: impl AsRef<str>, k: usize) -> Vec<SearchResult> {
+        let mut results = self.predictive_search_for(user, prefix);
+        results.truncate(k);
+        results
+    }
+
+    fn postprocess(&self, results: &mut Vec<SearchResult>) {
+        results.retain_mut(|result| match self.pipeline.postprocess(&result.key) {
+            Some(key) => {
+                if *key != *result.key {
+                    result.key = key.into();
+                }
+                true
+            }
+            None => false,
+        });
+    }
+
+    fn check_slow(&self, query: &str, elapsed: Duration, num_results: usize) {
+        if let Some((threshold, on_slow_query)) = &self.slow_query {
+            if elapsed >= *threshold {
+                on_slow_query(query, elapsed, num_results);
+            }
+        }
+    }
+}
+
+impl Dictionary {
+    fn result(&self, id: usize, key: String) -> SearchResult {
+        let score = self.score.as_ref().map_or(0.0, |score| score(&key));
+        SearchResult {
+            dictionary: self.name.clone(),
+            priority: self.priority,
+            id,
+            key: key.into(),
+            score,
+        }
+    }
+}
+
+impl Overlays {
+    fn result(&self, id: usize, key: String, score: f32) -> SearchResult {
+        SearchResult {
+            dictionary: self.name.clone(),
+            priority: self.priority,
+            id,
+            key: key.into(),
+            score,
+        }
+    }
+}
+
+fn preferred(a: &SearchResult, b: &SearchResult) -> bool {
+    (a.priority, a.score) > (b.priority, b.score)
+}
+
+fn sort(results: &mut [SearchResult]) {
+    results.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then(b.score.total_cmp(&a.score))
+            .then_with(|| a.key.cmp(&b.key))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{MultiTrie, PrefixCacheConfig};
+    use crate::{
+        metrics::MetricsSink,
+        overlay::{OverlayLimits, UserOverlays},
+        pipeline::QueryPipeline,
+        test_util::build,
+    };
+
+    #[test]
+    fn higher_priority_wins_duplicates() {
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["apple", "apricot", "banana"])), 0);
+        multi.push("user", Arc::new(build(&["apple", "ape"])), 10);
+
+        let hit = multi.lookup("apple").unwrap();
+        assert_eq!(&*hit.dictionary, "user");
+
+        let results = multi.predictive_search("ap");
+        let keys: Vec<(&str, &str)> = results.iter().map(|r| (&*r.dictionary, &*r.key)).collect();
+        assert_eq!(
+            keys,
+            vec![("user", "ape"), ("user", "apple"), ("base", "apricot")]
+        );
+    }
+
+    #[test]
+    fn score_fn_orders_within_priority() {
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["aa", "ab", "ac"])), 0);
+        assert!(multi.set_score_fn("base", |key| if key == "ac" { 1.0 } else { 0.0 }));
+        assert!(!multi.set_score_fn("missing", |_| 0.0));
+
+        let keys: Vec<String> = multi
+            .predictive_search("a")
+            .into_iter()
+            .map(|r| r.key.to_string())
+            .collect();
+        assert_eq!(keys, vec!["ac", "aa", "ab"]);
+    }
+
+    #[test]
+    fn lookup_misses() {
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["a"])), 0);
+        assert!(multi.lookup("b").is_none());
+    }
+
+    #[test]
+    fn pipeline_wraps_searches() {
+        let mut multi = MultiTrie::new();
+        multi.push(
+            "base",
+            Arc::new(build(&["apple", "apricot", "_internal"])),
+            0,
+        );
+        multi.set_prefix_cache(PrefixCacheConfig::default());
+        multi.set_pipeline(
+            QueryPipeline::new()
+                .trim()
+                .lowercase()
+                .filter_result(|key| !key.starts_with('_'))
+                .map_result(str::to_uppercase),
+        );
+
+        let hit = multi.lookup(" Apple ").unwrap();
+        assert_eq!(&*hit.key, "APPLE");
+        assert_eq!(
+            hit.id,
+            build(&["apple", "apricot", "_internal"])
+                .lookup("apple")
+                .unwrap()
+        );
+        let keys = |results: Vec<super::SearchResult>| -> Vec<String> {
+            results.into_iter().map(|r| r.key.to_string()).collect()
+        };
+        assert_eq!(
+            keys(multi.predictive_search("AP")),
+            vec!["APPLE", "APRICOT"]
+        );
+        assert_eq!(keys(multi.top_k("A", 5)), vec!["APPLE", "APRICOT"]);
+        assert_eq!(keys(multi.top_k("a ", 5)), vec!["APPLE", "APRICOT"]);
+        assert!(multi.predictive_search("_").is_empty());
+    }
+
+    #[test]
+    fn caches_short_prefixes() {
+        let scored = Arc::new(Mutex::new(0));
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["aa", "ab", "abc", "b"])), 0);
+        let count = scored.clone();
+        multi.set_score_fn("base", move |_| {
+            *count.lock().unwrap() += 1;
+            0.0
+        });
+        multi.set_prefix_cache(PrefixCacheConfig {
+            max_prefix_chars: 1,
+            k: 2,
+            max_entries: 16,
+        });
+
+        let keys = |results: Vec<super::SearchResult>| -> Vec<String> {
+            results.into_iter().map(|r| r.key.to_string()).collect()
+        };
+        assert_eq!(keys(multi.top_k("a", 2)), vec!["aa", "ab"]);
+        let searched = *scored.lock().unwrap();
+        assert_eq!(keys(multi.top_k("a", 1)), vec!["aa"]);
+        assert_eq!(*scored.lock().unwrap(), searched);
+
+        // Longer prefixes and larger k go to the tries.
+        assert_eq!(keys(multi.top_k("ab", 2)), vec!["ab", "abc"]);
+        assert_eq!(multi.top_k("a", 3).len(), 3);
+        assert!(*scored.lock().unwrap() > searched);
+
+        // Cached lists are shared rather than copied.
+        let first = multi.top_k_shared("a", 2);
+        let second = multi.top_k_shared("a", 1);
+        assert_eq!(second.len(), 1);
+        assert!(Arc::ptr_eq(&first[0].key, &second[0].key));
+        let uncached = multi.top_k_shared("ab", 2);
+        assert!(!Arc::ptr_eq(&uncached[0].key, &first[1].key));
+
+        multi.replace("base", Arc::new(build(&["ax"])));
+        assert_eq!(keys(multi.top_k("a", 2)), vec!["ax"]);
+    }
+
+    #[test]
+    fn user_overlays_layer_over_shared() {
+        let users = Arc::new(UserOverlays::new(OverlayLimits::default()));
+        users.add("ana", "apex", 1.0);
+        users.add("ana", "apple", 1.0);
+        users.add("bo", "apse", 1.0);
+
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["apple", "apricot"])), 0);
+        multi.set_prefix_cache(PrefixCacheConfig::default());
+        multi.set_user_overlays("user", users, 5);
+
+        let found = |results: Vec<super::SearchResult>| -> Vec<(String, String)> {
+            results
+                .into_iter()
+                .map(|r| (r.dictionary.to_string(), r.key.to_string()))
+                .collect()
+        };
+        let pair = |d: &str, k: &str| (d.to_owned(), k.to_owned());
+        assert_eq!(
+            found(multi.predictive_search_for("ana", "ap")),
+            vec![
+                pair("user", "apex"),
+                pair("user", "apple"),
+                pair("base", "apricot"),
+            ]
+        );
+        assert_eq!(multi.top_k("ap", 5).len(), 2);
+        assert_eq!(
+            found(multi.top_k_for("bo", "ap", 2)),
+            vec![pair("user", "apse"), pair("base", "apple")]
+        );
+        assert_eq!(multi.lookup_for("ana", "apex").unwrap().id, 0);
+        assert!(multi.lookup("apex").is_none());
+        assert_eq!(
+            &*multi.lookup_for("cy", "apple").unwrap().dictionary,
+            "base"
+        );
+        assert_eq!(multi.user_overlays().unwrap().num_users(), 2);
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        lookups: Mutex<Vec<bool>>,
+        searches: Mutex<Vec<usize>>,
+    }
+
+    impl MetricsSink for Recorder {
+        fn on_lookup(&self, hit: bool, _: Duration) {
+            self.lookups.lock().unwrap().push(hit);
+        }
+
+        fn on_predictive_search(&self, num_results: usize, _: Duration) {
+            self.searches.lock().unwrap().push(num_results);
+        }
+    }
+
+    #[test]
+    fn reports_metrics() {
+        let recorder = Arc::new(Recorder::default());
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["a", "ab"])), 0);
+        multi.set_metrics(recorder.clone());
+
+        multi.lookup("a");
+        multi.lookup("b");
+        multi.predictive_search("a");
+        assert_eq!(*recorder.lookups.lock().unwrap(), vec![true, false]);
+        assert_eq!(*recorder.searches.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn slow_query_hook() {
+        let slow = Arc::new(Mutex::new(Vec::new()));
+        let mut multi = MultiTrie::new();
+        multi.push("base", Arc::new(build(&["a", "ab"])), 0);
+
+        let sink = slow.clone();
+        multi.on_slow_query(Duration::ZERO, move |query, _, num_results| {
+            sink.lock().unwrap().push((query.to_owned(), num_results));
+        });
+        multi.predictive_search("a");
+        multi.lookup("b");
+        assert_eq!(
+            *slow.lock().unwrap(),
+            vec![("a".to_owned(), 2), ("b".to_owned(), 0)]
+        );
+
+        multi.on_slow_query(Duration::from_secs(3600), |query, _, _| {
+            panic!("{query} reported as slow")
+        });
+        multi.predictive_search("a");
+    }
+}
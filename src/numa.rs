@@ -0,0 +1,277 @@
+//! Placing large dictionaries on multi-socket Linux hosts, where reads
+//! from another NUMA node's memory dominate tail latency.
+//!
+//! A file mapping lives in the shared page cache, wherever the kernel
+//! happened to allocate it, so [`NumaTrie`] instead reads the image into
+//! anonymous memory with a placement policy set before the first write.
+//! The price is that the image is resident once per replica rather than
+//! shared with other processes mapping the same file.
+//!
+//! Placement is advisory: where the kernel refuses a policy (no NUMA
+//! support, or a sandbox blocking `mbind`), the memory is allocated
+//! normally and a warning logged.
+
+use std::{fs::File, io::Read, path::Path, ptr::NonNull};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::Trie,
+};
+
+// From <linux/mempolicy.h>; libc doesn't export them for every target.
+const MPOL_BIND: libc::c_long = 2;
+const MPOL_INTERLEAVE: libc::c_long = 3;
+
+/// Nodes a policy can name; the kernel supports up to 1024.
+const MAX_NODES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Spreads pages round-robin over every online node, evening out
+    /// cross-node traffic.
+    Interleave,
+    /// Keeps every page on one node, for processes pinned to that node.
+    Bind(usize),
+    /// One copy per online node; [`NumaTrie::trie`] serves the copy local
+    /// to the calling thread.
+    Replicate,
+}
+
+/// A region of anonymous memory, unmapped on drop.
+struct Region {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// The region is only written before the trie maps it.
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    fn new(len: usize) -> Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len.max(1),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).expect("mmap succeeded"),
+            len,
+        })
+    }
+
+    /// Applies `mode` over `nodes`; must run before the pages are touched.
+    fn bind(&self, mode: libc::c_long, nodes: &[usize]) {
+        let mut mask = [0 as libc::c_ulong; MAX_NODES / libc::c_ulong::BITS as usize];
+        let bits = libc::c_ulong::BITS as usize;
+        for &node in nodes.iter().filter(|&&node| node < MAX_NODES) {
+            mask[node / bits] |= 1 << (node % bits);
+        }
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                self.ptr.as_ptr(),
+                self.len.max(1),
+                mode,
+                mask.as_ptr(),
+                // The kernel reads one bit fewer than it is told.
+                MAX_NODES + 1,
+                0,
+            )
+        };
+        if result != 0 {
+            log_warn!(
+                "mbind to nodes {nodes:?} failed, using default placement: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len.max(1)) };
+    }
+}
+
+struct Replica {
+    // Declared before `region` so the trie is dropped first.
+    trie: Trie,
+    node: usize,
+    region: Region,
+}
+
+/// A dictionary read into NUMA-placed memory.
+pub struct NumaTrie {
+    replicas: Vec<Replica>,
+    policy: NumaPolicy,
+}
+
+impl NumaTrie {
+    pub fn open(path: &Path, policy: NumaPolicy) -> Result<Self> {
+        let _span = trace_span!(
+            "marisa::numa_open",
+            path = path.to_str().unwrap_or_default()
+        );
+        let nodes = online_nodes();
+        let placements: Vec<(libc::c_long, Vec<usize>)> = match policy {
+            NumaPolicy::Interleave => vec![(MPOL_INTERLEAVE, nodes)],
+            NumaPolicy::Bind(node) => vec![(MPOL_BIND, vec![node])],
+            NumaPolicy::Replicate => nodes
+                .into_iter()
+                .map(|node| (MPOL_BIND, vec![node]))
+                .collect(),
+        };
+
+        let mut file = File::open(path).map_err(|err| MarisaError::File {
+            operation: "open",
+            path: path.to_owned(),
+            source: err,
+        })?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| MarisaError::incompatible("image does not fit in memory"))?;
+        let mut replicas: Vec<Replica> = Vec::with_capacity(placements.len());
+        for (mode, nodes) in placements {
+            let mut region = Region::new(len)?;
+            region.bind(mode, &nodes);
+            match replicas.first() {
+                Some(first) => region
+                    .as_mut_slice()
+                    .copy_from_slice(first.region.as_slice()),
+                None => file.read_exact(region.as_mut_slice())?,
+            }
+            let mut trie = Trie::default();
+            // SAFETY: the region is owned by the replica and outlives the
+            // trie, which is declared first and so dropped first.
+            unsafe { trie.map_unbounded(region.as_slice())? };
+            replicas.push(Replica {
+                trie,
+                node: nodes.first().copied().unwrap_or(0),
+                region,
+            });
+        }
+        trace_event!("placed trie", replicas = replicas.len());
+        Ok(Self { replicas, policy })
+    }
+
+    pub fn policy(&self) -> NumaPolicy {
+        self.policy
+    }
+
+    pub fn num_replicas(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// The copy on the calling thread's node, or the only copy.
+    pub fn trie(&self) -> &Trie {
+        if self.replicas.len() > 1 {
+            if let Some(node) = current_node() {
+                if let Some(replica) = self.replicas.iter().find(|r| r.node == node) {
+                    return &replica.trie;
+                }
+            }
+        }
+        &self.replicas[0].trie
+    }
+}
+
+/// The node of the CPU the calling thread runs on.
+pub fn current_node() -> Option<usize> {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu,
+            &mut node,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    (result == 0).then_some(node as usize)
+}
+
+/// Online nodes from sysfs; just node 0 where that isn't available.
+pub fn online_nodes() -> Vec<usize> {
+    std::fs::read_to_string("/sys/devices/system/node/online")
+        .ok()
+        .and_then(|list| parse_node_list(list.trim()))
+        .filter(|nodes| !nodes.is_empty())
+        .unwrap_or_else(|| vec![0])
+}
+
+/// Parses the kernel's list format, e.g. `0-3,8`.
+fn parse_node_list(list: &str) -> Option<Vec<usize>> {
+    let mut nodes = Vec::new();
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => {
+                let node = range.parse().ok()?;
+                (node, node)
+            }
+        };
+        nodes.extend(first..=last);
+    }
+    Some(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{online_nodes, parse_node_list, NumaPolicy, NumaTrie};
+    use crate::marisa::{Keyset, Trie};
+
+    #[test]
+    fn node_lists() {
+        assert_eq!(parse_node_list("0"), Some(vec![0]));
+        assert_eq!(parse_node_list("0-2,5"), Some(vec![0, 1, 2, 5]));
+        assert_eq!(parse_node_list("x"), None);
+        assert!(!online_nodes().is_empty());
+    }
+
+    #[test]
+    fn placed_tries() {
+        let path = std::env::temp_dir().join(format!("marisa-numa-{}.marisa", std::process::id()));
+        let mut keyset = Keyset::default();
+        keyset.push_all(&["fu", "fufi", "pes"]).unwrap();
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        trie.save(&path).unwrap();
+
+        for policy in [
+            NumaPolicy::Interleave,
+            NumaPolicy::Bind(0),
+            NumaPolicy::Replicate,
+        ] {
+            let placed = NumaTrie::open(&path, policy).unwrap();
+            assert_eq!(placed.policy(), policy);
+            assert_eq!(
+                placed.num_replicas(),
+                if policy == NumaPolicy::Replicate {
+                    online_nodes().len()
+                } else {
+                    1
+                }
+            );
+            assert_eq!(placed.trie().lookup("fufi"), trie.lookup("fufi"));
+            assert_eq!(placed.trie().predictive_search("fu").len(), 2);
+        }
+        assert!(NumaTrie::open(&path.with_extension("missing"), NumaPolicy::Interleave).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
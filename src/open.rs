@@ -0,0 +1,243 @@
+//! Opening a saved dictionary with the load strategy, warm-up and
+//! validation chosen in one place:
+//!
+//! ```no_run
+//! # use marisa::marisa::Trie;
+//! let trie = Trie::open("words.marisa")
+//!     .mmap(true)
+//!     .prefault(true)
+//!     .verify(true)
+//!     .read()?;
+//! # Ok::<(), marisa::error::MarisaError>(())
+//! ```
+
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::Trie,
+    utils::Fnv1a,
+};
+
+/// How [`Trie::open`] opens a dictionary. Every option is off by default,
+/// which reads the file like [`Trie::load`].
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    path: PathBuf,
+    mmap: bool,
+    locked: bool,
+    prefault: bool,
+    verify: bool,
+    #[cfg(feature = "serde")]
+    verify_checksum: bool,
+    fingerprint: Option<u64>,
+}
+
+impl Trie {
+    pub fn open(path: impl AsRef<Path>) -> OpenOptions {
+        OpenOptions {
+            path: path.as_ref().to_owned(),
+            mmap: false,
+            locked: false,
+            prefault: false,
+            verify: false,
+            #[cfg(feature = "serde")]
+            verify_checksum: false,
+            fingerprint: None,
+        }
+    }
+}
+
+impl OpenOptions {
+    /// Maps the file instead of reading it, like [`Trie::mmap`].
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Holds a shared advisory lock while opening, like
+    /// [`Trie::load_locked`], from before any of the checks. A mapping is
+    /// only protected from a `save_locked` in progress while it is set up.
+    pub fn locked(mut self, locked: bool) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    /// Reads a mapped file through once before mapping it, so its pages are
+    /// in the page cache and the first searches don't wait on the disk. Has
+    /// no effect without `mmap`.
+    pub fn prefault(mut self, prefault: bool) -> Self {
+        self.prefault = prefault;
+        self
+    }
+
+    /// Runs [`Trie::verify`] on the opened trie. This touches every key.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Checks the file against the manifest stored next to it, as
+    /// [`Trie::load_with_manifest`] does, before opening it.
+    #[cfg(feature = "serde")]
+    pub fn verify_checksum(mut self, verify_checksum: bool) -> Self {
+        self.verify_checksum = verify_checksum;
+        self
+    }
+
+    /// Fails with `ManifestMismatch` unless the file hashes to
+    /// `fingerprint`, as returned by [`Trie::fingerprint`] when it was
+    /// saved. Without `mmap` the bytes hashed are the ones loaded. A
+    /// mapping is made by path after the file is hashed, so a file renamed
+    /// over it in between goes unnoticed.
+    pub fn expect_fingerprint(mut self, fingerprint: u64) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    pub fn read(self) -> Result<Trie> {
+        let path = self.path.as_path();
+        let at = |err: std::io::Error| MarisaError::from(err).at("open", path);
+        // Taken first, so the checks below see the file that gets opened.
+        let lock = if self.locked {
+            let file = File::open(path).map_err(at)?;
+            file.lock_shared().map_err(at)?;
+            Some(file)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "serde")]
+        if self.verify_checksum {
+            use crate::manifest::Manifest;
+            Manifest::load(&Manifest::path_for(path))?.check(path)?;
+        }
+
+        let mut trie = Trie::default();
+        match (self.mmap, lock) {
+            (true, _) => {
+                if self.fingerprint.is_some() || self.prefault {
+                    let found = scan(File::open(path).map_err(at)?)
+                        .map_err(|err| MarisaError::from(err).at("read", path))?;
+                    check_fingerprint(self.fingerprint, found)?;
+                }
+                trie.mmap(path)?;
+            }
+            (false, lock) if self.fingerprint.is_some() => {
+                // Hash the bytes that are loaded, not a second read of the
+                // path.
+                let mut file = match lock {
+                    Some(file) => file,
+                    None => File::open(path).map_err(at)?,
+                };
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)
+                    .map_err(|err| MarisaError::from(err).at("read", path))?;
+                let mut hasher = Fnv1a::default();
+                hasher.update(&bytes);
+                check_fingerprint(self.fingerprint, hasher.finish())?;
+                trie.read_from(&mut Cursor::new(bytes))
+                    .map_err(|err| err.at("load", path))?;
+            }
+            (false, Some(mut file)) => trie
+                .read_from(&mut file)
+                .map_err(|err| err.at("load", path))?,
+            (false, None) => trie.load(path)?,
+        }
+
+        if self.verify {
+            trie.verify()?;
+        }
+        Ok(trie)
+    }
+}
+
+fn check_fingerprint(expected: Option<u64>, found: u64) -> Result<()> {
+    match expected {
+        Some(expected) if expected != found => Err(MarisaError::ManifestMismatch {
+            field: "fingerprint",
+            expected: format!("{expected:016x}"),
+            found: format!("{found:016x}"),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the whole file, returning its FNV-1a hash.
+fn scan(mut file: File) -> std::io::Result<u64> {
+    let mut hasher = Fnv1a::default();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::MarisaError, marisa::Trie};
+
+    #[test]
+    fn open_with_options() {
+        let path = std::env::temp_dir().join(format!("marisa-open-{}", std::process::id()));
+        let trie = Trie::try_from(&["fu", "fufi", "pes"][..]).unwrap();
+        trie.save(&path).unwrap();
+        let fingerprint = trie.fingerprint().unwrap();
+
+        for mmap in [false, true] {
+            for locked in [false, true] {
+                let opened = Trie::open(&path)
+                    .mmap(mmap)
+                    .locked(locked)
+                    .prefault(true)
+                    .verify(true)
+                    .expect_fingerprint(fingerprint)
+                    .read()
+                    .unwrap();
+                assert_eq!(opened.num_keys(), 3);
+                assert!(opened.lookup("fufi").is_some());
+            }
+        }
+
+        assert!(matches!(
+            Trie::open(&path).expect_fingerprint(!fingerprint).read(),
+            Err(MarisaError::ManifestMismatch {
+                field: "fingerprint",
+                ..
+            })
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            Trie::open(&path).mmap(true).read(),
+            Err(MarisaError::File { .. })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn verify_checksum_against_manifest() {
+        use crate::manifest::Manifest;
+
+        let path =
+            std::env::temp_dir().join(format!("marisa-open-manifest-{}", std::process::id()));
+        let trie = Trie::try_from(&["fu", "fufi"][..]).unwrap();
+        trie.save_with_manifest(&path, "test").unwrap();
+        assert!(Trie::open(&path).verify_checksum(true).read().is_ok());
+
+        Trie::try_from(&["pes"][..]).unwrap().save(&path).unwrap();
+        assert!(Trie::open(&path).read().is_ok());
+        assert!(matches!(
+            Trie::open(&path).verify_checksum(true).read(),
+            Err(MarisaError::ManifestMismatch { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(Manifest::path_for(&path)).unwrap();
+    }
+}
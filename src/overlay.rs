@@ -0,0 +1,245 @@
+//! Small per-user dictionaries layered over shared tries in a
+//! [`MultiTrie`](crate::multi::MultiTrie), for personalized completion
+//! without per-user copies of the base dictionary.
+//!
+//! Overlays are [`DynamicTrie`]s, so adding words never rebuilds anything.
+//! Memory is bounded by per-user and total byte caps; when the total or
+//! the user count would be exceeded, the users idle the longest are
+//! evicted.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use crate::dynamic::DynamicTrie;
+
+/// Estimated bytes per overlay entry on top of its key.
+const ENTRY_OVERHEAD: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverlayLimits {
+    pub max_users: usize,
+    /// Estimated bytes across all overlays.
+    pub max_total_bytes: usize,
+    /// Estimated bytes of one user's overlay; words past it are refused.
+    pub max_user_bytes: usize,
+}
+
+impl Default for OverlayLimits {
+    fn default() -> Self {
+        Self {
+            max_users: 10_000,
+            max_total_bytes: 64 << 20,
+            max_user_bytes: 64 << 10,
+        }
+    }
+}
+
+/// One user's words: an id numbering them in insertion order, and a score.
+struct Overlay {
+    words: DynamicTrie<(usize, f32)>,
+    bytes: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Users {
+    overlays: HashMap<String, Overlay>,
+    /// Users by the tick they were last used at, oldest first.
+    idle: BTreeMap<u64, String>,
+    tick: u64,
+    total_bytes: usize,
+}
+
+pub struct UserOverlays {
+    limits: OverlayLimits,
+    users: Mutex<Users>,
+}
+
+impl UserOverlays {
+    pub fn new(limits: OverlayLimits) -> Self {
+        Self {
+            limits,
+            users: Mutex::default(),
+        }
+    }
+
+    pub fn limits(&self) -> &OverlayLimits {
+        &self.limits
+    }
+
+    pub fn num_users(&self) -> usize {
+        self.users.lock().unwrap().overlays.len()
+    }
+
+    /// Estimated bytes held across all overlays.
+    pub fn total_bytes(&self) -> usize {
+        self.users.lock().unwrap().total_bytes
+    }
+
+    /// Adds `word` to `user`'s overlay, or updates its score. Returns
+    /// `false` if the word would take the overlay past `max_user_bytes`.
+    /// May evict other users to stay within the limits.
+    pub fn add(&self, user: impl AsRef<str>, word: impl AsRef<str>, score: f32) -> bool {
+        let user = user.as_ref();
+        let word = word.as_ref();
+        let mut users = self.users.lock().unwrap();
+        let users = &mut *users;
+        if !users.overlays.contains_key(user) {
+            while users.overlays.len() >= self.limits.max_users.max(1) {
+                users.evict_oldest();
+            }
+            users.overlays.insert(
+                user.to_owned(),
+                Overlay {
+                    words: DynamicTrie::default(),
+                    bytes: 0,
+                    last_used: 0,
+                },
+            );
+        }
+        users.touch(user);
+
+        let overlay = users.overlays.get_mut(user).expect("inserted above");
+        if let Some((_, existing)) = overlay.words.get_mut(word) {
+            *existing = score;
+            return true;
+        }
+        let bytes = word.len() + ENTRY_OVERHEAD;
+        if overlay.bytes + bytes > self.limits.max_user_bytes {
+            return false;
+        }
+        let id = overlay.words.len();
+        overlay.words.insert(word, (id, score));
+        overlay.bytes += bytes;
+        users.total_bytes += bytes;
+
+        while users.total_bytes > self.limits.max_total_bytes {
+            match users.idle.first_key_value() {
+                Some((_, oldest)) if oldest != user => users.evict_oldest(),
+                _ => break,
+            }
+        }
+        true
+    }
+
+    pub fn remove_user(&self, user: impl AsRef<str>) -> bool {
+        let user = user.as_ref();
+        self.users.lock().unwrap().remove(user)
+    }
+
+    /// `user`'s words starting with `prefix` as `(id, word, score)`,
+    /// sorted by word.
+    pub fn predictive_search(
+        &self,
+        user: impl AsRef<str>,
+        prefix: impl AsRef<str>,
+    ) -> Vec<(usize, String, f32)> {
+        let user = user.as_ref();
+        let prefix = prefix.as_ref();
+        let mut users = self.users.lock().unwrap();
+        if !users.touch(user) {
+            return Vec::new();
+        }
+        users.overlays[user]
+            .words
+            .predictive_search(prefix)
+            .into_iter()
+            .map(|(word, &(id, score))| (id, word, score))
+            .collect()
+    }
+
+    pub fn lookup(&self, user: impl AsRef<str>, word: impl AsRef<str>) -> Option<(usize, f32)> {
+        let user = user.as_ref();
+        let word = word.as_ref();
+        let mut users = self.users.lock().unwrap();
+        if !users.touch(user) {
+            return None;
+        }
+        users.overlays[user].words.get(word).copied()
+    }
+}
+
+impl Users {
+    /// Marks `user` as just used; `false` if it has no overlay.
+    fn touch(&mut self, user: &str) -> bool {
+        let Some(overlay) = self.overlays.get_mut(user) else {
+            return false;
+        };
+        self.idle.remove(&overlay.last_used);
+        self.tick += 1;
+        overlay.last_used = self.tick;
+        self.idle.insert(self.tick, user.to_owned());
+        true
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, user)) = self.idle.pop_first() {
+            self.remove(&user);
+        }
+    }
+
+    fn remove(&mut self, user: &str) -> bool {
+        match self.overlays.remove(user) {
+            Some(overlay) => {
+                self.idle.remove(&overlay.last_used);
+                self.total_bytes -= overlay.bytes;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OverlayLimits, UserOverlays, ENTRY_OVERHEAD};
+
+    #[test]
+    fn per_user_words() {
+        let overlays = UserOverlays::new(OverlayLimits::default());
+        assert!(overlays.add("ana", "fufi", 2.0));
+        assert!(overlays.add("ana", "fu", 1.0));
+        assert!(overlays.add("ana", "fufi", 5.0));
+        assert!(overlays.add("bo", "pes", 1.0));
+
+        let words: Vec<(usize, String, f32)> = overlays.predictive_search("ana", "fu");
+        assert_eq!(
+            words,
+            vec![(1, "fu".to_owned(), 1.0), (0, "fufi".to_owned(), 5.0)]
+        );
+        assert!(overlays.predictive_search("bo", "fu").is_empty());
+        assert_eq!(overlays.lookup("bo", "pes"), Some((0, 1.0)));
+        assert_eq!(overlays.lookup("cy", "pes"), None);
+        assert_eq!(overlays.total_bytes(), 4 + 2 + 3 + 3 * ENTRY_OVERHEAD);
+
+        assert!(overlays.remove_user("ana"));
+        assert_eq!(overlays.num_users(), 1);
+        assert_eq!(overlays.total_bytes(), 3 + ENTRY_OVERHEAD);
+    }
+
+    #[test]
+    fn caps_and_evicts_idle_users() {
+        let overlays = UserOverlays::new(OverlayLimits {
+            max_users: 2,
+            max_total_bytes: 3 * (ENTRY_OVERHEAD + 1),
+            max_user_bytes: 2 * (ENTRY_OVERHEAD + 1),
+        });
+        assert!(overlays.add("ana", "a", 1.0));
+        assert!(overlays.add("ana", "b", 1.0));
+        assert!(!overlays.add("ana", "c", 1.0));
+
+        assert!(overlays.add("bo", "a", 1.0));
+        // Using ana makes bo the idle one when cy arrives.
+        overlays.lookup("ana", "a");
+        assert!(overlays.add("cy", "a", 1.0));
+        assert_eq!(overlays.lookup("bo", "a"), None);
+        assert_eq!(overlays.num_users(), 2);
+
+        // Past the total cap, ana is evicted rather than cy.
+        assert!(overlays.add("cy", "b", 1.0));
+        assert_eq!(overlays.lookup("ana", "a"), None);
+        assert_eq!(overlays.num_users(), 1);
+    }
+}
@@ -0,0 +1,106 @@
+//! Autocomplete for multi-word input: the last word is completed on its
+//! own and the words before it are kept as typed, optionally after trying
+//! whole phrases from a separate trie.
+
+use crate::weighted::WeightedTrie;
+
+pub struct PhraseCompleter {
+    words: WeightedTrie,
+    phrases: Option<WeightedTrie>,
+}
+
+impl PhraseCompleter {
+    pub fn new(words: WeightedTrie) -> Self {
+        Self {
+            words,
+            phrases: None,
+        }
+    }
+
+    /// Completes the whole input against `phrases` before falling back to
+    /// completing its last word.
+    pub fn with_phrases(mut self, phrases: WeightedTrie) -> Self {
+        self.phrases = Some(phrases);
+        self
+    }
+
+    /// Up to `k` completions of `input` with their weights. Phrase matches
+    /// come first, heaviest first; then the input with its last word
+    /// replaced by each completion of it, heaviest first. Input ending in a
+    /// space has no last word to complete.
+    pub fn complete(&self, input: impl AsRef<str>, k: usize) -> Vec<(String, f32)> {
+        let input = input.as_ref();
+        let mut completions: Vec<(String, f32)> = match &self.phrases {
+            Some(phrases) => phrases
+                .top_k(input, k)
+                .into_iter()
+                .map(|(_, phrase, weight)| (phrase, weight))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let (leading, last) = match input.rfind(' ') {
+            Some(space) => input.split_at(space + 1),
+            None => ("", input),
+        };
+        if last.is_empty() {
+            return completions;
+        }
+        // `k` words are enough even if every phrase match repeats one.
+        for (_, word, weight) in self.words.top_k(last, k) {
+            if completions.len() == k {
+                break;
+            }
+            let completion = format!("{leading}{word}");
+            if !completions
+                .iter()
+                .any(|(existing, _)| *existing == completion)
+            {
+                completions.push((completion, weight));
+            }
+        }
+        completions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhraseCompleter;
+    use crate::weighted::WeightedTrie;
+
+    fn texts(completions: Vec<(String, f32)>) -> Vec<String> {
+        completions.into_iter().map(|(text, _)| text).collect()
+    }
+
+    #[test]
+    fn completes_the_last_word() {
+        let words =
+            WeightedTrie::build([("york", 5.0), ("yoga", 7.0), ("new", 9.0), ("pizza", 1.0)])
+                .unwrap();
+        let completer = PhraseCompleter::new(words);
+
+        assert_eq!(
+            texts(completer.complete("new yo", 5)),
+            vec!["new yoga", "new york"]
+        );
+        assert_eq!(texts(completer.complete("ne", 5)), vec!["new"]);
+        assert_eq!(texts(completer.complete("new yo", 1)), vec!["new yoga"]);
+        assert!(completer.complete("new ", 5).is_empty());
+    }
+
+    #[test]
+    fn phrases_come_first() {
+        let words = WeightedTrie::build([("york", 5.0), ("yoga", 7.0)]).unwrap();
+        let phrases = WeightedTrie::build([("new york city", 3.0), ("new york", 4.0)]).unwrap();
+        let completer = PhraseCompleter::new(words).with_phrases(phrases);
+
+        assert_eq!(
+            texts(completer.complete("new yo", 10)),
+            vec!["new york", "new york city", "new yoga"]
+        );
+        assert_eq!(
+            texts(completer.complete("new ", 10)),
+            vec!["new york", "new york city"]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Text hygiene applied around searches: hooks that rewrite the query
+//! before it reaches the tries and hooks that rewrite or drop result keys
+//! afterwards. Hooks run in the order they were added.
+
+use std::sync::Arc;
+
+pub type QueryHook = dyn Fn(&str) -> String + Send + Sync;
+
+/// Returns the key to report, or `None` to drop the result.
+pub type ResultHook = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+#[derive(Clone, Default)]
+pub struct QueryPipeline {
+    before: Vec<Arc<QueryHook>>,
+    after: Vec<Arc<ResultHook>>,
+}
+
+impl QueryPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes leading and trailing whitespace from the query.
+    pub fn trim(self) -> Self {
+        self.map_query(|query| query.trim().to_owned())
+    }
+
+    pub fn lowercase(self) -> Self {
+        self.map_query(str::to_lowercase)
+    }
+
+    /// Removes punctuation from the query, keeping letters, digits and
+    /// whitespace.
+    pub fn strip_punctuation(self) -> Self {
+        self.map_query(|query| {
+            query
+                .chars()
+                .filter(|c| !c.is_ascii_punctuation())
+                .collect()
+        })
+    }
+
+    pub fn map_query(mut self, hook: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.before.push(Arc::new(hook));
+        self
+    }
+
+    /// Rewrites every result key, e.g. for display.
+    pub fn map_result(self, hook: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.after_search(move |key| Some(hook(key)))
+    }
+
+    /// Drops results whose key fails `keep`.
+    pub fn filter_result(self, keep: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.after_search(move |key| keep(key).then(|| key.to_owned()))
+    }
+
+    pub fn after_search(
+        mut self,
+        hook: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.after.push(Arc::new(hook));
+        self
+    }
+
+    pub fn preprocess(&self, query: &str) -> String {
+        self.before
+            .iter()
+            .fold(query.to_owned(), |query, hook| hook(&query))
+    }
+
+    /// The key to report for a result with `key`, or `None` if a hook
+    /// dropped it.
+    pub fn postprocess(&self, key: &str) -> Option<String> {
+        self.after
+            .iter()
+            .try_fold(key.to_owned(), |key, hook| hook(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryPipeline;
+
+    #[test]
+    fn hooks_run_in_order() {
+        let pipeline = QueryPipeline::new()
+            .trim()
+            .strip_punctuation()
+            .lowercase()
+            .map_query(|query| query.replace("colour", "color"));
+        assert_eq!(pipeline.preprocess("  Colour, Red! "), "color red");
+
+        let pipeline = QueryPipeline::new()
+            .filter_result(|key| !key.starts_with('_'))
+            .map_result(str::to_uppercase);
+        assert_eq!(pipeline.postprocess("fu").as_deref(), Some("FU"));
+        assert_eq!(pipeline.postprocess("_hidden"), None);
+        assert_eq!(QueryPipeline::new().preprocess(" x "), " x ");
+    }
+}
@@ -0,0 +1,73 @@
+//! Opt-in per-query profiling.
+//!
+//! The bindings keep libmarisa's traversal state opaque, so node visits
+//! can't be counted directly; the agent steps (one libmarisa search call
+//! each) and the wall time are what is observable from this side.
+
+use std::time::{Duration, Instant};
+
+use crate::marisa::Trie;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueryProfile {
+    /// Calls into libmarisa made for the query, including the final one
+    /// that reports no more results.
+    pub steps: usize,
+    pub num_results: usize,
+    /// Bytes of keys copied out into results.
+    pub key_bytes: usize,
+    pub elapsed: Duration,
+}
+
+impl Trie {
+    pub fn lookup_profiled(&self, key: impl AsRef<str>) -> (Option<usize>, QueryProfile) {
+        let key = key.as_ref();
+        let start = Instant::now();
+        let id = self.lookup(key);
+        let profile = QueryProfile {
+            steps: 1,
+            num_results: id.is_some() as usize,
+            key_bytes: 0,
+            elapsed: start.elapsed(),
+        };
+        (id, profile)
+    }
+
+    pub fn predictive_search_profiled(
+        &self,
+        prefix: impl AsRef<str>,
+    ) -> (Vec<(usize, String)>, QueryProfile) {
+        let prefix = prefix.as_ref();
+        let start = Instant::now();
+        let mut steps = 0;
+        let results = self.predictive_search_counting(prefix, &mut steps);
+        let profile = QueryProfile {
+            steps,
+            num_results: results.len(),
+            key_bytes: results.iter().map(|(_, key)| key.len()).sum(),
+            elapsed: start.elapsed(),
+        };
+        (results, profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marisa::Trie;
+
+    #[test]
+    fn counts_steps_and_results() {
+        let trie = Trie::try_from(&["app", "apple", "banana"][..]).unwrap();
+
+        let (results, profile) = trie.predictive_search_profiled("app");
+        assert_eq!(results.len(), 2);
+        assert_eq!(profile.num_results, 2);
+        assert_eq!(profile.steps, 3);
+        assert_eq!(profile.key_bytes, 8);
+
+        let (id, profile) = trie.lookup_profiled("banana");
+        assert!(id.is_some());
+        assert_eq!(profile.num_results, 1);
+        assert_eq!(trie.lookup_profiled("ban").1.num_results, 0);
+    }
+}
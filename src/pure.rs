@@ -0,0 +1,216 @@
+//! A std-only trie backend for targets where linking libmarisa is
+//! impractical, such as `wasm32-unknown-unknown`.
+//!
+//! Keys are kept as a sorted array, so lookups are binary searches and
+//! memory use is that of the plain strings. Ids follow key order, unlike
+//! libmarisa's, so ids are not interchangeable between backends.
+//!
+//! Only building and the searches are covered: lookup, reverse lookup,
+//! predictive and common-prefix search, plus [`PrefixDictionary`]. There is
+//! no save, load or mmap, since libmarisa's image format would have to be
+//! reimplemented to read its files; the modules built on those, and every
+//! other module gated on the `libmarisa` feature, are absent without it.
+
+use crate::{
+    backend::{KeysetBackend, PrefixDictionary, TrieBackend},
+    config,
+    error::{MarisaError, Result},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Keyset {
+    keys: Vec<(String, f32)>,
+    allow_empty_key: bool,
+}
+
+impl Keyset {
+    /// Enforces the same empty-key policy and limits as the libmarisa keyset.
+    pub fn push(&mut self, key: impl AsRef<str>, weight: Option<f32>) -> Result<()> {
+        let key = key.as_ref();
+        if key.is_empty() && !self.allow_empty_key {
+            return Err(MarisaError::EmptyKey);
+        }
+        config::check_limits(self.keys.len(), key.len())?;
+        self.keys.push((key.to_owned(), weight.unwrap_or(1.0)));
+        Ok(())
+    }
+
+    pub fn set_allow_empty_key(&mut self, allow: bool) {
+        self.allow_empty_key = allow;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    pub fn num_keys(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Trie {
+    keys: Vec<String>,
+}
+
+impl Trie {
+    /// Builds from `keyset`, which is left empty. Weights only affect node
+    /// order in libmarisa and are ignored here.
+    pub fn build(&mut self, keyset: &mut Keyset) {
+        let mut keys: Vec<String> = keyset.keys.drain(..).map(|(key, _)| key).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        self.keys = keys;
+    }
+
+    pub fn num_keys(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    pub fn lookup(&self, key: impl AsRef<str>) -> Option<usize> {
+        let key = key.as_ref();
+        self.keys.binary_search_by(|k| k.as_str().cmp(key)).ok()
+    }
+
+    pub fn reverse_lookup(&self, id: usize) -> Option<String> {
+        self.keys.get(id).cloned()
+    }
+
+    pub fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<(usize, String)> {
+        let prefix = prefix.as_ref();
+        let start = self.keys.partition_point(|k| k.as_str() < prefix);
+        self.keys[start..]
+            .iter()
+            .take_while(|k| k.starts_with(prefix))
+            .enumerate()
+            .map(|(offset, key)| (start + offset, key.clone()))
+            .collect()
+    }
+
+    /// Keys that are prefixes of `query`, shortest first.
+    pub fn common_prefix_search(&self, query: impl AsRef<str>) -> Vec<(usize, String)> {
+        let query = query.as_ref();
+        (0..=query.len())
+            .filter(|&end| query.is_char_boundary(end))
+            .filter_map(|end| Some((self.lookup(&query[..end])?, query[..end].to_owned())))
+            .collect()
+    }
+}
+
+impl KeysetBackend for Keyset {
+    fn push(&mut self, key: impl AsRef<str>, weight: Option<f32>) -> Result<()> {
+        Keyset::push(self, key, weight)
+    }
+
+    fn num_keys(&self) -> usize {
+        Keyset::num_keys(self)
+    }
+}
+
+impl TrieBackend for Trie {
+    type Keyset = Keyset;
+
+    fn build(&mut self, keyset: &mut Keyset) {
+        Trie::build(self, keyset)
+    }
+
+    fn num_keys(&self) -> usize {
+        Trie::num_keys(self)
+    }
+
+    fn lookup(&self, key: impl AsRef<str>) -> Option<usize> {
+        Trie::lookup(self, key)
+    }
+
+    fn reverse_lookup(&self, id: usize) -> Option<String> {
+        Trie::reverse_lookup(self, id)
+    }
+
+    fn predictive_search(&self, prefix: impl AsRef<str>) -> Vec<(usize, String)> {
+        Trie::predictive_search(self, prefix)
+    }
+}
+
+impl PrefixDictionary for Trie {
+    fn lookup(&self, key: &str) -> Option<usize> {
+        Trie::lookup(self, key)
+    }
+
+    fn prefix_iter<'a>(&'a self, query: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a> {
+        Box::new(self.common_prefix_search(query).into_iter())
+    }
+
+    fn complete<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (usize, String)> + 'a> {
+        let start = self.keys.partition_point(|k| k.as_str() < prefix);
+        let prefix = prefix.to_owned();
+        Box::new(
+            self.keys[start..]
+                .iter()
+                .take_while(move |k| k.starts_with(&prefix))
+                .enumerate()
+                .map(move |(offset, key)| (start + offset, key.clone())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{KeysetBackend, TrieBackend};
+
+    fn build<T: TrieBackend>(keys: &[&str]) -> T {
+        let mut keyset = T::Keyset::default();
+        for key in keys {
+            keyset.push(key, None).unwrap();
+        }
+        let mut trie = T::default();
+        trie.build(&mut keyset);
+        trie
+    }
+
+    fn check<T: TrieBackend>() {
+        let trie: T = build(&["fufi", "fi", "fu", "pes", "fu"]);
+        assert_eq!(trie.num_keys(), 4);
+
+        let id = trie.lookup("fufi").unwrap();
+        assert_eq!(trie.reverse_lookup(id).as_deref(), Some("fufi"));
+        assert_eq!(trie.lookup("f"), None);
+        assert_eq!(trie.reverse_lookup(4), None);
+
+        let mut keys: Vec<String> = trie
+            .predictive_search("fu")
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["fu", "fufi"]);
+        assert_eq!(trie.predictive_search("").len(), 4);
+    }
+
+    #[test]
+    fn pure_backend() {
+        check::<super::Trie>();
+
+        let trie: super::Trie = build(&["fu", "fufi", "pes"]);
+        let prefixes: Vec<String> = trie
+            .common_prefix_search("fufik")
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        assert_eq!(prefixes, ["fu", "fufi"]);
+        assert!(trie.common_prefix_search("f").is_empty());
+    }
+
+    #[cfg(feature = "libmarisa")]
+    #[test]
+    fn backends_agree() {
+        check::<crate::marisa::Trie>();
+    }
+}
@@ -0,0 +1,253 @@
+//! Python bindings, so dictionaries built in a Python pipeline can be saved
+//! and then served by Rust, or the other way round.
+//!
+//! Build the extension with maturin, which enables `pyo3/extension-module`:
+//!
+//! ```text
+//! maturin build --release --features pyo3
+//! ```
+//!
+//! ```python
+//! import marisa
+//! keyset = marisa.Keyset()
+//! keyset.extend(["apple", "apricot"])
+//! trie = marisa.Trie.build(keyset)
+//! trie.save("fruit.marisa")
+//! trie.predictive_search("ap")  # [(0, 'apple'), (1, 'apricot')]
+//! ```
+//!
+//! Searches and loads release the GIL.
+
+// The `#[pymethods]` expansion converts `PyResult` errors into `PyErr` again.
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+
+use pyo3::{
+    exceptions::{PyIndexError, PyRuntimeError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    error::MarisaError,
+    marisa::{Keyset, Trie},
+};
+
+impl From<MarisaError> for PyErr {
+    fn from(err: MarisaError) -> Self {
+        let message = err.to_string();
+        match err {
+            // Keeps the error kind, so a missing file is a FileNotFoundError.
+            MarisaError::Io(source) | MarisaError::File { source, .. } => {
+                std::io::Error::new(source.kind(), message).into()
+            }
+            MarisaError::InvalidId { .. } => PyIndexError::new_err(message),
+            MarisaError::NotReady | MarisaError::CompactionConflict => {
+                PyRuntimeError::new_err(message)
+            }
+            _ => PyValueError::new_err(message),
+        }
+    }
+}
+
+/// Keys to build a `Trie` from. Building takes the keys out, leaving the
+/// keyset empty.
+#[pyclass(name = "Keyset", module = "marisa", unsendable)]
+pub struct PyKeyset {
+    keyset: Keyset,
+}
+
+#[pymethods]
+impl PyKeyset {
+    #[new]
+    #[pyo3(signature = (allow_empty_key = false))]
+    fn new(allow_empty_key: bool) -> Self {
+        let mut keyset = Keyset::default();
+        keyset.set_allow_empty_key(allow_empty_key);
+        Self { keyset }
+    }
+
+    #[pyo3(signature = (key, weight = None))]
+    fn push(&mut self, key: &str, weight: Option<f32>) -> PyResult<()> {
+        Ok(self.keyset.push(key, weight)?)
+    }
+
+    /// Adds every key with weight 1.0, or none of them on error.
+    fn extend(&mut self, keys: Vec<String>) -> PyResult<()> {
+        Ok(self.keyset.push_all(&keys)?)
+    }
+
+    fn __len__(&self) -> usize {
+        self.keyset.num_keys()
+    }
+}
+
+#[pyclass(name = "Trie", module = "marisa", frozen)]
+pub struct PyTrie {
+    trie: Trie,
+}
+
+#[pymethods]
+impl PyTrie {
+    /// Builds from a sequence of strings.
+    #[new]
+    fn new(py: Python<'_>, keys: Vec<String>) -> PyResult<Self> {
+        let trie = py.allow_threads(|| {
+            let mut keyset = Keyset::default();
+            keyset.push_all(&keys)?;
+            Trie::build_from(keyset)
+        })?;
+        Ok(Self { trie })
+    }
+
+    #[staticmethod]
+    fn build(keyset: &mut PyKeyset) -> PyResult<Self> {
+        let trie = Trie::build_from(std::mem::take(&mut keyset.keyset))?;
+        Ok(Self { trie })
+    }
+
+    #[staticmethod]
+    fn load(py: Python<'_>, path: PathBuf) -> PyResult<Self> {
+        let trie = py.allow_threads(|| {
+            let mut trie = Trie::default();
+            trie.load(&path).map(|()| trie)
+        })?;
+        Ok(Self { trie })
+    }
+
+    /// Maps the file instead of reading it; pages are loaded on first use.
+    #[staticmethod]
+    fn mmap(py: Python<'_>, path: PathBuf) -> PyResult<Self> {
+        let trie = py.allow_threads(|| {
+            let mut trie = Trie::default();
+            trie.mmap(&path).map(|()| trie)
+        })?;
+        Ok(Self { trie })
+    }
+
+    fn save(&self, py: Python<'_>, path: PathBuf) -> PyResult<()> {
+        Ok(py.allow_threads(|| self.trie.save(&path))?)
+    }
+
+    fn __len__(&self) -> usize {
+        self.trie.num_keys()
+    }
+
+    fn __contains__(&self, key: &str) -> bool {
+        self.trie.lookup(key).is_some()
+    }
+
+    fn lookup(&self, key: &str) -> Option<usize> {
+        self.trie.lookup(key)
+    }
+
+    /// Raises IndexError for an id past the number of keys.
+    fn reverse_lookup(&self, id: usize) -> PyResult<String> {
+        self.trie.reverse_lookup(id).ok_or_else(|| {
+            MarisaError::InvalidId {
+                id,
+                num_keys: self.trie.num_keys(),
+            }
+            .into()
+        })
+    }
+
+    /// `(id, key)` for keys starting with `prefix`, at most `limit` of them.
+    #[pyo3(signature = (prefix, limit = None))]
+    fn predictive_search(
+        &self,
+        py: Python<'_>,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> Vec<(usize, String)> {
+        py.allow_threads(|| {
+            self.trie
+                .predictive_iter(prefix)
+                .take(limit.unwrap_or(usize::MAX))
+                .collect()
+        })
+    }
+
+    /// `(id, key)` for keys that are prefixes of `query`, shortest first.
+    fn common_prefix_search(&self, py: Python<'_>, query: &str) -> Vec<(usize, String)> {
+        py.allow_threads(|| self.trie.common_prefix_search(query))
+    }
+
+    /// `(id, key, distance)` for keys within `max_distance` edits of
+    /// `query`, closest first.
+    #[pyo3(signature = (query, max_distance = 1, prefix_len = 0))]
+    fn fuzzy_search(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_distance: u32,
+        prefix_len: usize,
+    ) -> Vec<(usize, String, u32)> {
+        py.allow_threads(|| {
+            self.trie
+                .fuzzy_search(query, max_distance, prefix_len)
+                .into_iter()
+                .map(|m| (m.id, m.key, m.distance))
+                .collect()
+        })
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "marisa")]
+pub fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyKeyset>()?;
+    m.add_class::<PyTrie>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::{prelude::*, types::PyDict};
+
+    #[test]
+    fn build_save_and_search_from_python() {
+        pyo3::prepare_freethreaded_python();
+        let path = std::env::temp_dir().join(format!("marisa-python-{}", std::process::id()));
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "marisa").unwrap();
+            super::python_module(&module).unwrap();
+            let locals = PyDict::new_bound(py);
+            locals.set_item("marisa", module).unwrap();
+            locals.set_item("path", path.to_str().unwrap()).unwrap();
+            py.run_bound(
+                r#"
+keyset = marisa.Keyset()
+keyset.extend(["apple", "apricot", "app"])
+keyset.push("banana", 2.0)
+assert len(keyset) == 4
+trie = marisa.Trie.build(keyset)
+assert len(keyset) == 0
+assert len(trie) == 4 and "apple" in trie and "ap" not in trie
+trie.save(path)
+
+trie = marisa.Trie.load(path)
+assert sorted(k for _, k in trie.predictive_search("ap")) == ["app", "apple", "apricot"]
+assert len(trie.predictive_search("ap", limit=2)) == 2
+assert [k for _, k in trie.common_prefix_search("apples")] == ["app", "apple"]
+assert trie.fuzzy_search("banan")[0][1:] == ("banana", 1)
+assert trie.reverse_lookup(trie.lookup("apple")) == "apple"
+try:
+    trie.reverse_lookup(4)
+    assert False
+except IndexError:
+    pass
+try:
+    marisa.Trie.load(path + ".missing")
+    assert False
+except FileNotFoundError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+//! A `BTreeSet` oracle for the search operations, and property tests
+//! comparing the backends against it on random keysets.
+//!
+//! Ids differ between implementations, so results are compared as keys and
+//! ids are only checked for consistency within one trie.
+
+use std::{collections::BTreeSet, ops::Bound};
+
+use crate::backend::{KeysetBackend, PrefixDictionary, TrieBackend};
+
+pub(crate) struct Reference {
+    keys: BTreeSet<String>,
+}
+
+impl Reference {
+    pub(crate) fn new<S: AsRef<str>>(keys: &[S]) -> Self {
+        Self {
+            keys: keys.iter().map(|key| key.as_ref().to_owned()).collect(),
+        }
+    }
+
+    pub(crate) fn num_keys(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub(crate) fn predictive_search(&self, prefix: &str) -> Vec<String> {
+        self.keys
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Keys that are prefixes of `query`, shortest first.
+    pub(crate) fn common_prefix_search(&self, query: &str) -> Vec<String> {
+        (0..=query.len())
+            .filter(|&end| query.is_char_boundary(end))
+            .map(|end| &query[..end])
+            .filter(|prefix| self.keys.contains(*prefix))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// `start..end` of every non-empty key occurring in `text`, by start
+    /// and then length.
+    #[cfg(feature = "libmarisa")]
+    pub(crate) fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        text.char_indices()
+            .flat_map(|(start, _)| {
+                self.common_prefix_search(&text[start..])
+                    .into_iter()
+                    .filter(|key| !key.is_empty())
+                    .map(move |key| (start, start + key.len()))
+            })
+            .collect()
+    }
+}
+
+/// The keysets' `set_allow_empty_key`, which the backend trait leaves out.
+pub(crate) trait AllowEmptyKey {
+    fn allow_empty_key(&mut self);
+}
+
+#[cfg(feature = "libmarisa")]
+impl AllowEmptyKey for crate::marisa::Keyset {
+    fn allow_empty_key(&mut self) {
+        self.set_allow_empty_key(true);
+    }
+}
+
+#[cfg(feature = "pure-rust")]
+impl AllowEmptyKey for crate::pure::Keyset {
+    fn allow_empty_key(&mut self) {
+        self.set_allow_empty_key(true);
+    }
+}
+
+pub(crate) fn build<T, S>(keys: &[S]) -> T
+where
+    T: TrieBackend,
+    T::Keyset: AllowEmptyKey,
+    S: AsRef<str>,
+{
+    let mut keyset = T::Keyset::default();
+    keyset.allow_empty_key();
+    for key in keys {
+        keyset.push(key.as_ref(), None).unwrap();
+    }
+    let mut trie = T::default();
+    trie.build(&mut keyset);
+    trie
+}
+
+fn sorted(results: impl IntoIterator<Item = (usize, String)>) -> Vec<String> {
+    let mut keys: Vec<String> = results.into_iter().map(|(_, key)| key).collect();
+    keys.sort();
+    keys
+}
+
+/// Checks every search operation of `trie` against `reference` for `queries`.
+pub(crate) fn check<T>(trie: &T, reference: &Reference, queries: &[String])
+where
+    T: TrieBackend + PrefixDictionary,
+{
+    assert_eq!(TrieBackend::num_keys(trie), reference.num_keys());
+
+    let mut ids = BTreeSet::new();
+    for key in &reference.keys {
+        let id = TrieBackend::lookup(trie, key).unwrap_or_else(|| panic!("{key:?} not found"));
+        assert!(id < TrieBackend::num_keys(trie));
+        assert!(ids.insert(id), "{key:?} shares id {id}");
+        assert_eq!(trie.reverse_lookup(id).as_deref(), Some(key.as_str()));
+    }
+    assert_eq!(trie.reverse_lookup(TrieBackend::num_keys(trie)), None);
+
+    let consistent = |results: &[(usize, String)], what: &str| {
+        for (id, key) in results {
+            assert_eq!(TrieBackend::lookup(trie, key), Some(*id), "{what}: {key:?}");
+        }
+    };
+    for query in queries {
+        assert_eq!(
+            TrieBackend::lookup(trie, query).is_some(),
+            reference.contains(query),
+            "lookup({query:?})"
+        );
+
+        let results = trie.predictive_search(query);
+        consistent(&results, "predictive_search");
+        assert_eq!(
+            sorted(results),
+            reference.predictive_search(query),
+            "predictive_search({query:?})"
+        );
+        let completions: Vec<_> = trie.complete(query).collect();
+        consistent(&completions, "complete");
+        assert_eq!(
+            sorted(completions),
+            reference.predictive_search(query),
+            "complete({query:?})"
+        );
+
+        let prefixes: Vec<_> = trie.prefix_iter(query).collect();
+        consistent(&prefixes, "prefix_iter");
+        let prefixes: Vec<String> = prefixes.into_iter().map(|(_, key)| key).collect();
+        assert_eq!(
+            prefixes,
+            reference.common_prefix_search(query),
+            "prefix_iter({query:?})"
+        );
+    }
+}
+
+/// The libmarisa-only searches: the iterator and callback forms, and
+/// `find_all` with each query as the text.
+#[cfg(feature = "libmarisa")]
+pub(crate) fn check_libmarisa(
+    trie: &crate::marisa::Trie,
+    reference: &Reference,
+    queries: &[String],
+) {
+    use std::ops::ControlFlow;
+
+    check(trie, reference, queries);
+
+    let mut all = Vec::new();
+    let _ = trie.for_each_key(|id, key| {
+        all.push((id, String::from_utf8(key.to_vec()).unwrap()));
+        ControlFlow::<()>::Continue(())
+    });
+    assert_eq!(sorted(all), reference.predictive_search(""), "for_each_key");
+
+    for query in queries {
+        let expected = reference.predictive_search(query);
+        assert_eq!(
+            sorted(trie.predictive_iter(query)),
+            expected,
+            "predictive_iter({query:?})"
+        );
+        let mut found = Vec::new();
+        trie.for_each_predictive(query, |id, key| found.push((id, key.to_owned())));
+        assert_eq!(sorted(found), expected, "for_each_predictive({query:?})");
+
+        let expected = reference.common_prefix_search(query);
+        let keys = |results: Vec<(usize, String)>| -> Vec<String> {
+            results.into_iter().map(|(_, key)| key).collect()
+        };
+        assert_eq!(
+            keys(trie.common_prefix_search(query)),
+            expected,
+            "common_prefix_search({query:?})"
+        );
+        assert_eq!(
+            keys(trie.common_prefix_iter(query).collect()),
+            expected,
+            "common_prefix_iter({query:?})"
+        );
+        let mut found = Vec::new();
+        trie.for_each_common_prefix(query, |id, key| found.push((id, key.to_owned())));
+        assert_eq!(keys(found), expected, "for_each_common_prefix({query:?})");
+
+        let matches = trie.find_all(query);
+        for m in &matches {
+            assert_eq!(trie.lookup(&query[m.start..m.end]), Some(m.id));
+        }
+        let spans: Vec<(usize, usize)> = matches.iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, reference.find_all(query), "find_all({query:?})");
+    }
+}
+
+mod tests {
+    use proptest::prelude::*;
+
+    #[cfg(feature = "pure-rust")]
+    use super::check;
+    #[cfg(feature = "libmarisa")]
+    use super::check_libmarisa;
+    use super::{build, Reference};
+
+    // Short keys over a small alphabet mixing one- to four-byte UTF-8, so
+    // keys share prefixes often, plus occasional long ones around 256
+    // bytes and the empty key.
+    fn key() -> impl Strategy<Value = String> {
+        prop_oneof![
+            16 => "[ab\u{e9}\u{65e5}\u{1F600}]{1,6}",
+            2 => "[ab]{200,300}",
+            1 => "[ab]{255,257}",
+            1 => Just(String::new()),
+        ]
+    }
+
+    fn keys_and_queries() -> impl Strategy<Value = (Vec<String>, Vec<String>)> {
+        prop::collection::vec(key(), 0..40).prop_flat_map(|keys| {
+            // Prefixes and extensions of the keys, so searches hit often.
+            let near: Vec<String> = keys
+                .iter()
+                .flat_map(|key| {
+                    key.char_indices()
+                        .map(|(i, _)| key[..i].to_owned())
+                        .chain([format!("{key}a"), format!("b{key}")])
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            let query = if near.is_empty() {
+                "[ab\u{e9}]{0,4}".boxed()
+            } else {
+                prop_oneof![
+                    "[ab\u{e9}]{0,4}".boxed(),
+                    prop::sample::select(near).boxed(),
+                ]
+                .boxed()
+            };
+            (Just(keys), prop::collection::vec(query, 1..20))
+        })
+    }
+
+    // Keys at the lengths where libmarisa's label and tail encodings change.
+    fn boundary_keys() -> (Vec<String>, Vec<String>) {
+        let lengths = [0, 1, 2, 127, 128, 255, 256, 257, 1023, 1024, 1025];
+        let keys: Vec<String> = lengths.iter().map(|&n| "a".repeat(n)).collect();
+        let mut queries: Vec<String> = lengths
+            .iter()
+            .flat_map(|&n| ["a".repeat(n + 1), format!("{}b", "a".repeat(n))])
+            .collect();
+        queries.extend(keys.iter().cloned());
+        (keys, queries)
+    }
+
+    #[cfg(feature = "libmarisa")]
+    #[test]
+    fn libmarisa_boundary_lengths() {
+        let (keys, queries) = boundary_keys();
+        let trie: crate::marisa::Trie = build(&keys);
+        check_libmarisa(&trie, &Reference::new(&keys), &queries);
+    }
+
+    #[cfg(feature = "pure-rust")]
+    #[test]
+    fn pure_boundary_lengths() {
+        let (keys, queries) = boundary_keys();
+        let trie: crate::pure::Trie = build(&keys);
+        check(&trie, &Reference::new(&keys), &queries);
+    }
+
+    proptest! {
+        #[cfg(feature = "libmarisa")]
+        #[test]
+        fn libmarisa_matches_reference((keys, queries) in keys_and_queries()) {
+            let trie: crate::marisa::Trie = build(&keys);
+            check_libmarisa(&trie, &Reference::new(&keys), &queries);
+        }
+
+        #[cfg(feature = "pure-rust")]
+        #[test]
+        fn pure_matches_reference((keys, queries) in keys_and_queries()) {
+            let trie: crate::pure::Trie = build(&keys);
+            check(&trie, &Reference::new(&keys), &queries);
+        }
+    }
+}
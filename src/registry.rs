@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::Trie,
+    metrics::MetricsSink,
+    multi::MultiTrie,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadMode {
+    Load,
+    Mmap,
+}
+
+struct Entry {
+    source: Option<(PathBuf, LoadMode)>,
+    trie: Arc<Trie>,
+}
+
+/// A set of named tries shared between callers through `Arc` handles.
+///
+/// Reloading or unloading a dictionary only replaces the registry's handle;
+/// callers holding an older `Arc<Trie>` keep using it until they drop it.
+/// For a dictionary opened with [`LoadMode::Mmap`] that only holds if the
+/// file is replaced by writing a new one and renaming it over the old
+/// path: `Trie::save` truncates the file in place, under the old mapping.
+#[derive(Default)]
+pub struct DictionaryRegistry {
+    entries: RwLock<HashMap<String, Entry>>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+}
+
+pub(crate) fn open(path: &Path, mode: LoadMode) -> Result<Trie> {
+    let mut trie = Trie::default();
+    match mode {
+        LoadMode::Load => trie.load(path)?,
+        LoadMode::Mmap => trie.mmap(path)?,
+    }
+    Ok(trie)
+}
+
+impl DictionaryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports reloads to `metrics`, and hands it to every `MultiTrie`
+    /// created by [`DictionaryRegistry::multi`].
+    pub fn with_metrics(metrics: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            entries: RwLock::default(),
+            metrics: Some(metrics),
+        }
+    }
+
+    /// Loads `path` and registers it as `name`, replacing any previous entry.
+    pub fn load(&self, name: &str, path: impl AsRef<Path>) -> Result<Arc<Trie>> {
+        self.open(name, path.as_ref(), LoadMode::Load)
+    }
+
+    /// Memory-maps `path` and registers it as `name`, replacing any previous entry.
+    pub fn mmap(&self, name: &str, path: impl AsRef<Path>) -> Result<Arc<Trie>> {
+        self.open(name, path.as_ref(), LoadMode::Mmap)
+    }
+
+    /// Registers an already built trie. Such entries cannot be reloaded.
+    pub fn insert(&self, name: &str, trie: Trie) -> Arc<Trie> {
+        let trie = Arc::new(trie);
+        self.entries.write().unwrap().insert(
+            name.to_owned(),
+            Entry {
+                source: None,
+                trie: trie.clone(),
+            },
+        );
+        trie
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Trie>> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|entry| entry.trie.clone())
+    }
+
+    pub fn unload(&self, name: &str) -> Option<Arc<Trie>> {
+        self.entries
+            .write()
+            .unwrap()
+            .remove(name)
+            .map(|entry| entry.trie)
+    }
+
+    /// Re-opens the file `name` was registered from. On failure the
+    /// currently registered trie stays in place.
+    pub fn reload(&self, name: &str) -> Result<Arc<Trie>> {
+        let source = match self.entries.read().unwrap().get(name) {
+            Some(entry) => entry.source.clone(),
+            None => return Err(MarisaError::UnknownDictionary(name.to_owned())),
+        };
+        let (path, mode) = source.ok_or_else(|| MarisaError::NotReloadable(name.to_owned()))?;
+        let start = Instant::now();
+        let result = self.open(name, &path, mode);
+        if let Some(metrics) = &self.metrics {
+            metrics.on_reload(name, result.is_ok(), start.elapsed());
+        }
+        result
+    }
+
+    /// Snapshots the named dictionaries into a `MultiTrie`, each with the
+    /// given priority.
+    pub fn multi(&self, dictionaries: &[(&str, i32)]) -> Result<MultiTrie> {
+        let mut multi = MultiTrie::new();
+        if let Some(metrics) = &self.metrics {
+            multi.set_metrics(metrics.clone());
+        }
+        for &(name, priority) in dictionaries {
+            let trie = self
+                .get(name)
+                .ok_or_else(|| MarisaError::UnknownDictionary(name.to_owned()))?;
+            multi.push(name, trie, priority);
+        }
+        Ok(multi)
+    }
+
+    /// Points `multi` at the currently registered trie of each of its
+    /// dictionaries, e.g. after a reload, and drops its cached completions
+    /// if any changed. Returns how many were replaced.
+    pub fn refresh(&self, multi: &mut MultiTrie) -> usize {
+        let entries = self.entries.read().unwrap();
+        multi.refresh_with(|name| entries.get(name).map(|entry| entry.trie.clone()))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.read().unwrap().contains_key(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.entries.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().unwrap().is_empty()
+    }
+
+    fn open(&self, name: &str, path: &Path, mode: LoadMode) -> Result<Arc<Trie>> {
+        // Open outside the lock so a slow load doesn't block readers.
+        let trie = Arc::new(open(path, mode)?);
+        self.entries.write().unwrap().insert(
+            name.to_owned(),
+            Entry {
+                source: Some((path.to_owned(), mode)),
+                trie: trie.clone(),
+            },
+        );
+        Ok(trie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::DictionaryRegistry;
+    use crate::{
+        error::MarisaError,
+        metrics::MetricsSink,
+        multi::PrefixCacheConfig,
+        test_util::{build, temp_path},
+    };
+
+    #[test]
+    fn load_get_unload() {
+        let path = temp_path("registry-load_get_unload");
+        build(&["a", "b", "c"]).save(&path).unwrap();
+
+        let registry = DictionaryRegistry::new();
+        let trie = registry.load("base", &path).unwrap();
+        assert_eq!(trie.num_keys(), 3);
+        assert!(Arc::ptr_eq(&trie, &registry.get("base").unwrap()));
+        assert_eq!(registry.names(), vec!["base".to_owned()]);
+
+        let removed = registry.unload("base").unwrap();
+        assert!(registry.get("base").is_none());
+        assert!(registry.is_empty());
+        // Handles given out earlier stay usable.
+        assert_eq!(removed.num_keys(), 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_picks_up_new_file() {
+        let path = temp_path("registry-reload");
+        build(&["a"]).save(&path).unwrap();
+
+        let registry = DictionaryRegistry::new();
+        let old = registry.mmap("words", &path).unwrap();
+        assert_eq!(old.num_keys(), 1);
+
+        // Replaced by renaming, so the old mapping keeps its own file.
+        let temp = path.with_extension("tmp");
+        build(&["a", "b"]).save(&temp).unwrap();
+        std::fs::rename(&temp, &path).unwrap();
+        let new = registry.reload("words").unwrap();
+        assert_eq!(new.num_keys(), 2);
+        assert_eq!(registry.get("words").unwrap().num_keys(), 2);
+        assert_eq!(old.num_keys(), 1);
+        assert_eq!(old.lookup("b"), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refresh_invalidates_cached_completions() {
+        let path = temp_path("registry-refresh");
+        build(&["ab"]).save(&path).unwrap();
+        let registry = DictionaryRegistry::new();
+        registry.load("words", &path).unwrap();
+        registry.insert("fixed", build(&["ax"]));
+
+        let mut multi = registry.multi(&[("words", 0), ("fixed", 0)]).unwrap();
+        multi.set_prefix_cache(PrefixCacheConfig::default());
+        assert_eq!(multi.top_k("a", 10).len(), 2);
+
+        build(&["ab", "ac"]).save(&path).unwrap();
+        registry.reload("words").unwrap();
+        assert_eq!(multi.top_k("a", 10).len(), 2);
+        assert_eq!(registry.refresh(&mut multi), 1);
+        assert_eq!(multi.top_k("a", 10).len(), 3);
+        assert_eq!(registry.refresh(&mut multi), 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_errors() {
+        let registry = DictionaryRegistry::new();
+        assert!(matches!(
+            registry.reload("missing"),
+            Err(MarisaError::UnknownDictionary(_))
+        ));
+
+        registry.insert("memory", build(&["x"]));
+        assert!(matches!(
+            registry.reload("memory"),
+            Err(MarisaError::NotReloadable(_))
+        ));
+
+        assert!(matches!(
+            registry.load("nope", temp_path("registry-does-not-exist")),
+            Err(MarisaError::File {
+                operation: "load",
+                ..
+            })
+        ));
+        assert!(!registry.contains("nope"));
+    }
+
+    #[test]
+    fn multi_search_across_dictionaries() {
+        let registry = DictionaryRegistry::new();
+        registry.insert("base", build(&["hello", "help"]));
+        registry.insert("user", build(&["hello", "helm"]));
+
+        let multi = registry.multi(&[("user", 1), ("base", 0)]).unwrap();
+        let results = multi.predictive_search("hel");
+        assert_eq!(results.len(), 3);
+        assert_eq!(&*results[0].dictionary, "user");
+        assert_eq!(&*multi.lookup("hello").unwrap().dictionary, "user");
+
+        assert!(matches!(
+            registry.multi(&[("other", 0)]),
+            Err(MarisaError::UnknownDictionary(_))
+        ));
+    }
+
+    #[derive(Default)]
+    struct Reloads(Mutex<Vec<(String, bool)>>);
+
+    impl MetricsSink for Reloads {
+        fn on_reload(&self, dictionary: &str, success: bool, _: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((dictionary.to_owned(), success));
+        }
+    }
+
+    #[test]
+    fn reports_reloads() {
+        let path = temp_path("registry-metrics");
+        build(&["a"]).save(&path).unwrap();
+
+        let reloads = Arc::new(Reloads::default());
+        let registry = DictionaryRegistry::with_metrics(reloads.clone());
+        registry.load("words", &path).unwrap();
+        registry.reload("words").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(registry.reload("words").is_err());
+
+        assert_eq!(
+            *reloads.0.lock().unwrap(),
+            vec![("words".to_owned(), true), ("words".to_owned(), false)]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Resolving URL or file paths to the most specific registered prefix.
+
+use crate::{error::Result, map::TrieMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Any string prefix matches: `/api` matches `/apiary`.
+    Prefix,
+    /// A prefix only matches whole segments: `/api` matches `/api` and
+    /// `/api/users` but not `/apiary`. A prefix ending in the separator
+    /// matches anything below it.
+    #[default]
+    Segment,
+}
+
+/// A resolved path: the matched prefix and what follows it, both slices
+/// of the requested path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route<'p, 'a, T> {
+    pub prefix: &'p str,
+    pub rest: &'p str,
+    pub payload: &'a T,
+}
+
+pub struct PathRouter<T> {
+    prefixes: TrieMap<T>,
+    mode: MatchMode,
+    separator: char,
+}
+
+impl<T> PathRouter<T> {
+    /// Builds from `(prefix, payload)` entries, separating segments with
+    /// `/`; for a prefix given more than once, the last payload is kept.
+    /// The empty prefix acts as a catch-all route.
+    pub fn new<'a>(
+        routes: impl IntoIterator<Item = (&'a str, T)>,
+        mode: MatchMode,
+    ) -> Result<Self> {
+        Ok(Self {
+            prefixes: TrieMap::new(routes)?,
+            mode,
+            separator: '/',
+        })
+    }
+
+    /// Uses `separator` between segments instead of `/`, e.g. `\\` for
+    /// Windows paths or `.` for dotted names.
+    pub fn with_separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The longest registered prefix of `path` allowed by the match mode.
+    pub fn resolve<'p>(&self, path: &'p str) -> Option<Route<'p, '_, T>> {
+        let mut best = None;
+        self.prefixes
+            .keys()
+            .for_each_common_prefix(path, |id, prefix| {
+                if self.matches(prefix, path) {
+                    best = Some((id, prefix.len()));
+                }
+            });
+        let (id, len) = best?;
+        Some(Route {
+            prefix: &path[..len],
+            rest: &path[len..],
+            payload: self.prefixes.get_by_id(id)?,
+        })
+    }
+
+    fn matches(&self, prefix: &str, path: &str) -> bool {
+        match self.mode {
+            MatchMode::Prefix => true,
+            MatchMode::Segment => {
+                let rest = &path[prefix.len()..];
+                prefix.is_empty()
+                    || rest.is_empty()
+                    || prefix.ends_with(self.separator)
+                    || rest.starts_with(self.separator)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchMode, PathRouter};
+
+    #[test]
+    fn most_specific_segment_match() {
+        let router = PathRouter::new(
+            [
+                ("/", "root"),
+                ("/api", "api"),
+                ("/api/v2", "v2"),
+                ("/static/", "static"),
+            ],
+            MatchMode::Segment,
+        )
+        .unwrap();
+
+        let resolve = |path| {
+            router
+                .resolve(path)
+                .map(|route| (route.prefix, route.rest, *route.payload))
+        };
+        assert_eq!(resolve("/api/v2/users"), Some(("/api/v2", "/users", "v2")));
+        assert_eq!(resolve("/api/v21"), Some(("/api", "/v21", "api")));
+        assert_eq!(resolve("/api"), Some(("/api", "", "api")));
+        assert_eq!(resolve("/apiary"), Some(("/", "apiary", "root")));
+        assert_eq!(
+            resolve("/static/app.js"),
+            Some(("/static/", "app.js", "static"))
+        );
+        assert_eq!(resolve("relative"), None);
+    }
+
+    #[test]
+    fn plain_prefix_and_separator() {
+        let router = PathRouter::new([("/api", 1)], MatchMode::Prefix).unwrap();
+        assert_eq!(router.resolve("/apiary").map(|r| *r.payload), Some(1));
+
+        let router = PathRouter::new([("com.example", 1), ("", 0)], MatchMode::Segment)
+            .unwrap()
+            .with_separator('.');
+        assert_eq!(
+            router.resolve("com.example.app").map(|r| *r.payload),
+            Some(1)
+        );
+        assert_eq!(router.resolve("com.examples").map(|r| *r.payload), Some(0));
+    }
+}
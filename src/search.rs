@@ -0,0 +1,322 @@
+//! Streaming forms of the enumerating searches.
+//!
+//! The iterators step libmarisa's agent one key at a time, so only the
+//! current key is held; the `for_each_*` methods go further and hand out
+//! `&str` views of the agent's buffer without allocating at all.
+
+use std::{
+    ops::ControlFlow,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::marisa::{Agent, Trie};
+
+/// Keys starting with a prefix, from [`Trie::predictive_iter`]. Non-UTF-8
+/// keys are skipped with a warning, as in `predictive_search`.
+pub struct PredictiveSearch<'a> {
+    trie: &'a Trie,
+    agent: Agent,
+}
+
+impl Iterator for PredictiveSearch<'_> {
+    type Item = (usize, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.trie.predictive_search_agent(&mut self.agent) {
+            let key = self.agent.key();
+            match key.as_str() {
+                Ok(str) => return Some((key.id() as usize, str.to_owned())),
+                Err(err) => log_warn!(
+                    "predictive search skipped non-UTF-8 key {}: {err}",
+                    key.id()
+                ),
+            }
+        }
+        None
+    }
+}
+
+/// Keys that are prefixes of a query, shortest first, from
+/// [`Trie::common_prefix_iter`]. Non-UTF-8 keys are skipped with a warning.
+pub struct CommonPrefixSearch<'a> {
+    trie: &'a Trie,
+    agent: Agent,
+}
+
+impl Iterator for CommonPrefixSearch<'_> {
+    type Item = (usize, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.trie.common_prefix_search_agent(&mut self.agent) {
+            let key = self.agent.key();
+            // A key that is a prefix of a `&str` can still split a character.
+            match key.as_str() {
+                Ok(str) => return Some((key.id() as usize, str.to_owned())),
+                Err(err) => log_warn!(
+                    "common prefix search skipped non-UTF-8 key {}: {err}",
+                    key.id()
+                ),
+            }
+        }
+        None
+    }
+}
+
+impl Trie {
+    pub fn predictive_iter(&self, prefix: impl AsRef<str>) -> PredictiveSearch<'_> {
+        let prefix = prefix.as_ref();
+        let mut agent = Agent::default();
+        agent.set_query(prefix);
+        PredictiveSearch { trie: self, agent }
+    }
+
+    pub fn common_prefix_iter(&self, query: impl AsRef<str>) -> CommonPrefixSearch<'_> {
+        let query = query.as_ref();
+        let mut agent = Agent::default();
+        agent.set_query(query);
+        CommonPrefixSearch { trie: self, agent }
+    }
+
+    /// Calls `f` with the id and text of every key starting with `prefix`.
+    /// The `&str` points into libmarisa's buffer and is only valid for the
+    /// call. Non-UTF-8 keys are skipped with a warning, as in
+    /// [`Trie::predictive_iter`]; [`Trie::for_each_key`] sees them.
+    pub fn for_each_predictive(&self, prefix: impl AsRef<str>, mut f: impl FnMut(usize, &str)) {
+        let prefix = prefix.as_ref();
+        let mut agent = Agent::default();
+        agent.set_query(prefix);
+        while self.predictive_search_agent(&mut agent) {
+            match std::str::from_utf8(agent.key_bytes()) {
+                Ok(str) => f(agent.key().id() as usize, str),
+                Err(err) => log_warn!(
+                    "predictive search skipped non-UTF-8 key {}: {err}",
+                    agent.key().id()
+                ),
+            }
+        }
+    }
+
+    /// Calls `f` with the id and text of every key that is a prefix of
+    /// `query`, shortest first, skipping non-UTF-8 keys with a warning.
+    pub fn for_each_common_prefix(&self, query: impl AsRef<str>, mut f: impl FnMut(usize, &str)) {
+        let query = query.as_ref();
+        let mut agent = Agent::default();
+        agent.set_query(query);
+        while self.common_prefix_search_agent(&mut agent) {
+            match std::str::from_utf8(agent.key_bytes()) {
+                Ok(str) => f(agent.key().id() as usize, str),
+                Err(err) => log_warn!(
+                    "common prefix search skipped non-UTF-8 key {}: {err}",
+                    agent.key().id()
+                ),
+            }
+        }
+    }
+
+    /// Calls `f` with the id and bytes of every key, in the trie's
+    /// traversal order, until it returns `ControlFlow::Break`. Non-UTF-8
+    /// keys are included. Nothing is allocated past the one agent, so this
+    /// is the cheapest way to scan a whole dictionary.
+    pub fn for_each_key<B>(
+        &self,
+        mut f: impl FnMut(usize, &[u8]) -> ControlFlow<B>,
+    ) -> ControlFlow<B> {
+        let mut agent = Agent::default();
+        agent.set_query("");
+        while self.predictive_search_agent(&mut agent) {
+            f(agent.key().id() as usize, agent.key_bytes())?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// `predictive_search` split across `threads` threads, or one per
+    /// available core when `threads` is 0, for scans over very many keys.
+    ///
+    /// The keys under `prefix` are partitioned by the byte following it,
+    /// and threads take partitions as they finish. Results come grouped by
+    /// that byte in ascending order, after `prefix` itself if it is a key;
+    /// within a group they are in `predictive_search` order.
+    pub fn par_predictive_search(
+        &self,
+        prefix: impl AsRef<str>,
+        threads: usize,
+    ) -> Vec<(usize, String)> {
+        let prefix = prefix.as_ref();
+        if !self.is_ready() {
+            return Vec::new();
+        }
+        let _span = trace_span!("marisa::par_predictive_search", prefix_len = prefix.len());
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(256);
+
+        let next = AtomicUsize::new(0);
+        let mut partitions: Vec<(u8, Vec<(usize, String)>)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut agent = Agent::default();
+                        let mut query = prefix.as_bytes().to_vec();
+                        let mut done = Vec::new();
+                        loop {
+                            let byte = next.fetch_add(1, Ordering::Relaxed);
+                            let Ok(byte) = u8::try_from(byte) else {
+                                return done;
+                            };
+                            query.truncate(prefix.len());
+                            query.push(byte);
+                            agent.set_query_bytes(&query);
+                            let mut keys = Vec::new();
+                            while self.predictive_search_agent(&mut agent) {
+                                if let Ok(key) = std::str::from_utf8(agent.key_bytes()) {
+                                    keys.push((agent.key().id() as usize, key.to_owned()));
+                                }
+                            }
+                            if !keys.is_empty() {
+                                done.push((byte, keys));
+                            }
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("search worker panicked"))
+                .collect()
+        });
+        partitions.sort_unstable_by_key(|&(byte, _)| byte);
+
+        let mut results: Vec<(usize, String)> = self
+            .lookup(prefix)
+            .map(|id| (id, prefix.to_owned()))
+            .into_iter()
+            .collect();
+        results.reserve(partitions.iter().map(|(_, keys)| keys.len()).sum());
+        for (_, keys) in partitions {
+            results.extend(keys);
+        }
+        trace_event!(
+            "parallel predictive search finished",
+            num_results = results.len()
+        );
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::ControlFlow;
+
+    use crate::marisa::{Keyset, Trie};
+
+    #[test]
+    fn streams_match_collected_results() {
+        let trie = Trie::try_from(&["f", "fu", "fufi", "fi", "pes"][..]).unwrap();
+
+        let streamed: Vec<_> = trie.predictive_iter("fu").collect();
+        assert_eq!(streamed, trie.predictive_search("fu"));
+        assert_eq!(trie.predictive_iter("").count(), 5);
+        assert_eq!(trie.predictive_iter("fu").take(1).count(), 1);
+
+        let mut keys = Vec::new();
+        trie.for_each_common_prefix("fufik", |_, key| keys.push(key.to_owned()));
+        assert_eq!(keys, vec!["f", "fu", "fufi"]);
+        assert_eq!(
+            trie.common_prefix_iter("fufik").collect::<Vec<_>>(),
+            trie.common_prefix_search("fufik")
+        );
+
+        let mut total = 0;
+        trie.for_each_predictive("f", |_, key| total += key.len());
+        assert_eq!(total, 1 + 2 + 4 + 2);
+
+        assert_eq!(Trie::default().predictive_iter("").count(), 0);
+    }
+
+    #[test]
+    fn streams_skip_non_utf8_keys() {
+        let mut keyset = Keyset::default();
+        keyset.push_bytes(b"caf\xe9", None).unwrap();
+        keyset.push("caf", None).unwrap();
+        keyset.push("cafe", None).unwrap();
+        let trie = Trie::build_from(keyset).unwrap();
+
+        let mut keys = Vec::new();
+        trie.for_each_predictive("caf", |_, key| keys.push(key.to_owned()));
+        keys.sort();
+        assert_eq!(keys, vec!["caf", "cafe"]);
+        assert_eq!(trie.predictive_iter("caf").count(), 2);
+
+        let mut keys = Vec::new();
+        trie.for_each_common_prefix("cafe", |_, key| keys.push(key.to_owned()));
+        assert_eq!(keys, vec!["caf", "cafe"]);
+
+        let mut all = 0;
+        let _ = trie.for_each_key(|_, _| {
+            all += 1;
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(all, 3);
+    }
+
+    #[test]
+    fn parallel_predictive_search() {
+        let keys: Vec<String> = (0..500)
+            .map(|i| format!("{}{i}", ["a", "b", "č", "ab"][i % 4]))
+            .chain(["a", "č"].map(String::from))
+            .collect();
+        let trie =
+            Trie::try_from(&keys.iter().map(String::as_str).collect::<Vec<_>>()[..]).unwrap();
+
+        for prefix in ["", "a", "č", "ab1", "zz"] {
+            let mut expected = trie.predictive_search(prefix);
+            expected.sort();
+            for threads in [0, 1, 3] {
+                let found = trie.par_predictive_search(prefix, threads);
+                if trie.lookup(prefix).is_some() {
+                    assert_eq!(found[0].1, prefix);
+                }
+                let mut found = found;
+                found.sort();
+                assert_eq!(found, expected, "prefix {prefix:?}");
+            }
+        }
+        // Grouped by the byte after the prefix.
+        let bytes: Vec<u8> = trie
+            .par_predictive_search("", 4)
+            .iter()
+            .filter_map(|(_, key)| key.bytes().next())
+            .collect();
+        assert!(bytes.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(Trie::default().par_predictive_search("", 2).is_empty());
+    }
+
+    #[test]
+    fn visits_every_key() {
+        let trie = Trie::try_from(&["f", "fu", "fufi", "fi", "pes"][..]).unwrap();
+
+        let mut ids = Vec::new();
+        let flow = trie.for_each_key(|id, key| {
+            assert_eq!(trie.reverse_lookup(id).unwrap().as_bytes(), key);
+            ids.push(id);
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(flow, ControlFlow::Continue(()));
+        ids.sort_unstable();
+        assert_eq!(ids, (0..5).collect::<Vec<_>>());
+
+        let mut seen = 0;
+        let found = trie.for_each_key(|id, key| {
+            seen += 1;
+            if key == b"fu" {
+                ControlFlow::Break(id)
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(found, ControlFlow::Break(trie.lookup("fu").unwrap()));
+        assert!(seen < 5);
+    }
+}
@@ -0,0 +1,247 @@
+//! A dictionary split into several files by the first byte of the key,
+//! with each shard opened on first use.
+
+use std::{io, path::PathBuf, sync::OnceLock};
+
+use crate::{
+    error::Result,
+    marisa::{Keyset, Trie},
+    pipeline::QueryPipeline,
+    registry::{self, LoadMode},
+};
+
+/// The shard `key` belongs to among `num_shards`. Shards cover contiguous
+/// ranges of first bytes, so each holds a disjoint, ordered slice of the
+/// key space; the empty key goes to shard 0.
+pub fn shard_of(key: impl AsRef<[u8]>, num_shards: usize) -> usize {
+    let key = key.as_ref();
+    key.first()
+        .map_or(0, |&byte| byte as usize * num_shards / 256)
+}
+
+/// Splits `keys` with [`shard_of`] and saves one trie per entry of
+/// `paths`, including empty ones. Fails with `InvalidInput` if `paths` is
+/// empty, since the keys would have nowhere to go.
+pub fn write_shards(
+    keys: impl IntoIterator<Item = impl AsRef<str>>,
+    paths: &[PathBuf],
+) -> Result<()> {
+    if paths.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no shard paths").into());
+    }
+    let mut keysets: Vec<Keyset> = paths.iter().map(|_| Keyset::default()).collect();
+    for key in keys {
+        let key = key.as_ref();
+        keysets[shard_of(key, paths.len())].push(key, None)?;
+    }
+    for (keyset, path) in keysets.into_iter().zip(paths) {
+        Trie::build_from(keyset)?.save(path)?;
+    }
+    Ok(())
+}
+
+/// A key's location: ids are only unique within a shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShardedId {
+    pub shard: usize,
+    pub id: usize,
+}
+
+struct Shard {
+    path: PathBuf,
+    trie: OnceLock<Trie>,
+}
+
+/// Shards written by [`write_shards`], opened lazily. A query touches only
+/// the shard its first byte routes to, except an empty prefix, which
+/// needs all of them.
+///
+/// A shard that fails to open reports the error to the query that needed
+/// it and is retried on the next one.
+pub struct ShardedTrie {
+    mode: LoadMode,
+    shards: Vec<Shard>,
+    pipeline: QueryPipeline,
+}
+
+impl ShardedTrie {
+    pub fn new(paths: impl IntoIterator<Item = impl Into<PathBuf>>, mode: LoadMode) -> Self {
+        Self {
+            mode,
+            shards: paths
+                .into_iter()
+                .map(|path| Shard {
+                    path: path.into(),
+                    trie: OnceLock::new(),
+                })
+                .collect(),
+            pipeline: QueryPipeline::default(),
+        }
+    }
+
+    /// Runs `pipeline` on the query of `lookup` and `predictive_search`,
+    /// and on every result key after. A rewritten key keeps the id of the
+    /// key it came from; a lookup whose key the pipeline drops misses.
+    pub fn with_pipeline(mut self, pipeline: QueryPipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Shards opened so far.
+    pub fn num_resident(&self) -> usize {
+        self.shards
+            .iter()
+            .filter(|shard| shard.trie.get().is_some())
+            .count()
+    }
+
+    fn shard(&self, index: usize) -> Result<&Trie> {
+        let shard = &self.shards[index];
+        if let Some(trie) = shard.trie.get() {
+            return Ok(trie);
+        }
+        let trie = registry::open(&shard.path, self.mode)?;
+        // Another thread may have opened it meanwhile; either copy will do.
+        let _ = shard.trie.set(trie);
+        Ok(shard.trie.get().unwrap())
+    }
+
+    fn route(&self, key: &str) -> usize {
+        shard_of(key.as_bytes(), self.shards.len())
+    }
+
+    pub fn lookup(&self, key: impl AsRef<str>) -> Result<Option<ShardedId>> {
+        let key = &self.pipeline.preprocess(key.as_ref());
+        if self.shards.is_empty() || self.pipeline.postprocess(key).is_none() {
+            return Ok(None);
+        }
+        let shard = self.route(key);
+        Ok(self
+            .shard(shard)?
+            .lookup(key)
+            .map(|id| ShardedId { shard, id }))
+    }
+
+    pub fn reverse_lookup(&self, id: ShardedId) -> Result<Option<String>> {
+        if id.shard >= self.shards.len() {
+            return Ok(None);
+        }
+        Ok(self.shard(id.shard)?.reverse_lookup(id.id))
+    }
+
+    pub fn predictive_search(&self, prefix: impl AsRef<str>) -> Result<Vec<(ShardedId, String)>> {
+        let prefix = &self.pipeline.preprocess(prefix.as_ref());
+        let shards = if prefix.is_empty() {
+            0..self.shards.len()
+        } else if self.shards.is_empty() {
+            0..0
+        } else {
+            let shard = self.route(prefix);
+            shard..shard + 1
+        };
+        let mut results = Vec::new();
+        for shard in shards {
+            results.extend(
+                self.shard(shard)?
+                    .predictive_iter(prefix)
+                    .filter_map(|(id, key)| {
+                        Some((ShardedId { shard, id }, self.pipeline.postprocess(&key)?))
+                    }),
+            );
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shard_of, write_shards, ShardedTrie};
+    use crate::{
+        error::MarisaError, pipeline::QueryPipeline, registry::LoadMode, test_util::temp_paths,
+    };
+
+    #[test]
+    fn routes_by_first_byte() {
+        assert_eq!(shard_of(b"", 4), 0);
+        assert_eq!(shard_of([0x00], 4), 0);
+        assert_eq!(shard_of([0x40], 4), 1);
+        assert_eq!(shard_of([0xff], 4), 3);
+        assert!(shard_of(b"a", 4) <= shard_of(b"z", 4));
+    }
+
+    #[test]
+    fn needs_a_shard_path() {
+        assert!(matches!(
+            write_shards(["apple"], &[]),
+            Err(MarisaError::Io(err)) if err.kind() == std::io::ErrorKind::InvalidInput
+        ));
+    }
+
+    #[test]
+    fn opens_shards_on_first_use() {
+        let paths = temp_paths("shard-lazy", 4);
+        write_shards(["apple", "banana", "avocado", "~tilde"], &paths).unwrap();
+
+        let trie = ShardedTrie::new(&paths, LoadMode::Mmap);
+        assert_eq!(trie.num_shards(), 4);
+        assert_eq!(trie.num_resident(), 0);
+
+        let id = trie.lookup("apple").unwrap().unwrap();
+        assert_eq!(trie.num_resident(), 1);
+        assert_eq!(trie.reverse_lookup(id).unwrap().as_deref(), Some("apple"));
+        assert_eq!(trie.predictive_search("a").unwrap().len(), 2);
+        assert_eq!(trie.lookup("cherry").unwrap(), None);
+        assert_eq!(trie.num_resident(), 1);
+
+        assert_eq!(trie.predictive_search("").unwrap().len(), 4);
+        assert_eq!(trie.num_resident(), 4);
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn missing_shard_fails_only_when_used() {
+        let paths = temp_paths("shard-missing", 2);
+        let trie = ShardedTrie::new(&paths, LoadMode::Load);
+        assert!(matches!(
+            trie.lookup("a"),
+            Err(MarisaError::File {
+                operation: "load",
+                ..
+            })
+        ));
+        assert_eq!(trie.num_resident(), 0);
+    }
+
+    #[test]
+    fn pipeline_wraps_searches() {
+        let paths = temp_paths("shard-pipeline", 2);
+        write_shards(["apple", "avocado", "banana"], &paths).unwrap();
+        let trie = ShardedTrie::new(&paths, LoadMode::Load).with_pipeline(
+            QueryPipeline::new()
+                .trim()
+                .lowercase()
+                .filter_result(|key| key != "avocado")
+                .map_result(str::to_uppercase),
+        );
+
+        let id = trie.lookup(" Apple ").unwrap().unwrap();
+        assert_eq!(trie.reverse_lookup(id).unwrap().as_deref(), Some("apple"));
+        assert_eq!(trie.lookup("avocado").unwrap(), None);
+        let keys: Vec<String> = trie
+            .predictive_search("A")
+            .unwrap()
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        assert_eq!(keys, vec!["APPLE"]);
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
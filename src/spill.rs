@@ -0,0 +1,423 @@
+//! Ingestion with bounded memory: keys are buffered up to a threshold and
+//! spilled to temporary files beyond it, then replayed into a `Keyset`
+//! just before the build.
+//!
+//! A resumable keyset keeps its spill files, or runs, in a directory of its
+//! own along with a manifest of the runs completed so far, so a long build
+//! interrupted midway picks up after the last completed run instead of
+//! starting over.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    config,
+    error::{MarisaError, Result},
+    marisa::Keyset,
+};
+
+/// A keyset front end that keeps at most about `threshold` bytes of keys
+/// in memory. Keys are replayed in push order, so weights and duplicates
+/// behave as if they had been pushed into the keyset directly.
+///
+/// Spill files are written as `u32` length, key bytes, `f32` weight (all
+/// little-endian) and removed when the `SpillingKeyset` is dropped.
+#[derive(Debug)]
+pub struct SpillingKeyset {
+    threshold: usize,
+    dir: PathBuf,
+    allow_empty_key: bool,
+    buffer: Vec<(String, f32)>,
+    buffered_bytes: usize,
+    spills: Vec<PathBuf>,
+    /// Keys in each spill file.
+    spill_keys: Vec<usize>,
+    num_keys: usize,
+    /// Whether the spills are runs listed in a manifest in `dir`, kept
+    /// across drops.
+    resumable: bool,
+}
+
+const MANIFEST: &str = "runs";
+const MANIFEST_HEADER: &str = "marisa-runs 1";
+
+impl SpillingKeyset {
+    /// Spills to the system temporary directory once more than `threshold`
+    /// bytes are buffered.
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            dir: std::env::temp_dir(),
+            allow_empty_key: false,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            spills: Vec::new(),
+            spill_keys: Vec::new(),
+            num_keys: 0,
+            resumable: false,
+        }
+    }
+
+    /// Spills runs into `dir`, creating it if needed, and picks up the runs
+    /// a previous, interrupted ingestion completed there. Keys pushed after
+    /// its last run were lost; [`SpillingKeyset::num_keys`] tells how many
+    /// input keys to skip before pushing the rest.
+    ///
+    /// Runs are kept when the keyset is dropped, and removed by a
+    /// successful [`SpillingKeyset::into_keyset`].
+    pub fn resume(threshold: usize, dir: impl Into<PathBuf>) -> Result<Self> {
+        let mut keys = Self::new(threshold).with_dir(dir);
+        keys.resumable = true;
+        let dir = keys.dir.clone();
+        fs::create_dir_all(&dir).map_err(|err| MarisaError::from(err).at("create", &dir))?;
+
+        let manifest = dir.join(MANIFEST);
+        if manifest.exists() {
+            let at = |err: MarisaError| err.at("resume from", &manifest);
+            let file = File::open(&manifest).map_err(|err| at(err.into()))?;
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let line = line.map_err(|err| at(err.into()))?;
+                if index == 0 {
+                    if line != MANIFEST_HEADER {
+                        return Err(at(MarisaError::incompatible_with(
+                            "unsupported run manifest",
+                            MANIFEST_HEADER,
+                            line,
+                        )));
+                    }
+                    continue;
+                }
+                let parsed = line.split_once(' ').and_then(|(name, rest)| {
+                    let (num_keys, len) = rest.split_once(' ')?;
+                    Some((name, num_keys.parse().ok()?, len.parse::<u64>().ok()?))
+                });
+                let Some((name, num_keys, len)) = parsed else {
+                    return Err(at(MarisaError::Parse {
+                        line: index + 1,
+                        details: format!("expected `run keys bytes`, found {line:?}"),
+                    }));
+                };
+                let path = dir.join(name);
+                let found = fs::metadata(&path).map(|meta| meta.len()).ok();
+                if found != Some(len) {
+                    return Err(MarisaError::Corrupt {
+                        id: None,
+                        details: format!(
+                            "run {} should hold {len} bytes, found {found:?}",
+                            path.display()
+                        ),
+                    });
+                }
+                keys.spills.push(path);
+                keys.spill_keys.push(num_keys);
+                keys.num_keys += num_keys;
+            }
+        }
+
+        // Runs missing from the manifest were cut short by the interruption.
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let is_run = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("run-"));
+            if is_run && !keys.spills.contains(&path) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Spills whatever is buffered, so everything pushed so far survives
+    /// an interruption of a resumable keyset.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.spill()
+    }
+
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    pub fn set_allow_empty_key(&mut self, allow: bool) {
+        self.allow_empty_key = allow;
+    }
+
+    /// Fails like `Keyset::push`, so errors surface while ingesting rather
+    /// than at replay.
+    pub fn push(&mut self, key: impl AsRef<str>, weight: Option<f32>) -> Result<()> {
+        let key = key.as_ref();
+        if key.is_empty() && !self.allow_empty_key {
+            return Err(MarisaError::EmptyKey);
+        }
+        config::check_limits(self.num_keys, key.len())?;
+        self.buffered_bytes += key.len() + mem::size_of::<(String, f32)>();
+        self.buffer.push((key.to_owned(), weight.unwrap_or(1.0)));
+        self.num_keys += 1;
+        if self.buffered_bytes > self.threshold {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    pub fn num_keys(&self) -> usize {
+        self.num_keys
+    }
+
+    /// Spill files written so far.
+    pub fn num_spills(&self) -> usize {
+        self.spills.len()
+    }
+
+    /// Reads the spilled keys back, followed by the ones still buffered.
+    pub fn into_keyset(mut self) -> Result<Keyset> {
+        let mut keyset = Keyset::default();
+        keyset.set_allow_empty_key(self.allow_empty_key);
+        for path in &self.spills {
+            replay(path, &mut keyset).map_err(|err| err.at("replay", path))?;
+        }
+        for (key, weight) in mem::take(&mut self.buffer) {
+            keyset.push(&key, Some(weight))?;
+        }
+        if self.resumable {
+            self.remove_spills();
+            let _ = fs::remove_file(self.dir.join(MANIFEST));
+        }
+        Ok(keyset)
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = if self.resumable {
+            self.dir.join(format!("run-{:06}", self.spills.len()))
+        } else {
+            self.dir.join(format!(
+                "marisa-spill-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ))
+        };
+        // A failed spill is cleaned up too, and the keys stay buffered for
+        // the next attempt, so nothing lists the partial file.
+        let mut partial = PartialSpill {
+            path: &path,
+            keep: false,
+        };
+        write_spill(&path, &self.buffer, self.resumable)
+            .map_err(|err| MarisaError::from(err).at("spill", &path))?;
+        self.spills.push(path.clone());
+        self.spill_keys.push(self.buffer.len());
+        if self.resumable {
+            if let Err(err) = self.write_manifest() {
+                self.spills.pop();
+                self.spill_keys.pop();
+                return Err(err);
+            }
+        }
+        partial.keep = true;
+        self.buffer.clear();
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    /// Records the runs written so far, replacing the manifest atomically.
+    fn write_manifest(&self) -> Result<()> {
+        let path = self.dir.join(MANIFEST);
+        let temp = self.dir.join(format!("{MANIFEST}.tmp"));
+        let at = |err: io::Error| MarisaError::from(err).at("write", &path);
+        let mut out = BufWriter::new(File::create(&temp).map_err(at)?);
+        writeln!(out, "{MANIFEST_HEADER}").map_err(at)?;
+        for (run, num_keys) in self.spills.iter().zip(&self.spill_keys) {
+            let len = fs::metadata(run).map_err(at)?.len();
+            let name = run
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            writeln!(out, "{name} {num_keys} {len}").map_err(at)?;
+        }
+        out.into_inner()
+            .map_err(|err| at(err.into_error()))?
+            .sync_all()
+            .map_err(at)?;
+        fs::rename(&temp, &path).map_err(at)
+    }
+
+    fn remove_spills(&mut self) {
+        for path in self.spills.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+        self.spill_keys.clear();
+    }
+}
+
+impl Drop for SpillingKeyset {
+    fn drop(&mut self) {
+        if !self.resumable {
+            self.remove_spills();
+        }
+    }
+}
+
+/// Removes a spill file on drop unless it was completed and recorded.
+struct PartialSpill<'a> {
+    path: &'a Path,
+    keep: bool,
+}
+
+impl Drop for PartialSpill<'_> {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(self.path);
+        }
+    }
+}
+
+fn write_spill(path: &Path, keys: &[(String, f32)], durable: bool) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (key, weight) in keys {
+        writer.write_all(&(key.len() as u32).to_le_bytes())?;
+        writer.write_all(key.as_bytes())?;
+        writer.write_all(&weight.to_le_bytes())?;
+    }
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    if durable {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+fn replay(path: &Path, keyset: &mut Keyset) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut key = Vec::new();
+    loop {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+        key.resize(u32::from_le_bytes(len) as usize, 0);
+        reader.read_exact(&mut key)?;
+        let mut weight = [0; 4];
+        reader.read_exact(&mut weight)?;
+        keyset.push(std::str::from_utf8(&key)?, Some(f32::from_le_bytes(weight)))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpillingKeyset, MANIFEST};
+    use crate::{error::MarisaError, marisa::Trie};
+
+    #[test]
+    fn spills_and_replays() {
+        let mut keys = SpillingKeyset::new(256);
+        for i in 0..100 {
+            keys.push(format!("key{i}"), Some(i as f32)).unwrap();
+        }
+        assert_eq!(keys.num_keys(), 100);
+        assert!(keys.num_spills() > 1);
+        let spills = keys.spills.clone();
+
+        let mut keyset = keys.into_keyset().unwrap();
+        assert!(spills.iter().all(|path| !path.exists()));
+        assert_eq!(keyset.num_keys(), 100);
+        assert_eq!(keyset.at(42).as_str().unwrap(), "key42");
+        assert_eq!(keyset.at(42).weight(), 42.0);
+
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        assert_eq!(trie.num_keys(), 100);
+        assert!(trie.lookup("key99").is_some());
+    }
+
+    #[test]
+    fn validates_on_push() {
+        let mut keys = SpillingKeyset::new(0);
+        assert!(matches!(keys.push("", None), Err(MarisaError::EmptyKey)));
+        keys.set_allow_empty_key(true);
+        keys.push("", None).unwrap();
+        assert_eq!(keys.num_spills(), 1);
+        assert_eq!(keys.into_keyset().unwrap().num_keys(), 1);
+    }
+
+    #[test]
+    fn failed_spill_keeps_keys_buffered() {
+        let dir = std::env::temp_dir().join(format!("marisa-spill-fail-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut keys = SpillingKeyset::new(100).with_dir(&dir);
+        keys.push("fufi", None).unwrap();
+        keys.push("fi", None).unwrap();
+        // The directory is missing, so the spill fails.
+        assert!(matches!(
+            keys.push("pes-and-more", None),
+            Err(MarisaError::File { .. })
+        ));
+        assert_eq!(keys.num_spills(), 0);
+
+        std::fs::create_dir_all(&dir).unwrap();
+        keys.push("fu-and-more", None).unwrap();
+        assert_eq!(keys.num_spills(), 1);
+        let keyset = keys.into_keyset().unwrap();
+        let mut found: Vec<String> = (0..keyset.num_keys())
+            .map(|i| keyset.at(i).as_str().unwrap().to_owned())
+            .collect();
+        found.sort();
+        assert_eq!(found, ["fi", "fu-and-more", "fufi", "pes-and-more"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resumes_completed_runs() {
+        let dir = std::env::temp_dir().join(format!("marisa-runs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let input: Vec<String> = (0..50).map(|i| format!("key{i}")).collect();
+
+        let mut keys = SpillingKeyset::resume(128, &dir).unwrap();
+        for key in &input[..20] {
+            keys.push(key, None).unwrap();
+        }
+        keys.checkpoint().unwrap();
+        for key in &input[20..25] {
+            keys.push(key, None).unwrap();
+        }
+        let runs = keys.num_spills();
+        // Interrupted: the buffered keys and a half-written run are lost.
+        drop(keys);
+        std::fs::write(dir.join(format!("run-{runs:06}")), b"partial").unwrap();
+
+        let mut keys = SpillingKeyset::resume(128, &dir).unwrap();
+        let done = keys.num_keys();
+        assert!((20..25).contains(&done));
+        assert_eq!(keys.num_spills(), runs);
+        assert!(!dir.join(format!("run-{runs:06}")).exists());
+        for key in &input[done..] {
+            keys.push(key, None).unwrap();
+        }
+        let mut keyset = keys.into_keyset().unwrap();
+        assert_eq!(keyset.num_keys(), 50);
+        assert_eq!(keyset.at(49).as_str().unwrap(), "key49");
+        assert!(!dir.join(MANIFEST).exists());
+
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        assert_eq!(trie.num_keys(), 50);
+
+        std::fs::write(dir.join(MANIFEST), "marisa-runs 1\nrun-000000 3 99\n").unwrap();
+        assert!(matches!(
+            SpillingKeyset::resume(128, &dir),
+            Err(MarisaError::Corrupt { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
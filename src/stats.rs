@@ -0,0 +1,153 @@
+//! Size statistics for a built trie, broken down the way libmarisa lays
+//! the dictionary out, for tuning `num_tries`, tail mode and cache level
+//! against a memory budget.
+
+use crate::{
+    config::Config,
+    error::Result,
+    format::{self, LevelInfo},
+    marisa::{Trie, TrieState},
+};
+
+/// Bytes used by one LOUDS trie, not counting the tries nested in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LevelSizes {
+    pub louds: u64,
+    pub terminal_flags: u64,
+    pub link_flags: u64,
+    pub bases: u64,
+    pub extras: u64,
+    /// Suffixes stored outside the trie; only the innermost level has any.
+    pub tail: u64,
+    pub cache: u64,
+    pub total: u64,
+}
+
+impl From<&LevelInfo> for LevelSizes {
+    fn from(level: &LevelInfo) -> Self {
+        Self {
+            louds: level.louds.bytes,
+            terminal_flags: level.terminal_flags.bytes,
+            link_flags: level.link_flags.bytes,
+            bases: level.bases_bytes,
+            extras: level.extras_bytes,
+            tail: level.tail_bytes,
+            cache: level.cache_bytes,
+            total: level.bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrieStats {
+    pub num_keys: usize,
+    pub num_tries: usize,
+    pub num_nodes: usize,
+    pub config: Option<Config>,
+    /// In-memory size as reported by libmarisa.
+    pub total_size: usize,
+    pub io_size: usize,
+    /// Outermost trie first.
+    pub levels: Vec<LevelSizes>,
+}
+
+impl TrieStats {
+    pub fn tail_bytes(&self) -> u64 {
+        self.levels.iter().map(|level| level.tail).sum()
+    }
+
+    pub fn cache_bytes(&self) -> u64 {
+        self.levels.iter().map(|level| level.cache).sum()
+    }
+}
+
+/// Memory held by a trie, split by where it lives. Mapped bytes are
+/// backed by a file (or a caller's image) and count against page cache
+/// rather than the process heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    pub heap_bytes: usize,
+    pub mapped_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.heap_bytes + self.mapped_bytes
+    }
+}
+
+impl Trie {
+    /// Heap and mapped memory according to how the trie was opened. A
+    /// mapped trie's own bookkeeping on the heap is a few hundred bytes
+    /// and is not counted.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        match self.state() {
+            TrieState::Empty => MemoryUsage::default(),
+            TrieState::Built | TrieState::Loaded => MemoryUsage {
+                heap_bytes: self.total_size(),
+                mapped_bytes: 0,
+            },
+            TrieState::Mapped => MemoryUsage {
+                heap_bytes: 0,
+                mapped_bytes: self.io_size(),
+            },
+        }
+    }
+
+    /// Collects size statistics by inspecting the trie's serialized image.
+    pub fn stats(&self) -> Result<TrieStats> {
+        let info = format::inspect_bytes(&self.to_bytes()?)?;
+        Ok(TrieStats {
+            num_keys: self.num_keys(),
+            num_tries: self.num_tries(),
+            num_nodes: self.num_nodes(),
+            config: info.config(),
+            total_size: self.total_size(),
+            io_size: self.io_size(),
+            levels: info.levels.iter().map(LevelSizes::from).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryUsage;
+    use crate::{config::Config, format::HEADER, marisa::Trie};
+
+    #[test]
+    fn breakdown_adds_up() {
+        let trie = Trie::try_from(&["apple", "applet", "banana"][..]).unwrap();
+        let stats = trie.stats().unwrap();
+        assert_eq!(stats.num_keys, 3);
+        assert_eq!(stats.config, Some(Config::default()));
+        assert!(!stats.levels.is_empty());
+
+        let levels: u64 = stats.levels.iter().map(|level| level.total).sum();
+        assert_eq!(HEADER.len() as u64 + levels, stats.io_size as u64);
+        assert!(stats.tail_bytes() > 0);
+    }
+
+    #[test]
+    fn memory_usage_by_state() {
+        let trie = Trie::try_from(&["apple", "banana"][..]).unwrap();
+        let built = trie.memory_usage();
+        assert_eq!(built.heap_bytes, trie.total_size());
+        assert_eq!(built.mapped_bytes, 0);
+
+        let path = std::env::temp_dir().join(format!("marisa-usage-{}", std::process::id()));
+        trie.save(&path).unwrap();
+        let mut loaded = Trie::default();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.memory_usage(), built);
+
+        let mut mapped = Trie::default();
+        mapped.mmap(&path).unwrap();
+        let usage = mapped.memory_usage();
+        assert_eq!(usage.heap_bytes, 0);
+        assert_eq!(usage.mapped_bytes, trie.io_size());
+        std::fs::remove_file(path).unwrap();
+
+        mapped.clear();
+        assert_eq!(mapped.memory_usage(), MemoryUsage::default());
+    }
+}
@@ -0,0 +1,140 @@
+//! Query expansion with synonyms for search suggestions.
+
+use std::collections::HashMap;
+
+use crate::{error::Result, map::TrieMultimap};
+
+/// Expands queries whose leading words have synonyms: with `nyc → new york
+/// city`, the query `nyc pizza` also searches `new york city pizza`.
+pub struct SynonymExpander {
+    synonyms: TrieMultimap<String>,
+    penalty: f32,
+}
+
+impl SynonymExpander {
+    /// Builds from `(term, synonym)` pairs. Expansion only goes from term
+    /// to synonym; add the reverse pair too for symmetric synonyms.
+    pub fn new<T, S>(pairs: impl IntoIterator<Item = (T, S)>) -> Result<Self>
+    where
+        T: AsRef<str>,
+        S: Into<String>,
+    {
+        Ok(Self {
+            synonyms: TrieMultimap::new(
+                pairs
+                    .into_iter()
+                    .filter(|(term, _)| !term.as_ref().is_empty())
+                    .map(|(term, synonym)| (term, synonym.into())),
+            )?,
+            penalty: 0.8,
+        })
+    }
+
+    /// Multiplies the scores of results found through a synonym, 0.8 by
+    /// default.
+    pub fn with_penalty(mut self, penalty: f32) -> Self {
+        self.penalty = penalty;
+        self
+    }
+
+    /// `query` with weight 1.0, then each rewrite of its longest leading
+    /// term that has synonyms, weighted with the penalty. A term must end
+    /// where a word does.
+    pub fn expand(&self, query: impl AsRef<str>) -> Vec<(String, f32)> {
+        let query = query.as_ref();
+        let mut expansions = vec![(query.to_owned(), 1.0)];
+        let longest = self
+            .synonyms
+            .common_prefixes(query)
+            .into_iter()
+            .rev()
+            .find(|&(len, _)| query[len..].is_empty() || query[len..].starts_with(' '));
+        if let Some((len, synonyms)) = longest {
+            for synonym in synonyms {
+                expansions.push((format!("{synonym}{}", &query[len..]), self.penalty));
+            }
+        }
+        expansions
+    }
+
+    /// Runs `search` for every expansion of `query` and merges the scored
+    /// keys it returns: a key found more than once keeps its best score
+    /// after penalties. Returns the `k` best, highest score first and ties
+    /// in key order.
+    pub fn search(
+        &self,
+        query: &str,
+        k: usize,
+        mut search: impl FnMut(&str) -> Vec<(String, f32)>,
+    ) -> Vec<(String, f32)> {
+        let mut best: HashMap<String, f32> = HashMap::new();
+        for (expansion, weight) in self.expand(query) {
+            for (key, score) in search(&expansion) {
+                let score = score * weight;
+                let entry = best.entry(key).or_insert(score);
+                *entry = entry.max(score);
+            }
+        }
+        let mut merged: Vec<(String, f32)> = best.into_iter().collect();
+        merged.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        merged.truncate(k);
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SynonymExpander;
+    use crate::weighted::WeightedTrie;
+
+    #[test]
+    fn expands_leading_terms() {
+        let expander = SynonymExpander::new([
+            ("nyc", "new york city"),
+            ("nyc", "new york"),
+            ("ny", "new york"),
+            ("tv", "television"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            expander.expand("nyc pizza"),
+            vec![
+                ("nyc pizza".to_owned(), 1.0),
+                ("new york city pizza".to_owned(), 0.8),
+                ("new york pizza".to_owned(), 0.8),
+            ]
+        );
+        assert_eq!(expander.expand("nycx").len(), 1);
+        assert_eq!(expander.expand("tv").len(), 2);
+    }
+
+    #[test]
+    fn merges_results_with_penalties() {
+        let suggestions = WeightedTrie::build([
+            ("television repair", 10.0),
+            ("tv stand", 5.0),
+            ("television", 4.0),
+        ])
+        .unwrap();
+        let expander = SynonymExpander::new([("tv", "television")])
+            .unwrap()
+            .with_penalty(0.5);
+
+        let results = expander.search("tv", 10, |query| {
+            suggestions
+                .top_k(query, 10)
+                .into_iter()
+                .map(|(_, key, weight)| (key, weight))
+                .collect()
+        });
+        assert_eq!(
+            results,
+            vec![
+                ("television repair".to_owned(), 5.0),
+                ("tv stand".to_owned(), 5.0),
+                ("television".to_owned(), 2.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,21 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::PathBuf;
+
+use crate::marisa::Trie;
+
+/// A trie over `keys`, which must all be valid keys.
+pub(crate) fn build(keys: &[&str]) -> Trie {
+    Trie::try_from(keys).unwrap()
+}
+
+/// A path in the temp directory that no other test or test run uses, given
+/// a `name` unique across the crate's tests.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("marisa-{}-{name}", std::process::id()))
+}
+
+/// `n` such paths, numbered from 0.
+pub(crate) fn temp_paths(name: &str, n: usize) -> Vec<PathBuf> {
+    (0..n).map(|i| temp_path(&format!("{name}-{i}"))).collect()
+}
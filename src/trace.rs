@@ -0,0 +1,62 @@
+//! `tracing` spans and events, and `log` warnings, that compile to nothing
+//! without the `tracing` and `log` features respectively.
+//!
+//! The span and event macros take a literal name or message followed by `field = value`
+//! pairs. Durations come from the span lifetimes, so subscribers reporting
+//! span close times (e.g. `FmtSpan::CLOSE`) get them for free.
+
+// Only the libmarisa backend is instrumented.
+#![cfg_attr(not(feature = "libmarisa"), allow(unused_macros, dead_code))]
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        tracing::debug_span!($name $(, $field = $value)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {{
+        if false {
+            $(let _ = &$value;)*
+        }
+        $crate::trace::NoSpan
+    }};
+}
+
+/// Stands in for an entered span so call sites can hold a guard either way.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($message:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        tracing::debug!($($field = $value,)* $message)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($message:literal $(, $field:ident = $value:expr)* $(,)?) => {{
+        if false {
+            $(let _ = &$value;)*
+        }
+    }};
+}
+
+#[cfg(feature = "log")]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        log::warn!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
@@ -0,0 +1,163 @@
+//! Search-as-you-type over a single trie.
+//!
+//! libmarisa can't resume a predictive search from an earlier agent, so a
+//! session instead remembers the matches of every prefix typed so far.
+//! Once a prefix has few enough matches, longer prefixes filter that list
+//! without touching the trie, and backspace restores the previous list.
+
+use crate::marisa::Trie;
+
+enum Matches {
+    Listed(Vec<(usize, String)>),
+    /// More than the session's limit; searched again when asked for.
+    TooMany,
+}
+
+struct Level {
+    /// Length of the query in bytes at this level.
+    end: usize,
+    matches: Matches,
+}
+
+pub struct TypeaheadSession<'a> {
+    trie: &'a Trie,
+    limit: usize,
+    query: String,
+    levels: Vec<Level>,
+}
+
+impl<'a> TypeaheadSession<'a> {
+    pub fn new(trie: &'a Trie) -> Self {
+        Self::with_limit(trie, 1024)
+    }
+
+    /// Keeps match lists of up to `limit` keys per prefix.
+    pub fn with_limit(trie: &'a Trie, limit: usize) -> Self {
+        let mut session = Self {
+            trie,
+            limit,
+            query: String::new(),
+            levels: Vec::new(),
+        };
+        let matches = session.search();
+        session.levels.push(Level { end: 0, matches });
+        session
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Appends a typed character.
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        let matches = match &self.current().matches {
+            Matches::Listed(parent) => Matches::Listed(
+                parent
+                    .iter()
+                    .filter(|(_, key)| key.starts_with(&self.query))
+                    .cloned()
+                    .collect(),
+            ),
+            Matches::TooMany => self.search(),
+        };
+        self.levels.push(Level {
+            end: self.query.len(),
+            matches,
+        });
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.push(c);
+        }
+    }
+
+    /// Removes the last character, as on backspace.
+    pub fn pop(&mut self) -> Option<char> {
+        if self.levels.len() == 1 {
+            return None;
+        }
+        self.levels.pop();
+        let c = self.query.pop();
+        debug_assert_eq!(self.query.len(), self.current().end);
+        c
+    }
+
+    /// Every key starting with the query, or `None` if there are more
+    /// than the session's limit.
+    pub fn matches(&self) -> Option<&[(usize, String)]> {
+        match &self.current().matches {
+            Matches::Listed(matches) => Some(matches),
+            Matches::TooMany => None,
+        }
+    }
+
+    /// Up to `k` keys starting with the query.
+    pub fn first(&self, k: usize) -> Vec<(usize, String)> {
+        match &self.current().matches {
+            Matches::Listed(matches) => matches.iter().take(k).cloned().collect(),
+            Matches::TooMany => self.trie.predictive_iter(&self.query).take(k).collect(),
+        }
+    }
+
+    fn current(&self) -> &Level {
+        self.levels.last().unwrap()
+    }
+
+    fn search(&self) -> Matches {
+        let matches: Vec<(usize, String)> = self
+            .trie
+            .predictive_iter(&self.query)
+            .take(self.limit + 1)
+            .collect();
+        if matches.len() > self.limit {
+            Matches::TooMany
+        } else {
+            Matches::Listed(matches)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypeaheadSession;
+    use crate::marisa::Trie;
+
+    fn keys(matches: &[(usize, String)]) -> Vec<&str> {
+        let mut keys: Vec<&str> = matches.iter().map(|(_, key)| key.as_str()).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn types_and_backspaces() {
+        let trie = Trie::try_from(&["a", "ab", "abc", "abd", "b", "ba"][..]).unwrap();
+        let mut session = TypeaheadSession::with_limit(&trie, 4);
+        assert!(session.matches().is_none());
+        assert_eq!(session.first(2).len(), 2);
+
+        session.push('a');
+        assert_eq!(
+            keys(session.matches().unwrap()),
+            vec!["a", "ab", "abc", "abd"]
+        );
+        session.push_str("bc");
+        assert_eq!(session.query(), "abc");
+        assert_eq!(keys(session.matches().unwrap()), vec!["abc"]);
+        session.push('x');
+        assert!(session.matches().unwrap().is_empty());
+
+        assert_eq!(session.pop(), Some('x'));
+        assert_eq!(session.pop(), Some('c'));
+        assert_eq!(keys(session.matches().unwrap()), vec!["ab", "abc", "abd"]);
+        session.pop();
+        session.pop();
+        assert_eq!(session.pop(), None);
+        assert_eq!(session.query(), "");
+        assert!(session.matches().is_none());
+
+        session.push('b');
+        assert_eq!(keys(&session.first(10)), vec!["b", "ba"]);
+    }
+}
@@ -0,0 +1,437 @@
+//! Loading dictionary images through io_uring, keeping several large
+//! reads in flight so cold starts of multi-gigabyte dictionaries run at
+//! NVMe speed rather than at the pace of one `read` call at a time.
+//!
+//! The ring is driven with raw syscalls, so the feature adds no
+//! dependencies. Kernels without io_uring, and sandboxes blocking it, get
+//! plain positional reads instead, with a warning logged.
+
+use std::{
+    fs::File,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::atomic::{AtomicU32, Ordering},
+    thread::JoinHandle,
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    format,
+    marisa::Trie,
+};
+
+/// Bytes per read request.
+const CHUNK: usize = 1 << 20;
+/// Reads kept in flight.
+const QUEUE_DEPTH: u32 = 16;
+
+const IORING_OP_READ: u8 = 22;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+// Layouts from <linux/io_uring.h>.
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A shared memory region of the ring, unmapped on drop.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: i32, len: usize, offset: i64) -> std::io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `offset` must be in bounds and aligned for `T`.
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.add(offset as usize).cast()
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+struct Ring {
+    params: Params,
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    fd: i32,
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl Ring {
+    fn new(entries: u32) -> std::io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut params) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = fd as i32;
+        let mapped = (|| {
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len = params.cq_off.cqes as usize
+                + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+            let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+            Ok((
+                Mapping::new(fd, sq_len, IORING_OFF_SQ_RING)?,
+                Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?,
+                Mapping::new(fd, sqes_len, IORING_OFF_SQES)?,
+            ))
+        })();
+        match mapped {
+            Ok((sq, cq, sqes)) => Ok(Self {
+                params,
+                sq,
+                cq,
+                sqes,
+                fd,
+            }),
+            Err(err) => {
+                unsafe { libc::close(fd) };
+                Err(err)
+            }
+        }
+    }
+
+    fn sq_entries(&self) -> u32 {
+        self.params.sq_entries
+    }
+
+    /// Queues a read of `buf.len()` bytes at `offset` into `buf`; there
+    /// must be a free submission slot.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must stay valid until the read completes.
+    unsafe fn push_read(&mut self, fd: i32, buf: &mut [u8], offset: u64, user_data: u64) {
+        let off = &self.params.sq_off;
+        let tail = &*self.sq.at::<AtomicU32>(off.tail);
+        let mask = *self.sq.at::<u32>(off.ring_mask);
+        let index = tail.load(Ordering::Relaxed) & mask;
+        self.sqes.at::<Sqe>(0).add(index as usize).write(Sqe {
+            opcode: IORING_OP_READ,
+            flags: 0,
+            ioprio: 0,
+            fd,
+            off: offset,
+            addr: buf.as_mut_ptr() as u64,
+            len: buf.len() as u32,
+            rw_flags: 0,
+            user_data,
+            buf_index: 0,
+            personality: 0,
+            splice_fd_in: 0,
+            addr3: 0,
+            pad: 0,
+        });
+        *self.sq.at::<u32>(off.array).add(index as usize) = index;
+        tail.fetch_add(1, Ordering::Release);
+    }
+
+    /// Submits `to_submit` queued entries and waits for at least one
+    /// completion.
+    fn enter(&self, to_submit: u32) -> std::io::Result<()> {
+        loop {
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    to_submit,
+                    1u32,
+                    IORING_ENTER_GETEVENTS,
+                    std::ptr::null::<libc::c_void>(),
+                    0usize,
+                )
+            };
+            if result >= 0 {
+                return Ok(());
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Calls `f` with the user data and result of every completion.
+    fn reap(&self, mut f: impl FnMut(u64, i32)) {
+        let off = &self.params.cq_off;
+        unsafe {
+            let head = &*self.cq.at::<AtomicU32>(off.head);
+            let tail = &*self.cq.at::<AtomicU32>(off.tail);
+            let mask = *self.cq.at::<u32>(off.ring_mask);
+            let cqes = self.cq.at::<Cqe>(off.cqes);
+            let mut current = head.load(Ordering::Relaxed);
+            while current != tail.load(Ordering::Acquire) {
+                let cqe = &*cqes.add((current & mask) as usize);
+                f(cqe.user_data, cqe.res);
+                current = current.wrapping_add(1);
+            }
+            head.store(current, Ordering::Release);
+        }
+    }
+}
+
+/// Fills `buf` from `file` starting at `offset`, through io_uring where
+/// the kernel allows it.
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    let ring = match Ring::new(QUEUE_DEPTH) {
+        Ok(ring) => ring,
+        Err(err) => {
+            log_warn!("io_uring unavailable, reading with pread: {err}");
+            return Ok(std::os::unix::fs::FileExt::read_exact_at(
+                file, buf, offset,
+            )?);
+        }
+    };
+    read_with_ring(ring, file, buf, offset)
+}
+
+fn read_with_ring(mut ring: Ring, file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    let fd = file.as_raw_fd();
+    // Ranges of `buf` still to read, as (start, end); a short read puts
+    // its remainder back.
+    let mut pending: Vec<(usize, usize)> = (0..buf.len())
+        .step_by(CHUNK)
+        .map(|start| (start, (start + CHUNK).min(buf.len())))
+        .rev()
+        .collect();
+    let mut in_flight: Vec<Option<(usize, usize)>> = vec![None; ring.sq_entries() as usize];
+    let mut num_in_flight = 0;
+    let base = buf.as_mut_ptr();
+    while !pending.is_empty() || num_in_flight > 0 {
+        let mut queued = 0;
+        while let Some(slot) = in_flight.iter().position(Option::is_none) {
+            let Some((start, end)) = pending.pop() else {
+                break;
+            };
+            // SAFETY: ranges in flight are disjoint, and `buf` outlives
+            // them since this loop only exits once all have completed.
+            unsafe {
+                let chunk = std::slice::from_raw_parts_mut(base.add(start), end - start);
+                ring.push_read(fd, chunk, offset + start as u64, slot as u64);
+            }
+            in_flight[slot] = Some((start, end));
+            queued += 1;
+        }
+        num_in_flight += queued;
+        ring.enter(queued)?;
+
+        let mut failure = None;
+        ring.reap(|slot, res| {
+            let (start, end) = in_flight[slot as usize].take().expect("slot in flight");
+            num_in_flight -= 1;
+            match res {
+                res if res < 0 => failure = Some(std::io::Error::from_raw_os_error(-res).into()),
+                0 => {
+                    failure = Some(MarisaError::Io(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "file ended before the image",
+                    )))
+                }
+                read if start + (read as usize) < end => pending.push((start + read as usize, end)),
+                _ => {}
+            }
+        });
+        if let Some(err) = failure {
+            // Reads still in flight point into `buf`; wait for them.
+            while num_in_flight > 0 {
+                ring.enter(0)?;
+                ring.reap(|slot, _| {
+                    in_flight[slot as usize] = None;
+                    num_in_flight -= 1;
+                });
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+impl Trie {
+    /// Loads `path` with io_uring, like [`Trie::load`] otherwise.
+    pub fn load_uring(&mut self, path: &Path) -> Result<()> {
+        let _span = trace_span!(
+            "marisa::load_uring",
+            path = path.to_str().unwrap_or_default()
+        );
+        let info = format::inspect_file(path).map_err(|err| err.at("load", path))?;
+        let file = File::open(path).map_err(|err| MarisaError::Io(err).at("load", path))?;
+        self.read_image(info.io_size as usize, |bytes| {
+            read_exact_at(&file, bytes, 0)
+        })
+    }
+}
+
+/// A dictionary being loaded on a background thread.
+pub struct PendingTrie {
+    handle: JoinHandle<Result<Trie>>,
+}
+
+impl PendingTrie {
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Blocks until the load is done.
+    pub fn wait(self) -> Result<Trie> {
+        self.handle.join().expect("loader thread panicked")
+    }
+}
+
+/// Starts loading `path` with [`Trie::load_uring`] on a new thread, so the
+/// caller can get on with its own startup meanwhile.
+pub fn load_async(path: impl AsRef<Path>) -> PendingTrie {
+    let path = path.as_ref().to_owned();
+    PendingTrie {
+        handle: std::thread::spawn(move || {
+            let mut trie = Trie::default();
+            trie.load_uring(&path)?;
+            Ok(trie)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use super::{load_async, read_exact_at, CHUNK};
+    use crate::{
+        marisa::{Keyset, Trie},
+        test_util::temp_path,
+    };
+
+    #[test]
+    fn reads_in_chunks() {
+        let path = temp_path("uring-bytes");
+        let data: Vec<u8> = (0..3 * CHUNK + 7).map(|i| (i % 251) as u8).collect();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let file = File::open(&path).unwrap();
+        let mut buf = vec![0; data.len() - 5];
+        read_exact_at(&file, &mut buf, 5).unwrap();
+        assert_eq!(buf, data[5..]);
+
+        let mut too_long = vec![0; data.len() + 1];
+        assert!(read_exact_at(&file, &mut too_long, 0).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn loads_tries() {
+        let path = temp_path("uring-trie");
+        let mut keyset = Keyset::default();
+        keyset.push_all(&["fu", "fufi", "pes"]).unwrap();
+        let mut trie = Trie::default();
+        trie.build(&mut keyset);
+        trie.save(&path).unwrap();
+
+        let mut loaded = Trie::default();
+        loaded.load_uring(&path).unwrap();
+        assert_eq!(loaded.lookup("pes"), trie.lookup("pes"));
+
+        let pending = load_async(&path);
+        let loaded = pending.wait().unwrap();
+        assert_eq!(loaded.predictive_search("fu").len(), 2);
+
+        assert!(load_async(temp_path("uring-missing")).wait().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! Restoring keys that may not be valid UTF-8, as found in dictionaries
+//! built from binary or legacy-encoded data by other tools.
+//!
+//! The `&str`-based search methods skip such keys; the `_with` variants
+//! here let the caller choose what happens instead.
+
+use std::borrow::Cow;
+
+use crate::{error::Result, marisa::Trie};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// Fail with `MarisaError::Utf8` on the first invalid key.
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD.
+    Lossy,
+    /// Return every key as raw bytes.
+    Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoredKey {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl RestoredKey {
+    pub fn from_bytes(bytes: &[u8], policy: Utf8Policy) -> Result<RestoredKey> {
+        Ok(match policy {
+            Utf8Policy::Strict => RestoredKey::Text(std::str::from_utf8(bytes)?.to_owned()),
+            Utf8Policy::Lossy => RestoredKey::Text(String::from_utf8_lossy(bytes).into_owned()),
+            Utf8Policy::Bytes => RestoredKey::Bytes(bytes.to_vec()),
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            RestoredKey::Text(text) => text.as_bytes(),
+            RestoredKey::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// The key as text, lossily decoded if it was restored as bytes.
+    pub fn to_str(&self) -> Cow<'_, str> {
+        match self {
+            RestoredKey::Text(text) => Cow::Borrowed(text),
+            RestoredKey::Bytes(bytes) => String::from_utf8_lossy(bytes),
+        }
+    }
+}
+
+impl Trie {
+    pub fn reverse_lookup_with(
+        &self,
+        id: usize,
+        policy: Utf8Policy,
+    ) -> Result<Option<RestoredKey>> {
+        self.reverse_lookup_bytes(id)
+            .map(|bytes| RestoredKey::from_bytes(&bytes, policy))
+            .transpose()
+    }
+
+    pub fn predictive_search_with(
+        &self,
+        prefix: impl AsRef<str>,
+        policy: Utf8Policy,
+    ) -> Result<Vec<(usize, RestoredKey)>> {
+        let mut results = Vec::new();
+        self.predictive_search_bytes(prefix.as_ref(), |id, bytes| {
+            results.push((id, RestoredKey::from_bytes(bytes, policy)?));
+            Ok(())
+        })?;
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RestoredKey, Utf8Policy};
+    use crate::{error::MarisaError, marisa::Trie};
+
+    #[test]
+    fn policies() {
+        let invalid = b"caf\xe9";
+        assert!(matches!(
+            RestoredKey::from_bytes(invalid, Utf8Policy::Strict),
+            Err(MarisaError::Utf8(_))
+        ));
+        assert_eq!(
+            RestoredKey::from_bytes(invalid, Utf8Policy::Lossy).unwrap(),
+            RestoredKey::Text("caf\u{fffd}".to_owned())
+        );
+        let bytes = RestoredKey::from_bytes(invalid, Utf8Policy::Bytes).unwrap();
+        assert_eq!(bytes.as_bytes(), invalid);
+        assert_eq!(bytes.to_str(), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn searches_with_policy() {
+        let trie = Trie::try_from(&["café", "cafe"][..]).unwrap();
+        let id = trie.lookup("café").unwrap();
+        assert_eq!(
+            trie.reverse_lookup_with(id, Utf8Policy::Bytes).unwrap(),
+            Some(RestoredKey::Bytes("café".as_bytes().to_vec()))
+        );
+        assert_eq!(
+            trie.reverse_lookup_with(2, Utf8Policy::Strict).unwrap(),
+            None
+        );
+
+        let mut keys: Vec<String> = trie
+            .predictive_search_with(String::from("caf"), Utf8Policy::Strict)
+            .unwrap()
+            .into_iter()
+            .map(|(_, key)| key.to_str().into_owned())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["cafe", "café"]);
+    }
+}
@@ -0,0 +1,151 @@
+use std::ops::ControlFlow;
+
+use crate::{
+    error::{MarisaError, Result},
+    format,
+    marisa::{Agent, Trie},
+};
+
+fn corrupt(id: Option<usize>, details: impl Into<String>) -> MarisaError {
+    MarisaError::Corrupt {
+        id,
+        details: details.into(),
+    }
+}
+
+/// A key for an error message, escaping bytes that aren't UTF-8.
+fn show(key: &[u8]) -> String {
+    format!("\"{}\"", key.escape_ascii())
+}
+
+impl Trie {
+    /// Checks that the trie is internally consistent: its image parses and
+    /// agrees with the reported sizes, every id round-trips through
+    /// reverse lookup and lookup, and enumerating all keys yields each id
+    /// exactly once with the same key. Keys are compared as bytes, so tries
+    /// with non-UTF-8 keys verify too.
+    ///
+    /// This touches every key, so it is meant for deploy-time checks rather
+    /// than the request path.
+    pub fn verify(&self) -> Result<()> {
+        let image = self.to_bytes()?;
+        let info = format::inspect_bytes(&image)?;
+        if info.io_size != image.len() as u64 || self.io_size() != image.len() {
+            return Err(corrupt(
+                None,
+                format!(
+                    "image is {} bytes but io_size reports {} and the layout {}",
+                    image.len(),
+                    self.io_size(),
+                    info.io_size
+                ),
+            ));
+        }
+        let num_keys = self.num_keys();
+        if info.num_keys() != num_keys {
+            return Err(corrupt(
+                None,
+                format!(
+                    "{num_keys} keys reported but the image has {} terminal nodes",
+                    info.num_keys()
+                ),
+            ));
+        }
+        if info.levels.len() != self.num_tries() {
+            return Err(corrupt(
+                None,
+                format!(
+                    "{} tries reported but the image has {} levels",
+                    self.num_tries(),
+                    info.levels.len()
+                ),
+            ));
+        }
+
+        // Bytes throughout: keys from other tools need not be UTF-8.
+        let mut agent = Agent::default();
+        let mut keys = Vec::with_capacity(num_keys);
+        for id in 0..num_keys {
+            let key = self
+                .reverse_lookup_bytes(id)
+                .ok_or_else(|| corrupt(Some(id), "reverse lookup failed"))?;
+            agent.set_query_bytes(&key);
+            if !self.lookup_agent(&mut agent) {
+                return Err(corrupt(
+                    Some(id),
+                    format!("key {} is not found", show(&key)),
+                ));
+            }
+            let found = agent.key().id() as usize;
+            if found != id {
+                return Err(corrupt(
+                    Some(id),
+                    format!("key {} looks up as id {found}", show(&key)),
+                ));
+            }
+            keys.push(key);
+        }
+
+        let mut seen = vec![false; num_keys];
+        let enumerated = self.for_each_key(|id, key| {
+            if id >= num_keys {
+                return ControlFlow::Break(corrupt(Some(id), "enumerated id is out of range"));
+            }
+            if std::mem::replace(&mut seen[id], true) {
+                return ControlFlow::Break(corrupt(Some(id), "enumerated more than once"));
+            }
+            if key != keys[id] {
+                return ControlFlow::Break(corrupt(
+                    Some(id),
+                    format!(
+                        "enumerated as {} but reverse lookup gives {}",
+                        show(key),
+                        show(&keys[id])
+                    ),
+                ));
+            }
+            ControlFlow::Continue(())
+        });
+        if let ControlFlow::Break(err) = enumerated {
+            return Err(err);
+        }
+        if let Some(id) = seen.iter().position(|&seen| !seen) {
+            return Err(corrupt(Some(id), "missing from enumeration"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marisa::{Keyset, Trie};
+
+    #[test]
+    fn verifies_built_trie() {
+        let trie = Trie::try_from(&["a", "ab", "abc", "b", "日本"][..]).unwrap();
+        trie.verify().unwrap();
+    }
+
+    #[test]
+    fn verifies_loaded_trie() {
+        let path = std::env::temp_dir().join(format!("marisa-verify-{}", std::process::id()));
+        Trie::try_from(&["x", "xy"][..])
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let mut trie = Trie::default();
+        trie.load(&path).unwrap();
+        trie.verify().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn verifies_non_utf8_keys() {
+        let mut keyset = Keyset::default();
+        keyset.push_bytes(b"caf\xe9", None).unwrap();
+        keyset.push_bytes(b"cafe", None).unwrap();
+        let trie = Trie::build_from(keyset).unwrap();
+        assert_eq!(trie.num_keys(), 2);
+        trie.verify().unwrap();
+    }
+}
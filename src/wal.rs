@@ -0,0 +1,356 @@
+//! A write-ahead log for [`DynamicTrie`] changes, so a pseudo-dynamic
+//! dictionary survives a crash without losing its recent additions.
+//!
+//! Each insertion or removal is appended to the log before it is applied.
+//! Opening the log replays it; a record cut short by a crash mid-write is
+//! dropped and the log truncated to the last whole record.
+//!
+//! The log is relative to the static trie it was started against, like a
+//! delta. [`LoggedTrie::checkpoint`] saves a delta and starts the log
+//! afresh, which keeps it from growing without bound.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dynamic::{decode_change, encode_change, Change, DeltaValue, DynamicTrie},
+    error::{MarisaError, Result},
+    utils::Fnv1a,
+};
+
+const WAL_MAGIC: &[u8; 8] = b"MRSWAL\0\0";
+const WAL_VERSION: u32 = 1;
+const HEADER_LEN: usize = 8 + 4 + 8;
+/// Every record is framed by its length and a checksum of its bytes.
+const FRAME_LEN: usize = 4 + 8;
+
+/// When appended records are flushed to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// After every record: nothing acknowledged is lost.
+    #[default]
+    Always,
+    /// At most this long after a record, checked on the next write; a
+    /// crash loses up to that much.
+    Interval(Duration),
+    /// Only on [`LoggedTrie::sync`] and checkpoints, leaving the rest to
+    /// the OS.
+    Manual,
+}
+
+/// A [`DynamicTrie`] whose changes are logged before they are applied.
+pub struct LoggedTrie<V> {
+    trie: DynamicTrie<V>,
+    log: File,
+    path: PathBuf,
+    policy: FsyncPolicy,
+    last_sync: Instant,
+    unsynced: bool,
+    buf: Vec<u8>,
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+impl<V: DeltaValue> LoggedTrie<V> {
+    /// Opens or creates the log at `path` and replays it onto `trie`,
+    /// which must have the static trie the log was started against.
+    pub fn open(mut trie: DynamicTrie<V>, path: &Path, policy: FsyncPolicy) -> Result<Self> {
+        let at = |err: std::io::Error| MarisaError::from(err).at("open", path);
+        let mut log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(at)?;
+        let bytes = std::fs::read(path).map_err(at)?;
+        let fingerprint = trie.base_fingerprint()?;
+        if bytes.is_empty() {
+            log.write_all(&header(fingerprint)).map_err(at)?;
+            log.sync_all().map_err(at)?;
+        } else {
+            let valid = replay(&mut trie, &bytes, fingerprint)?;
+            if valid < bytes.len() {
+                log_warn!(
+                    "dropping {} bytes of a partly written record at the end of {}",
+                    bytes.len() - valid,
+                    path.display()
+                );
+                log.set_len(valid as u64).map_err(at)?;
+            }
+        }
+        log.seek(SeekFrom::End(0)).map_err(at)?;
+        Ok(Self {
+            trie,
+            log,
+            path: path.to_owned(),
+            policy,
+            last_sync: Instant::now(),
+            unsynced: false,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn trie(&self) -> &DynamicTrie<V> {
+        &self.trie
+    }
+
+    pub fn into_trie(self) -> DynamicTrie<V> {
+        self.trie
+    }
+
+    /// Logs and applies the insertion, returning the previous value.
+    pub fn insert(&mut self, key: &str, value: V) -> Result<Option<V>> {
+        self.append(Change::Insert(key.to_owned(), &value))?;
+        Ok(self.trie.insert(key, value))
+    }
+
+    /// Logs and applies the removal, returning whether `key` was present.
+    pub fn remove(&mut self, key: &str) -> Result<bool> {
+        if !self.trie.contains_key(key) {
+            return Ok(false);
+        }
+        self.append(Change::Remove(key.to_owned()))?;
+        Ok(self.trie.remove(key))
+    }
+
+    fn append(&mut self, change: Change<&V>) -> Result<()> {
+        let at = |err: std::io::Error| MarisaError::from(err).at("append to", &self.path);
+        self.buf.clear();
+        self.buf.extend_from_slice(&[0; FRAME_LEN]);
+        encode_change(change, &mut self.buf);
+        let len = (self.buf.len() - FRAME_LEN) as u32;
+        let sum = checksum(&self.buf[FRAME_LEN..]);
+        self.buf[..4].copy_from_slice(&len.to_le_bytes());
+        self.buf[4..FRAME_LEN].copy_from_slice(&sum.to_le_bytes());
+        self.log.write_all(&self.buf).map_err(at)?;
+        self.unsynced = true;
+        match self.policy {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Flushes appended records to stable storage.
+    pub fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            self.log
+                .sync_data()
+                .map_err(|err| MarisaError::from(err).at("sync", &self.path))?;
+            self.unsynced = false;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Saves the changes so far as a delta at `delta_path`, then empties
+    /// the log. Recover with [`DynamicTrie::load_delta`] before
+    /// [`LoggedTrie::open`].
+    pub fn checkpoint(&mut self, delta_path: &Path) -> Result<()> {
+        self.trie.save_delta(delta_path)?;
+        self.restart()
+    }
+
+    /// Compacts the trie, hands it to `persist` to save durably, and only
+    /// then empties the log, which from there on assumes the compacted
+    /// trie as its base. If `persist` fails the log is left as it was: it
+    /// still replays onto the old static trie, so recovery needs nothing
+    /// from the failed save.
+    pub fn compact(&mut self, persist: impl FnOnce(&DynamicTrie<V>) -> Result<()>) -> Result<()> {
+        self.trie.compact()?;
+        persist(&self.trie)?;
+        self.restart()
+    }
+
+    // Writes the fresh log beside the old one and renames it over, so a
+    // crash leaves either the old log or the new one, never a torn header.
+    fn restart(&mut self) -> Result<()> {
+        let at = |err: std::io::Error| MarisaError::from(err).at("restart", &self.path);
+        let fingerprint = self.trie.base_fingerprint()?;
+        let mut temp = self.path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut log = File::create(&temp).map_err(at)?;
+        log.write_all(&header(fingerprint)).map_err(at)?;
+        log.sync_all().map_err(at)?;
+        std::fs::rename(&temp, &self.path).map_err(at)?;
+        self.log = log;
+        self.unsynced = false;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Bytes in the log, header included.
+    pub fn log_len(&self) -> Result<u64> {
+        Ok(self.log.metadata()?.len())
+    }
+}
+
+fn header(fingerprint: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(WAL_MAGIC);
+    header.extend_from_slice(&WAL_VERSION.to_le_bytes());
+    header.extend_from_slice(&fingerprint.to_le_bytes());
+    header
+}
+
+/// Applies the records of `bytes` to `trie`, returning the length of the
+/// valid prefix: everything up to a torn or corrupt last record.
+fn replay<V: DeltaValue>(
+    trie: &mut DynamicTrie<V>,
+    bytes: &[u8],
+    fingerprint: u64,
+) -> Result<usize> {
+    if bytes.len() < HEADER_LEN || &bytes[..8] != WAL_MAGIC {
+        return Err(MarisaError::incompatible("not a write-ahead log"));
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().expect("4 bytes"));
+    if version != WAL_VERSION {
+        return Err(MarisaError::incompatible_with(
+            "unsupported write-ahead log version",
+            WAL_VERSION.to_string(),
+            version.to_string(),
+        ));
+    }
+    let saved = u64::from_le_bytes(bytes[12..HEADER_LEN].try_into().expect("8 bytes"));
+    if saved != fingerprint {
+        return Err(MarisaError::ManifestMismatch {
+            field: "base_fingerprint",
+            expected: format!("{fingerprint:016x}"),
+            found: format!("{saved:016x}"),
+        });
+    }
+
+    let mut offset = HEADER_LEN;
+    while let Some(frame) = bytes.get(offset..offset + FRAME_LEN) {
+        let len = u32::from_le_bytes(frame[..4].try_into().expect("4 bytes")) as usize;
+        let sum = u64::from_le_bytes(frame[4..].try_into().expect("8 bytes"));
+        let Some(record) = bytes.get(offset + FRAME_LEN..offset + FRAME_LEN + len) else {
+            break;
+        };
+        if checksum(record) != sum {
+            break;
+        }
+        match decode_change::<V>(record) {
+            Some((change, used)) if used == len => trie.apply(change),
+            _ => break,
+        }
+        offset += FRAME_LEN + len;
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{FsyncPolicy, LoggedTrie};
+    use crate::{dynamic::DynamicTrie, error::MarisaError, test_util::temp_path};
+
+    fn base() -> DynamicTrie<u32> {
+        DynamicTrie::new([("fu", 1), ("pes", 2)]).unwrap()
+    }
+
+    fn entries(trie: &DynamicTrie<u32>) -> Vec<(String, u32)> {
+        trie.predictive_search("")
+            .into_iter()
+            .map(|(key, value)| (key, *value))
+            .collect()
+    }
+
+    #[test]
+    fn recovers_after_crash() {
+        let path = temp_path("wal-recover");
+        let _ = std::fs::remove_file(&path);
+        let mut logged = LoggedTrie::open(base(), &path, FsyncPolicy::Always).unwrap();
+        assert_eq!(logged.insert("fufi", 3).unwrap(), None);
+        assert_eq!(logged.insert("fu", 10).unwrap(), Some(1));
+        assert!(logged.remove("pes").unwrap());
+        assert!(!logged.remove("pes").unwrap());
+        let expected = entries(logged.trie());
+        let len = logged.log_len().unwrap();
+        drop(logged);
+
+        // A torn record at the end is dropped.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[9, 0, 0, 0, 1, 2])
+            .unwrap();
+        let logged = LoggedTrie::open(base(), &path, FsyncPolicy::Manual).unwrap();
+        assert_eq!(entries(logged.trie()), expected);
+        assert_eq!(logged.log_len().unwrap(), len);
+
+        let other = DynamicTrie::new([("x", 0)]).unwrap();
+        assert!(matches!(
+            LoggedTrie::open(other, &path, FsyncPolicy::Always),
+            Err(MarisaError::ManifestMismatch { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checkpoints_and_compacts() {
+        let path = temp_path("wal-checkpoint");
+        let delta = temp_path("wal-checkpoint-delta");
+        let _ = std::fs::remove_file(&path);
+        let mut logged = LoggedTrie::open(
+            base(),
+            &path,
+            FsyncPolicy::Interval(std::time::Duration::from_secs(60)),
+        )
+        .unwrap();
+        logged.insert("fi", 3).unwrap();
+        logged.checkpoint(&delta).unwrap();
+        let empty = logged.log_len().unwrap();
+        logged.insert("fufi", 4).unwrap();
+        logged.sync().unwrap();
+        let expected = entries(logged.trie());
+        drop(logged);
+
+        let mut restored = base();
+        restored.load_delta(&delta).unwrap();
+        let mut logged = LoggedTrie::open(restored, &path, FsyncPolicy::Always).unwrap();
+        assert_eq!(entries(logged.trie()), expected);
+
+        // A failed save of the compacted trie keeps the log for the old one.
+        let len = logged.log_len().unwrap();
+        let failed = logged.compact(|_| Err(MarisaError::NotReady));
+        assert!(matches!(failed, Err(MarisaError::NotReady)));
+        assert_eq!(logged.log_len().unwrap(), len);
+        let mut restored = base();
+        restored.load_delta(&delta).unwrap();
+        let reopened = LoggedTrie::open(restored, &path, FsyncPolicy::Always).unwrap();
+        assert_eq!(entries(reopened.trie()), expected);
+        drop(reopened);
+
+        let mut saved = Vec::new();
+        logged
+            .compact(|trie| {
+                saved = entries(trie);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(saved, expected);
+        assert_eq!(logged.log_len().unwrap(), empty);
+        assert_eq!(logged.trie().num_pending(), 0);
+        logged.insert("pesfi", 5).unwrap();
+        let expected = entries(logged.trie());
+        drop(logged);
+        let compacted = DynamicTrie::new(saved).unwrap();
+        let logged = LoggedTrie::open(compacted, &path, FsyncPolicy::Always).unwrap();
+        assert_eq!(entries(&logged.into_trie()), expected);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&delta).unwrap();
+    }
+}
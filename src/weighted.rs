@@ -0,0 +1,721 @@
+//! Top-k weighted completion without enumerating the whole subtree.
+//!
+//! The keys starting with a prefix are a contiguous run of the keys in
+//! sorted order. A max tree over the weights in that order, kept next to
+//! the trie, gives the heaviest weight of every block of the run, so the
+//! search expands only the blocks that can still hold one of the `k` best
+//! keys. A one-character prefix over 10M keys costs about `k * log n` tree
+//! steps instead of a predictive search over a large part of the
+//! dictionary.
+//!
+//! [`WeightedTrie::sample`] draws keys in proportion to the same weights
+//! through an alias table, built over the sidecar on first use.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    hash::Hash,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{MarisaError, Result},
+    marisa::{Agent, Keyset, Trie},
+    pipeline::QueryPipeline,
+};
+
+/// A trie with a weight per key and the sidecar for [`WeightedTrie::top_k`].
+pub struct WeightedTrie {
+    keys: Trie,
+    /// Key ids in sorted key order.
+    order: Vec<u32>,
+    /// Max tree over the weights in `order`: leaves start at `leaves`, node
+    /// `i` holds the maximum of nodes `2i` and `2i + 1`.
+    tree: Vec<f32>,
+    leaves: usize,
+    decay: Option<Decay>,
+    sampler: OnceLock<AliasTable>,
+    pipeline: QueryPipeline,
+}
+
+/// Walker's alias table over the positions in `order`: position `i` is
+/// drawn with probability `prob[i]` and otherwise `alias[i]` is.
+struct AliasTable {
+    prob: Vec<f32>,
+    alias: Vec<u32>,
+}
+
+impl AliasTable {
+    /// Vose's construction; weights that aren't positive and finite count
+    /// as zero. `None` if no weight is left.
+    fn new(weights: &[f32]) -> Option<Self> {
+        let usable = |w: f32| {
+            if w.is_finite() && w > 0.0 {
+                w as f64
+            } else {
+                0.0
+            }
+        };
+        let total: f64 = weights.iter().map(|&w| usable(w)).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let n = weights.len();
+        let mut scaled: Vec<f64> = weights
+            .iter()
+            .map(|&w| usable(w) * n as f64 / total)
+            .collect();
+        let (mut small, mut large): (Vec<u32>, Vec<u32>) =
+            (0..n as u32).partition(|&i| scaled[i as usize] < 1.0);
+        let mut prob = vec![1.0; n];
+        let mut alias: Vec<u32> = (0..n as u32).collect();
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            prob[s as usize] = scaled[s as usize] as f32;
+            alias[s as usize] = l;
+            scaled[l as usize] += scaled[s as usize] - 1.0;
+            if scaled[l as usize] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Left over from rounding; a zero weight must still never be drawn.
+        let fallback = weights
+            .iter()
+            .position(|&w| usable(w) > 0.0)
+            .expect("total is positive") as u32;
+        for s in small {
+            if usable(weights[s as usize]) == 0.0 {
+                prob[s as usize] = 0.0;
+                alias[s as usize] = fallback;
+            }
+        }
+        Some(Self { prob, alias })
+    }
+
+    /// Uses the high half of `bits` for the position and the low half for
+    /// the coin.
+    fn draw(&self, bits: u64) -> usize {
+        let i = (((bits >> 32) * self.prob.len() as u64) >> 32) as usize;
+        let coin = (bits as u32) as f32 / 4_294_967_296.0;
+        if coin < self.prob[i] {
+            i
+        } else {
+            self.alias[i] as usize
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.prob.len() * (std::mem::size_of::<f32>() + std::mem::size_of::<u32>())
+    }
+}
+
+/// Last-used times for [`WeightedTrie::top_k_decayed`].
+struct Decay {
+    half_life: Duration,
+    /// Seconds since the Unix epoch, indexed by key id.
+    last_used: Box<[AtomicU64]>,
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl WeightedTrie {
+    /// Builds from `(key, weight)` entries; a key given more than once
+    /// keeps its highest weight.
+    pub fn build<K: AsRef<str>>(entries: impl IntoIterator<Item = (K, f32)>) -> Result<Self> {
+        let mut best: HashMap<String, f32> = HashMap::new();
+        for (key, weight) in entries {
+            let entry = best
+                .entry(key.as_ref().to_owned())
+                .or_insert(f32::NEG_INFINITY);
+            *entry = entry.max(weight);
+        }
+        let mut keyset = Keyset::default();
+        for key in best.keys() {
+            keyset.push(key, None)?;
+        }
+        let keys = Trie::build_from(keyset)?;
+        let mut weights = vec![0.0; keys.num_keys()];
+        for (key, weight) in best {
+            weights[keys.lookup(&key).expect("key was just added")] = weight;
+        }
+        Ok(Self::from_trie(keys, |id| weights[id]))
+    }
+
+    /// Uses an existing trie, weighing each key id with `weight_of`.
+    pub fn from_trie(keys: Trie, mut weight_of: impl FnMut(usize) -> f32) -> Self {
+        let mut sorted: Vec<(Vec<u8>, u32)> = Vec::with_capacity(keys.num_keys());
+        let _ = keys.for_each_key(|id, key| {
+            sorted.push((key.to_vec(), id as u32));
+            std::ops::ControlFlow::<()>::Continue(())
+        });
+        sorted.sort_unstable();
+        let order: Vec<u32> = sorted.into_iter().map(|(_, id)| id).collect();
+
+        let leaves = order.len().next_power_of_two();
+        let mut tree = vec![f32::NEG_INFINITY; 2 * leaves];
+        for (position, &id) in order.iter().enumerate() {
+            tree[leaves + position] = weight_of(id as usize);
+        }
+        for node in (1..leaves).rev() {
+            tree[node] = tree[2 * node].max(tree[2 * node + 1]);
+        }
+        Self {
+            keys,
+            order,
+            tree,
+            leaves,
+            decay: None,
+            sampler: OnceLock::new(),
+            pipeline: QueryPipeline::default(),
+        }
+    }
+
+    pub fn keys(&self) -> &Trie {
+        &self.keys
+    }
+
+    /// Runs `pipeline` on the prefix of the `top_k` searches, and of
+    /// [`crate::feedback::FeedbackStore::top_k`], and on every result key
+    /// after. A rewritten key keeps its id and weight; a dropped one makes
+    /// room for the next best. `sample` draws are not searches and skip it.
+    pub fn with_pipeline(mut self, pipeline: QueryPipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Tracks when each key was last used, starting every key at `since`,
+    /// so `top_k_decayed` can halve a key's weight for every `half_life`
+    /// it goes unused.
+    pub fn with_decay(mut self, half_life: Duration, since: SystemTime) -> Self {
+        let since = unix_seconds(since);
+        self.decay = Some(Decay {
+            half_life,
+            last_used: (0..self.keys.num_keys())
+                .map(|_| AtomicU64::new(since))
+                .collect(),
+        });
+        self
+    }
+
+    /// Marks key `id` as used at `at`. Does nothing without `with_decay`,
+    /// or if the key was already used later.
+    pub fn touch(&self, id: usize, at: SystemTime) {
+        if let Some(time) = self.decay.as_ref().and_then(|d| d.last_used.get(id)) {
+            time.fetch_max(unix_seconds(at), AtomicOrdering::Relaxed);
+        }
+    }
+
+    pub fn last_used(&self, id: usize) -> Option<SystemTime> {
+        let time = self.decay.as_ref()?.last_used.get(id)?;
+        Some(UNIX_EPOCH + Duration::from_secs(time.load(AtomicOrdering::Relaxed)))
+    }
+
+    /// Writes the last-used times as `id seconds` lines, replacing `path`
+    /// atomically. Like the times themselves, the file belongs to this
+    /// build of the trie. Writes no lines without `with_decay`.
+    pub fn save_last_used(&self, path: &Path) -> Result<()> {
+        let at = |err: std::io::Error| MarisaError::from(err).at("save", path);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut out = BufWriter::new(fs::File::create(&temp).map_err(at)?);
+        if let Some(decay) = &self.decay {
+            for (id, time) in decay.last_used.iter().enumerate() {
+                writeln!(out, "{id} {}", time.load(AtomicOrdering::Relaxed)).map_err(at)?;
+            }
+        }
+        out.into_inner()
+            .map_err(|err| at(err.into_error()))?
+            .sync_all()
+            .map_err(at)?;
+        fs::rename(&temp, path).map_err(at)
+    }
+
+    /// Touches every key with the time saved for it at `path`, so times
+    /// already later are kept. Does nothing without `with_decay`.
+    pub fn load_last_used(&self, path: &Path) -> Result<()> {
+        let file = fs::File::open(path).map_err(|err| MarisaError::from(err).at("load", path))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let parsed = line
+                .split_once(' ')
+                .and_then(|(id, secs)| Some((id.parse().ok()?, secs.parse().ok()?)));
+            let Some((id, secs)) = parsed else {
+                return Err(MarisaError::Parse {
+                    line: index + 1,
+                    details: format!("expected `id seconds`, found {line:?}"),
+                });
+            };
+            self.touch(id, UNIX_EPOCH + Duration::from_secs(secs));
+        }
+        Ok(())
+    }
+
+    /// Bytes held by the sidecar, on top of the trie itself, including
+    /// the alias table once `sample` has built it.
+    pub fn sidecar_size(&self) -> usize {
+        self.order.len() * std::mem::size_of::<u32>()
+            + self.tree.len() * std::mem::size_of::<f32>()
+            + self
+                .decay
+                .as_ref()
+                .map_or(0, |d| d.last_used.len() * std::mem::size_of::<u64>())
+            + self.sampler.get().map_or(0, AliasTable::size)
+    }
+
+    /// The `k` heaviest keys starting with `prefix` as `(id, key, weight)`,
+    /// heaviest first and ties in key order. Keys that aren't UTF-8 are
+    /// skipped, as in [`Trie::predictive_iter`].
+    pub fn top_k(&self, prefix: impl AsRef<str>, k: usize) -> Vec<(usize, String, f32)> {
+        self.ranked(prefix.as_ref()).take(k).collect()
+    }
+
+    /// Like `top_k`, but keeps only the heaviest key of those that
+    /// `normalize` maps to the same value, e.g. the first word or the
+    /// case-folded key, so the results aren't variants of one phrase.
+    pub fn top_k_distinct<N: Eq + Hash>(
+        &self,
+        prefix: impl AsRef<str>,
+        k: usize,
+        mut normalize: impl FnMut(&str) -> N,
+    ) -> Vec<(usize, String, f32)> {
+        let mut seen = HashSet::new();
+        self.ranked(prefix.as_ref())
+            .filter(|(_, key, _)| seen.insert(normalize(key)))
+            .take(k)
+            .collect()
+    }
+
+    /// Like `top_k`, with each weight decayed by how long its key has gone
+    /// unused as of `now`. Assumes non-negative weights, which decay can
+    /// only lower; without `with_decay` this is `top_k`.
+    pub fn top_k_decayed(
+        &self,
+        prefix: impl AsRef<str>,
+        k: usize,
+        now: SystemTime,
+    ) -> Vec<(usize, String, f32)> {
+        let Some(decay) = &self.decay else {
+            return self.top_k(prefix, k);
+        };
+        let now = unix_seconds(now);
+        let half_life = decay.half_life.as_secs_f32();
+        self.rerank(prefix.as_ref(), k, 0.0, |id, weight| {
+            let idle = now.saturating_sub(decay.last_used[id].load(AtomicOrdering::Relaxed));
+            weight * 0.5f32.powf(idle as f32 / half_life)
+        })
+    }
+
+    /// Draws `n` keys with replacement as `(id, key, weight)`, each with
+    /// probability proportional to its stored weight; decay is not applied.
+    /// Keys whose weight isn't positive and finite are never drawn, and if
+    /// no key is left the result is empty. `rng` returns uniformly random
+    /// bits, e.g. `|| rng.gen()` with the `rand` crate; each draw takes one
+    /// call. The alias table is built on the first call, in `O(num_keys)`.
+    /// A draw that lands on a key that isn't UTF-8 is skipped, so fewer than
+    /// `n` keys come back from a trie holding such keys.
+    pub fn sample(&self, mut rng: impl FnMut() -> u64, n: usize) -> Vec<(usize, String, f32)> {
+        let weights = &self.tree[self.leaves..self.leaves + self.order.len()];
+        let Some(table) = self.sampler.get().or_else(|| {
+            let table = AliasTable::new(weights)?;
+            Some(self.sampler.get_or_init(|| table))
+        }) else {
+            return Vec::new();
+        };
+        let mut agent = Agent::default();
+        (0..n)
+            .filter_map(|_| {
+                let position = table.draw(rng());
+                let id = self.order[position] as usize;
+                agent.set_query_id(id);
+                self.keys.reverse_lookup_agent(&mut agent);
+                match String::from_utf8(agent.key_bytes().to_vec()) {
+                    Ok(key) => Some((id, key, weights[position])),
+                    Err(err) => {
+                        log_warn!("sample skipped non-UTF-8 key {id}: {}", err.utf8_error());
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// The `k` best keys starting with `prefix` after `adjust` rewrites
+    /// their weights, given that it adds at most `headroom`. Keys are
+    /// visited in static weight order until none of the rest can overtake
+    /// the `k`th adjusted weight.
+    pub(crate) fn rerank(
+        &self,
+        prefix: &str,
+        k: usize,
+        headroom: f32,
+        mut adjust: impl FnMut(usize, f32) -> f32,
+    ) -> Vec<(usize, String, f32)> {
+        let mut top: Vec<(usize, String, f32)> = Vec::new();
+        if k == 0 {
+            return top;
+        }
+        for (id, key, weight) in self.ranked(prefix) {
+            if top.len() >= k && weight + headroom <= top[k - 1].2 {
+                break;
+            }
+            top.push((id, key, adjust(id, weight)));
+            top.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.1.cmp(&b.1)));
+            top.truncate(k);
+        }
+        top
+    }
+
+    /// Keys starting with `prefix`, heaviest first, found lazily.
+    pub(crate) fn ranked(&self, prefix: &str) -> Ranked<'_> {
+        let prefix = self.pipeline.preprocess(prefix);
+        let prefix = prefix.as_bytes();
+        let mut ranked = Ranked {
+            trie: self,
+            agent: Agent::default(),
+            heap: BinaryHeap::new(),
+        };
+        let start = self.order.partition_point(|&id| ranked.key_of(id) < prefix);
+        let end = start
+            + self.order[start..].partition_point(|&id| ranked.key_of(id).starts_with(prefix));
+
+        let (mut lo, mut hi) = (start + self.leaves, end + self.leaves);
+        while lo < hi {
+            if lo & 1 == 1 {
+                ranked.heap.push(self.candidate(lo));
+                lo += 1;
+            }
+            if hi & 1 == 1 {
+                hi -= 1;
+                ranked.heap.push(self.candidate(hi));
+            }
+            lo /= 2;
+            hi /= 2;
+        }
+        ranked
+    }
+
+    fn candidate(&self, node: usize) -> Candidate {
+        let mut first = node;
+        while first < self.leaves {
+            first *= 2;
+        }
+        Candidate {
+            weight: self.tree[node],
+            first: Reverse(first),
+            node,
+        }
+    }
+}
+
+pub(crate) struct Ranked<'a> {
+    trie: &'a WeightedTrie,
+    agent: Agent,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Ranked<'_> {
+    fn key_of(&mut self, id: u32) -> &[u8] {
+        self.agent.set_query_id(id as usize);
+        self.trie.keys.reverse_lookup_agent(&mut self.agent);
+        self.agent.key_bytes()
+    }
+}
+
+impl Iterator for Ranked<'_> {
+    type Item = (usize, String, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Candidate { node, weight, .. }) = self.heap.pop() {
+            let leaves = self.trie.leaves;
+            if node >= leaves {
+                let id = self.trie.order[node - leaves];
+                match String::from_utf8(self.key_of(id).to_vec()) {
+                    Ok(key) => match self.trie.pipeline.postprocess(&key) {
+                        Some(key) => return Some((id as usize, key, weight)),
+                        None => continue,
+                    },
+                    Err(err) => {
+                        log_warn!(
+                            "weighted search skipped non-UTF-8 key {id}: {}",
+                            err.utf8_error()
+                        );
+                        continue;
+                    }
+                }
+            }
+            self.heap.push(self.trie.candidate(2 * node));
+            self.heap.push(self.trie.candidate(2 * node + 1));
+        }
+        None
+    }
+}
+
+/// A tree node in the search frontier. The heaviest node comes out first;
+/// among equal weights the one covering the earliest key does, so ties
+/// come out in key order.
+struct Candidate {
+    weight: f32,
+    first: Reverse<usize>,
+    node: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.weight
+            .total_cmp(&other.weight)
+            .then(self.first.cmp(&other.first))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::WeightedTrie;
+    use crate::{
+        marisa::{Keyset, Trie},
+        pipeline::QueryPipeline,
+    };
+
+    fn keys(top: Vec<(usize, String, f32)>) -> Vec<String> {
+        top.into_iter().map(|(_, key, _)| key).collect()
+    }
+
+    #[test]
+    fn top_k_completions() {
+        let trie = WeightedTrie::build([
+            ("fu", 3.0),
+            ("fufi", 9.0),
+            ("fi", 5.0),
+            ("fuj", 3.0),
+            ("pes", 7.0),
+            ("fu", 4.0),
+        ])
+        .unwrap();
+
+        assert_eq!(keys(trie.top_k("f", 2)), vec!["fufi", "fi"]);
+        assert_eq!(keys(trie.top_k("fu", 10)), vec!["fufi", "fu", "fuj"]);
+        assert_eq!(keys(trie.top_k("", 1)), vec!["fufi"]);
+        assert!(trie.top_k("x", 3).is_empty());
+        assert!(trie.top_k("f", 0).is_empty());
+
+        let (id, key, weight) = trie.top_k("p", 1).remove(0);
+        assert_eq!((key.as_str(), weight), ("pes", 7.0));
+        assert_eq!(trie.keys().lookup("pes"), Some(id));
+
+        let by_length =
+            WeightedTrie::from_trie(Trie::try_from(&["a", "abc", "ab"][..]).unwrap(), |id| {
+                id as f32
+            });
+        assert_eq!(by_length.top_k("a", 1)[0].2, 2.0);
+    }
+
+    #[test]
+    fn distinct_completions() {
+        let trie = WeightedTrie::build([
+            ("New York", 9.0),
+            ("new york", 8.0),
+            ("New York City", 7.0),
+            ("Newark", 6.0),
+            ("NEW YORK", 5.0),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            keys(trie.top_k_distinct("", 3, str::to_lowercase)),
+            vec!["New York", "New York City", "Newark"]
+        );
+        let first_word = |key: &str| key.split(' ').next().unwrap_or_default().to_lowercase();
+        assert_eq!(
+            keys(trie.top_k_distinct("N", 10, first_word)),
+            vec!["New York", "Newark"]
+        );
+    }
+
+    #[test]
+    fn unused_keys_decay() {
+        let start = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let day = Duration::from_secs(86_400);
+        let trie = WeightedTrie::build([("fufi", 8.0), ("fu", 3.0), ("fuj", 1.0)])
+            .unwrap()
+            .with_decay(day, start);
+        let fu = trie.keys().lookup("fu").unwrap();
+
+        assert_eq!(keys(trie.top_k_decayed("f", 1, start)), vec!["fufi"]);
+        trie.touch(fu, start + 3 * day);
+        assert_eq!(trie.last_used(fu), Some(start + 3 * day));
+        // fufi has halved three times, to 1.0; fu hasn't decayed.
+        let top = trie.top_k_decayed("f", 2, start + 3 * day);
+        assert_eq!(keys(top.clone()), vec!["fu", "fufi"]);
+        assert_eq!(top[1].2, 1.0);
+        assert_eq!(keys(trie.top_k("f", 1)), vec!["fufi"]);
+        assert!(trie.top_k_decayed("f", 0, start + 3 * day).is_empty());
+
+        let plain = WeightedTrie::build([("fu", 1.0)]).unwrap();
+        plain.touch(0, start);
+        assert_eq!(plain.last_used(0), None);
+    }
+
+    #[test]
+    fn last_used_survives_reload() {
+        let path = std::env::temp_dir().join(format!("marisa-decay-{}", std::process::id()));
+        let start = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let day = Duration::from_secs(86_400);
+        let build = || {
+            WeightedTrie::build([("fufi", 8.0), ("fu", 3.0), ("fuj", 1.0)])
+                .unwrap()
+                .with_decay(day, start)
+        };
+        let trie = build();
+        let fu = trie.keys().lookup("fu").unwrap();
+        trie.touch(fu, start + 3 * day);
+        trie.save_last_used(&path).unwrap();
+
+        let reloaded = build();
+        reloaded.load_last_used(&path).unwrap();
+        for id in 0..3 {
+            assert_eq!(reloaded.last_used(id), trie.last_used(id));
+        }
+        assert_eq!(
+            keys(reloaded.top_k_decayed("f", 2, start + 3 * day)),
+            vec!["fu", "fufi"]
+        );
+
+        std::fs::write(&path, "0 1\nbad\n").unwrap();
+        assert!(matches!(
+            reloaded.load_last_used(&path),
+            Err(crate::error::MarisaError::Parse { line: 2, .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn samples_in_proportion_to_weight() {
+        let trie = WeightedTrie::build([
+            ("fu", 1.0),
+            ("fufi", 3.0),
+            ("fi", 0.0),
+            ("pes", 6.0),
+            ("nan", f32::NAN),
+        ])
+        .unwrap();
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let splitmix = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+
+        let before = trie.sidecar_size();
+        let drawn = trie.sample(splitmix, 10_000);
+        assert!(trie.sidecar_size() > before);
+        assert_eq!(drawn.len(), 10_000);
+        let count = |key: &str| drawn.iter().filter(|(_, k, _)| k == key).count();
+        assert_eq!(count("fi") + count("nan"), 0);
+        assert!((800..1200).contains(&count("fu")), "{}", count("fu"));
+        assert!((2700..3300).contains(&count("fufi")), "{}", count("fufi"));
+        assert!((5600..6400).contains(&count("pes")), "{}", count("pes"));
+        for (id, key, weight) in &drawn[..10] {
+            assert_eq!(trie.keys().lookup(key), Some(*id));
+            let expected = match key.as_str() {
+                "fu" => 1.0,
+                "fufi" => 3.0,
+                _ => 6.0,
+            };
+            assert_eq!(*weight, expected);
+        }
+
+        let unweighted = WeightedTrie::build([("fu", 0.0), ("fi", -1.0)]).unwrap();
+        assert!(unweighted.sample(|| 0, 5).is_empty());
+        assert!(trie.sample(|| u64::MAX, 0).is_empty());
+        assert_eq!(trie.sample(|| u64::MAX, 1).len(), 1);
+    }
+
+    #[test]
+    fn skips_non_utf8_keys() {
+        let mut keyset = Keyset::default();
+        keyset.push_bytes(b"caf\xe9", None).unwrap();
+        keyset.push("cafe", None).unwrap();
+        let built = Trie::build_from(keyset).unwrap();
+        let cafe = built.lookup("cafe").unwrap();
+        let trie = WeightedTrie::from_trie(built, |id| if id == cafe { 1.0 } else { 5.0 });
+
+        assert_eq!(keys(trie.top_k("caf", 2)), vec!["cafe"]);
+        let mut bits = 0u64;
+        let drawn = trie.sample(
+            || {
+                bits = bits.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                bits
+            },
+            100,
+        );
+        assert!(!drawn.is_empty() && drawn.len() < 100);
+        assert!(drawn
+            .iter()
+            .all(|(id, key, _)| *id == cafe && key == "cafe"));
+    }
+
+    #[test]
+    fn matches_full_enumeration() {
+        let words: Vec<String> = (0..300).map(|i| format!("k{}", i * 7919 % 1000)).collect();
+        let weight = |word: &str| (word.len() * 31 % 17) as f32;
+        let trie = WeightedTrie::build(words.iter().map(|w| (w.as_str(), weight(w)))).unwrap();
+
+        for prefix in ["", "k", "k1", "k99", "k5"] {
+            let mut expected: Vec<(f32, String)> = trie
+                .keys()
+                .predictive_search(prefix)
+                .into_iter()
+                .map(|(_, key)| (weight(&key), key))
+                .collect();
+            expected.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            expected.truncate(10);
+            let expected: Vec<String> = expected.into_iter().map(|(_, key)| key).collect();
+            assert_eq!(keys(trie.top_k(prefix, 10)), expected, "prefix {prefix:?}");
+        }
+    }
+
+    #[test]
+    fn pipeline_wraps_top_k() {
+        let trie = WeightedTrie::build([("fufi", 5.0), ("fu", 4.0), ("fuj", 1.0)])
+            .unwrap()
+            .with_pipeline(
+                QueryPipeline::new()
+                    .trim()
+                    .lowercase()
+                    .filter_result(|key| key != "fufi")
+                    .map_result(str::to_uppercase),
+            );
+        assert_eq!(keys(trie.top_k(" FU", 2)), vec!["FU", "FUJ"]);
+        let top = trie.top_k_decayed("Fu", 1, std::time::SystemTime::now());
+        assert_eq!(top[0].2, 4.0);
+    }
+}